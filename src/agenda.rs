@@ -0,0 +1,451 @@
+//! Drawing of the daily agenda from an iCal calendar.
+//!
+//! Every morning at the configured time, the calendar is downloaded, the
+//! events of the current day are extracted and rendered onto the board.
+//!
+//! Note: Recurring events (`RRULE`) are not expanded and time zones given via
+//! `TZID` are treated as local time.
+use std::thread;
+use std::time::Duration;
+
+use log::{error, info, warn};
+use serde_derive::{Deserialize, Serialize};
+use svg2polylines::Polyline;
+use time::{Date, Month, OffsetDateTime, Time, UtcOffset};
+
+use crate::holidays::SharedHolidays;
+use crate::jobqueue::Job;
+use crate::robot::PrintTask;
+use crate::scaling::Bounds;
+use crate::text;
+use crate::timelimits::{self, SharedTimeLimits};
+use crate::RobotQueue;
+
+const TIMEOUT_SECONDS_NETWORK: u64 = 30;
+
+time::serde::format_description!(hm_time, Time, "[hour]:[minute]");
+
+/// Configuration of the agenda integration.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct AgendaConfig {
+    /// The iCal URL of the calendar (`https://` or `webcal://`).
+    /// Not serialized, since these URLs often contain secret tokens.
+    #[serde(skip_serializing)]
+    url: String,
+    /// Time of day when the agenda should be drawn.
+    #[serde(with = "hm_time")]
+    time: Time,
+    /// Number of columns.
+    #[serde(default = "default_columns")]
+    columns: usize,
+    /// Cap height of the text in mm.
+    #[serde(default = "default_text_size")]
+    text_size: f64,
+}
+
+fn default_columns() -> usize {
+    2
+}
+
+fn default_text_size() -> f64 {
+    6.0
+}
+
+/// A single calendar event.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct Event {
+    /// First day of the event.
+    pub(crate) start_date: Date,
+    /// Start time, or `None` for all-day events.
+    start_time: Option<Time>,
+    /// Last day of the event (inclusive).
+    pub(crate) end_date: Date,
+    summary: String,
+}
+
+impl Event {
+    fn is_on(&self, date: Date) -> bool {
+        self.start_date <= date && date <= self.end_date
+    }
+
+    /// Format the event as a single line of text.
+    fn to_line(&self) -> String {
+        match self.start_time {
+            Some(t) => format!("{:02}:{:02} {}", t.hour(), t.minute(), self.summary),
+            None => format!("----- {}", self.summary),
+        }
+    }
+}
+
+/// Unfold content lines (RFC 5545, section 3.1).
+fn unfold(ics: &str) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    for line in ics.lines() {
+        match (
+            line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')),
+            lines.last_mut(),
+        ) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+/// Unescape a TEXT value.
+fn unescape(value: &str) -> String {
+    value
+        .replace("\\n", " ")
+        .replace("\\N", " ")
+        .replace("\\,", ",")
+        .replace("\\;", ";")
+        .replace("\\\\", "\\")
+}
+
+/// Parse a DATE or DATE-TIME value into the local date and time.
+///
+/// UTC values (with a `Z` suffix) are converted to the specified offset.
+fn parse_datetime(value: &str, offset: UtcOffset) -> Option<(Date, Option<Time>)> {
+    let number = |range: std::ops::Range<usize>| value.get(range)?.parse::<u8>().ok();
+    let year = value.get(0..4)?.parse::<i32>().ok()?;
+    let month = Month::try_from(number(4..6)?).ok()?;
+    let date = Date::from_calendar_date(year, month, number(6..8)?).ok()?;
+    if value.len() == 8 {
+        return Some((date, None));
+    }
+    if value.get(8..9)? != "T" {
+        return None;
+    }
+    let time = Time::from_hms(number(9..11)?, number(11..13)?, number(13..15)?).ok()?;
+    if value.ends_with('Z') {
+        let local = date.with_time(time).assume_utc().to_offset(offset);
+        Some((local.date(), Some(local.time())))
+    } else {
+        Some((date, Some(time)))
+    }
+}
+
+/// Parse the events from an iCal file.
+pub(crate) fn parse_events(ics: &str, offset: UtcOffset) -> Vec<Event> {
+    let mut events = vec![];
+    let mut start: Option<(Date, Option<Time>)> = None;
+    let mut end: Option<(Date, Option<Time>)> = None;
+    let mut summary = None;
+    let mut in_event = false;
+    for line in unfold(ics) {
+        let (name, value) = match line.split_once(':') {
+            Some(parts) => parts,
+            None => continue,
+        };
+        // Strip parameters (e.g. `DTSTART;VALUE=DATE`)
+        let name = name.split(';').next().unwrap_or(name);
+        match (name, value) {
+            ("BEGIN", "VEVENT") => {
+                in_event = true;
+                start = None;
+                end = None;
+                summary = None;
+            }
+            ("END", "VEVENT") => {
+                in_event = false;
+                if let Some((start_date, start_time)) = start {
+                    let end_date = match (end, start_time) {
+                        // The end date of all-day events is exclusive
+                        (Some((date, None)), None) => date.previous_day().unwrap_or(date),
+                        (Some((date, _)), Some(_)) => date,
+                        _ => start_date,
+                    };
+                    events.push(Event {
+                        start_date,
+                        start_time,
+                        end_date: end_date.max(start_date),
+                        summary: summary.take().unwrap_or_default(),
+                    });
+                } else {
+                    warn!("Skipping calendar event without start date");
+                }
+            }
+            ("DTSTART", value) if in_event => start = parse_datetime(value, offset),
+            ("DTEND", value) if in_event => end = parse_datetime(value, offset),
+            ("SUMMARY", value) if in_event => summary = Some(unescape(value)),
+            _ => {}
+        }
+    }
+    events
+}
+
+/// Return the events on the specified date, ordered by start time.
+/// All-day events are listed first.
+fn events_on(events: &[Event], date: Date) -> Vec<Event> {
+    let mut todays: Vec<Event> = events.iter().filter(|e| e.is_on(date)).cloned().collect();
+    todays.sort_by_key(|e| e.start_time.filter(|_| e.start_date == date));
+    todays
+}
+
+/// Lay out the agenda for the specified date within the bounds.
+///
+/// The first line contains the date, the events are distributed over the
+/// configured number of columns. If there are more events than fit onto the
+/// board, the last line indicates how many events were left out.
+fn layout(events: &[Event], date: Date, bounds: &Bounds, config: &AgendaConfig) -> Vec<Polyline> {
+    let size = config.text_size;
+    let line_height = size * text::LINE_HEIGHT;
+    let columns = config.columns.max(1);
+    let column_gap = size;
+    let column_width = (bounds.x.spread() - column_gap * (columns - 1) as f64) / columns as f64;
+
+    let mut polylines = text::render_line(
+        &format!("Agenda {}", date),
+        bounds.x.min,
+        bounds.y.min,
+        size,
+    );
+
+    let rows = ((bounds.y.spread() - line_height) / line_height)
+        .floor()
+        .max(0.0) as usize;
+    let capacity = rows * columns;
+    let mut lines: Vec<String> = events.iter().map(Event::to_line).collect();
+    if lines.is_empty() {
+        lines.push("No events".to_string());
+    } else if lines.len() > capacity && capacity > 0 {
+        let hidden = lines.len() - capacity + 1;
+        lines.truncate(capacity - 1);
+        lines.push(format!("+{} more", hidden));
+    }
+
+    for (i, line) in lines.iter().take(capacity).enumerate() {
+        let column = i / rows;
+        let row = i % rows;
+        let x = bounds.x.min + column as f64 * (column_width + column_gap);
+        let y = bounds.y.min + (row + 1) as f64 * line_height;
+        let line = text::truncate(line, column_width, size);
+        polylines.extend(text::render_line(&line, x, y, size));
+    }
+    polylines
+}
+
+/// Download the calendar.
+pub(crate) fn fetch(url: &str) -> Result<String, String> {
+    // `webcal://` is just an alias for `https://`
+    let url = match url.strip_prefix("webcal://") {
+        Some(rest) => format!("https://{}", rest),
+        None => url.to_string(),
+    };
+    ureq::get(&url)
+        .timeout(Duration::from_secs(TIMEOUT_SECONDS_NETWORK))
+        .call()
+        .map_err(|e| format!("Could not fetch calendar: {}", e))?
+        .into_string()
+        .map_err(|e| format!("Could not read calendar: {}", e))
+}
+
+/// Return the duration until the next occurrence of the specified time.
+fn duration_until(now: OffsetDateTime, time: Time) -> Duration {
+    let mut next = now.replace_time(time);
+    if next <= now {
+        next += time::Duration::DAY;
+    }
+    Duration::from_secs((next - now).whole_seconds().max(0) as u64)
+}
+
+/// Draw the agenda of the current day.
+fn draw_agenda(config: &AgendaConfig, margin: f64, robot_queue: &RobotQueue) -> Result<(), String> {
+    let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+    let ics = fetch(&config.url)?;
+    let events = events_on(&parse_events(&ics, now.offset()), now.date());
+    info!("Agenda: {} event(s) today", events.len());
+
+    let bounds = Bounds::drawable(margin);
+    let polylines = layout(&events, now.date(), &bounds, config);
+
+    let tx = robot_queue
+        .lock()
+        .map_err(|e| format!("Could not communicate with robot thread: {}", e))?;
+    // The agenda is drawn along with the schedule instead of replacing it
+    let job = Job::new("Agenda".to_string(), polylines);
+    tx.send(PrintTask::OneOff(job))
+        .map_err(|e| format!("Could not send print request to robot thread: {}", e))
+}
+
+/// Spawn a thread that draws the agenda every day at the configured time.
+///
+/// If time limits are configured and the configured time is outside of them,
+/// or on holidays, the agenda is not drawn. It is laid out within the margin.
+pub(crate) fn spawn(
+    config: AgendaConfig,
+    margin: f64,
+    time_limits: SharedTimeLimits,
+    holidays: SharedHolidays,
+    robot_queue: RobotQueue,
+) {
+    info!(
+        "Drawing agenda every day at {:02}:{:02}",
+        config.time.hour(),
+        config.time.minute()
+    );
+    thread::spawn(move || loop {
+        let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+        thread::sleep(duration_until(now, config.time));
+
+        if let Some(limits) = timelimits::current(&time_limits) {
+            if !limits.is_within_limits(&config.time) {
+                info!("Agenda: Skipping (outside of time limits)");
+                continue;
+            }
+        }
+        if holidays.is_today() {
+            info!("Agenda: Skipping (holiday)");
+            continue;
+        }
+
+        info!("Agenda: Drawing today's agenda");
+        if let Err(e) = draw_agenda(&config, margin, &robot_queue) {
+            error!("Agenda: {}", e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::{date, datetime, offset, time};
+
+    use super::*;
+    use crate::scaling::Range;
+
+    const ICS: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+BEGIN:VEVENT\r
+DTSTART:20220712T070000Z\r
+DTEND:20220712T073000Z\r
+SUMMARY:Standup\\, daily\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+DTSTART;VALUE=DATE:20220711\r
+DTEND;VALUE=DATE:20220713\r
+SUMMARY:Conference with a very long na\r
+ me\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+DTSTART;TZID=Europe/Zurich:20220713T140000\r
+SUMMARY:Tomorrow\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+    fn config(columns: usize) -> AgendaConfig {
+        AgendaConfig {
+            url: String::new(),
+            time: time!(7:00),
+            columns,
+            text_size: 6.0,
+        }
+    }
+
+    fn bounds(width: f64, height: f64) -> Bounds {
+        Bounds {
+            x: Range {
+                min: 0.0,
+                max: width,
+            },
+            y: Range {
+                min: 0.0,
+                max: height,
+            },
+        }
+    }
+
+    #[test]
+    fn test_parse_events() {
+        let events = parse_events(ICS, offset!(+2));
+        assert_eq!(
+            events,
+            vec![
+                Event {
+                    start_date: date!(2022 - 07 - 12),
+                    start_time: Some(time!(9:00)),
+                    end_date: date!(2022 - 07 - 12),
+                    summary: "Standup, daily".to_string(),
+                },
+                Event {
+                    start_date: date!(2022 - 07 - 11),
+                    start_time: None,
+                    end_date: date!(2022 - 07 - 12),
+                    summary: "Conference with a very long name".to_string(),
+                },
+                Event {
+                    start_date: date!(2022 - 07 - 13),
+                    start_time: Some(time!(14:00)),
+                    end_date: date!(2022 - 07 - 13),
+                    summary: "Tomorrow".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_events_on() {
+        let events = parse_events(ICS, offset!(+2));
+        let todays = events_on(&events, date!(2022 - 07 - 12));
+        assert_eq!(todays.len(), 2);
+        assert_eq!(
+            todays[0].to_line(),
+            "----- Conference with a very long name"
+        );
+        assert_eq!(todays[1].to_line(), "09:00 Standup, daily");
+    }
+
+    #[test]
+    fn test_parse_datetime_invalid() {
+        assert_eq!(parse_datetime("2022", offset!(UTC)), None);
+        assert_eq!(parse_datetime("20221312", offset!(UTC)), None);
+        assert_eq!(parse_datetime("20220712X", offset!(UTC)), None);
+    }
+
+    #[test]
+    fn test_layout_overflow() {
+        let events: Vec<Event> = (0..10)
+            .map(|i| Event {
+                start_date: date!(2022 - 07 - 12),
+                start_time: Some(Time::from_hms(i, 0, 0).unwrap()),
+                end_date: date!(2022 - 07 - 12),
+                summary: "X".to_string(),
+            })
+            .collect();
+        // Line height is 9.6 mm, so there's room for a header and 3 rows
+        let polylines = layout(
+            &events,
+            date!(2022 - 07 - 12),
+            &bounds(200.0, 40.0),
+            &config(2),
+        );
+        // The last of the six lines shows "+5 more"
+        let y = 3.0 * (6.0 * text::LINE_HEIGHT);
+        let more = text::render_line("+5 more", 103.0, y, 6.0);
+        assert!(polylines.ends_with(&more));
+    }
+
+    #[test]
+    fn test_layout_empty() {
+        let polylines = layout(&[], date!(2022 - 07 - 12), &bounds(200.0, 40.0), &config(1));
+        assert!(polylines.ends_with(&text::render_line(
+            "No events",
+            0.0,
+            6.0 * text::LINE_HEIGHT,
+            6.0
+        )));
+    }
+
+    #[test]
+    fn test_duration_until() {
+        let now = datetime!(2022-07-12 6:30 +2);
+        assert_eq!(
+            duration_until(now, time!(7:00)),
+            Duration::from_secs(30 * 60)
+        );
+        assert_eq!(
+            duration_until(now, time!(6:00)),
+            Duration::from_secs(23 * 60 * 60 + 30 * 60)
+        );
+    }
+}
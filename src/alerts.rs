@@ -0,0 +1,57 @@
+//! Alerts about degraded performance, e.g. a dying SD card slowing down the
+//! HTTP handlers, or serial retries slowing down the jobs.
+//!
+//! Requests that take longer than `slow_request_ms` and jobs that take more
+//! than `job_overrun_minutes` longer than their simulated duration are logged
+//! as warnings and reported as events (`slow_request` and `job_overrun`).
+use std::time::Duration;
+
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct AlertConfig {
+    /// Threshold for slow HTTP requests.
+    #[serde(default)]
+    pub(crate) slow_request_ms: Option<u64>,
+    /// Threshold for jobs that take longer than estimated.
+    #[serde(default)]
+    pub(crate) job_overrun_minutes: Option<u64>,
+}
+
+impl AlertConfig {
+    /// Return whether a request that took `elapsed` is slow.
+    pub(crate) fn is_slow_request(&self, elapsed: Duration) -> bool {
+        self.slow_request_ms
+            .is_some_and(|ms| elapsed > Duration::from_millis(ms))
+    }
+
+    /// Return whether a job that is running for `elapsed` took too much
+    /// longer than its `estimate`.
+    pub(crate) fn is_job_overrun(&self, elapsed: Duration, estimate: Duration) -> bool {
+        self.job_overrun_minutes
+            .is_some_and(|minutes| elapsed > estimate + Duration::from_secs(minutes * 60))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thresholds() {
+        let alerts = AlertConfig {
+            slow_request_ms: Some(500),
+            job_overrun_minutes: Some(2),
+        };
+        assert!(!alerts.is_slow_request(Duration::from_millis(500)));
+        assert!(alerts.is_slow_request(Duration::from_millis(501)));
+        let estimate = Duration::from_secs(60);
+        assert!(!alerts.is_job_overrun(Duration::from_secs(180), estimate));
+        assert!(alerts.is_job_overrun(Duration::from_secs(181), estimate));
+
+        // Without thresholds, nothing is reported
+        let alerts = AlertConfig::default();
+        assert!(!alerts.is_slow_request(Duration::from_secs(3600)));
+        assert!(!alerts.is_job_overrun(Duration::from_secs(3600), estimate));
+    }
+}
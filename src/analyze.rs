@@ -0,0 +1,96 @@
+//! Statistics of a drawing, e.g. to decide whether to print it.
+//!
+//! Besides the number of paths and points, the length of the strokes and the
+//! distance the lifted pen travels between them show how complex a drawing
+//! is, and the simulated duration how long the robot takes to draw it.
+use std::time::Duration;
+
+use serde_derive::Serialize;
+use svg2polylines::Polyline;
+
+use crate::order;
+use crate::preflight::Extent;
+use crate::scaling;
+
+#[derive(Debug, Serialize, PartialEq)]
+pub(crate) struct Analysis {
+    /// The number of polylines.
+    pub(crate) paths: usize,
+    /// The number of points of all polylines.
+    pub(crate) points: usize,
+    /// The length of all strokes, in mm.
+    pub(crate) draw_length: f64,
+    /// The distance the lifted pen travels between the strokes, in mm.
+    pub(crate) travel_length: f64,
+    /// The bounding box of the drawing (none if it is empty).
+    pub(crate) bounds: Option<Extent>,
+    /// The estimated duration of the print (including erasing the board), in
+    /// seconds.
+    pub(crate) estimated_seconds: u64,
+}
+
+/// Return the statistics of the polylines, which take `estimate` to draw.
+pub(crate) fn analyze(polylines: &[Polyline], estimate: Duration) -> Analysis {
+    Analysis {
+        paths: polylines.len(),
+        points: polylines.iter().map(Vec::len).sum(),
+        draw_length: polylines.iter().map(order::length).sum(),
+        travel_length: order::travel(polylines),
+        bounds: scaling::get_bounds(polylines).map(|bounds| Extent::from(&bounds)),
+        estimated_seconds: estimate.as_secs_f64().round() as u64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{polyline, square};
+
+    #[test]
+    fn test_analyze() {
+        let polylines = vec![
+            polyline(&[(0.0, 0.0), (30.0, 0.0), (30.0, 40.0)]),
+            polyline(&[(30.0, 50.0), (60.0, 50.0)]),
+        ];
+        let analysis = analyze(&polylines, Duration::from_millis(61_600));
+        assert_eq!(
+            analysis,
+            Analysis {
+                paths: 2,
+                points: 5,
+                draw_length: 100.0,
+                travel_length: 10.0,
+                bounds: Some(Extent {
+                    x_min: 0.0,
+                    x_max: 60.0,
+                    y_min: 0.0,
+                    y_max: 50.0,
+                }),
+                estimated_seconds: 62,
+            }
+        );
+
+        let empty = analyze(&[], Duration::ZERO);
+        assert_eq!(empty.paths, 0);
+        assert_eq!(empty.bounds, None);
+    }
+
+    #[test]
+    fn test_single_closed_path() {
+        // A closed path is drawn without lifting the pen
+        let analysis = analyze(&[square(10.0, 20.0, 5.0)], Duration::from_millis(1_400));
+        assert_eq!(analysis.points, 5);
+        assert_eq!(analysis.draw_length, 20.0);
+        assert_eq!(analysis.travel_length, 0.0);
+        assert_eq!(
+            analysis.bounds,
+            Some(Extent {
+                x_min: 10.0,
+                x_max: 15.0,
+                y_min: 20.0,
+                y_max: 25.0,
+            })
+        );
+        assert_eq!(analysis.estimated_seconds, 1);
+    }
+}
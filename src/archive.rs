@@ -0,0 +1,217 @@
+//! Archival of completed jobs.
+//!
+//! With an `archive` section in the config, the polylines of every job that
+//! was completely drawn (in board coordinates, after scaling and placement)
+//! are sent to external storage together with some metadata, so that
+//! everything drawn can be kept without a local history:
+//!
+//! - `webhook`: The entry is POSTed as JSON to the `url`.
+//! - `put`: The entry is PUT as a JSON file to `<url><board>/<time>-<id>.json`,
+//!   e.g. to a WebDAV share. The `headers` are sent with every request (e.g.
+//!   a token). Requests aren't signed in any other way.
+//!
+//! The entries are uploaded by a background thread, so that slow storage
+//! doesn't delay the robot. Failed uploads are logged and retried a few times.
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, error, info, warn};
+use serde_derive::{Deserialize, Serialize};
+use svg2polylines::Polyline;
+use time::OffsetDateTime;
+
+use crate::jobs::JobId;
+
+const TIMEOUT_SECONDS_NETWORK: u64 = 30;
+
+/// How often an upload is attempted.
+const MAX_ATTEMPTS: u32 = 3;
+
+/// How long to wait before uploading an entry again.
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub(crate) enum ArchiveConfig {
+    Webhook {
+        /// Not serialized, since webhook URLs often contain secret tokens.
+        #[serde(skip_serializing)]
+        url: String,
+    },
+    /// Formerly called `bucket`.
+    #[serde(alias = "bucket")]
+    Put {
+        /// The base URL, which the object key is appended to.
+        url: String,
+        /// Not serialized, since headers may contain credentials.
+        #[serde(default, skip_serializing)]
+        headers: HashMap<String, String>,
+    },
+}
+
+/// A completed job, as archived.
+#[derive(Debug, Serialize, Clone)]
+pub(crate) struct ArchiveEntry {
+    pub(crate) board: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) job_id: Option<JobId>,
+    pub(crate) label: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub(crate) finished: OffsetDateTime,
+    pub(crate) seconds: f64,
+    /// Whether the board was erased before the job was drawn.
+    pub(crate) erased: bool,
+    pub(crate) polylines: Vec<Polyline>,
+}
+
+/// A job that is being drawn, which is archived once it is completed.
+#[derive(Debug)]
+pub(crate) struct PendingEntry {
+    pub(crate) job_id: Option<JobId>,
+    pub(crate) label: String,
+    pub(crate) erased: bool,
+    pub(crate) polylines: Vec<Polyline>,
+}
+
+impl PendingEntry {
+    pub(crate) fn complete(self, board: &str, seconds: f64) -> ArchiveEntry {
+        ArchiveEntry {
+            board: board.to_string(),
+            job_id: self.job_id,
+            label: self.label,
+            finished: OffsetDateTime::now_utc(),
+            seconds,
+            erased: self.erased,
+            polylines: self.polylines,
+        }
+    }
+}
+
+impl ArchiveEntry {
+    /// Return the key of the object that the entry is stored in.
+    fn object_key(&self) -> String {
+        let board: String = self
+            .board
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let time = format!(
+            "{:04}{:02}{:02}-{:02}{:02}{:02}",
+            self.finished.year(),
+            u8::from(self.finished.month()),
+            self.finished.day(),
+            self.finished.hour(),
+            self.finished.minute(),
+            self.finished.second()
+        );
+        match self.job_id {
+            Some(id) => format!("{}/{}-{}.json", board, time, id),
+            None => format!("{}/{}.json", board, time),
+        }
+    }
+}
+
+impl ArchiveConfig {
+    fn upload(&self, entry: &ArchiveEntry) -> Result<(), String> {
+        let json = serde_json::to_string(entry).map_err(|e| e.to_string())?;
+        let request = match self {
+            ArchiveConfig::Webhook { url } => ureq::post(url),
+            ArchiveConfig::Put { url, headers } => headers.iter().fold(
+                ureq::put(&format!("{}{}", url, entry.object_key())),
+                |request, (name, value)| request.set(name, value),
+            ),
+        };
+        request
+            .timeout(Duration::from_secs(TIMEOUT_SECONDS_NETWORK))
+            .set("Content-Type", "application/json")
+            .send_string(&json)
+            .map(|_| ())
+            .map_err(|e| format!("Archive request failed: {}", e))
+    }
+}
+
+/// Where the completed jobs are sent to for archival.
+pub(crate) type Archive = Sender<ArchiveEntry>;
+
+/// Spawn the archival thread.
+pub(crate) fn spawn(config: ArchiveConfig) -> Archive {
+    info!("Archiving completed jobs");
+    let (tx, rx) = channel::<ArchiveEntry>();
+    thread::spawn(move || {
+        for entry in rx {
+            for attempt in 1..=MAX_ATTEMPTS {
+                match config.upload(&entry) {
+                    Ok(()) => {
+                        debug!("Archived {}", entry.label);
+                        break;
+                    }
+                    Err(e) if attempt < MAX_ATTEMPTS => {
+                        warn!(
+                            "Could not archive {} (attempt {}): {}",
+                            entry.label, attempt, e
+                        );
+                        thread::sleep(RETRY_DELAY);
+                    }
+                    Err(e) => error!("Could not archive {}: {}", entry.label, e),
+                }
+            }
+        }
+    });
+    tx
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::datetime;
+
+    use super::*;
+
+    fn entry(board: &str, job_id: Option<JobId>) -> ArchiveEntry {
+        ArchiveEntry {
+            board: board.to_string(),
+            job_id,
+            label: "Single print".to_string(),
+            finished: datetime!(2026-10-17 09:05:03 UTC),
+            seconds: 12.5,
+            erased: true,
+            polylines: vec![],
+        }
+    }
+
+    #[test]
+    fn test_object_key() {
+        assert_eq!(
+            entry("default", Some(7)).object_key(),
+            "default/20261017-090503-7.json"
+        );
+        assert_eq!(
+            entry("Hall 2", None).object_key(),
+            "Hall_2/20261017-090503.json"
+        );
+    }
+
+    #[test]
+    fn test_config() {
+        let config: ArchiveConfig = serde_json::from_str(
+            r#"{"type": "put", "url": "https://dav.example.com/drawings/", "headers": {"Authorization": "Bearer secret"}}"#,
+        )
+        .unwrap();
+        assert!(matches!(config, ArchiveConfig::Put { ref headers, .. } if headers.len() == 1));
+        let json = serde_json::to_value(&config).unwrap();
+        assert!(json.get("headers").is_none());
+
+        // The old name of the sink is still accepted
+        let config: ArchiveConfig =
+            serde_json::from_str(r#"{"type": "bucket", "url": "https://example.com/"}"#).unwrap();
+        assert!(matches!(config, ArchiveConfig::Put { .. }));
+
+        let config: ArchiveConfig = serde_json::from_str(
+            r#"{"type": "webhook", "url": "https://example.com/?token=secret"}"#,
+        )
+        .unwrap();
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(json, serde_json::json!({"type": "webhook"}));
+    }
+}
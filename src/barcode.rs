@@ -0,0 +1,274 @@
+//! Rendering of Code 128 barcodes, e.g. for inventory or visitor labels.
+use serde_derive::Deserialize;
+use svg2polylines::{CoordinatePair, Polyline};
+
+use crate::scaling::Bounds;
+use crate::text;
+
+/// Bar and space widths (in modules) of every symbol, starting with a bar.
+const PATTERNS: [&str; 107] = [
+    "212222", "222122", "222221", "121223", "121322", "131222", "122213", "122312", "132212",
+    "221213", "221312", "231212", "112232", "122132", "122231", "113222", "123122", "123221",
+    "223211", "221132", "221231", "213212", "223112", "312131", "311222", "321122", "321221",
+    "312212", "322112", "322211", "212123", "212321", "232121", "111323", "131123", "131321",
+    "112313", "132113", "132311", "211313", "231113", "231311", "112133", "112331", "132131",
+    "113123", "113321", "133121", "313121", "211331", "231131", "213113", "213311", "213131",
+    "311123", "311321", "331121", "312113", "312311", "332111", "314111", "221411", "431111",
+    "111224", "111422", "121124", "121421", "141122", "141221", "112214", "112412", "122114",
+    "122411", "142112", "142211", "241211", "221114", "413111", "241112", "134111", "111242",
+    "121142", "121241", "114212", "124112", "124211", "411212", "421112", "421211", "212141",
+    "214121", "412121", "111143", "111341", "131141", "114113", "114311", "411113", "411311",
+    "113141", "114131", "311141", "411131", "211412", "211214", "211232", "2331112",
+];
+
+const START_B: usize = 104;
+const START_C: usize = 105;
+const STOP: usize = 106;
+
+/// Width of the quiet zone on both sides of the barcode, in modules.
+const QUIET_ZONE: usize = 10;
+
+/// Cap height of the human readable text in mm.
+const TEXT_SIZE: f64 = 5.0;
+
+/// Gap between the bars and the text in mm.
+const TEXT_GAP: f64 = 2.0;
+
+/// The thinnest pen the bars are filled with, in mm.
+const MIN_PEN_WIDTH: f64 = 0.1;
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct Barcode {
+    /// The encoded text (printable ASCII).
+    data: String,
+    /// Width of the narrowest bar in mm. The barcode is shrunk if it doesn't
+    /// fit the board.
+    #[serde(default = "default_module_width")]
+    module_width: f64,
+    /// Height of the bars in mm.
+    #[serde(default = "default_height")]
+    height: f64,
+    /// Width of the pen stroke in mm, which determines how densely bars
+    /// are filled.
+    #[serde(default = "default_pen_width")]
+    pen_width: f64,
+    /// Whether to draw the text below the bars.
+    #[serde(default = "default_show_text")]
+    show_text: bool,
+}
+
+fn default_module_width() -> f64 {
+    1.0
+}
+
+fn default_height() -> f64 {
+    40.0
+}
+
+fn default_pen_width() -> f64 {
+    0.8
+}
+
+fn default_show_text() -> bool {
+    true
+}
+
+/// Return the symbol values of the data, including start, checksum and stop
+/// symbols. Even-length numbers use the denser code set C.
+fn encode(data: &str) -> Result<Vec<usize>, String> {
+    if data.is_empty() {
+        return Err("The barcode data must not be empty".to_string());
+    }
+    let mut symbols = if data.len().is_multiple_of(2) && data.bytes().all(|b| b.is_ascii_digit()) {
+        let mut symbols = vec![START_C];
+        symbols.extend(
+            data.as_bytes()
+                .chunks(2)
+                .map(|pair| usize::from((pair[0] - b'0') * 10 + (pair[1] - b'0'))),
+        );
+        symbols
+    } else {
+        let mut symbols = vec![START_B];
+        for c in data.chars() {
+            if !(' '..='~').contains(&c) {
+                return Err(format!("Character {:?} can't be encoded in Code 128", c));
+            }
+            symbols.push(c as usize - 32);
+        }
+        symbols
+    };
+    let checksum = symbols
+        .iter()
+        .enumerate()
+        .map(|(i, symbol)| i.max(1) * symbol)
+        .sum::<usize>()
+        % 103;
+    symbols.push(checksum);
+    symbols.push(STOP);
+    Ok(symbols)
+}
+
+/// Return the bars as (start, width) in modules, excluding the quiet zone.
+fn bars(symbols: &[usize]) -> Vec<(usize, usize)> {
+    let mut bars = vec![];
+    let mut position = 0;
+    for &symbol in symbols {
+        for (i, width) in PATTERNS[symbol].bytes().enumerate() {
+            let width = usize::from(width - b'0');
+            if i % 2 == 0 {
+                bars.push((position, width));
+            }
+            position += width;
+        }
+    }
+    bars
+}
+
+fn point(x: f64, y: f64) -> CoordinatePair {
+    CoordinatePair { x, y }
+}
+
+/// Fill a bar with vertical strokes, drawn as a single zigzag line.
+fn fill_bar(left: f64, width: f64, top: f64, bottom: f64, pen_width: f64) -> Polyline {
+    let strokes = ((width / pen_width).ceil() as usize).max(1);
+    // Keep the strokes within the bar, so that the bar doesn't grow
+    let inner = (width - pen_width).max(0.0);
+    let mut polyline = vec![];
+    for i in 0..strokes {
+        let x = if strokes == 1 {
+            left + width / 2.0
+        } else {
+            left + pen_width / 2.0 + inner * i as f64 / (strokes - 1) as f64
+        };
+        if i % 2 == 0 {
+            polyline.push(point(x, top));
+            polyline.push(point(x, bottom));
+        } else {
+            polyline.push(point(x, bottom));
+            polyline.push(point(x, top));
+        }
+    }
+    polyline
+}
+
+impl Barcode {
+    /// Render the barcode centered in the specified bounds.
+    pub(crate) fn render(&self, bounds: &Bounds) -> Result<Vec<Polyline>, String> {
+        if self.module_width <= 0.0 || self.height <= 0.0 {
+            return Err("The barcode dimensions must be positive".to_string());
+        }
+        // Every stroke is drawn, so thin pens would make for huge drawings
+        if !(self.pen_width.is_finite() && self.pen_width >= MIN_PEN_WIDTH) {
+            return Err(format!(
+                "The pen width must be at least {} mm",
+                MIN_PEN_WIDTH
+            ));
+        }
+        let symbols = encode(&self.data)?;
+        let bars = bars(&symbols);
+        let modules = 11 * (symbols.len() - 1) + 13 + 2 * QUIET_ZONE;
+        let module_width = self.module_width.min(bounds.x.spread() / modules as f64);
+
+        let text_height = if self.show_text {
+            TEXT_GAP + TEXT_SIZE
+        } else {
+            0.0
+        };
+        let height = self.height.min(bounds.y.spread() - text_height);
+        if height <= 0.0 {
+            return Err("The barcode does not fit the board".to_string());
+        }
+
+        let width = (modules - 2 * QUIET_ZONE) as f64 * module_width;
+        let left = bounds.x.min + (bounds.x.spread() - width) / 2.0;
+        let top = bounds.y.min + (bounds.y.spread() - height - text_height) / 2.0;
+        let mut polylines: Vec<Polyline> = bars
+            .iter()
+            .map(|&(start, bar_width)| {
+                fill_bar(
+                    left + start as f64 * module_width,
+                    bar_width as f64 * module_width,
+                    top,
+                    top + height,
+                    self.pen_width,
+                )
+            })
+            .collect();
+
+        if self.show_text {
+            let label = text::truncate(&self.data, bounds.x.spread(), TEXT_SIZE);
+            let x = bounds.x.min + (bounds.x.spread() - text::line_width(&label, TEXT_SIZE)) / 2.0;
+            polylines.extend(text::render_line(
+                &label,
+                x,
+                top + height + TEXT_GAP,
+                TEXT_SIZE,
+            ));
+        }
+        Ok(polylines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patterns() {
+        for (i, pattern) in PATTERNS.iter().enumerate() {
+            let modules: u32 = pattern.bytes().map(|b| u32::from(b - b'0')).sum();
+            assert_eq!(modules, if i == STOP { 13 } else { 11 }, "symbol {}", i);
+        }
+    }
+
+    #[test]
+    fn test_encode() {
+        // Checksum: (104 + 48 + 2*42 + 3*42 + 4*17 + 5*18 + 6*19 + 7*35) % 103
+        assert_eq!(
+            encode("PJJ123C").unwrap(),
+            vec![START_B, 48, 42, 42, 17, 18, 19, 35, 55, STOP]
+        );
+        // Checksum: (105 + 12 + 2*34) % 103
+        assert_eq!(encode("1234").unwrap(), vec![START_C, 12, 34, 82, STOP]);
+        assert!(encode("").is_err());
+        assert!(encode("Grüße").is_err());
+    }
+
+    #[test]
+    fn test_render_fits_bounds() {
+        let barcode: Barcode =
+            serde_json::from_str(r#"{"data": "a rather long inventory label 0123456789"}"#)
+                .unwrap();
+        let mut bounds = Bounds::board();
+        bounds.add_padding(5.0);
+        let polylines = barcode.render(&bounds).unwrap();
+        assert!(!polylines.is_empty());
+        for p in polylines.iter().flatten() {
+            assert!(p.x >= bounds.x.min - 1e-9 && p.x <= bounds.x.max + 1e-9);
+            assert!(p.y >= bounds.y.min - 1e-9 && p.y <= bounds.y.max + 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_render_rejects_thin_pens() {
+        let bounds = Bounds::board();
+        for pen_width in ["1e-9", "0", "-1"] {
+            let json = format!(r#"{{"data": "INV-004217", "pen_width": {}}}"#, pen_width);
+            let barcode: Barcode = serde_json::from_str(&json).unwrap();
+            assert!(barcode.render(&bounds).is_err());
+        }
+        let barcode: Barcode =
+            serde_json::from_str(r#"{"data": "INV-004217", "pen_width": 0.1}"#).unwrap();
+        assert!(barcode.render(&bounds).is_ok());
+    }
+
+    #[test]
+    fn test_fill_bar() {
+        let bar = fill_bar(10.0, 2.0, 0.0, 5.0, 0.8);
+        // Three strokes, alternating direction
+        assert_eq!(bar.len(), 6);
+        assert_eq!(bar[0].x, 10.4);
+        assert_eq!(bar[5].x, 11.6);
+        assert_eq!((bar[1].y, bar[2].y), (5.0, 5.0));
+    }
+}
@@ -0,0 +1,216 @@
+//! Virtual state of the board, i.e. the polylines that are currently drawn
+//! on it.
+//!
+//! This allows drawing only the new strokes of slowly-growing content (e.g. a
+//! tally), without erasing the board first. With a state dir, the content is
+//! also persisted, so that it can be redrawn after an unexpected restart.
+use std::fs;
+use std::path::Path;
+
+use log::warn;
+use svg2polylines::{CoordinatePair, Polyline};
+
+use crate::scaling::{self, Bounds};
+
+/// Maximum distance in mm between two points that are considered equal.
+const TOLERANCE: f64 = 0.1;
+
+fn same_point(a: &CoordinatePair, b: &CoordinatePair) -> bool {
+    (a.x - b.x).abs() <= TOLERANCE && (a.y - b.y).abs() <= TOLERANCE
+}
+
+fn same_polyline(a: &[CoordinatePair], b: &[CoordinatePair]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same_point(a, b))
+}
+
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Board {
+    /// The drawn polylines, or `None` if the state of the board is unknown
+    /// (e.g. after a job was interrupted).
+    content: Option<Vec<Polyline>>,
+    /// Incremented on every change, to tell when the content must be
+    /// persisted again.
+    revision: u64,
+}
+
+/// Load the content of the board persisted by `Board::save`, if any.
+pub(crate) fn load(path: &Path) -> Option<Vec<Polyline>> {
+    let json = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&json)
+        .map_err(|e| warn!("Could not parse {}: {}", path.display(), e))
+        .ok()
+}
+
+impl Board {
+    /// Return the polylines that need to be added to the board to show the
+    /// specified polylines. If anything would have to be removed from the
+    /// board, `None` is returned, and the board must be erased.
+    pub(crate) fn additions(&self, polylines: &[Polyline]) -> Option<Vec<Polyline>> {
+        let content = self.content.as_ref()?;
+        let mut unmatched: Vec<&Polyline> = polylines.iter().collect();
+        for drawn in content {
+            let index = unmatched
+                .iter()
+                .position(|polyline| same_polyline(polyline, drawn))?;
+            unmatched.swap_remove(index);
+        }
+        // Keep the original order of the additions
+        Some(
+            polylines
+                .iter()
+                .filter(|polyline| unmatched.iter().any(|u| std::ptr::eq(*u, *polyline)))
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// Return the drawn polylines, or `None` if the state of the board is
+    /// unknown.
+    pub(crate) fn content(&self) -> Option<&[Polyline]> {
+        self.content.as_deref()
+    }
+
+    pub(crate) fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Persist the content of the board. An unknown content is not
+    /// persisted, so that the last known content is kept.
+    pub(crate) fn save(&self, path: &Path) {
+        let Some(ref content) = self.content else {
+            return;
+        };
+        match serde_json::to_string(content) {
+            Ok(json) => {
+                if let Err(e) = fs::write(path, json) {
+                    warn!("Could not write {}: {}", path.display(), e);
+                }
+            }
+            Err(e) => warn!("Could not serialize the board: {}", e),
+        }
+    }
+
+    /// Record that the polylines were drawn, after erasing the board if
+    /// `erased` is set.
+    pub(crate) fn draw(&mut self, polylines: &[Polyline], erased: bool) {
+        self.revision += 1;
+        match self.content {
+            Some(ref mut content) if !erased => content.extend_from_slice(polylines),
+            _ if erased => self.content = Some(polylines.to_vec()),
+            _ => {}
+        }
+    }
+
+    /// Record that the polylines were erased.
+    pub(crate) fn remove(&mut self, polylines: &[Polyline]) {
+        self.revision += 1;
+        if let Some(ref mut content) = self.content {
+            for polyline in polylines {
+                if let Some(index) = content
+                    .iter()
+                    .position(|drawn| same_polyline(drawn, polyline))
+                {
+                    content.remove(index);
+                }
+            }
+        }
+    }
+
+    /// Record that a region of the board was erased. Polylines that were
+    /// partially erased are considered erased entirely.
+    pub(crate) fn erase_region(&mut self, region: &Bounds) {
+        self.revision += 1;
+        if let Some(ref mut content) = self.content {
+            content.retain(|polyline| {
+                !scaling::get_bounds(std::slice::from_ref(polyline))
+                    .is_some_and(|bounds| bounds.intersects(region))
+            });
+        }
+    }
+
+    /// Record that strokes were wiped with the eraser. Polylines that were
+    /// partially wiped are considered erased entirely.
+    pub(crate) fn wipe(&mut self, strokes: &[Polyline]) {
+        for stroke in strokes {
+            if let Some(bounds) = scaling::get_bounds(std::slice::from_ref(stroke)) {
+                self.erase_region(&bounds);
+            }
+        }
+    }
+
+    /// Record that the state of the board is unknown.
+    pub(crate) fn forget(&mut self) {
+        self.revision += 1;
+        self.content = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(x: f64) -> Polyline {
+        vec![CoordinatePair { x, y: 0.0 }, CoordinatePair { x, y: 10.0 }]
+    }
+
+    #[test]
+    fn test_unknown_board() {
+        let board = Board::default();
+        assert_eq!(board.additions(&[line(1.0)]), None);
+    }
+
+    #[test]
+    fn test_additions() {
+        let mut board = Board::default();
+        board.draw(&[line(1.0), line(2.0)], true);
+
+        // A tally mark was added (with slightly different rounding)
+        let tally = vec![line(1.0), line(3.0), line(2.05)];
+        assert_eq!(board.additions(&tally), Some(vec![line(3.0)]));
+        board.draw(&[line(3.0)], false);
+        assert_eq!(board.additions(&tally), Some(vec![]));
+
+        // A mark was removed
+        assert_eq!(board.additions(&[line(1.0), line(3.0)]), None);
+        board.remove(&[line(2.0)]);
+        assert_eq!(board.additions(&[line(1.0), line(3.0)]), Some(vec![]));
+
+        // A region around the first mark was erased
+        let region = Bounds {
+            x: scaling::Range { min: 0.5, max: 1.5 },
+            y: scaling::Range { min: 5.0, max: 8.0 },
+        };
+        board.erase_region(&region);
+        assert_eq!(board.content(), Some(&[line(3.0)][..]));
+
+        // An eraser stroke across the remaining mark
+        let stroke = vec![
+            CoordinatePair { x: 2.0, y: 5.0 },
+            CoordinatePair { x: 4.0, y: 5.0 },
+        ];
+        let mut wiped = board.clone();
+        wiped.wipe(&[stroke]);
+        assert_eq!(wiped.content(), Some(&[][..]));
+
+        board.forget();
+        assert_eq!(board.additions(&tally), None);
+        board.draw(&[line(3.0)], false);
+        assert_eq!(board.additions(&tally), None);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("ibb-board-{}", std::process::id()));
+        let mut board = Board::default();
+        board.draw(&[line(1.0), line(2.0)], true);
+        board.save(&path);
+        assert_eq!(load(&path), Some(vec![line(1.0), line(2.0)]));
+
+        // The last known content is kept
+        board.forget();
+        board.save(&path);
+        assert_eq!(load(&path), Some(vec![line(1.0), line(2.0)]));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(load(&path), None);
+    }
+}
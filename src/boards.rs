@@ -0,0 +1,123 @@
+//! Multiple boards: One server drives several robots, each with its own
+//! robot thread and job queue.
+//!
+//! Two boards mounted next to each other can form a canvas: Drawings on the
+//! canvas are split at the seam and each part is drawn by its board.
+use std::collections::HashSet;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::scaling::Layout;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub(crate) struct BoardConfig {
+    /// Name of the board, used to select it in print requests.
+    pub(crate) name: String,
+    /// The serial device of the robot (or `auto` or `mock`).
+    pub(crate) device: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub(crate) struct CanvasConfig {
+    /// Name of the canvas, used to select it like a board.
+    pub(crate) name: String,
+    /// The names of the two boards, left to right or top to bottom.
+    pub(crate) boards: [String; 2],
+    pub(crate) layout: Layout,
+}
+
+/// Check that the canvases have unique names (different from the board
+/// names) and consist of two different boards.
+pub(crate) fn validate_canvases(
+    canvases: &[CanvasConfig],
+    boards: &[BoardConfig],
+) -> Result<(), String> {
+    let mut names: HashSet<&String> = boards.iter().map(|board| &board.name).collect();
+    for canvas in canvases {
+        if !names.insert(&canvas.name) {
+            return Err(format!("Duplicate canvas name: {}", canvas.name));
+        }
+        for name in &canvas.boards {
+            if !boards.iter().any(|board| &board.name == name) {
+                return Err(format!("Unknown board of canvas {}: {}", canvas.name, name));
+            }
+        }
+        if canvas.boards[0] == canvas.boards[1] {
+            return Err(format!("Canvas {} needs two different boards", canvas.name));
+        }
+    }
+    Ok(())
+}
+
+/// Check that there is at least one board and that the board names are
+/// unique and can be used in file names.
+pub(crate) fn validate(boards: &[BoardConfig]) -> Result<(), String> {
+    if boards.is_empty() {
+        return Err("At least one board is required".to_string());
+    }
+    let mut names = HashSet::new();
+    for board in boards {
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if board.name.is_empty() || !board.name.chars().all(valid) {
+            return Err(format!("Invalid board name: \"{}\"", board.name));
+        }
+        if !names.insert(&board.name) {
+            return Err(format!("Duplicate board name: {}", board.name));
+        }
+    }
+    Ok(())
+}
+
+/// Return the name of a file in the state dir with state of the board (e.g.
+/// `last_key` for the last drawn key). The first board keeps the name used
+/// with a single board.
+pub(crate) fn state_file(name: &str, index: usize, board: &BoardConfig) -> String {
+    match index {
+        0 => name.to_string(),
+        _ => format!("{}.{}", name, board.name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(name: &str) -> BoardConfig {
+        BoardConfig {
+            name: name.to_string(),
+            device: "mock".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&[board("lobby"), board("kitchen")]).is_ok());
+        assert!(validate(&[]).is_err());
+        assert!(validate(&[board("")]).is_err());
+        assert!(validate(&[board("../lobby")]).is_err());
+        assert!(validate(&[board("lobby"), board("lobby")]).is_err());
+    }
+
+    #[test]
+    fn test_validate_canvases() {
+        let boards = [board("left"), board("right")];
+        let canvas = |name: &str, first: &str, second: &str| CanvasConfig {
+            name: name.to_string(),
+            boards: [first.to_string(), second.to_string()],
+            layout: Layout::SideBySide,
+        };
+        assert!(validate_canvases(&[canvas("wall", "left", "right")], &boards).is_ok());
+        assert!(validate_canvases(&[canvas("left", "left", "right")], &boards).is_err());
+        assert!(validate_canvases(&[canvas("wall", "left", "top")], &boards).is_err());
+        assert!(validate_canvases(&[canvas("wall", "left", "left")], &boards).is_err());
+    }
+
+    #[test]
+    fn test_state_file() {
+        assert_eq!(state_file("last_key", 0, &board("lobby")), "last_key");
+        assert_eq!(
+            state_file("last_key", 1, &board("kitchen")),
+            "last_key.kitchen"
+        );
+    }
+}
@@ -0,0 +1,347 @@
+//! Captures of the serial traffic, for debugging firmware quirks.
+//!
+//! With a `serial_capture` section in the config, all bytes written to and
+//! read from the robot are recorded, one file per connection. Every line of a
+//! capture is a record with a timestamp, the direction (`>` for bytes sent to
+//! the robot, `<` for bytes received from it) and the bytes in hex:
+//!
+//! ```text
+//! 2026-10-16T09:15:02.5Z > fa 9f a1 fa 90 01
+//! 2026-10-16T09:15:03.1Z < 43 4c 20 53 54 41 54 55 53 3d 52 45 41 44 59 0a
+//! ```
+//!
+//! `iboardbot-web replay` sends the bytes of a capture to a device again,
+//! with the original delays. The same records are mirrored to the serial
+//! console (see the `console` module). While the disk is nearly full (see
+//! the `watchdog` module), nothing is written to the captures.
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use serde_derive::{Deserialize, Serialize};
+use serial::BaudRate;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::console::{self, SerialConsole};
+use crate::robot::{self, Connection};
+use crate::simulate::SimulationConfig;
+use crate::watchdog::SharedWatchdog;
+
+/// How long to wait for responses after the last replayed record.
+const REPLAY_TAIL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct CaptureConfig {
+    /// The directory that the captures are written to.
+    pub(crate) dir: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    /// Bytes sent to the robot.
+    Sent,
+    /// Bytes received from the robot.
+    Received,
+}
+
+impl Direction {
+    fn symbol(self) -> &'static str {
+        match self {
+            Direction::Sent => ">",
+            Direction::Received => "<",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Record {
+    time: OffsetDateTime,
+    direction: Direction,
+    bytes: Vec<u8>,
+}
+
+fn format_record(time: OffsetDateTime, direction: Direction, bytes: &[u8]) -> String {
+    let time = time.format(&Rfc3339).unwrap_or_default();
+    let bytes: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{} {} {}\n", time, direction.symbol(), bytes.join(" "))
+}
+
+/// Parse a capture. Empty lines and comments (starting with `#`) are
+/// skipped.
+fn parse(capture: &str) -> Result<Vec<Record>, String> {
+    let mut records = vec![];
+    for (i, line) in capture.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |reason: &str| format!("Invalid record in line {}: {}", i + 1, reason);
+        let mut parts = line.split_whitespace();
+        let time = parts
+            .next()
+            .and_then(|time| OffsetDateTime::parse(time, &Rfc3339).ok())
+            .ok_or_else(|| invalid("Invalid timestamp"))?;
+        let direction = match parts.next() {
+            Some(">") => Direction::Sent,
+            Some("<") => Direction::Received,
+            _ => return Err(invalid("Invalid direction")),
+        };
+        let bytes = parts
+            .map(|byte| u8::from_str_radix(byte, 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| invalid("Invalid byte"))?;
+        records.push(Record {
+            time,
+            direction,
+            bytes,
+        });
+    }
+    Ok(records)
+}
+
+/// A connection that records all traffic to a capture file and/or the
+/// serial console.
+pub(crate) struct Recorder<C: Connection> {
+    inner: C,
+    file: Option<BufWriter<File>>,
+    console: Option<SerialConsole>,
+    watchdog: SharedWatchdog,
+}
+
+impl<C: Connection> Recorder<C> {
+    fn record(&mut self, direction: Direction, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        let time = OffsetDateTime::now_utc();
+        if let Some(ref mut file) = self
+            .file
+            .as_mut()
+            .filter(|_| !self.watchdog.writes_paused())
+        {
+            let record = format_record(time, direction, bytes);
+            if let Err(e) = file.write_all(record.as_bytes()).and_then(|_| file.flush()) {
+                warn!("Could not write serial capture: {}", e);
+            }
+        }
+        if let Some(ref console) = self.console {
+            console::send(console, || {
+                format_record(time, direction, bytes).trim_end().to_string()
+            });
+        }
+    }
+}
+
+impl<C: Connection> Read for Recorder<C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.record(Direction::Received, &buf[..count]);
+        Ok(count)
+    }
+}
+
+impl<C: Connection> BufRead for Recorder<C> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        // The bytes are still buffered, so this doesn't read from the device
+        if let Ok(buf) = self.inner.fill_buf() {
+            let bytes = buf[..amount.min(buf.len())].to_vec();
+            self.record(Direction::Received, &bytes);
+        }
+        self.inner.consume(amount);
+    }
+}
+
+impl<C: Connection> Write for Recorder<C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = self.inner.write(buf)?;
+        self.record(Direction::Sent, &buf[..count]);
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Return the path of a new capture of the device.
+fn capture_path(dir: &str, device: &str, now: OffsetDateTime) -> PathBuf {
+    let device: String = device
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let time = format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        now.year(),
+        u8::from(now.month()),
+        now.day(),
+        now.hour(),
+        now.minute(),
+        now.second()
+    );
+    Path::new(dir).join(format!("serial-{}-{}.log", time, device.trim_matches('_')))
+}
+
+/// Create a new capture file for the device.
+fn create_capture(config: &CaptureConfig, device: &str) -> Option<BufWriter<File>> {
+    let path = capture_path(&config.dir, device, OffsetDateTime::now_utc());
+    let file = fs::create_dir_all(&config.dir).and_then(|_| File::create(&path));
+    match file {
+        Ok(file) => {
+            info!("Recording serial traffic to {}", path.display());
+            Some(BufWriter::new(file))
+        }
+        Err(e) => {
+            warn!("Could not create serial capture {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Record the traffic of the connection to the device in a new capture
+/// file, if configured, and mirror it to the serial console, if enabled. If
+/// the file can't be created (or writes are paused), the traffic isn't
+/// recorded.
+pub(crate) fn record(
+    connection: Box<dyn Connection>,
+    config: Option<&CaptureConfig>,
+    console: Option<&SerialConsole>,
+    watchdog: &SharedWatchdog,
+    device: &str,
+) -> Box<dyn Connection> {
+    let file = config
+        .filter(|_| !watchdog.writes_paused())
+        .and_then(|config| create_capture(config, device));
+    if file.is_none() && console.is_none() {
+        return connection;
+    }
+    Box::new(Recorder {
+        inner: connection,
+        file,
+        console: console.cloned(),
+        watchdog: watchdog.clone(),
+    })
+}
+
+/// Log the lines received from the robot until the deadline.
+fn read_until(connection: &mut dyn Connection, deadline: Instant) -> io::Result<()> {
+    let mut line = String::new();
+    while Instant::now() < deadline {
+        line.clear();
+        match connection.read_line(&mut line) {
+            Ok(0) => thread::sleep(Duration::from_millis(50)),
+            Ok(_) => info!("< {}", line.trim_end()),
+            Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Send the bytes of a capture to the device, with the original delays in
+/// between, and log the responses. Return the number of records sent.
+pub(crate) fn replay(capture: &str, device: &str) -> io::Result<usize> {
+    let records = parse(&fs::read_to_string(capture)?)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+    let (mut connection, path) =
+        robot::connect(device, BaudRate::Baud115200, SimulationConfig::default())?;
+    info!("Replaying {} to {}", capture, path);
+    let mut previous: Option<OffsetDateTime> = None;
+    let mut sent = 0;
+    for record in records.iter().filter(|r| r.direction == Direction::Sent) {
+        if let Some(previous) = previous {
+            let delay = (record.time - previous).unsigned_abs();
+            read_until(connection.as_mut(), Instant::now() + delay)?;
+        }
+        info!("> {} bytes", record.bytes.len());
+        connection.write_all(&record.bytes)?;
+        connection.flush()?;
+        previous = Some(record.time);
+        sent += 1;
+    }
+    read_until(connection.as_mut(), Instant::now() + REPLAY_TAIL)?;
+    Ok(sent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use bufstream::BufStream;
+
+    use time::macros::datetime;
+
+    use crate::watchdog::Watchdog;
+
+    #[test]
+    fn test_parse() {
+        let time = datetime!(2026-10-16 09:15:02.5 UTC);
+        let line = format_record(time, Direction::Sent, &[0xfa, 0x9f, 0xa1]);
+        assert_eq!(line, "2026-10-16T09:15:02.5Z > fa 9f a1\n");
+        let capture = format!("# Comment\n\n{}", line);
+        assert_eq!(
+            parse(&capture),
+            Ok(vec![Record {
+                time,
+                direction: Direction::Sent,
+                bytes: vec![0xfa, 0x9f, 0xa1],
+            }])
+        );
+        assert!(parse("2026-10-16T09:15:02Z ? fa").is_err());
+        assert!(parse("2026-10-16T09:15:02Z < xy").is_err());
+        assert!(parse("yesterday < fa").is_err());
+    }
+
+    #[test]
+    fn test_recorder() {
+        let dir = std::env::temp_dir().join(format!("ibb-capture-{}", std::process::id()));
+        let config = CaptureConfig {
+            dir: dir.to_string_lossy().to_string(),
+        };
+        let connection: Box<dyn Connection> =
+            Box::new(BufStream::new(Cursor::new(b"CL STATUS=READY\n".to_vec())));
+        let mut connection = record(
+            connection,
+            Some(&config),
+            None,
+            &Watchdog::new_shared(),
+            "/dev/ttyACM0",
+        );
+        let mut line = String::new();
+        connection.read_line(&mut line).unwrap();
+        drop(connection);
+
+        let file = fs::read_dir(&dir).unwrap().next().unwrap().unwrap().path();
+        assert!(file.to_string_lossy().ends_with("dev_ttyACM0.log"));
+        let records = parse(&fs::read_to_string(&file).unwrap()).unwrap();
+        let received: Vec<u8> = records.into_iter().flat_map(|r| r.bytes).collect();
+        assert_eq!(received, b"CL STATUS=READY\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_console() {
+        let console = console::new();
+        let mut records = console.subscribe();
+        let connection: Box<dyn Connection> = Box::new(BufStream::new(Cursor::new(vec![])));
+        let mut connection = record(
+            connection,
+            None,
+            Some(&console),
+            &Watchdog::new_shared(),
+            "mock",
+        );
+        connection.write_all(&[0xfa, 0x9f, 0xa1]).unwrap();
+        let record = records.try_recv().unwrap();
+        assert!(record.ends_with("Z > fa 9f a1"));
+    }
+}
@@ -0,0 +1,287 @@
+//! Rendering of simple line and bar charts.
+use serde_derive::Deserialize;
+use svg2polylines::{CoordinatePair, Polyline};
+
+use crate::scaling::Bounds;
+use crate::text;
+
+/// Cap height of axis and data labels in mm.
+const LABEL_SIZE: f64 = 4.0;
+
+/// Cap height of the title in mm.
+const TITLE_SIZE: f64 = 6.0;
+
+/// Length of the axis ticks in mm.
+const TICK_LENGTH: f64 = 2.0;
+
+/// Number of ticks on the y axis.
+const Y_TICKS: usize = 3;
+
+/// Fraction of the available horizontal space per value used by a bar.
+const BAR_WIDTH: f64 = 0.6;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ChartKind {
+    Line,
+    Bar,
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct Chart {
+    kind: ChartKind,
+    /// The data series.
+    values: Vec<f64>,
+    /// Optional labels for the x axis, one per value.
+    #[serde(default)]
+    labels: Vec<String>,
+    /// Optional title, drawn above the chart.
+    title: Option<String>,
+}
+
+/// Format a value for an axis label.
+fn format_value(value: f64) -> String {
+    if value.fract() == 0.0 {
+        format!("{:.0}", value)
+    } else {
+        let formatted = format!("{:.2}", value);
+        formatted
+            .trim_end_matches('0')
+            .trim_end_matches('.')
+            .to_string()
+    }
+}
+
+fn point(x: f64, y: f64) -> CoordinatePair {
+    CoordinatePair { x, y }
+}
+
+impl Chart {
+    /// Return the range of values covered by the y axis.
+    fn value_range(&self) -> (f64, f64) {
+        let mut min = self.values.iter().cloned().fold(f64::INFINITY, f64::min);
+        let mut max = self
+            .values
+            .iter()
+            .cloned()
+            .fold(f64::NEG_INFINITY, f64::max);
+        if self.kind == ChartKind::Bar {
+            // Bars always start at zero
+            min = min.min(0.0);
+            max = max.max(0.0);
+        }
+        if min == max {
+            min -= 1.0;
+            max += 1.0;
+        }
+        (min, max)
+    }
+
+    /// Render the chart into polylines that fill the specified bounds.
+    pub(crate) fn render(&self, bounds: &Bounds) -> Result<Vec<Polyline>, String> {
+        if self.values.is_empty() {
+            return Err("Chart has no values".into());
+        }
+        if self.values.iter().any(|v| !v.is_finite()) {
+            return Err("Chart values must be finite numbers".into());
+        }
+        if !self.labels.is_empty() && self.labels.len() != self.values.len() {
+            return Err(format!(
+                "Chart has {} values but {} labels",
+                self.values.len(),
+                self.labels.len()
+            ));
+        }
+
+        let mut polylines = vec![];
+        let label_line = LABEL_SIZE * text::LINE_HEIGHT;
+
+        // Title
+        let mut top = bounds.y.min;
+        if let Some(ref title) = self.title {
+            let title = text::truncate(title, bounds.x.spread(), TITLE_SIZE);
+            let x = bounds.x.min + (bounds.x.spread() - text::line_width(&title, TITLE_SIZE)) / 2.0;
+            polylines.extend(text::render_line(&title, x, top, TITLE_SIZE));
+            top += TITLE_SIZE * text::LINE_HEIGHT;
+        }
+        // Leave room for half a label above the topmost tick label
+        top += LABEL_SIZE / 2.0;
+
+        // Y axis labels
+        let (min, max) = self.value_range();
+        let tick_labels: Vec<(f64, String)> = (0..Y_TICKS)
+            .map(|i| {
+                let value = min + (max - min) * i as f64 / (Y_TICKS - 1) as f64;
+                (value, format_value(value))
+            })
+            .collect();
+        let label_width = tick_labels
+            .iter()
+            .map(|(_, label)| text::line_width(label, LABEL_SIZE))
+            .fold(0.0, f64::max);
+
+        // Plot area
+        let left = bounds.x.min + label_width + TICK_LENGTH + 1.0;
+        let right = bounds.x.max;
+        let bottom = if self.labels.is_empty() {
+            bounds.y.max - LABEL_SIZE / 2.0
+        } else {
+            bounds.y.max - label_line
+        };
+        if right <= left || bottom <= top {
+            return Err("Not enough room for the chart".into());
+        }
+        let to_y = |value: f64| bottom - (value - min) / (max - min) * (bottom - top);
+
+        // Axes
+        polylines.push(vec![
+            point(left, top),
+            point(left, bottom),
+            point(right, bottom),
+        ]);
+
+        // Y ticks
+        for (value, label) in tick_labels {
+            let y = to_y(value);
+            polylines.push(vec![point(left - TICK_LENGTH, y), point(left, y)]);
+            let x = left - TICK_LENGTH - 1.0 - text::line_width(&label, LABEL_SIZE);
+            polylines.extend(text::render_line(
+                &label,
+                x,
+                y - LABEL_SIZE / 2.0,
+                LABEL_SIZE,
+            ));
+        }
+
+        // Data
+        let slot = (right - left) / self.values.len() as f64;
+        let center = |i: usize| left + slot * (i as f64 + 0.5);
+        match self.kind {
+            ChartKind::Line => {
+                polylines.push(
+                    self.values
+                        .iter()
+                        .enumerate()
+                        .map(|(i, &value)| point(center(i), to_y(value)))
+                        .collect(),
+                );
+            }
+            ChartKind::Bar => {
+                let half_width = slot * BAR_WIDTH / 2.0;
+                let base = to_y(0.0);
+                for (i, &value) in self.values.iter().enumerate() {
+                    let (x0, x1) = (center(i) - half_width, center(i) + half_width);
+                    let y = to_y(value);
+                    polylines.push(vec![
+                        point(x0, base),
+                        point(x0, y),
+                        point(x1, y),
+                        point(x1, base),
+                        point(x0, base),
+                    ]);
+                }
+            }
+        }
+
+        // X labels
+        for (i, label) in self.labels.iter().enumerate() {
+            let label = text::truncate(label, slot, LABEL_SIZE);
+            let x = center(i) - text::line_width(&label, LABEL_SIZE) / 2.0;
+            polylines.extend(text::render_line(
+                &label,
+                x,
+                bottom + LABEL_SIZE / 2.0,
+                LABEL_SIZE,
+            ));
+        }
+
+        Ok(polylines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scaling::Range;
+
+    fn bounds() -> Bounds {
+        Bounds {
+            x: Range {
+                min: 0.0,
+                max: 100.0,
+            },
+            y: Range {
+                min: 0.0,
+                max: 50.0,
+            },
+        }
+    }
+
+    fn chart(kind: ChartKind, values: Vec<f64>) -> Chart {
+        Chart {
+            kind,
+            values,
+            labels: vec![],
+            title: None,
+        }
+    }
+
+    #[test]
+    fn test_format_value() {
+        assert_eq!(format_value(3.0), "3");
+        assert_eq!(format_value(-12.0), "-12");
+        assert_eq!(format_value(2.5), "2.5");
+        assert_eq!(format_value(1.0 / 3.0), "0.33");
+    }
+
+    #[test]
+    fn test_value_range() {
+        assert_eq!(
+            chart(ChartKind::Line, vec![2.0, 4.0]).value_range(),
+            (2.0, 4.0)
+        );
+        assert_eq!(
+            chart(ChartKind::Bar, vec![2.0, 4.0]).value_range(),
+            (0.0, 4.0)
+        );
+        assert_eq!(chart(ChartKind::Line, vec![3.0]).value_range(), (2.0, 4.0));
+    }
+
+    #[test]
+    fn test_render_bar_chart() {
+        let polylines = chart(ChartKind::Bar, vec![0.0, 4.0])
+            .render(&bounds())
+            .unwrap();
+        // The last two polylines are the bars
+        let bars = &polylines[polylines.len() - 2..];
+        let last_bar = &bars[1];
+        assert_eq!(last_bar.len(), 5);
+        // The bar for the maximum value reaches the top of the plot area
+        assert_eq!(last_bar[1].y, LABEL_SIZE / 2.0);
+        assert_eq!(last_bar[0].y, 50.0 - LABEL_SIZE / 2.0);
+        // The bar for zero is flat
+        assert_eq!(bars[0][0].y, bars[0][1].y);
+    }
+
+    #[test]
+    fn test_render_line_chart() {
+        let polylines = chart(ChartKind::Line, vec![1.0, 3.0, 2.0])
+            .render(&bounds())
+            .unwrap();
+        let line = polylines.last().unwrap();
+        assert_eq!(line.len(), 3);
+        assert!(line[0].x < line[1].x && line[1].x < line[2].x);
+        assert!(line[1].y < line[2].y && line[2].y < line[0].y);
+    }
+
+    #[test]
+    fn test_render_invalid() {
+        assert!(chart(ChartKind::Line, vec![]).render(&bounds()).is_err());
+        assert!(chart(ChartKind::Line, vec![f64::NAN])
+            .render(&bounds())
+            .is_err());
+        let mut with_labels = chart(ChartKind::Bar, vec![1.0, 2.0]);
+        with_labels.labels = vec!["Mon".into()];
+        assert!(with_labels.render(&bounds()).is_err());
+    }
+}
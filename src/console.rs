@@ -0,0 +1,69 @@
+//! A live view of the serial traffic, for debugging the firmware.
+//!
+//! With the `--debug` flag or `"serial_console": true` in the config, all
+//! bytes written to and read from the robot of a board are mirrored to the
+//! WebSocket clients of `/debug/serial/`. Every text message is a record in
+//! the format of the serial captures (see the `capture` module). Clients
+//! that can't keep up miss records, instead of slowing down the robot.
+use actix_ws::{Message, MessageStream, Session};
+use log::{info, warn};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// How many records are buffered for slow clients.
+const BUFFERED_RECORDS: usize = 256;
+
+/// Where the records of the serial traffic of a board are sent to.
+pub(crate) type SerialConsole = broadcast::Sender<String>;
+
+pub(crate) fn new() -> SerialConsole {
+    broadcast::channel(BUFFERED_RECORDS).0
+}
+
+/// Send a record to all clients, if there are any.
+pub(crate) fn send(console: &SerialConsole, record: impl FnOnce() -> String) {
+    if console.receiver_count() > 0 {
+        // Clients may disconnect in the meantime
+        let _ = console.send(record());
+    }
+}
+
+/// Forward the records to a WebSocket client until it disconnects. Messages
+/// of the client are ignored (except for pings and the close message).
+pub(crate) async fn stream(
+    console: SerialConsole,
+    mut session: Session,
+    mut messages: MessageStream,
+) {
+    let mut records = console.subscribe();
+    info!("Serial console client connected");
+    loop {
+        tokio::select! {
+            record = records.recv() => match record {
+                Ok(record) => {
+                    if session.text(record).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("Serial console client missed {} record(s)", missed);
+                    let notice = format!("# {} record(s) missed", missed);
+                    if session.text(notice).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Closed) => break,
+            },
+            message = messages.recv() => match message {
+                Some(Ok(Message::Ping(bytes))) => {
+                    if session.pong(&bytes).await.is_err() {
+                        break;
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+    let _ = session.close(None).await;
+    info!("Serial console client disconnected");
+}
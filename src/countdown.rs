@@ -0,0 +1,114 @@
+//! Drawing of a countdown (or count-up) to a configured date, e.g.
+//! "42 days until launch".
+use serde_derive::{Deserialize, Serialize};
+use svg2polylines::Polyline;
+use time::{Date, OffsetDateTime};
+
+use crate::drawing::{Content, Drawing, Generator};
+use crate::scaling::Bounds;
+use crate::text;
+
+time::serde::format_description!(iso_date, Date, "[year]-[month]-[day]");
+
+/// Configuration of the countdown.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct CountdownConfig {
+    /// The date that is counted down to.
+    #[serde(with = "iso_date")]
+    date: Date,
+    /// What happens on that date.
+    label: String,
+    /// Maximum cap height of the text in mm. The text is shrunk to fit
+    /// the board.
+    #[serde(default = "default_text_size")]
+    text_size: f64,
+}
+
+fn default_text_size() -> f64 {
+    20.0
+}
+
+/// Return the countdown text for the specified day.
+fn message(date: Date, label: &str, today: Date) -> String {
+    let days = (date - today).whole_days();
+    match days {
+        0 => format!("Today: {}", label),
+        1 => format!("1 day until {}", label),
+        -1 => format!("1 day since {}", label),
+        d if d > 0 => format!("{} days until {}", d, label),
+        d => format!("{} days since {}", -d, label),
+    }
+}
+
+impl CountdownConfig {
+    /// Render the countdown for the specified day, centered in the bounds.
+    fn render(&self, today: Date, bounds: &Bounds) -> Vec<Polyline> {
+        let message = message(self.date, &self.label, today);
+        let width = text::line_width(&message, self.text_size);
+        let size = if width > bounds.x.spread() {
+            self.text_size * bounds.x.spread() / width
+        } else {
+            self.text_size
+        };
+        let x = bounds.x.min + (bounds.x.spread() - text::line_width(&message, size)) / 2.0;
+        let y = bounds.y.min + (bounds.y.spread() - size) / 2.0;
+        text::render_line(&message, x, y, size)
+    }
+
+    /// Return a drawing that renders the countdown for the current day every
+    /// time it is drawn, within the margin. The countdown is only redrawn
+    /// once per day.
+    pub(crate) fn drawing(&self, margin: f64) -> Drawing {
+        let config = self.clone();
+        Drawing::Generated(Generator::new(move || {
+            let today = OffsetDateTime::now_local()
+                .unwrap_or_else(|_| OffsetDateTime::now_utc())
+                .date();
+            let bounds = Bounds::drawable(margin);
+            Ok(Content {
+                key: Some(format!("countdown:{}:{}", config.label, today)),
+                polylines: config.render(today, &bounds).into(),
+            })
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::date;
+
+    #[test]
+    fn test_message() {
+        let launch = date!(2022 - 06 - 01);
+        assert_eq!(
+            message(launch, "launch", date!(2022 - 05 - 01)),
+            "31 days until launch"
+        );
+        assert_eq!(
+            message(launch, "launch", date!(2022 - 05 - 31)),
+            "1 day until launch"
+        );
+        assert_eq!(message(launch, "launch", launch), "Today: launch");
+        assert_eq!(
+            message(launch, "launch", date!(2022 - 06 - 03)),
+            "2 days since launch"
+        );
+    }
+
+    #[test]
+    fn test_render_fits_bounds() {
+        let config: CountdownConfig = serde_json::from_str(
+            r#"{"date": "2022-06-01", "label": "the very long awaited product launch"}"#,
+        )
+        .unwrap();
+        let mut bounds = Bounds::board();
+        bounds.add_padding(5.0);
+        let polylines = config.render(date!(2022 - 05 - 01), &bounds);
+        assert!(!polylines.is_empty());
+        for point in polylines.iter().flatten() {
+            assert!(point.x >= bounds.x.min - 1e-9 && point.x <= bounds.x.max + 1e-9);
+            assert!(point.y >= bounds.y.min && point.y <= bounds.y.max);
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! Auto-detection of the serial device of the robot.
+//!
+//! The device path (e.g. `/dev/ttyACM0` or `/dev/ttyUSB1`) may change when
+//! the robot is replugged. With the device `auto`, all USB serial devices
+//! are scanned: Devices with the USB IDs of the iBoardBot board are used
+//! directly, others are probed by waiting for a message from the robot.
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use log::{debug, info};
+use serial::BaudRate;
+
+use crate::robot;
+
+/// The device name that enables auto-detection.
+pub(crate) const AUTO_DEVICE: &str = "auto";
+
+/// USB vendor and product IDs of the iBoardBot board (an Arduino Leonardo).
+const KNOWN_IDS: [(&str, &str); 2] = [("2341", "8036"), ("2a03", "8036")];
+
+/// How long to wait for a message from the robot when probing a device.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Return whether the device name is that of a USB serial device.
+fn is_candidate(name: &str) -> bool {
+    name.starts_with("ttyACM") || name.starts_with("ttyUSB")
+}
+
+fn is_known_id(vendor: &str, product: &str) -> bool {
+    KNOWN_IDS
+        .iter()
+        .any(|&(v, p)| v.eq_ignore_ascii_case(vendor) && p.eq_ignore_ascii_case(product))
+}
+
+/// Return all USB serial devices, sorted by path.
+fn candidates() -> Vec<PathBuf> {
+    let mut devices: Vec<PathBuf> = fs::read_dir("/dev")
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter(|entry| entry.file_name().to_str().is_some_and(is_candidate))
+                .map(|entry| entry.path())
+                .collect()
+        })
+        .unwrap_or_default();
+    devices.sort();
+    devices
+}
+
+/// Return the USB vendor and product ID of a serial device from sysfs.
+fn usb_ids(device: &Path) -> Option<(String, String)> {
+    let name = device.file_name()?;
+    let sysfs = fs::canonicalize(Path::new("/sys/class/tty").join(name).join("device")).ok()?;
+    // The IDs are attributes of the USB device, which is an ancestor of the
+    // tty device
+    sysfs.ancestors().take(4).find_map(|dir| {
+        let read = |file: &str| {
+            fs::read_to_string(dir.join(file))
+                .ok()
+                .map(|id| id.trim().to_string())
+        };
+        Some((read("idVendor")?, read("idProduct")?))
+    })
+}
+
+/// Return whether the robot sends a message on the device within the probe
+/// timeout.
+fn probe(device: &Path, baud_rate: BaudRate) -> bool {
+    let mut ser = match robot::open_serial(&device.to_string_lossy(), baud_rate) {
+        Ok(ser) => ser,
+        Err(e) => {
+            debug!("Could not open {}: {}", device.display(), e);
+            return false;
+        }
+    };
+    let deadline = Instant::now() + PROBE_TIMEOUT;
+    let mut buf = String::new();
+    while Instant::now() < deadline {
+        if ser.read_line(&mut buf).is_ok() && buf.trim().starts_with("CL ") {
+            return true;
+        }
+        buf.clear();
+    }
+    false
+}
+
+/// Find the serial device of the robot.
+fn detect(baud_rate: BaudRate) -> io::Result<String> {
+    let devices = candidates();
+    let (known, unknown): (Vec<_>, Vec<_>) = devices.into_iter().partition(|device| {
+        usb_ids(device).is_some_and(|(vendor, product)| is_known_id(&vendor, &product))
+    });
+    let found = known
+        .into_iter()
+        .next()
+        .or_else(|| unknown.into_iter().find(|device| probe(device, baud_rate)));
+    match found {
+        Some(device) => {
+            let device = device.to_string_lossy().into_owned();
+            info!("Detected robot at {}", device);
+            Ok(device)
+        }
+        None => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "Could not detect the robot on any serial device",
+        )),
+    }
+}
+
+/// Return the path of the configured device, detecting it if necessary.
+pub(crate) fn resolve(device: &str, baud_rate: BaudRate) -> io::Result<String> {
+    if device == AUTO_DEVICE {
+        detect(baud_rate)
+    } else {
+        Ok(device.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_candidate() {
+        assert!(is_candidate("ttyACM0"));
+        assert!(is_candidate("ttyUSB1"));
+        assert!(!is_candidate("ttyS0"));
+        assert!(!is_candidate("tty1"));
+    }
+
+    #[test]
+    fn test_is_known_id() {
+        assert!(is_known_id("2341", "8036"));
+        assert!(is_known_id("2A03", "8036"));
+        assert!(!is_known_id("0403", "6001"));
+    }
+
+    #[test]
+    fn test_resolve_explicit_device() {
+        assert_eq!(
+            resolve("/dev/ttyACM3", BaudRate::Baud115200).unwrap(),
+            "/dev/ttyACM3"
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! Content of scheduled print tasks.
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::Arc;
+
+use svg2polylines::Polyline;
+
+type GeneratorFn = dyn Fn() -> Result<Content<'static>, String> + Send + Sync;
+
+/// The polylines produced by a drawing. The polylines of static drawings are
+/// borrowed instead of copied.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Content<'a> {
+    /// The semantic identity of generated content (e.g. `countdown:2022-05-01`).
+    /// Content with the same key is never drawn twice in a row.
+    pub(crate) key: Option<String>,
+    pub(crate) polylines: Cow<'a, [Polyline]>,
+}
+
+/// A function that generates the polylines to draw every time a scheduled
+/// task runs, e.g. by expanding a template.
+#[derive(Clone)]
+pub struct Generator(Arc<GeneratorFn>);
+
+impl Generator {
+    pub(crate) fn new<F>(f: F) -> Self
+    where
+        F: Fn() -> Result<Content<'static>, String> + Send + Sync + 'static,
+    {
+        Generator(Arc::new(f))
+    }
+
+    pub(crate) fn generate(&self) -> Result<Content<'static>, String> {
+        (self.0)()
+    }
+}
+
+impl fmt::Debug for Generator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Generator")
+    }
+}
+
+impl PartialEq for Generator {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Drawing {
+    /// Polylines that are drawn unchanged every time.
+    Static(Vec<Polyline>),
+    /// Polylines that are generated anew every time.
+    Generated(Generator),
+}
+
+impl Drawing {
+    /// A drawing that always produces the given content, e.g. content that
+    /// was generated once to check it before printing.
+    pub(crate) fn from_content(content: Content<'_>) -> Drawing {
+        match content.key {
+            None => Drawing::Static(content.polylines.into_owned()),
+            Some(key) => {
+                let content = Content {
+                    key: Some(key),
+                    polylines: Cow::Owned(content.polylines.into_owned()),
+                };
+                Drawing::Generated(Generator::new(move || Ok(content.clone())))
+            }
+        }
+    }
+
+    pub(crate) fn content(&self) -> Result<Content<'_>, String> {
+        match self {
+            Drawing::Static(polylines) => Ok(Content {
+                key: None,
+                polylines: Cow::Borrowed(polylines),
+            }),
+            Drawing::Generated(generator) => generator.generate(),
+        }
+    }
+
+    /// Return a drawing whose polylines are transformed by `f`, e.g. to apply
+    /// a pen profile.
+    pub(crate) fn map_polylines<F>(self, f: F) -> Drawing
+    where
+        F: Fn(Vec<Polyline>) -> Vec<Polyline> + Send + Sync + 'static,
+    {
+        match self {
+            Drawing::Static(polylines) => Drawing::Static(f(polylines)),
+            Drawing::Generated(generator) => Drawing::Generated(Generator::new(move || {
+                generator.generate().map(|content| Content {
+                    key: content.key,
+                    polylines: f(content.polylines.into_owned()).into(),
+                })
+            })),
+        }
+    }
+
+    pub(crate) fn into_polylines(self) -> Result<Vec<Polyline>, String> {
+        match self {
+            Drawing::Static(polylines) => Ok(polylines),
+            Drawing::Generated(generator) => generator
+                .generate()
+                .map(|content| content.polylines.into_owned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use svg2polylines::CoordinatePair;
+
+    #[test]
+    fn test_generated_anew() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let calls_clone = calls.clone();
+        let drawing = Drawing::Generated(Generator::new(move || {
+            let n = calls_clone.fetch_add(1, Ordering::SeqCst) as f64;
+            Ok(Content {
+                key: None,
+                polylines: vec![vec![CoordinatePair { x: n, y: n }]].into(),
+            })
+        }));
+        assert_eq!(drawing.content().unwrap().polylines[0][0].x, 0.0);
+        assert_eq!(drawing.content().unwrap().polylines[0][0].x, 1.0);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_static_borrowed() {
+        let drawing = Drawing::Static(vec![vec![CoordinatePair { x: 1.0, y: 2.0 }]]);
+        let content = drawing.content().unwrap();
+        assert!(matches!(content.polylines, Cow::Borrowed(_)));
+        assert_eq!(content.polylines[0][0].y, 2.0);
+    }
+}
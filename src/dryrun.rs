@@ -0,0 +1,72 @@
+//! Dry runs: Command blocks are written to a file instead of being sent to
+//! the robot, to inspect exactly what the firmware would receive or to replay
+//! it later.
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum DryRunFormat {
+    /// The raw bytes, as they would be sent over serial.
+    Binary,
+    /// One line of hex bytes per block, with a comment line per job.
+    #[default]
+    Hex,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct DryRunConfig {
+    /// The file that the blocks are appended to.
+    pub(crate) file: String,
+    #[serde(default)]
+    pub(crate) format: DryRunFormat,
+    /// Whether all jobs are dry runs.
+    #[serde(default)]
+    pub(crate) always: bool,
+}
+
+impl DryRunConfig {
+    /// Append the blocks of a job to the file.
+    pub(crate) fn write(&self, label: &str, blocks: &[Vec<u8>]) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.file)?;
+        file.write_all(&encode(self.format, label, blocks))
+    }
+}
+
+fn encode(format: DryRunFormat, label: &str, blocks: &[Vec<u8>]) -> Vec<u8> {
+    match format {
+        DryRunFormat::Binary => blocks.concat(),
+        DryRunFormat::Hex => {
+            let mut out = format!("# {} ({} blocks)\n", label, blocks.len());
+            for block in blocks {
+                let bytes: Vec<String> = block.iter().map(|b| format!("{:02x}", b)).collect();
+                out.push_str(&bytes.join(" "));
+                out.push('\n');
+            }
+            out.into_bytes()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let blocks = vec![vec![0xfa, 0x9f, 0xa1], vec![0x0b, 0x10]];
+        assert_eq!(
+            encode(DryRunFormat::Binary, "Test", &blocks),
+            vec![0xfa, 0x9f, 0xa1, 0x0b, 0x10]
+        );
+        assert_eq!(
+            String::from_utf8(encode(DryRunFormat::Hex, "Test", &blocks)).unwrap(),
+            "# Test (2 blocks)\nfa 9f a1\n0b 10\n"
+        );
+    }
+}
@@ -0,0 +1,244 @@
+//! Conversion of DXF drawings (as exported by CAD programs) into polylines.
+//!
+//! The `LINE`, `LWPOLYLINE`, `POLYLINE`, `CIRCLE` and `ARC` entities of the
+//! `ENTITIES` section are converted, everything else (text, hatches, blocks,
+//! …) is ignored. Coordinates are taken as mm, with the y axis pointing
+//! upwards. Bulges (arcs within polylines) are drawn as straight segments.
+use svg2polylines::{CoordinatePair, Polyline};
+
+/// Maximum length of the segments approximating circles and arcs, in mm.
+const ARC_SEGMENT: f64 = 1.0;
+
+/// An entity with its group codes and values.
+#[derive(Debug)]
+struct Entity<'a> {
+    kind: &'a str,
+    groups: Vec<(u16, &'a str)>,
+}
+
+impl Entity<'_> {
+    fn number(&self, code: u16) -> Result<f64, String> {
+        let value = self
+            .groups
+            .iter()
+            .find(|(c, _)| *c == code)
+            .map(|(_, value)| *value)
+            .ok_or_else(|| format!("{} without group code {}", self.kind, code))?;
+        value
+            .parse()
+            .map_err(|_| format!("Invalid number in {}: {:?}", self.kind, value))
+    }
+
+    /// Return the points given by pairs of x (code 10) and y (code 20)
+    /// groups.
+    fn points(&self) -> Result<Vec<CoordinatePair>, String> {
+        let mut points = vec![];
+        for (code, value) in &self.groups {
+            let number = || {
+                value
+                    .parse::<f64>()
+                    .map_err(|_| format!("Invalid number in {}: {:?}", self.kind, value))
+            };
+            match code {
+                10 => points.push(CoordinatePair {
+                    x: number()?,
+                    y: 0.0,
+                }),
+                20 => match points.last_mut() {
+                    Some(point) => point.y = number()?,
+                    None => return Err(format!("{} with y before x", self.kind)),
+                },
+                _ => {}
+            }
+        }
+        Ok(points)
+    }
+
+    fn is_closed(&self) -> bool {
+        self.number(70).unwrap_or(0.0) as u32 & 1 == 1
+    }
+}
+
+/// Split the file into group code/value pairs.
+fn groups(dxf: &str) -> Result<Vec<(u16, &str)>, String> {
+    let lines: Vec<&str> = dxf.lines().map(str::trim).collect();
+    lines
+        .chunks(2)
+        .filter(|pair| pair.len() == 2)
+        .map(|pair| {
+            pair[0]
+                .parse::<u16>()
+                .map(|code| (code, pair[1]))
+                .map_err(|_| format!("Invalid group code: {:?}", pair[0]))
+        })
+        .collect()
+}
+
+/// Return the entities of the `ENTITIES` section.
+fn entities<'a>(groups: &[(u16, &'a str)]) -> Vec<Entity<'a>> {
+    let mut entities: Vec<Entity> = vec![];
+    let mut in_entities = false;
+    for (i, &(code, value)) in groups.iter().enumerate() {
+        match (code, value) {
+            (2, "ENTITIES") if i > 0 && groups[i - 1] == (0, "SECTION") => in_entities = true,
+            (0, "ENDSEC") => in_entities = false,
+            (0, kind) if in_entities => entities.push(Entity {
+                kind,
+                groups: vec![],
+            }),
+            _ if in_entities => {
+                if let Some(entity) = entities.last_mut() {
+                    entity.groups.push((code, value));
+                }
+            }
+            _ => {}
+        }
+    }
+    entities
+}
+
+/// Return the points of an arc, with angles in degrees counterclockwise.
+fn arc(center: CoordinatePair, radius: f64, start: f64, end: f64) -> Polyline {
+    let mut sweep = (end - start).rem_euclid(360.0);
+    if sweep == 0.0 {
+        sweep = 360.0;
+    }
+    let (start, sweep) = (start.to_radians(), sweep.to_radians());
+    let steps = ((sweep * radius) / ARC_SEGMENT).ceil().max(4.0) as usize;
+    (0..=steps)
+        .map(|step| {
+            let angle = start + sweep * step as f64 / steps as f64;
+            CoordinatePair {
+                x: center.x + radius * angle.cos(),
+                y: center.y + radius * angle.sin(),
+            }
+        })
+        .collect()
+}
+
+fn close(mut polyline: Polyline) -> Polyline {
+    if let Some(&first) = polyline.first() {
+        polyline.push(first);
+    }
+    polyline
+}
+
+/// Convert a DXF drawing into polylines (in mm).
+pub(crate) fn parse(dxf: &str) -> Result<Vec<Polyline>, String> {
+    let groups = groups(dxf)?;
+    let entities = entities(&groups);
+    let mut polylines = vec![];
+    // The vertices of an old-style POLYLINE, and whether it is closed
+    let mut vertices: Option<(Polyline, bool)> = None;
+    for entity in &entities {
+        match entity.kind {
+            "LINE" => polylines.push(vec![
+                CoordinatePair {
+                    x: entity.number(10)?,
+                    y: entity.number(20)?,
+                },
+                CoordinatePair {
+                    x: entity.number(11)?,
+                    y: entity.number(21)?,
+                },
+            ]),
+            "LWPOLYLINE" => {
+                let points = entity.points()?;
+                polylines.push(match entity.is_closed() {
+                    true => close(points),
+                    false => points,
+                });
+            }
+            "POLYLINE" => vertices = Some((vec![], entity.is_closed())),
+            "VERTEX" => {
+                if let Some((ref mut points, _)) = vertices {
+                    points.extend(entity.points()?);
+                }
+            }
+            "SEQEND" => {
+                if let Some((points, closed)) = vertices.take() {
+                    polylines.push(match closed {
+                        true => close(points),
+                        false => points,
+                    });
+                }
+            }
+            "CIRCLE" | "ARC" => {
+                let center = CoordinatePair {
+                    x: entity.number(10)?,
+                    y: entity.number(20)?,
+                };
+                let radius = entity.number(40)?;
+                let (start, end) = match entity.kind {
+                    "ARC" => (entity.number(50)?, entity.number(51)?),
+                    _ => (0.0, 360.0),
+                };
+                polylines.push(arc(center, radius, start, end));
+            }
+            _ => {}
+        }
+    }
+    polylines.retain(|polyline| polyline.len() > 1);
+    Ok(polylines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dxf(entities: &[&str]) -> String {
+        let mut lines = vec!["0", "SECTION", "2", "HEADER", "0", "ENDSEC"];
+        lines.extend(["0", "SECTION", "2", "ENTITIES"]);
+        for entity in entities {
+            lines.extend(entity.split(' '));
+        }
+        lines.extend(["0", "ENDSEC", "0", "EOF"]);
+        lines.join("\n")
+    }
+
+    fn points(polyline: &Polyline) -> Vec<(f64, f64)> {
+        polyline.iter().map(|p| (p.x, p.y)).collect()
+    }
+
+    #[test]
+    fn test_lines() {
+        let dxf = dxf(&[
+            "0 LINE 8 0 10 1 20 2 11 3 21 4",
+            "0 LWPOLYLINE 90 3 70 1 10 0 20 0 10 5 20 0 10 5 20 5",
+            "0 POLYLINE 70 0 0 VERTEX 10 1 20 1 0 VERTEX 10 2 20 1 0 SEQEND",
+            "0 TEXT 10 0 20 0 1 Hello",
+        ]);
+        let polylines = parse(&dxf).unwrap();
+        assert_eq!(polylines.len(), 3);
+        assert_eq!(points(&polylines[0]), vec![(1.0, 2.0), (3.0, 4.0)]);
+        assert_eq!(
+            points(&polylines[1]),
+            vec![(0.0, 0.0), (5.0, 0.0), (5.0, 5.0), (0.0, 0.0)]
+        );
+        assert_eq!(points(&polylines[2]), vec![(1.0, 1.0), (2.0, 1.0)]);
+    }
+
+    #[test]
+    fn test_arcs() {
+        let dxf = dxf(&[
+            "0 CIRCLE 10 10 20 10 40 5",
+            "0 ARC 10 0 20 0 40 10 50 0 51 90",
+        ]);
+        let polylines = parse(&dxf).unwrap();
+        let circle = &polylines[0];
+        assert!((circle[0].x - 15.0).abs() < 1e-9);
+        assert!((circle.last().unwrap().x - 15.0).abs() < 1e-9);
+        assert!(circle
+            .iter()
+            .all(|p| ((p.x - 10.0).hypot(p.y - 10.0) - 5.0).abs() < 1e-9));
+        let quarter = &polylines[1];
+        assert!(quarter.iter().all(|p| p.x >= -1e-9 && p.y >= -1e-9));
+        assert!((quarter.last().unwrap().y - 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(parse(&dxf(&["0 LINE 10 1 20 2"])).is_err());
+        assert!(parse("x\nLINE").is_err());
+    }
+}
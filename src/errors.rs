@@ -0,0 +1,134 @@
+//! The catalog of error codes.
+//!
+//! Every error response of the HTTP API carries a `code` next to its
+//! `details`, `job_dropped` events carry the `code` of the reason, and the
+//! server exits with the exit code of the error that stopped it (see
+//! `ErrorCode::exit_code`). The codes are stable, so that automations can
+//! branch on them instead of parsing the messages, which may change.
+use std::fmt;
+
+use serde_derive::Serialize;
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ErrorCode {
+    // Requests
+    /// The request is malformed or can't be fulfilled as requested.
+    InvalidRequest,
+    NotFound,
+    Unauthorized,
+    PasswordRequired,
+    /// Too many requests from the client.
+    RateLimited,
+    /// The daily quota of the tenant is exhausted.
+    QuotaExhausted,
+    UnsupportedMediaType,
+    /// The board can't take jobs right now (e.g. incompatible firmware).
+    Unavailable,
+    /// The robot is gone for too long, only previews are available.
+    RobotOffline,
+    /// The disk is nearly full, so nothing is stored.
+    DiskFull,
+    QueueFull,
+    /// The job violates the constraints checked before submission.
+    Rejected,
+    Internal,
+
+    // Schedules
+    NoSvgFiles,
+    Io,
+    InvalidSvg,
+    ScalingFailed,
+    RobotUnreachable,
+
+    // Jobs
+    DeadlinePassed,
+    /// The job couldn't be placed on the board.
+    PlacementFailed,
+    RobotStalled,
+    WriteFailed,
+
+    // Command line
+    ConfigUnreadable,
+    InvalidConfig,
+    ReplayFailed,
+}
+
+impl ErrorCode {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::InvalidRequest => "invalid_request",
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::PasswordRequired => "password_required",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::QuotaExhausted => "quota_exhausted",
+            ErrorCode::UnsupportedMediaType => "unsupported_media_type",
+            ErrorCode::Unavailable => "unavailable",
+            ErrorCode::RobotOffline => "robot_offline",
+            ErrorCode::DiskFull => "disk_full",
+            ErrorCode::QueueFull => "queue_full",
+            ErrorCode::Rejected => "rejected",
+            ErrorCode::Internal => "internal",
+            ErrorCode::NoSvgFiles => "no_svg_files",
+            ErrorCode::Io => "io",
+            ErrorCode::InvalidSvg => "invalid_svg",
+            ErrorCode::ScalingFailed => "scaling_failed",
+            ErrorCode::RobotUnreachable => "robot_unreachable",
+            ErrorCode::DeadlinePassed => "deadline_passed",
+            ErrorCode::PlacementFailed => "placement_failed",
+            ErrorCode::RobotStalled => "robot_stalled",
+            ErrorCode::WriteFailed => "write_failed",
+            ErrorCode::ConfigUnreadable => "config_unreadable",
+            ErrorCode::InvalidConfig => "invalid_config",
+            ErrorCode::ReplayFailed => "replay_failed",
+        }
+    }
+
+    /// The exit code of the server when it stops because of the error: 1 if
+    /// the config (or a capture) can't be read, 2 if the config is invalid,
+    /// and 3 if the server can't start for another reason.
+    pub(crate) fn exit_code(self) -> i32 {
+        match self {
+            ErrorCode::ConfigUnreadable | ErrorCode::ReplayFailed => 1,
+            ErrorCode::InvalidConfig => 2,
+            _ => 3,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes_match_json() {
+        let codes = [
+            ErrorCode::InvalidRequest,
+            ErrorCode::QuotaExhausted,
+            ErrorCode::UnsupportedMediaType,
+            ErrorCode::NoSvgFiles,
+            ErrorCode::DeadlinePassed,
+            ErrorCode::ConfigUnreadable,
+        ];
+        for code in codes {
+            assert_eq!(
+                serde_json::to_value(code).unwrap(),
+                serde_json::Value::from(code.as_str())
+            );
+        }
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        assert_eq!(ErrorCode::ConfigUnreadable.exit_code(), 1);
+        assert_eq!(ErrorCode::InvalidConfig.exit_code(), 2);
+        assert_eq!(ErrorCode::NoSvgFiles.exit_code(), 3);
+    }
+}
@@ -0,0 +1,107 @@
+//! Expiry of transient content: Jobs with a TTL (e.g. a notice like "visitor
+//! parking blocked today") are erased from the board again after the TTL.
+//!
+//! Only the region of the expired drawing is erased. Other content in that
+//! region (according to the board state) is redrawn afterwards.
+use std::time::Instant;
+
+use svg2polylines::Polyline;
+
+use crate::scaling::{self, Bounds, Range};
+
+/// Margin in mm around expired drawings that is erased as well.
+const ERASE_MARGIN: f64 = 2.0;
+
+/// Drawings that will expire.
+#[derive(Debug, Default)]
+pub(crate) struct Expiries {
+    pending: Vec<(Instant, Vec<Polyline>)>,
+}
+
+impl Expiries {
+    /// Erase the polylines at the specified time.
+    pub(crate) fn add(&mut self, at: Instant, polylines: Vec<Polyline>) {
+        self.pending.push((at, polylines));
+    }
+
+    /// Forget all pending expiries, e.g. because the board was erased.
+    pub(crate) fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Remove and return the polylines of a drawing that has expired.
+    pub(crate) fn pop_due(&mut self, now: Instant) -> Option<Vec<Polyline>> {
+        let index = self.pending.iter().position(|(at, _)| *at <= now)?;
+        Some(self.pending.remove(index).1)
+    }
+}
+
+/// Return the region to erase for the expired polylines, together with the
+/// remaining content of the board in that region, which must be redrawn.
+pub(crate) fn cleanup(
+    expired: &[Polyline],
+    content: Option<&[Polyline]>,
+) -> Option<(Bounds, Vec<Polyline>)> {
+    let bounds = scaling::get_bounds(expired)?;
+    let board = Bounds::board();
+    let region = Bounds {
+        x: Range {
+            min: (bounds.x.min - ERASE_MARGIN).max(board.x.min),
+            max: (bounds.x.max + ERASE_MARGIN).min(board.x.max),
+        },
+        y: Range {
+            min: (bounds.y.min - ERASE_MARGIN).max(board.y.min),
+            max: (bounds.y.max + ERASE_MARGIN).min(board.y.max),
+        },
+    };
+    let redraw = content
+        .unwrap_or_default()
+        .iter()
+        .filter(|polyline| {
+            scaling::get_bounds(std::slice::from_ref(*polyline))
+                .is_some_and(|other| region.intersects(&other))
+        })
+        .cloned()
+        .collect();
+    Some((region, redraw))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use svg2polylines::CoordinatePair;
+
+    fn line(x: f64, y: f64) -> Polyline {
+        vec![CoordinatePair { x, y }, CoordinatePair { x: x + 10.0, y }]
+    }
+
+    #[test]
+    fn test_pop_due() {
+        let now = Instant::now();
+        let mut expiries = Expiries::default();
+        expiries.add(now + Duration::from_secs(60), vec![line(0.0, 0.0)]);
+        expiries.add(now + Duration::from_secs(30), vec![line(0.0, 10.0)]);
+        assert_eq!(expiries.pop_due(now), None);
+        assert_eq!(
+            expiries.pop_due(now + Duration::from_secs(45)),
+            Some(vec![line(0.0, 10.0)])
+        );
+        expiries.clear();
+        assert_eq!(expiries.pop_due(now + Duration::from_secs(90)), None);
+    }
+
+    #[test]
+    fn test_cleanup() {
+        let expired = vec![line(20.0, 20.0)];
+        let content = vec![line(25.0, 21.0), line(100.0, 100.0)];
+        let (region, redraw) = cleanup(&expired, Some(&content)).unwrap();
+        assert_eq!((region.x.min, region.x.max), (18.0, 32.0));
+        assert_eq!((region.y.min, region.y.max), (18.0, 22.0));
+        assert_eq!(redraw, vec![line(25.0, 21.0)]);
+
+        let (_, redraw) = cleanup(&expired, None).unwrap();
+        assert!(redraw.is_empty());
+        assert!(cleanup(&[], None).is_none());
+    }
+}
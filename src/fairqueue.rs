@@ -0,0 +1,107 @@
+//! A queue that serves several owners in turns.
+use std::collections::{HashMap, VecDeque};
+
+/// Items are queued per owner and popped round-robin, so an owner with many
+/// queued items cannot starve the others.
+#[derive(Debug)]
+pub(crate) struct FairQueue<T> {
+    queues: HashMap<String, VecDeque<T>>,
+    /// Owners with queued items, in the order in which they will be served.
+    order: VecDeque<String>,
+}
+
+impl<T> Default for FairQueue<T> {
+    fn default() -> Self {
+        FairQueue {
+            queues: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+}
+
+impl<T> FairQueue<T> {
+    pub(crate) fn push(&mut self, owner: &str, item: T) {
+        let queue = self.queues.entry(owner.to_string()).or_default();
+        if queue.is_empty() {
+            self.order.push_back(owner.to_string());
+        }
+        queue.push_back(item);
+    }
+
+    /// Return the next item, together with its owner.
+    pub(crate) fn pop(&mut self) -> Option<(String, T)> {
+        let owner = self.order.pop_front()?;
+        let queue = self.queues.get_mut(&owner)?;
+        let item = queue.pop_front()?;
+        if queue.is_empty() {
+            self.queues.remove(&owner);
+        } else {
+            self.order.push_back(owner.clone());
+        }
+        Some((owner, item))
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.queues.values().map(VecDeque::len).sum()
+    }
+
+    /// Return the items, together with their owners, in the order in which
+    /// they would be popped.
+    pub(crate) fn items(&self) -> Vec<(&str, &T)> {
+        let mut items = vec![];
+        for round in 0.. {
+            let before = items.len();
+            for owner in &self.order {
+                if let Some(item) = self.queues.get(owner).and_then(|queue| queue.get(round)) {
+                    items.push((owner.as_str(), item));
+                }
+            }
+            if items.len() == before {
+                break;
+            }
+        }
+        items
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_robin() {
+        let mut queue = FairQueue::default();
+        queue.push("a", 1);
+        queue.push("a", 2);
+        queue.push("a", 3);
+        queue.push("b", 4);
+        assert_eq!(queue.len(), 4);
+        assert_eq!(queue.pop(), Some(("a".to_string(), 1)));
+        queue.push("c", 5);
+        assert_eq!(queue.pop(), Some(("b".to_string(), 4)));
+        assert_eq!(queue.pop(), Some(("a".to_string(), 2)));
+        assert_eq!(queue.pop(), Some(("c".to_string(), 5)));
+        assert_eq!(queue.pop(), Some(("a".to_string(), 3)));
+        assert_eq!(queue.pop(), None);
+        assert_eq!(queue.len(), 0);
+    }
+
+    #[test]
+    fn test_items() {
+        let mut queue = FairQueue::default();
+        queue.push("a", 1);
+        queue.push("a", 2);
+        queue.push("b", 3);
+        assert_eq!(queue.items(), vec![("a", &1), ("b", &3), ("a", &2)]);
+        assert_eq!(queue.len(), 3);
+    }
+
+    #[test]
+    fn test_owner_requeued_after_empty() {
+        let mut queue = FairQueue::default();
+        queue.push("a", 1);
+        assert_eq!(queue.pop(), Some(("a".to_string(), 1)));
+        queue.push("a", 2);
+        assert_eq!(queue.pop(), Some(("a".to_string(), 2)));
+    }
+}
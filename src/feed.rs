@@ -0,0 +1,310 @@
+//! A live feed of events for WebSocket clients.
+//!
+//! The clients of `/events/` receive the robot events (see the `notify`
+//! module) and a `job_state` event whenever a job is registered or changes
+//! its state. Every client has its own subscription: It can be limited to
+//! some event types and/or job ids, with the `events` and `jobs` query
+//! parameters or by sending a subscription message like
+//! `{"events": ["job_state"], "jobs": [3]}`, which replaces the current one.
+//! Events that don't belong to a job don't match a job filter.
+//!
+//! For scripts without a WebSocket client, `/events/ndjson` streams the same
+//! events as newline-delimited JSON over plain HTTP, with the subscription
+//! taken from the query parameters.
+use std::convert::Infallible;
+
+use actix_web::web::Bytes;
+use actix_ws::{Message, MessageStream, Session};
+use futures_util::stream::{self, Stream};
+use log::{info, warn};
+use serde_derive::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::jobs::{JobEntry, JobId};
+use crate::notify::Event;
+
+/// How many events are buffered for slow clients.
+const BUFFERED_EVENTS: usize = 256;
+
+/// Where the events for the WebSocket clients are sent to.
+pub(crate) type EventFeed = broadcast::Sender<FeedEvent>;
+
+pub(crate) fn new() -> EventFeed {
+    broadcast::channel(BUFFERED_EVENTS).0
+}
+
+/// An event as sent to the clients.
+#[derive(Debug, Clone)]
+pub(crate) struct FeedEvent {
+    name: &'static str,
+    job: Option<JobId>,
+    json: String,
+}
+
+impl FeedEvent {
+    pub(crate) fn robot(event: &Event) -> Self {
+        FeedEvent {
+            name: event.name(),
+            job: None,
+            json: event.to_value().to_string(),
+        }
+    }
+
+    pub(crate) fn job(job: &JobEntry) -> Self {
+        let mut value = serde_json::to_value(job).expect("Could not serialize job");
+        if let Some(object) = value.as_object_mut() {
+            object.insert("event".to_string(), "job_state".into());
+        }
+        FeedEvent {
+            name: "job_state",
+            job: Some(job.id),
+            json: value.to_string(),
+        }
+    }
+}
+
+/// Send an event to all clients, if there are any.
+pub(crate) fn send(feed: &EventFeed, event: impl FnOnce() -> FeedEvent) {
+    if feed.receiver_count() > 0 {
+        // Clients may disconnect in the meantime
+        let _ = feed.send(event());
+    }
+}
+
+/// The events a client is interested in. Missing filters match everything.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Subscription {
+    events: Option<Vec<String>>,
+    jobs: Option<Vec<JobId>>,
+}
+
+/// The subscription as passed in the query string, with comma separated
+/// lists.
+#[derive(Debug, Deserialize)]
+pub(crate) struct SubscriptionQuery {
+    events: Option<String>,
+    jobs: Option<String>,
+}
+
+impl TryFrom<&SubscriptionQuery> for Subscription {
+    type Error = String;
+
+    fn try_from(query: &SubscriptionQuery) -> Result<Self, Self::Error> {
+        let events = query
+            .events
+            .as_ref()
+            .map(|events| events.split(',').map(str::to_string).collect());
+        let jobs = match query.jobs {
+            Some(ref jobs) => Some(
+                jobs.split(',')
+                    .map(|id| {
+                        id.trim()
+                            .parse()
+                            .map_err(|_| format!("Invalid job id: {}", id))
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            None => None,
+        };
+        Ok(Subscription { events, jobs })
+    }
+}
+
+impl Subscription {
+    fn matches(&self, event: &FeedEvent) -> bool {
+        let event_matches = match self.events {
+            Some(ref events) => events.iter().any(|name| name == event.name),
+            None => true,
+        };
+        let job_matches = match (&self.jobs, event.job) {
+            (Some(jobs), Some(job)) => jobs.contains(&job),
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        event_matches && job_matches
+    }
+}
+
+/// Answer a subscription message: Replace the subscription and confirm it,
+/// or report why the message is invalid.
+fn resubscribe(subscription: &mut Subscription, message: &str) -> String {
+    match serde_json::from_str::<Subscription>(message) {
+        Ok(new) => {
+            *subscription = new;
+            serde_json::json!({ "subscription": subscription }).to_string()
+        }
+        Err(e) => {
+            serde_json::json!({ "error": format!("Invalid subscription: {}", e) }).to_string()
+        }
+    }
+}
+
+/// Forward the events matching the subscription to a WebSocket client until
+/// it disconnects. Clients that can't keep up miss events, instead of slowing
+/// down the server.
+pub(crate) async fn stream(
+    feed: EventFeed,
+    mut subscription: Subscription,
+    mut session: Session,
+    mut messages: MessageStream,
+) {
+    let mut events = feed.subscribe();
+    info!("Event feed client connected");
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => {
+                    if subscription.matches(&event) && session.text(event.json).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("Event feed client missed {} event(s)", missed);
+                    let notice = serde_json::json!({ "missed": missed }).to_string();
+                    if session.text(notice).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Closed) => break,
+            },
+            message = messages.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let answer = resubscribe(&mut subscription, &text);
+                    if session.text(answer).await.is_err() {
+                        break;
+                    }
+                }
+                Some(Ok(Message::Ping(bytes))) => {
+                    if session.pong(&bytes).await.is_err() {
+                        break;
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+    let _ = session.close(None).await;
+    info!("Event feed client disconnected");
+}
+
+/// Return a stream of the events matching the subscription, one JSON object
+/// per line. The stream ends when the client disconnects (and the response
+/// is dropped).
+pub(crate) fn ndjson(
+    feed: &EventFeed,
+    subscription: Subscription,
+) -> impl Stream<Item = Result<Bytes, Infallible>> {
+    info!("NDJSON event feed client connected");
+    stream::unfold(
+        (feed.subscribe(), subscription),
+        |(mut events, subscription)| async move {
+            let line = loop {
+                match events.recv().await {
+                    Ok(event) if subscription.matches(&event) => break event.json,
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!("NDJSON event feed client missed {} event(s)", missed);
+                        break serde_json::json!({ "missed": missed }).to_string();
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            };
+            Some((Ok(Bytes::from(line + "\n")), (events, subscription)))
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use time::OffsetDateTime;
+
+    use super::*;
+    use crate::jobs::JobState;
+
+    fn job(id: JobId) -> FeedEvent {
+        let now = OffsetDateTime::now_utc();
+        FeedEvent::job(&JobEntry {
+            id,
+            board: "default".to_string(),
+            owner: None,
+            label: "Single print".to_string(),
+            state: JobState::Pending,
+            created: now,
+            updated: now,
+            trace: None,
+        })
+    }
+
+    #[test]
+    fn test_matches() {
+        let stalled = FeedEvent::robot(&Event::RobotStalled { block: 2 });
+        assert!(Subscription::default().matches(&stalled));
+        assert!(Subscription::default().matches(&job(1)));
+
+        let query = SubscriptionQuery {
+            events: Some("job_state,robot_stalled".to_string()),
+            jobs: None,
+        };
+        let subscription = Subscription::try_from(&query).unwrap();
+        assert!(subscription.matches(&stalled));
+        assert!(!subscription.matches(&FeedEvent::robot(&Event::TaskReceived)));
+
+        let query = SubscriptionQuery {
+            events: None,
+            jobs: Some("3, 4".to_string()),
+        };
+        let subscription = Subscription::try_from(&query).unwrap();
+        assert!(subscription.matches(&job(3)));
+        assert!(!subscription.matches(&job(5)));
+        assert!(!subscription.matches(&stalled));
+
+        let query = SubscriptionQuery {
+            events: None,
+            jobs: Some("three".to_string()),
+        };
+        assert!(Subscription::try_from(&query).is_err());
+    }
+
+    #[test]
+    fn test_resubscribe() {
+        let mut subscription = Subscription::default();
+        let answer = resubscribe(&mut subscription, r#"{"jobs": [7]}"#);
+        assert_eq!(answer, r#"{"subscription":{"events":null,"jobs":[7]}}"#);
+        assert!(subscription.matches(&job(7)));
+        assert!(!subscription.matches(&job(8)));
+
+        let answer = resubscribe(&mut subscription, r#"{"job": 8}"#);
+        assert!(answer.starts_with(r#"{"error":"Invalid subscription"#));
+        assert!(subscription.matches(&job(7)));
+    }
+
+    #[test]
+    fn test_ndjson() {
+        use futures_util::{FutureExt, StreamExt};
+
+        let feed = new();
+        let query = SubscriptionQuery {
+            events: Some("robot_stalled".to_string()),
+            jobs: None,
+        };
+        let mut lines = Box::pin(ndjson(&feed, Subscription::try_from(&query).unwrap()));
+        send(&feed, || FeedEvent::robot(&Event::TaskReceived));
+        send(&feed, || {
+            FeedEvent::robot(&Event::RobotStalled { block: 2 })
+        });
+        let line = lines.next().now_or_never().flatten().unwrap().unwrap();
+        assert_eq!(line, "{\"block\":2,\"event\":\"robot_stalled\"}\n");
+        assert!(lines.next().now_or_never().is_none());
+    }
+
+    #[test]
+    fn test_job_event() {
+        let event = job(3);
+        let value: serde_json::Value = serde_json::from_str(&event.json).unwrap();
+        assert_eq!(value["event"], "job_state");
+        assert_eq!(value["id"], 3);
+        assert_eq!(value["state"], "pending");
+    }
+}
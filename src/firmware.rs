@@ -0,0 +1,167 @@
+//! Identification of the firmware of the robot.
+//!
+//! Only the serial firmware speaks the block protocol over USB. The original
+//! firmware of the iBoardBot fetches its drawings from the JJRobots cloud
+//! service and just prints debug messages on the USB port, so blocks sent to
+//! it are silently ignored. To tell them apart, the identify command
+//! (`0xfa 0xa0 0x00`) is sent after connecting. Serial firmware that knows it
+//! responds with `CL FIRMWARE=<name>&VERSION=<version>`, older serial
+//! firmware ignores it but still requests blocks with `CL` messages. Robots
+//! that send neither are identified by the messages of the cloud firmware.
+use std::io::{self, ErrorKind};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_derive::Serialize;
+
+use crate::robot::{Command, Connection};
+
+/// How long to wait for the robot to identify itself.
+pub(crate) const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Messages that only the cloud firmware prints.
+const CLOUD_MARKERS: &[&str] = &["JJROBOTS", "WIFI", "ESP8266", "ibbsvr"];
+
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum Firmware {
+    /// The serial firmware, with its identity if it reported one.
+    Serial {
+        name: Option<String>,
+        version: Option<String>,
+    },
+    /// The original firmware for the JJRobots cloud service.
+    Cloud,
+    /// The robot did not send anything during the handshake.
+    Unknown,
+}
+
+impl Firmware {
+    /// Return why the firmware can't draw the blocks of this server, if it
+    /// can't.
+    pub(crate) fn incompatibility(&self) -> Option<&'static str> {
+        match self {
+            Firmware::Cloud => Some(
+                "The robot runs the original cloud firmware, flash the serial firmware to print from this server",
+            ),
+            Firmware::Serial { .. } | Firmware::Unknown => None,
+        }
+    }
+}
+
+/// Parse the response to the identify command.
+fn parse_identity(line: &str) -> Option<Firmware> {
+    let fields = line.strip_prefix("CL FIRMWARE=")?;
+    let (name, version) = match fields.split_once("&VERSION=") {
+        Some((name, version)) => (name, Some(version.to_string())),
+        None => (fields, None),
+    };
+    Some(Firmware::Serial {
+        name: Some(name.to_string()),
+        version,
+    })
+}
+
+/// Identify the firmware from the lines received during the handshake.
+pub(crate) fn identify(lines: &[String]) -> Firmware {
+    if let Some(identity) = lines.iter().find_map(|line| parse_identity(line)) {
+        return identity;
+    }
+    if lines.iter().any(|line| line.starts_with("CL ")) {
+        return Firmware::Serial {
+            name: None,
+            version: None,
+        };
+    }
+    let is_cloud = lines.iter().any(|line| {
+        CLOUD_MARKERS
+            .iter()
+            .any(|marker| line.to_uppercase().contains(&marker.to_uppercase()))
+    });
+    match is_cloud {
+        true => Firmware::Cloud,
+        false => Firmware::Unknown,
+    }
+}
+
+/// Send the identify command and collect the responses until the robot
+/// identified itself or the timeout passed.
+pub(crate) fn handshake(
+    connection: &mut dyn Connection,
+    timeout: Duration,
+) -> io::Result<Firmware> {
+    connection.write_all(&Command::Identify.to_bytes())?;
+    connection.flush()?;
+    let deadline = Instant::now() + timeout;
+    let mut lines = vec![];
+    let mut line = String::new();
+    while Instant::now() < deadline {
+        line.clear();
+        match connection.read_line(&mut line) {
+            Ok(0) => thread::sleep(Duration::from_millis(50)),
+            Ok(_) => {
+                lines.push(line.trim().to_string());
+                if parse_identity(line.trim()).is_some() {
+                    break;
+                }
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(identify(&lines))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bufstream::BufStream;
+
+    use crate::mock::MockRobot;
+    use crate::simulate::SimulationConfig;
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn test_identify() {
+        assert_eq!(
+            identify(&lines(&[
+                "CL STATUS=READY",
+                "CL FIRMWARE=iboardbot-serial&VERSION=1.2"
+            ])),
+            Firmware::Serial {
+                name: Some("iboardbot-serial".to_string()),
+                version: Some("1.2".to_string())
+            }
+        );
+        assert_eq!(
+            identify(&lines(&["CL STATUS=READY"])),
+            Firmware::Serial {
+                name: None,
+                version: None
+            }
+        );
+        let cloud = identify(&lines(&["iBoardbot JJROBOTS", "Connecting to WIFI..."]));
+        assert_eq!(cloud, Firmware::Cloud);
+        assert!(cloud.incompatibility().is_some());
+        assert_eq!(identify(&[]), Firmware::Unknown);
+        assert!(Firmware::Unknown.incompatibility().is_none());
+    }
+
+    #[test]
+    fn test_handshake() {
+        let robot = MockRobot::new(SimulationConfig::default());
+        let mut connection = BufStream::new(robot);
+        let firmware = handshake(&mut connection, Duration::from_secs(2)).unwrap();
+        assert_eq!(
+            firmware,
+            Firmware::Serial {
+                name: Some("mock".to_string()),
+                version: Some(env!("CARGO_PKG_VERSION").to_string())
+            }
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! Flow control of the blocks sent to the robot.
+//!
+//! The firmware has room for a single block. It requests a block with
+//! `CL STATUS=READY` when it is idle and acknowledges every block with
+//! `CL STATUS=ACK&NUM=<n>` once it has been drawn. A block is only sent in
+//! response to one of these messages, and a block that the robot did not
+//! receive is sent again instead of being skipped.
+use log::warn;
+
+/// A message of the robot.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum Response {
+    /// The robot is idle and requests a block.
+    Ready,
+    /// The robot has drawn the block with the specified number.
+    Ack(u16),
+}
+
+/// Parse a line received from the robot. Lines that aren't flow control
+/// messages return `None`.
+pub(crate) fn parse(line: &str) -> Option<Response> {
+    if line == "CL STATUS=READY" {
+        return Some(Response::Ready);
+    }
+    line.strip_prefix("CL STATUS=ACK&NUM=")
+        .and_then(|number| number.parse().ok())
+        .map(Response::Ack)
+}
+
+/// Return the number of a block, from its header.
+pub(crate) fn block_number(block: &[u8]) -> Option<u16> {
+    match block {
+        [0xfa, 0x9f, 0xa1, 0xfa, high, low, ..] if high >> 4 == 0x09 => {
+            Some(u16::from(high & 0x0f) << 8 | u16::from(*low))
+        }
+        _ => None,
+    }
+}
+
+/// What to do after a response of the robot.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Action {
+    /// The robot can accept the next block.
+    SendNext,
+    /// The robot did not receive the block in flight, send it again.
+    Resend,
+    /// The robot is still busy (or the response is unexpected).
+    Wait,
+}
+
+/// Tracks the block that was sent but not acknowledged yet.
+#[derive(Debug, Default)]
+pub(crate) struct FlowControl {
+    in_flight: Option<Vec<u8>>,
+}
+
+impl FlowControl {
+    /// Decide what to do after a response of the robot. An acknowledged
+    /// block is no longer in flight.
+    pub(crate) fn respond(&mut self, response: Response) -> Action {
+        let expected = self.in_flight.as_deref().and_then(block_number);
+        match (response, expected) {
+            (Response::Ready, None) => Action::SendNext,
+            // The robot is idle, so it never got the block in flight
+            (Response::Ready, Some(_)) => Action::Resend,
+            (Response::Ack(number), Some(expected)) if number == expected => {
+                self.in_flight = None;
+                Action::SendNext
+            }
+            // The robot acknowledged the previous block again, so the block
+            // in flight got lost
+            (Response::Ack(number), Some(expected)) if number + 1 == expected => Action::Resend,
+            (Response::Ack(number), Some(expected)) => {
+                warn!("Got ack for non-current block ({} != {})", number, expected);
+                Action::Wait
+            }
+            // We probably started the server process after a few blocks were
+            // already drawn. Catch up.
+            (Response::Ack(_), None) => Action::SendNext,
+        }
+    }
+
+    /// Remember the block that was sent to the robot.
+    pub(crate) fn sent(&mut self, block: Vec<u8>) {
+        self.in_flight = Some(block);
+    }
+
+    /// Return the block that was sent but not acknowledged yet.
+    pub(crate) fn in_flight(&self) -> Option<&Vec<u8>> {
+        self.in_flight.as_ref()
+    }
+
+    /// Return the number of the block in flight (0 if there is none).
+    pub(crate) fn current_block(&self) -> u32 {
+        self.in_flight
+            .as_deref()
+            .and_then(block_number)
+            .map_or(0, u32::from)
+    }
+
+    /// Forget the block in flight, e.g. because the job was dropped.
+    pub(crate) fn reset(&mut self) {
+        self.in_flight = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(number: u16) -> Vec<u8> {
+        vec![
+            0xfa,
+            0x9f,
+            0xa1,
+            0xfa,
+            (0x90 | (number >> 8)) as u8,
+            (number & 0xff) as u8,
+        ]
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("CL STATUS=READY"), Some(Response::Ready));
+        assert_eq!(parse("CL STATUS=ACK&NUM=12"), Some(Response::Ack(12)));
+        assert_eq!(parse("CL STATUS=ACK&NUM=x"), None);
+        assert_eq!(parse("Hello"), None);
+        assert_eq!(block_number(&block(1)), Some(1));
+        assert_eq!(block_number(&block(300)), Some(300));
+        assert_eq!(block_number(&[0x00]), None);
+    }
+
+    #[test]
+    fn test_flow_control() {
+        let mut flow = FlowControl::default();
+        assert_eq!(flow.respond(Response::Ready), Action::SendNext);
+        flow.sent(block(1));
+        assert_eq!(flow.current_block(), 1);
+
+        // Don't send anything while the robot is drawing
+        assert_eq!(flow.respond(Response::Ack(7)), Action::Wait);
+        assert_eq!(flow.respond(Response::Ack(1)), Action::SendNext);
+        assert_eq!(flow.in_flight(), None);
+        flow.sent(block(2));
+
+        // Lost blocks are sent again
+        assert_eq!(flow.respond(Response::Ack(1)), Action::Resend);
+        assert_eq!(flow.respond(Response::Ready), Action::Resend);
+        assert_eq!(flow.in_flight(), Some(&block(2)));
+        assert_eq!(flow.respond(Response::Ack(2)), Action::SendNext);
+
+        flow.sent(block(3));
+        flow.reset();
+        assert_eq!(flow.respond(Response::Ack(3)), Action::SendNext);
+    }
+}
@@ -0,0 +1,239 @@
+//! Conversion of G-code (as written by plotter and laser cutter tools) into
+//! polylines.
+//!
+//! Rapid moves (`G0`) lift the pen, linear moves (`G1`) and arcs (`G2`/`G3`)
+//! draw. If the program moves the Z axis, only moves at Z ≤ 0 draw. Inches
+//! (`G20`) and relative coordinates (`G91`) are supported. The y axis points
+//! upwards, like in the G-code.
+use std::f64::consts::PI;
+
+use svg2polylines::{CoordinatePair, Polyline};
+
+/// Maximum length of the segments approximating an arc, in mm.
+const ARC_SEGMENT: f64 = 1.0;
+
+#[derive(Debug)]
+struct Machine {
+    position: CoordinatePair,
+    z: f64,
+    scale: f64,
+    relative: bool,
+    /// The current motion mode (`G0` to `G3`), which applies to lines
+    /// without a G word.
+    motion: u32,
+    polylines: Vec<Polyline>,
+    current: Polyline,
+}
+
+impl Machine {
+    fn pen_up(&mut self) {
+        if self.current.len() > 1 {
+            self.polylines.push(std::mem::take(&mut self.current));
+        }
+        self.current.clear();
+    }
+
+    fn draw_to(&mut self, point: CoordinatePair) {
+        if self.current.is_empty() {
+            self.current.push(self.position);
+        }
+        self.current.push(point);
+    }
+}
+
+/// Remove the comments of a line.
+fn strip_comments(line: &str) -> String {
+    let line = line.split(';').next().unwrap_or("");
+    let mut result = String::new();
+    let mut depth = 0;
+    for c in line.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            c if depth == 0 => result.push(c),
+            _ => {}
+        }
+    }
+    result
+}
+
+/// Split a line into its words, e.g. `G1X10Y-2.5` into
+/// `[('G', 1), ('X', 10), ('Y', -2.5)]`.
+fn words(line: &str) -> Result<Vec<(char, f64)>, String> {
+    let mut words = vec![];
+    let mut chars = line.chars().filter(|c| !c.is_whitespace()).peekable();
+    while let Some(letter) = chars.next() {
+        if !letter.is_ascii_alphabetic() {
+            return Err(format!("Unexpected character {:?}", letter));
+        }
+        let mut number = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_ascii_digit() || c == '.' || c == '-' || c == '+' {
+                number.push(c);
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        let value = number
+            .parse::<f64>()
+            .map_err(|_| format!("Invalid number after {}: {:?}", letter, number))?;
+        words.push((letter.to_ascii_uppercase(), value));
+    }
+    Ok(words)
+}
+
+/// Return the points of an arc from `start` to `end` around `center`,
+/// without the start point.
+fn arc(
+    start: CoordinatePair,
+    end: CoordinatePair,
+    center: CoordinatePair,
+    clockwise: bool,
+) -> Vec<CoordinatePair> {
+    let radius = (start.x - center.x).hypot(start.y - center.y);
+    let from = (start.y - center.y).atan2(start.x - center.x);
+    let to = (end.y - center.y).atan2(end.x - center.x);
+    let mut sweep = to - from;
+    if clockwise && sweep >= 0.0 {
+        sweep -= 2.0 * PI;
+    } else if !clockwise && sweep <= 0.0 {
+        sweep += 2.0 * PI;
+    }
+    let steps = ((sweep.abs() * radius) / ARC_SEGMENT).ceil().max(1.0) as usize;
+    let mut points: Vec<CoordinatePair> = (1..steps)
+        .map(|step| {
+            let angle = from + sweep * step as f64 / steps as f64;
+            CoordinatePair {
+                x: center.x + radius * angle.cos(),
+                y: center.y + radius * angle.sin(),
+            }
+        })
+        .collect();
+    points.push(end);
+    points
+}
+
+/// Convert a G-code program into polylines (in mm).
+pub(crate) fn parse(gcode: &str) -> Result<Vec<Polyline>, String> {
+    let mut machine = Machine {
+        position: CoordinatePair { x: 0.0, y: 0.0 },
+        z: 0.0,
+        scale: 1.0,
+        relative: false,
+        motion: 0,
+        polylines: vec![],
+        current: vec![],
+    };
+    for (number, line) in gcode.lines().enumerate() {
+        let words =
+            words(&strip_comments(line)).map_err(|e| format!("Line {}: {}", number + 1, e))?;
+        let mut target = (None, None, None);
+        let mut offset = (0.0, 0.0);
+        for (letter, value) in words {
+            match letter {
+                'G' => match value as u32 {
+                    motion @ 0..=3 => machine.motion = motion,
+                    20 => machine.scale = 25.4,
+                    21 => machine.scale = 1.0,
+                    90 => machine.relative = false,
+                    91 => machine.relative = true,
+                    _ => {}
+                },
+                'X' => target.0 = Some(value * machine.scale),
+                'Y' => target.1 = Some(value * machine.scale),
+                'Z' => target.2 = Some(value * machine.scale),
+                'I' => offset.0 = value * machine.scale,
+                'J' => offset.1 = value * machine.scale,
+                _ => {}
+            }
+        }
+        if target == (None, None, None) {
+            continue;
+        }
+        let resolve = |current: f64, value: Option<f64>, relative: bool| match value {
+            Some(value) if relative => current + value,
+            Some(value) => value,
+            None => current,
+        };
+        let point = CoordinatePair {
+            x: resolve(machine.position.x, target.0, machine.relative),
+            y: resolve(machine.position.y, target.1, machine.relative),
+        };
+        machine.z = resolve(machine.z, target.2, machine.relative);
+        let drawing = machine.motion != 0 && machine.z <= 0.0;
+        if !drawing {
+            machine.pen_up();
+        } else if point != machine.position {
+            match machine.motion {
+                1 => machine.draw_to(point),
+                motion => {
+                    let center = CoordinatePair {
+                        x: machine.position.x + offset.0,
+                        y: machine.position.y + offset.1,
+                    };
+                    for p in arc(machine.position, point, center, motion == 2) {
+                        machine.draw_to(p);
+                    }
+                }
+            }
+        }
+        machine.position = point;
+    }
+    machine.pen_up();
+    Ok(machine.polylines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(polyline: &Polyline) -> Vec<(f64, f64)> {
+        polyline.iter().map(|p| (p.x, p.y)).collect()
+    }
+
+    #[test]
+    fn test_lines() {
+        let gcode = "G21 G90 ; mm, absolute\n\
+                     G0 X10 Y10\n\
+                     G1 X20 (draw)\n\
+                     Y20\n\
+                     G0 X0 Y0\n\
+                     G91 G1 X5 Y5\n";
+        let polylines = parse(gcode).unwrap();
+        assert_eq!(polylines.len(), 2);
+        assert_eq!(
+            points(&polylines[0]),
+            vec![(10.0, 10.0), (20.0, 10.0), (20.0, 20.0)]
+        );
+        assert_eq!(points(&polylines[1]), vec![(0.0, 0.0), (5.0, 5.0)]);
+    }
+
+    #[test]
+    fn test_z_and_inches() {
+        let gcode = "G20\nG0 Z0.1\nG1 X1 Y0\nG1 Z-0.01\nG1 X2\nG1 Z0.1\nG1 X3\n";
+        let polylines = parse(gcode).unwrap();
+        assert_eq!(polylines.len(), 1);
+        assert_eq!(points(&polylines[0]), vec![(25.4, 0.0), (50.8, 0.0)]);
+    }
+
+    #[test]
+    fn test_arc() {
+        // Half circle counterclockwise around (10, 0)
+        let polylines = parse("G0 X0 Y0\nG3 X20 Y0 I10 J0\n").unwrap();
+        let polyline = &polylines[0];
+        assert!(polyline.len() > 10);
+        assert_eq!(
+            polyline.last().unwrap(),
+            &CoordinatePair { x: 20.0, y: 0.0 }
+        );
+        assert!(polyline
+            .iter()
+            .all(|p| ((p.x - 10.0).hypot(p.y) - 10.0).abs() < 1e-9 && p.y <= 1e-9));
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(parse("G1 X1\nG1 X=2\n").unwrap_err().starts_with("Line 2"));
+    }
+}
@@ -0,0 +1,450 @@
+//! Built-in parametric generators for generative art.
+//!
+//! Every generator produces polylines that fill the specified bounds.
+//! Generators that use randomness produce a new result every time they are
+//! drawn, unless a `seed` is specified.
+use std::f64::consts::PI;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_derive::{Deserialize, Serialize};
+use svg2polylines::{CoordinatePair, Polyline};
+
+use crate::drawing::{Content, Drawing, Generator};
+use crate::scaling::{self, Bounds};
+
+/// Number of points used to draw curves.
+const CURVE_POINTS: usize = 2000;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "generator", rename_all = "snake_case")]
+pub(crate) enum GeneratorConfig {
+    /// A random maze.
+    Maze {
+        /// Width and height of a maze cell in mm.
+        #[serde(default = "default_cell_size")]
+        cell_size: f64,
+        seed: Option<u64>,
+    },
+    /// A Lissajous curve with the frequencies `a` and `b`.
+    Lissajous {
+        #[serde(default = "default_lissajous_a")]
+        a: u32,
+        #[serde(default = "default_lissajous_b")]
+        b: u32,
+        /// Phase shift in degrees.
+        #[serde(default = "default_lissajous_phase")]
+        phase: f64,
+    },
+    /// A spirograph curve (hypotrochoid).
+    Spirograph {
+        /// Radius of the fixed circle.
+        #[serde(default = "default_fixed_radius")]
+        fixed_radius: u32,
+        /// Radius of the circle rolling inside of the fixed circle.
+        #[serde(default = "default_rolling_radius")]
+        rolling_radius: u32,
+        /// Distance of the pen from the center of the rolling circle.
+        #[serde(default = "default_pen_offset")]
+        pen_offset: f64,
+    },
+    /// Lines following a pseudo-random flow field.
+    FlowField {
+        #[serde(default = "default_flow_lines")]
+        lines: usize,
+        /// Number of steps of 1 mm per line.
+        #[serde(default = "default_flow_steps")]
+        steps: usize,
+        seed: Option<u64>,
+    },
+}
+
+fn default_cell_size() -> f64 {
+    10.0
+}
+
+fn default_lissajous_a() -> u32 {
+    3
+}
+
+fn default_lissajous_b() -> u32 {
+    2
+}
+
+fn default_lissajous_phase() -> f64 {
+    90.0
+}
+
+fn default_fixed_radius() -> u32 {
+    96
+}
+
+fn default_rolling_radius() -> u32 {
+    36
+}
+
+fn default_pen_offset() -> f64 {
+    30.0
+}
+
+fn default_flow_lines() -> usize {
+    150
+}
+
+fn default_flow_steps() -> usize {
+    60
+}
+
+/// A simple xorshift pseudo-random number generator.
+#[derive(Debug)]
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: Option<u64>) -> Self {
+        let seed = seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or(0)
+        });
+        // The state must not be zero
+        Rng(seed ^ 0x9e37_79b9_7f4a_7c15)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+
+    /// Return a random number in `[0, 1)`.
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Return a random number in `[0, n)`.
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+fn point(x: f64, y: f64) -> CoordinatePair {
+    CoordinatePair { x, y }
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Generate a maze with the recursive backtracker algorithm and return its
+/// walls, centered in the bounds.
+fn maze(cell_size: f64, rng: &mut Rng, bounds: &Bounds) -> Result<Vec<Polyline>, String> {
+    if cell_size <= 0.0 {
+        return Err("The cell size must be positive".to_string());
+    }
+    let cols = (bounds.x.spread() / cell_size).floor() as usize;
+    let rows = (bounds.y.spread() / cell_size).floor() as usize;
+    if cols == 0 || rows == 0 {
+        return Err("The cell size is too large".to_string());
+    }
+
+    // Walls to the right of and below every cell
+    let mut right = vec![vec![true; cols]; rows];
+    let mut bottom = vec![vec![true; cols]; rows];
+    let mut visited = vec![vec![false; cols]; rows];
+    let mut stack = vec![(0, 0)];
+    visited[0][0] = true;
+    while let Some(&(row, col)) = stack.last() {
+        let mut neighbours = vec![];
+        if row > 0 && !visited[row - 1][col] {
+            neighbours.push((row - 1, col));
+        }
+        if row + 1 < rows && !visited[row + 1][col] {
+            neighbours.push((row + 1, col));
+        }
+        if col > 0 && !visited[row][col - 1] {
+            neighbours.push((row, col - 1));
+        }
+        if col + 1 < cols && !visited[row][col + 1] {
+            neighbours.push((row, col + 1));
+        }
+        if neighbours.is_empty() {
+            stack.pop();
+            continue;
+        }
+        let (next_row, next_col) = neighbours[rng.below(neighbours.len())];
+        match (next_row.cmp(&row), next_col.cmp(&col)) {
+            (std::cmp::Ordering::Greater, _) => bottom[row][col] = false,
+            (std::cmp::Ordering::Less, _) => bottom[next_row][col] = false,
+            (_, std::cmp::Ordering::Greater) => right[row][col] = false,
+            _ => right[row][next_col] = false,
+        }
+        visited[next_row][next_col] = true;
+        stack.push((next_row, next_col));
+    }
+
+    // Convert walls to polylines, merging adjacent wall segments
+    let left = bounds.x.min + (bounds.x.spread() - cols as f64 * cell_size) / 2.0;
+    let top = bounds.y.min + (bounds.y.spread() - rows as f64 * cell_size) / 2.0;
+    let x = |col: usize| left + col as f64 * cell_size;
+    let y = |row: usize| top + row as f64 * cell_size;
+    let mut polylines = vec![];
+    // Horizontal walls (the top border, and below every row)
+    let horizontal = |line: usize, col: usize| line == 0 || bottom[line - 1][col];
+    for line in 0..=rows {
+        let mut start = None;
+        for col in 0..=cols {
+            let wall = col < cols && horizontal(line, col);
+            match (wall, start) {
+                (true, None) => start = Some(col),
+                (false, Some(s)) => {
+                    polylines.push(vec![point(x(s), y(line)), point(x(col), y(line))]);
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+    // Vertical walls (the left border, and to the right of every column)
+    let vertical = |line: usize, row: usize| line == 0 || right[row][line - 1];
+    for line in 0..=cols {
+        let mut start = None;
+        for row in 0..=rows {
+            let wall = row < rows && vertical(line, row);
+            match (wall, start) {
+                (true, None) => start = Some(row),
+                (false, Some(s)) => {
+                    polylines.push(vec![point(x(line), y(s)), point(x(line), y(row))]);
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(polylines)
+}
+
+/// Return the curve with the specified parametrization for `t` in
+/// `[0, t_max]`.
+fn curve<F: Fn(f64) -> CoordinatePair>(t_max: f64, f: F) -> Polyline {
+    (0..=CURVE_POINTS)
+        .map(|i| f(t_max * i as f64 / CURVE_POINTS as f64))
+        .collect()
+}
+
+/// Return the lines of a flow field, starting at random points.
+fn flow_field(lines: usize, steps: usize, rng: &mut Rng, bounds: &Bounds) -> Vec<Polyline> {
+    // Random parameters of the field
+    let (fx, fy) = (0.01 + rng.next_f64() * 0.03, 0.01 + rng.next_f64() * 0.03);
+    let (px, py) = (rng.next_f64() * 2.0 * PI, rng.next_f64() * 2.0 * PI);
+    let angle = |x: f64, y: f64| ((x * fx + px).sin() + (y * fy + py).cos()) * PI;
+    let contains = |p: &CoordinatePair| {
+        p.x >= bounds.x.min && p.x <= bounds.x.max && p.y >= bounds.y.min && p.y <= bounds.y.max
+    };
+
+    (0..lines)
+        .map(|_| {
+            let mut p = point(
+                bounds.x.min + rng.next_f64() * bounds.x.spread(),
+                bounds.y.min + rng.next_f64() * bounds.y.spread(),
+            );
+            let mut polyline = vec![p];
+            for _ in 0..steps {
+                let a = angle(p.x, p.y);
+                p = point(p.x + a.cos(), p.y + a.sin());
+                if !contains(&p) {
+                    break;
+                }
+                polyline.push(p);
+            }
+            polyline
+        })
+        .filter(|polyline| polyline.len() > 1)
+        .collect()
+}
+
+impl GeneratorConfig {
+    /// Return a human readable name of the generator, e.g. for the status.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            GeneratorConfig::Maze { .. } => "Maze",
+            GeneratorConfig::Lissajous { .. } => "Lissajous curve",
+            GeneratorConfig::Spirograph { .. } => "Spirograph",
+            GeneratorConfig::FlowField { .. } => "Flow field",
+        }
+    }
+
+    /// Generate the polylines, filling the bounds.
+    pub(crate) fn generate(&self, bounds: &Bounds) -> Result<Vec<Polyline>, String> {
+        let mut polylines = match *self {
+            GeneratorConfig::Maze { cell_size, seed } => {
+                return maze(cell_size, &mut Rng::new(seed), bounds);
+            }
+            GeneratorConfig::FlowField { lines, steps, seed } => {
+                return Ok(flow_field(lines, steps, &mut Rng::new(seed), bounds));
+            }
+            GeneratorConfig::Lissajous { a, b, phase } => {
+                let (a, b) = (f64::from(a), f64::from(b));
+                let phase = phase.to_radians();
+                let aspect = bounds.x.spread() / bounds.y.spread();
+                vec![curve(2.0 * PI, |t| {
+                    point(aspect * (a * t + phase).sin(), (b * t).sin())
+                })]
+            }
+            GeneratorConfig::Spirograph {
+                fixed_radius,
+                rolling_radius,
+                pen_offset,
+            } => {
+                if fixed_radius == 0 || rolling_radius == 0 {
+                    return Err("The radii must be positive".to_string());
+                }
+                // The curve closes after the rolling circle has made this
+                // many revolutions
+                let revolutions = rolling_radius / gcd(fixed_radius, rolling_radius);
+                let (r_fixed, r_rolling) = (f64::from(fixed_radius), f64::from(rolling_radius));
+                let diff = r_fixed - r_rolling;
+                vec![curve(2.0 * PI * f64::from(revolutions), |t| {
+                    point(
+                        diff * t.cos() + pen_offset * (diff / r_rolling * t).cos(),
+                        diff * t.sin() - pen_offset * (diff / r_rolling * t).sin(),
+                    )
+                })]
+            }
+        };
+        scaling::fit_polylines(&mut polylines, bounds)?;
+        Ok(polylines)
+    }
+
+    /// Return the semantic key of the generated content. Random content
+    /// without a seed has no key, since it is different every time.
+    fn key(&self) -> Option<String> {
+        let deterministic = match *self {
+            GeneratorConfig::Maze { seed, .. } | GeneratorConfig::FlowField { seed, .. } => {
+                seed.is_some()
+            }
+            GeneratorConfig::Lissajous { .. } | GeneratorConfig::Spirograph { .. } => true,
+        };
+        deterministic.then(|| {
+            format!(
+                "generate:{}",
+                serde_json::to_string(self).unwrap_or_default()
+            )
+        })
+    }
+
+    /// Return a drawing that is generated anew every time it is drawn,
+    /// filling the board within the margin.
+    pub(crate) fn drawing(&self, margin: f64) -> Drawing {
+        let config = self.clone();
+        Drawing::Generated(Generator::new(move || {
+            let bounds = Bounds::drawable(margin);
+            Ok(Content {
+                key: config.key(),
+                polylines: config.generate(&bounds)?.into(),
+            })
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bounds() -> Bounds {
+        let mut bounds = Bounds::board();
+        bounds.add_padding(5.0);
+        bounds
+    }
+
+    fn assert_within(polylines: &[Polyline], bounds: &Bounds) {
+        assert!(!polylines.is_empty());
+        for p in polylines.iter().flatten() {
+            assert!(p.x >= bounds.x.min - 1e-9 && p.x <= bounds.x.max + 1e-9);
+            assert!(p.y >= bounds.y.min - 1e-9 && p.y <= bounds.y.max + 1e-9);
+        }
+    }
+
+    fn config(json: &str) -> GeneratorConfig {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_all_generators_fit() {
+        let bounds = bounds();
+        for json in [
+            r#"{"generator": "maze"}"#,
+            r#"{"generator": "lissajous"}"#,
+            r#"{"generator": "spirograph"}"#,
+            r#"{"generator": "flow_field"}"#,
+        ] {
+            assert_within(&config(json).generate(&bounds).unwrap(), &bounds);
+        }
+    }
+
+    #[test]
+    fn test_seed_is_reproducible() {
+        let bounds = bounds();
+        let maze = config(r#"{"generator": "maze", "seed": 42}"#);
+        assert_eq!(
+            maze.generate(&bounds).unwrap(),
+            maze.generate(&bounds).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_maze_is_perfect() {
+        // A perfect maze with n cells has n - 1 passages. Every removed wall
+        // segment is a passage.
+        let bounds = Bounds {
+            x: scaling::Range {
+                min: 0.0,
+                max: 40.0,
+            },
+            y: scaling::Range {
+                min: 0.0,
+                max: 30.0,
+            },
+        };
+        let polylines = maze(10.0, &mut Rng::new(Some(1)), &bounds).unwrap();
+        let wall_length: f64 = polylines
+            .iter()
+            .map(|p| (p[1].x - p[0].x).abs() + (p[1].y - p[0].y).abs())
+            .sum();
+        // Grid of 4x3 cells: 5 vertical lines of 30 and 4 horizontal lines of 40
+        let grid_length = 5.0 * 30.0 + 4.0 * 40.0;
+        assert_eq!(wall_length, grid_length - 11.0 * 10.0);
+    }
+
+    #[test]
+    fn test_key() {
+        assert_eq!(config(r#"{"generator": "maze"}"#).key(), None);
+        assert_eq!(
+            config(r#"{"generator": "maze", "seed": 42}"#).key(),
+            Some(r#"generate:{"generator":"maze","cell_size":10.0,"seed":42}"#.to_string())
+        );
+        assert!(config(r#"{"generator": "lissajous"}"#).key().is_some());
+    }
+
+    #[test]
+    fn test_invalid_parameters() {
+        let bounds = bounds();
+        assert!(config(r#"{"generator": "maze", "cell_size": 0}"#)
+            .generate(&bounds)
+            .is_err());
+        assert!(config(r#"{"generator": "maze", "cell_size": 500}"#)
+            .generate(&bounds)
+            .is_err());
+        assert!(
+            config(r#"{"generator": "spirograph", "rolling_radius": 0}"#)
+                .generate(&bounds)
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,180 @@
+//! Hatch fill for closed shapes.
+//!
+//! The pen can only draw strokes, so solid shapes (e.g. logos) come out as
+//! outlines. With a `hatch` fill, the closed polylines are filled with
+//! parallel lines at the given `spacing` (in mm) and `angle` (in degrees to
+//! the horizontal). Shapes within other shapes are holes (even-odd rule).
+use serde_derive::{Deserialize, Serialize};
+use svg2polylines::{CoordinatePair, Polyline};
+
+/// Polylines whose ends are closer than this (in mm) are closed.
+const CLOSED_EPSILON: f64 = 0.1;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub(crate) struct HatchFill {
+    /// Distance between the hatch lines in mm.
+    #[serde(default = "default_spacing")]
+    spacing: f64,
+    /// Angle of the hatch lines to the horizontal in degrees.
+    #[serde(default = "default_angle")]
+    angle: f64,
+}
+
+fn default_spacing() -> f64 {
+    2.0
+}
+
+fn default_angle() -> f64 {
+    45.0
+}
+
+fn is_closed(polyline: &[CoordinatePair]) -> bool {
+    match (polyline.first(), polyline.last()) {
+        (Some(first), Some(last)) if polyline.len() >= 4 => {
+            (last.x - first.x).hypot(last.y - first.y) <= CLOSED_EPSILON
+        }
+        _ => false,
+    }
+}
+
+/// Rotate the point around the origin.
+fn rotate(point: &CoordinatePair, (sin, cos): (f64, f64)) -> CoordinatePair {
+    CoordinatePair {
+        x: point.x * cos - point.y * sin,
+        y: point.x * sin + point.y * cos,
+    }
+}
+
+impl HatchFill {
+    /// Check that the fill can be drawn.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if !(self.spacing.is_finite() && self.spacing >= 0.1) {
+            return Err("The hatch spacing must be at least 0.1 mm".to_string());
+        }
+        if !self.angle.is_finite() {
+            return Err("The hatch angle must be a number".to_string());
+        }
+        Ok(())
+    }
+
+    /// Return the hatch lines that fill the closed polylines.
+    fn hatch(&self, polylines: &[Polyline]) -> Vec<Polyline> {
+        // Rotate the shapes so that the hatch lines are horizontal
+        let angle = self.angle.to_radians();
+        let forward = (-angle).sin_cos();
+        let backward = angle.sin_cos();
+        let shapes: Vec<Polyline> = polylines
+            .iter()
+            .filter(|polyline| is_closed(polyline))
+            .map(|polyline| polyline.iter().map(|p| rotate(p, forward)).collect())
+            .collect();
+        let (min_y, max_y) = shapes
+            .iter()
+            .flatten()
+            .fold((f64::MAX, f64::MIN), |(min, max), p| {
+                (min.min(p.y), max.max(p.y))
+            });
+        let mut lines = vec![];
+        let mut y = min_y + self.spacing / 2.0;
+        let mut row = 0;
+        while y < max_y {
+            // Crossings of the scanline with the edges of all shapes
+            let mut crossings: Vec<f64> = shapes
+                .iter()
+                .flat_map(|shape| shape.windows(2))
+                .filter(|edge| (edge[0].y <= y) != (edge[1].y <= y))
+                .map(|edge| {
+                    let t = (y - edge[0].y) / (edge[1].y - edge[0].y);
+                    edge[0].x + t * (edge[1].x - edge[0].x)
+                })
+                .collect();
+            crossings.sort_by(|a, b| a.total_cmp(b));
+            // Alternate the direction, so that the pen travels less
+            if row % 2 == 1 {
+                crossings.reverse();
+            }
+            for pair in crossings.chunks_exact(2) {
+                let line = [
+                    CoordinatePair { x: pair[0], y },
+                    CoordinatePair { x: pair[1], y },
+                ];
+                lines.push(line.iter().map(|p| rotate(p, backward)).collect());
+            }
+            y += self.spacing;
+            row += 1;
+        }
+        lines
+    }
+
+    /// Add the hatch lines of the closed polylines after the polylines.
+    pub(crate) fn apply(&self, mut polylines: Vec<Polyline>) -> Vec<Polyline> {
+        let lines = self.hatch(&polylines);
+        polylines.extend(lines);
+        polylines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::square;
+
+    fn fill(spacing: f64, angle: f64) -> HatchFill {
+        HatchFill { spacing, angle }
+    }
+
+    fn length(line: &Polyline) -> f64 {
+        (line[1].x - line[0].x).hypot(line[1].y - line[0].y)
+    }
+
+    #[test]
+    fn test_horizontal() {
+        let lines = fill(2.0, 0.0).hatch(&[square(0.0, 0.0, 10.0)]);
+        assert_eq!(lines.len(), 5);
+        for (i, line) in lines.iter().enumerate() {
+            assert!((line[0].y - (1.0 + 2.0 * i as f64)).abs() < 1e-9);
+            assert!((length(line) - 10.0).abs() < 1e-9);
+        }
+        // Alternating directions
+        assert!(lines[0][0].x < lines[0][1].x);
+        assert!(lines[1][0].x > lines[1][1].x);
+    }
+
+    #[test]
+    fn test_hole_and_open_polylines() {
+        // A frame: The inner square is a hole
+        let lines = fill(2.0, 0.0).hatch(&[square(0.0, 0.0, 10.0), square(4.0, 4.0, 2.0)]);
+        let through_hole: Vec<&Polyline> = lines
+            .iter()
+            .filter(|line| (line[0].y - 5.0).abs() < 1e-9)
+            .collect();
+        assert_eq!(through_hole.len(), 2);
+        assert!(through_hole
+            .iter()
+            .all(|line| (length(line) - 4.0).abs() < 1e-9));
+
+        // Open polylines are not filled
+        let mut open = square(0.0, 0.0, 10.0);
+        open.pop();
+        assert!(fill(2.0, 0.0).hatch(&[open]).is_empty());
+    }
+
+    #[test]
+    fn test_angle() {
+        let lines = fill(1.0, 45.0).hatch(&[square(0.0, 0.0, 10.0)]);
+        assert!(!lines.is_empty());
+        for line in &lines {
+            let (dx, dy) = (line[1].x - line[0].x, line[1].y - line[0].y);
+            assert!((dy.atan2(dx).to_degrees().rem_euclid(180.0) - 45.0).abs() < 1e-6);
+        }
+        let polylines = fill(1.0, 45.0).apply(vec![square(0.0, 0.0, 10.0)]);
+        assert_eq!(polylines.len(), lines.len() + 1);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(fill(2.0, 45.0).validate().is_ok());
+        assert!(fill(0.0, 45.0).validate().is_err());
+        assert!(fill(2.0, f64::NAN).validate().is_err());
+    }
+}
@@ -0,0 +1,146 @@
+//! Spatial statistics of the pen-down travel on the board.
+//!
+//! The board is divided into square cells, and the length of the strokes of
+//! every job that is started is added to the cells they cross. This shows
+//! which areas of the board wear out fastest, so that layouts can be rotated.
+//! With a state dir, the counters are kept across restarts.
+use std::fs;
+use std::path::PathBuf;
+
+use log::warn;
+use serde_derive::Serialize;
+use svg2polylines::{CoordinatePair, Polyline};
+
+use crate::robot::{IBB_HEIGHT, IBB_WIDTH};
+
+/// The size of a cell in mm.
+const CELL_MM: u16 = 10;
+
+/// The maximum distance between two sampled points of a stroke in mm.
+const SAMPLE_MM: f64 = 1.0;
+
+const COLUMNS: usize = IBB_WIDTH.div_ceil(CELL_MM) as usize;
+const ROWS: usize = IBB_HEIGHT.div_ceil(CELL_MM) as usize;
+
+#[derive(Debug)]
+pub(crate) struct Heatmap {
+    /// The pen-down travel per cell in mm, row by row from the top.
+    cells: Vec<Vec<f64>>,
+    /// File where the counters are persisted.
+    state_file: Option<PathBuf>,
+}
+
+/// The heatmap, as returned by `/stats/heatmap/`.
+#[derive(Debug, Serialize)]
+pub(crate) struct HeatmapReport<'a> {
+    cell_mm: u16,
+    columns: usize,
+    rows: usize,
+    /// The largest value of a cell.
+    max_mm: f64,
+    cells: &'a [Vec<f64>],
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Heatmap {
+            cells: vec![vec![0.0; COLUMNS]; ROWS],
+            state_file: None,
+        }
+    }
+}
+
+impl Heatmap {
+    /// Start with the counters from the state file (or none).
+    pub(crate) fn new(state_file: Option<PathBuf>) -> Self {
+        let cells = state_file
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|json| serde_json::from_str::<Vec<Vec<f64>>>(&json).ok())
+            .filter(|cells| cells.len() == ROWS && cells.iter().all(|row| row.len() == COLUMNS))
+            .unwrap_or_else(|| Heatmap::default().cells);
+        Heatmap { cells, state_file }
+    }
+
+    fn add_point(&mut self, point: &CoordinatePair, mm: f64) {
+        let cell = |value: f64, count: usize| {
+            ((value / f64::from(CELL_MM)).floor().max(0.0) as usize).min(count - 1)
+        };
+        self.cells[cell(point.y, ROWS)][cell(point.x, COLUMNS)] += mm;
+    }
+
+    /// Add the strokes of a job, in board coordinates.
+    pub(crate) fn add(&mut self, polylines: &[Polyline]) {
+        for polyline in polylines {
+            for segment in polyline.windows(2) {
+                let (a, b) = (&segment[0], &segment[1]);
+                let length = ((b.x - a.x).powi(2) + (b.y - a.y).powi(2)).sqrt();
+                let samples = (length / SAMPLE_MM).ceil().max(1.0) as usize;
+                for i in 0..samples {
+                    let t = (i as f64 + 0.5) / samples as f64;
+                    let point = CoordinatePair {
+                        x: a.x + (b.x - a.x) * t,
+                        y: a.y + (b.y - a.y) * t,
+                    };
+                    self.add_point(&point, length / samples as f64);
+                }
+            }
+        }
+        if let Some(ref path) = self.state_file {
+            match serde_json::to_string(&self.cells) {
+                Ok(json) => {
+                    if let Err(e) = fs::write(path, json) {
+                        warn!("Could not write {}: {}", path.display(), e);
+                    }
+                }
+                Err(e) => warn!("Could not serialize the heatmap: {}", e),
+            }
+        }
+    }
+
+    pub(crate) fn report(&self) -> HeatmapReport<'_> {
+        HeatmapReport {
+            cell_mm: CELL_MM,
+            columns: COLUMNS,
+            rows: ROWS,
+            max_mm: self.cells.iter().flatten().copied().fold(0.0, f64::max),
+            cells: &self.cells,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: f64, y: f64) -> CoordinatePair {
+        CoordinatePair { x, y }
+    }
+
+    #[test]
+    fn test_add() {
+        let mut heatmap = Heatmap::default();
+        // 20 mm across the first two cells of the top row
+        heatmap.add(&[vec![point(0.0, 5.0), point(20.0, 5.0)]]);
+        // A stroke beyond the edge counts for the last cell
+        heatmap.add(&[vec![point(400.0, 200.0), point(400.0, 202.0)]]);
+
+        let report = heatmap.report();
+        assert_eq!((report.columns, report.rows), (36, 13));
+        assert_eq!(report.max_mm, 10.0);
+        assert_eq!(report.cells[0][0], 10.0);
+        assert_eq!(report.cells[0][1], 10.0);
+        assert_eq!(report.cells[0][2], 0.0);
+        assert_eq!(report.cells[12][35], 2.0);
+    }
+
+    #[test]
+    fn test_persistence() {
+        let path = std::env::temp_dir().join(format!("ibb-heatmap-{}", std::process::id()));
+        let mut heatmap = Heatmap::new(Some(path.clone()));
+        heatmap.add(&[vec![point(0.0, 0.0), point(0.0, 5.0)]]);
+        let heatmap = Heatmap::new(Some(path.clone()));
+        assert_eq!(heatmap.report().cells[0][0], 5.0);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,154 @@
+//! In-memory history of the jobs sent to the robot, together with the
+//! heatmap of the pen-down travel (see the `heatmap` module).
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use serde_derive::Serialize;
+use time::OffsetDateTime;
+
+use crate::heatmap::Heatmap;
+use crate::jobs::JobId;
+
+/// Number of jobs kept in the history.
+const HISTORY_SIZE: usize = 100;
+
+pub(crate) type SharedHistory = Arc<Mutex<History>>;
+
+/// A job that was completely sent to the robot.
+#[derive(Debug, Serialize, Clone)]
+pub(crate) struct JobRecord {
+    /// Id of the job in the job registry, if it was a queued job.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) job_id: Option<JobId>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub(crate) finished: OffsetDateTime,
+    pub(crate) blocks: u32,
+    pub(crate) bytes: usize,
+    /// Measured duration, from sending the first block until the robot
+    /// responded after the last block.
+    pub(crate) actual_seconds: f64,
+    /// Duration predicted by the simulator.
+    pub(crate) simulated_seconds: f64,
+}
+
+impl JobRecord {
+    /// Deviation of the simulated from the actual duration in percent.
+    /// Positive values mean that the simulation was too optimistic.
+    pub(crate) fn deviation_percent(&self) -> Option<f64> {
+        if self.simulated_seconds > 0.0 {
+            Some((self.actual_seconds / self.simulated_seconds - 1.0) * 100.0)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct History {
+    jobs: VecDeque<JobRecord>,
+    pub(crate) heatmap: Heatmap,
+}
+
+/// Summary of the history, as returned over HTTP.
+#[derive(Debug, Serialize)]
+pub(crate) struct HistoryReport {
+    jobs: Vec<JobReport>,
+    /// Mean deviation of the simulated from the actual duration in percent.
+    mean_deviation_percent: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+struct JobReport {
+    #[serde(flatten)]
+    job: JobRecord,
+    deviation_percent: Option<f64>,
+}
+
+impl History {
+    /// Create the history, with the heatmap persisted in the state file.
+    pub(crate) fn new_shared(heatmap_file: Option<PathBuf>) -> SharedHistory {
+        Arc::new(Mutex::new(History {
+            jobs: VecDeque::new(),
+            heatmap: Heatmap::new(heatmap_file),
+        }))
+    }
+
+    pub(crate) fn push(&mut self, job: JobRecord) {
+        if self.jobs.len() >= HISTORY_SIZE {
+            self.jobs.pop_front();
+        }
+        self.jobs.push_back(job);
+    }
+
+    /// Return the jobs (newest first) with simulated-vs-actual comparisons.
+    pub(crate) fn report(&self) -> HistoryReport {
+        let deviations: Vec<f64> = self
+            .jobs
+            .iter()
+            .filter_map(JobRecord::deviation_percent)
+            .collect();
+        let mean_deviation_percent = if deviations.is_empty() {
+            None
+        } else {
+            Some(deviations.iter().sum::<f64>() / deviations.len() as f64)
+        };
+        HistoryReport {
+            jobs: self
+                .jobs
+                .iter()
+                .rev()
+                .map(|job| JobReport {
+                    job: job.clone(),
+                    deviation_percent: job.deviation_percent(),
+                })
+                .collect(),
+            mean_deviation_percent,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(actual_seconds: f64, simulated_seconds: f64) -> JobRecord {
+        JobRecord {
+            job_id: None,
+            finished: OffsetDateTime::UNIX_EPOCH,
+            blocks: 1,
+            bytes: 768,
+            actual_seconds,
+            simulated_seconds,
+        }
+    }
+
+    #[test]
+    fn test_deviation() {
+        let deviation = job(110.0, 100.0).deviation_percent().unwrap();
+        assert!((deviation - 10.0).abs() < 1e-9);
+        assert_eq!(job(50.0, 100.0).deviation_percent(), Some(-50.0));
+        assert_eq!(job(50.0, 0.0).deviation_percent(), None);
+    }
+
+    #[test]
+    fn test_report() {
+        let mut history = History::default();
+        history.push(job(150.0, 100.0));
+        history.push(job(50.0, 100.0));
+        let report = history.report();
+        assert_eq!(report.jobs.len(), 2);
+        assert_eq!(report.jobs[0].job.actual_seconds, 50.0);
+        assert_eq!(report.mean_deviation_percent, Some(0.0));
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut history = History::default();
+        for i in 0..(HISTORY_SIZE + 5) {
+            history.push(job(i as f64, 1.0));
+        }
+        assert_eq!(history.jobs.len(), HISTORY_SIZE);
+        assert_eq!(history.jobs[0].actual_seconds, 5.0);
+    }
+}
@@ -0,0 +1,165 @@
+//! Days on which scheduled prints are suppressed, e.g. public holidays.
+//!
+//! With a `holidays` section in the config, the schedule and the agenda are
+//! skipped on the listed `dates`, and on the days of the events of an iCal
+//! calendar (`ical_url`, e.g. a public holiday calendar), which is fetched
+//! again every `refresh_hours`. Single prints are not affected.
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use log::{error, info};
+use serde_derive::{Deserialize, Serialize};
+use time::{Date, OffsetDateTime};
+
+use crate::agenda;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct HolidayConfig {
+    #[serde(default, with = "iso_dates")]
+    pub(crate) dates: Vec<Date>,
+    /// URL of the iCal file. Not serialized, since these URLs often contain
+    /// secret tokens.
+    #[serde(default, skip_serializing)]
+    pub(crate) ical_url: Option<String>,
+    #[serde(default = "default_refresh_hours")]
+    pub(crate) refresh_hours: u64,
+}
+
+fn default_refresh_hours() -> u64 {
+    12
+}
+
+/// (De)serialize a list of dates like `2026-12-25`.
+mod iso_dates {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use time::macros::format_description;
+    use time::Date;
+
+    const FORMAT: &[time::format_description::FormatItem<'static>] =
+        format_description!("[year]-[month]-[day]");
+
+    pub(super) fn serialize<S: Serializer>(
+        dates: &[Date],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        dates
+            .iter()
+            .map(|date| date.format(FORMAT).map_err(serde::ser::Error::custom))
+            .collect::<Result<Vec<String>, S::Error>>()?
+            .serialize(serializer)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Date>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|date| {
+                Date::parse(date, FORMAT)
+                    .map_err(|_| serde::de::Error::custom(format!("Invalid date: {}", date)))
+            })
+            .collect()
+    }
+}
+
+pub(crate) type SharedHolidays = Arc<Holidays>;
+
+#[derive(Debug, Default)]
+pub(crate) struct Holidays {
+    /// The configured dates.
+    dates: Vec<Date>,
+    /// The first and last day of the events of the calendar.
+    calendar: Mutex<Vec<(Date, Date)>>,
+}
+
+impl Holidays {
+    pub(crate) fn new_shared(config: Option<&HolidayConfig>) -> SharedHolidays {
+        Arc::new(Holidays {
+            dates: config
+                .map(|config| config.dates.clone())
+                .unwrap_or_default(),
+            calendar: Mutex::default(),
+        })
+    }
+
+    /// Return whether scheduled prints are suppressed on the date.
+    pub(crate) fn is_holiday(&self, date: Date) -> bool {
+        if self.dates.contains(&date) {
+            return true;
+        }
+        match self.calendar.lock() {
+            Ok(calendar) => calendar
+                .iter()
+                .any(|&(first, last)| first <= date && date <= last),
+            Err(e) => {
+                error!("Could not unlock holiday calendar mutex: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Return whether today is a holiday.
+    pub(crate) fn is_today(&self) -> bool {
+        let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+        self.is_holiday(now.date())
+    }
+
+    fn set_calendar(&self, days: Vec<(Date, Date)>) {
+        match self.calendar.lock() {
+            Ok(mut calendar) => *calendar = days,
+            Err(e) => error!("Could not unlock holiday calendar mutex: {}", e),
+        }
+    }
+}
+
+/// Spawn a thread that fetches the holiday calendar regularly, if any.
+pub(crate) fn spawn(config: &HolidayConfig, holidays: SharedHolidays) {
+    let Some(url) = config.ical_url.clone() else {
+        return;
+    };
+    let refresh = Duration::from_secs(config.refresh_hours.max(1) * 3600);
+    thread::spawn(move || loop {
+        let offset = OffsetDateTime::now_local()
+            .unwrap_or_else(|_| OffsetDateTime::now_utc())
+            .offset();
+        match agenda::fetch(&url) {
+            Ok(ics) => {
+                let days: Vec<(Date, Date)> = agenda::parse_events(&ics, offset)
+                    .iter()
+                    .map(|event| (event.start_date, event.end_date))
+                    .collect();
+                info!("Holidays: {} event(s) in the calendar", days.len());
+                holidays.set_calendar(days);
+            }
+            // Keep the days of the last calendar
+            Err(e) => error!("Holidays: {}", e),
+        }
+        thread::sleep(refresh);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::date;
+
+    use super::*;
+
+    #[test]
+    fn test_is_holiday() {
+        let config: HolidayConfig =
+            serde_json::from_str(r#"{"dates": ["2026-12-25", "2027-01-01"]}"#).unwrap();
+        let holidays = Holidays::new_shared(Some(&config));
+        holidays.set_calendar(vec![(date!(2026 - 12 - 28), date!(2026 - 12 - 30))]);
+        assert!(holidays.is_holiday(date!(2026 - 12 - 25)));
+        assert!(!holidays.is_holiday(date!(2026 - 12 - 26)));
+        assert!(holidays.is_holiday(date!(2026 - 12 - 29)));
+        assert!(holidays.is_holiday(date!(2027 - 01 - 01)));
+
+        assert!(serde_json::from_str::<HolidayConfig>(r#"{"dates": ["25.12.2026"]}"#).is_err());
+        assert_eq!(
+            serde_json::to_value(&config).unwrap()["dates"][0],
+            "2026-12-25"
+        );
+    }
+}
@@ -0,0 +1,125 @@
+//! Conversion of HP-GL plotter files into polylines.
+//!
+//! Only the pen movement instructions are interpreted: `PU` and `PD` (pen up
+//! and down, optionally with points), `PA` and `PR` (absolute and relative
+//! points) and `IN`. Everything else (pen selection, labels, …) is ignored.
+//! Coordinates are in plotter units of 0.025 mm, with the y axis pointing
+//! upwards.
+use svg2polylines::{CoordinatePair, Polyline};
+
+/// Plotter units per mm.
+const UNITS_PER_MM: f64 = 40.0;
+
+#[derive(Debug, Default)]
+struct Plotter {
+    position: (f64, f64),
+    pen_down: bool,
+    relative: bool,
+    polylines: Vec<Polyline>,
+    current: Polyline,
+}
+
+impl Plotter {
+    fn lift(&mut self) {
+        self.pen_down = false;
+        if self.current.len() > 1 {
+            self.polylines.push(std::mem::take(&mut self.current));
+        }
+        self.current.clear();
+    }
+
+    fn move_to(&mut self, x: f64, y: f64) {
+        self.position = match self.relative {
+            true => (self.position.0 + x, self.position.1 + y),
+            false => (x, y),
+        };
+        let point = CoordinatePair {
+            x: self.position.0 / UNITS_PER_MM,
+            y: self.position.1 / UNITS_PER_MM,
+        };
+        if !self.pen_down {
+            self.current = vec![point];
+        } else if self.current.last() != Some(&point) {
+            self.current.push(point);
+        }
+    }
+}
+
+/// Convert an HP-GL file into polylines (in mm).
+pub(crate) fn parse(hpgl: &str) -> Result<Vec<Polyline>, String> {
+    let mut plotter = Plotter::default();
+    for instruction in hpgl.split([';', '\n']) {
+        let instruction = instruction.trim();
+        if instruction.len() < 2 || !instruction.is_char_boundary(2) {
+            continue;
+        }
+        let (name, parameters) = instruction.split_at(2);
+        let numbers = parameters
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|number| !number.is_empty())
+            .map(|number| {
+                number
+                    .parse::<f64>()
+                    .map_err(|_| format!("Invalid number in {}: {:?}", name, number))
+            })
+            .collect::<Result<Vec<f64>, String>>();
+        match name.to_ascii_uppercase().as_str() {
+            "IN" => {
+                plotter.lift();
+                plotter.position = (0.0, 0.0);
+                plotter.relative = false;
+                continue;
+            }
+            "PU" => plotter.lift(),
+            "PD" => {
+                if !plotter.pen_down && plotter.current.is_empty() {
+                    plotter.current = vec![CoordinatePair {
+                        x: plotter.position.0 / UNITS_PER_MM,
+                        y: plotter.position.1 / UNITS_PER_MM,
+                    }];
+                }
+                plotter.pen_down = true;
+            }
+            "PA" => plotter.relative = false,
+            "PR" => plotter.relative = true,
+            _ => continue,
+        }
+        let numbers = numbers?;
+        if numbers.len() % 2 != 0 {
+            return Err(format!("Odd number of coordinates in {}", instruction));
+        }
+        for point in numbers.chunks_exact(2) {
+            plotter.move_to(point[0], point[1]);
+        }
+    }
+    plotter.lift();
+    Ok(plotter.polylines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(polyline: &Polyline) -> Vec<(f64, f64)> {
+        polyline.iter().map(|p| (p.x, p.y)).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let hpgl = "IN;SP1;PU400,400;PD800,400,800,800;PU;PR;PU40,0;PD0,40;SP0;";
+        let polylines = parse(hpgl).unwrap();
+        assert_eq!(polylines.len(), 2);
+        assert_eq!(
+            points(&polylines[0]),
+            vec![(10.0, 10.0), (20.0, 10.0), (20.0, 20.0)]
+        );
+        assert_eq!(points(&polylines[1]), vec![(21.0, 20.0), (21.0, 21.0)]);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(parse("PU0,0;PD10,x;").is_err());
+        assert!(parse("PD10;").is_err());
+        assert!(parse("LBHello;").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,210 @@
+//! The input formats of `POST /jobs/`, keyed by content type.
+//!
+//! Every importer converts an uploaded file into polylines in board
+//! coordinates. Formats with arbitrary units (PDF points, image pixels) are
+//! fitted to the board by default, formats in mm are drawn where they are
+//! (unless fitting is requested). Formats whose y axis points upwards
+//! (G-code, HP-GL, DXF) are flipped. A new format only needs a parser and an
+//! entry in `IMPORTERS`.
+use svg2polylines::Polyline;
+
+use crate::scaling::{self, Bounds};
+use crate::{dxf, gcode, hpgl, pdf, png, svg, SVG2POLYLINES_TOLERANCE};
+
+/// Options of an import, given in the query string.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct ImportOptions {
+    /// The page of a PDF file (starting at 1).
+    pub(crate) page: u32,
+    /// The layers of an SVG file to draw (all if empty).
+    pub(crate) layers: Vec<String>,
+    /// Whether to fit the drawing to the board, overriding the default of
+    /// the format.
+    pub(crate) fit: Option<bool>,
+    /// The margin along the edges of the board that fitted drawings keep
+    /// free, in mm.
+    pub(crate) margin: f64,
+}
+
+type ImportFn = fn(&[u8], &ImportOptions) -> Result<Vec<Polyline>, String>;
+
+#[derive(Debug)]
+pub(crate) struct Importer {
+    pub(crate) name: &'static str,
+    pub(crate) content_type: &'static str,
+    /// Whether the drawing is fitted to the board by default.
+    fit: bool,
+    /// Whether the y axis of the format points upwards.
+    y_up: bool,
+    parse: ImportFn,
+}
+
+fn text(data: &[u8]) -> Result<&str, String> {
+    std::str::from_utf8(data).map_err(|e| format!("Invalid UTF-8: {}", e))
+}
+
+pub(crate) const IMPORTERS: &[Importer] = &[
+    Importer {
+        name: "svg",
+        content_type: "image/svg+xml",
+        fit: false,
+        y_up: false,
+        parse: |data, options| {
+            svg::parse_layers(text(data)?, SVG2POLYLINES_TOLERANCE, &options.layers)
+        },
+    },
+    Importer {
+        name: "pdf",
+        content_type: "application/pdf",
+        fit: true,
+        y_up: false,
+        parse: |data, options| pdf::parse(data, options.page),
+    },
+    Importer {
+        name: "json-polylines",
+        content_type: "application/json",
+        fit: false,
+        y_up: false,
+        parse: |data, _| {
+            serde_json::from_slice(data).map_err(|e| format!("Invalid polylines: {}", e))
+        },
+    },
+    Importer {
+        name: "gcode",
+        content_type: "text/x-gcode",
+        fit: false,
+        y_up: true,
+        parse: |data, _| gcode::parse(text(data)?),
+    },
+    Importer {
+        name: "hpgl",
+        content_type: "application/vnd.hp-hpgl",
+        fit: false,
+        y_up: true,
+        parse: |data, _| hpgl::parse(text(data)?),
+    },
+    Importer {
+        name: "dxf",
+        content_type: "image/vnd.dxf",
+        fit: false,
+        y_up: true,
+        parse: |data, _| dxf::parse(text(data)?),
+    },
+    Importer {
+        name: "png",
+        content_type: "image/png",
+        fit: true,
+        y_up: false,
+        parse: |data, _| png::parse(data),
+    },
+];
+
+/// Return the importer of a content type (ignoring parameters like the
+/// charset), if it is accepted.
+pub(crate) fn find(content_type: &str, accepted: &[String]) -> Option<&'static Importer> {
+    let content_type = content_type.split(';').next()?.trim().to_ascii_lowercase();
+    IMPORTERS.iter().find(|importer| {
+        importer.content_type == content_type
+            && accepted
+                .iter()
+                .any(|accepted| accepted == importer.content_type)
+    })
+}
+
+/// Return the content types of all importers.
+pub(crate) fn content_types() -> Vec<String> {
+    IMPORTERS
+        .iter()
+        .map(|importer| importer.content_type.to_string())
+        .collect()
+}
+
+/// Check that there is an importer for every accepted content type.
+pub(crate) fn validate(accepted: &[String]) -> Result<(), String> {
+    match accepted
+        .iter()
+        .find(|accepted| !IMPORTERS.iter().any(|i| &i.content_type == accepted))
+    {
+        Some(unknown) => Err(format!(
+            "Unknown content type {} (known: {})",
+            unknown,
+            content_types().join(", ")
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Mirror the polylines vertically within their bounds.
+fn flip_y(polylines: &mut [Polyline]) {
+    if let Some(bounds) = scaling::get_bounds(polylines) {
+        for point in polylines.iter_mut().flatten() {
+            point.y = bounds.y.max + bounds.y.min - point.y;
+        }
+    }
+}
+
+impl Importer {
+    pub(crate) fn import(
+        &self,
+        data: &[u8],
+        options: &ImportOptions,
+    ) -> Result<Vec<Polyline>, String> {
+        let mut polylines = (self.parse)(data, options)?;
+        if self.y_up {
+            flip_y(&mut polylines);
+        }
+        if options.fit.unwrap_or(self.fit) {
+            scaling::fit_polylines(&mut polylines, &Bounds::drawable(options.margin))?;
+        }
+        Ok(polylines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        let all = content_types();
+        assert_eq!(find("image/svg+xml", &all).unwrap().name, "svg");
+        assert_eq!(
+            find("Text/X-GCode; charset=utf-8", &all).unwrap().name,
+            "gcode"
+        );
+        assert!(find("image/gif", &all).is_none());
+        // Only accepted types are imported
+        let accepted = vec!["image/svg+xml".to_string()];
+        assert!(find("image/png", &accepted).is_none());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&content_types()).is_ok());
+        assert!(validate(&["image/gif".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_import() {
+        let options = ImportOptions::default();
+        // G-code is flipped, so that it isn't drawn upside down
+        let gcode = find("text/x-gcode", &content_types()).unwrap();
+        let polylines = gcode.import(b"G1 X10 Y10\nG1 X10 Y20\n", &options).unwrap();
+        let ys: Vec<f64> = polylines[0].iter().map(|p| p.y).collect();
+        assert_eq!(ys, vec![20.0, 10.0, 0.0]);
+
+        // Fitting can be requested for formats in mm
+        let json = find("application/json", &content_types()).unwrap();
+        let fit = ImportOptions {
+            fit: Some(true),
+            margin: 10.0,
+            ..ImportOptions::default()
+        };
+        let polylines = json
+            .import(br#"[[{"x": 0, "y": 0}, {"x": 1000, "y": 10}]]"#, &fit)
+            .unwrap();
+        assert_eq!(polylines[0][0].x, 10.0);
+        assert_eq!(polylines[0][1].x, f64::from(crate::robot::IBB_WIDTH) - 10.0);
+        assert!(json.import(b"[", &options).is_err());
+    }
+}
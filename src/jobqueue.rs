@@ -0,0 +1,739 @@
+//! Jobs waiting for the robot, from interactive and scheduled sources.
+//!
+//! The scheduling policy decides which source is served next when the robot
+//! becomes idle:
+//!
+//! - `preempt` (default): Interactive jobs cancel the current schedule and
+//!   are always drawn first.
+//! - `weighted`: Interactive jobs don't cancel the schedule. The sources are
+//!   served by weighted round-robin, e.g. with weights 2 and 1, two
+//!   interactive jobs are drawn for every scheduled job (as long as both
+//!   sources have jobs waiting).
+//!
+//! Scheduled jobs with a semantic key (e.g. `countdown:2022-05-01`) are never
+//! drawn twice in a row. The key of the last job is kept in a state file, so
+//! that this also holds across restarts.
+//!
+//! The waiting jobs can be exported as a snapshot and imported again later,
+//! e.g. around a maintenance window.
+//!
+//! Every enqueued job is registered in the job registry, and jobs that are
+//! removed from the queue without being drawn are marked as cancelled.
+use std::collections::VecDeque;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{info, warn};
+
+use serde_derive::{Deserialize, Serialize};
+use svg2polylines::Polyline;
+use time::OffsetDateTime;
+
+use crate::fairqueue::FairQueue;
+use crate::jobs::{self, JobId, JobRegistry, JobState, SharedJobRegistry};
+use crate::placement::{CollisionPolicy, Placement};
+use crate::retry::WritePolicy;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum SchedulingPolicy {
+    #[default]
+    Preempt,
+    Weighted,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub(crate) struct SchedulingConfig {
+    #[serde(default)]
+    pub(crate) policy: SchedulingPolicy,
+    /// Number of interactive jobs drawn in a row (weighted policy only).
+    #[serde(default = "default_weight")]
+    interactive_weight: u32,
+    /// Number of scheduled jobs drawn in a row (weighted policy only).
+    #[serde(default = "default_weight")]
+    scheduled_weight: u32,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+impl Default for SchedulingConfig {
+    fn default() -> Self {
+        SchedulingConfig {
+            policy: SchedulingPolicy::default(),
+            interactive_weight: default_weight(),
+            scheduled_weight: default_weight(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Source {
+    Interactive,
+    Scheduled,
+}
+
+impl Source {
+    fn other(self) -> Source {
+        match self {
+            Source::Interactive => Source::Scheduled,
+            Source::Scheduled => Source::Interactive,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub(crate) struct Job {
+    /// Id of the job in the job registry, once it is enqueued.
+    #[serde(skip)]
+    pub(crate) id: Option<JobId>,
+    /// Label of the job, e.g. for the status.
+    pub(crate) label: String,
+    pub(crate) polylines: Vec<Polyline>,
+    /// Strokes wiped with the eraser before the polylines are drawn, e.g. to
+    /// clear a region for an appended drawing.
+    #[serde(default)]
+    pub(crate) eraser: Vec<Polyline>,
+    /// If the job could not be started before the deadline, it is dropped
+    /// instead of being drawn late.
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub(crate) deadline: Option<OffsetDateTime>,
+    /// If set, the job is held until then (and until the time limits allow
+    /// it) instead of being drawn right away.
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub(crate) start_at: Option<OffsetDateTime>,
+    /// Semantic identity of the content, used to skip duplicates.
+    #[serde(default)]
+    pub(crate) key: Option<String>,
+    /// Whether to write the blocks to the dry run file instead of drawing
+    /// the job.
+    #[serde(default)]
+    pub(crate) dry_run: bool,
+    /// Where to place the drawing on the board.
+    #[serde(default)]
+    pub(crate) placement: Placement,
+    /// What to do if an appended drawing overlaps existing content.
+    #[serde(default)]
+    pub(crate) on_collision: CollisionPolicy,
+    /// If set, the drawing is erased again after this duration.
+    #[serde(default, rename = "ttl_seconds", with = "seconds")]
+    pub(crate) ttl: Option<Duration>,
+    /// Paper to feed before drawing, in mm, instead of the default of the
+    /// paper roll.
+    #[serde(default)]
+    pub(crate) feed_mm: Option<u32>,
+    /// How long to keep the drawing on the board before the next job, in
+    /// seconds.
+    #[serde(default)]
+    pub(crate) hold_seconds: Option<u32>,
+    /// How serial writes that time out are retried, instead of the policy
+    /// of the config.
+    #[serde(default)]
+    pub(crate) write_policy: Option<WritePolicy>,
+}
+
+/// (De)serialize an optional duration as whole seconds.
+mod seconds {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        duration.map(|d| d.as_secs()).serialize(serializer)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        Ok(Option::<u64>::deserialize(deserializer)?.map(Duration::from_secs))
+    }
+}
+
+/// A job in a queue snapshot.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct QueuedJob {
+    /// The owner (tenant) of an interactive job, if any.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    owner: String,
+    #[serde(flatten)]
+    job: Job,
+}
+
+/// The jobs waiting in a queue, in the order in which they would be drawn
+/// (per source).
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct QueueSnapshot {
+    #[serde(default)]
+    interactive: Vec<QueuedJob>,
+    #[serde(default)]
+    scheduled: Vec<QueuedJob>,
+}
+
+impl QueueSnapshot {
+    pub(crate) fn len(&self) -> usize {
+        self.interactive.len() + self.scheduled.len()
+    }
+}
+
+impl Job {
+    pub(crate) fn new(label: String, polylines: Vec<Polyline>) -> Self {
+        Job {
+            id: None,
+            label,
+            polylines,
+            eraser: vec![],
+            deadline: None,
+            start_at: None,
+            key: None,
+            dry_run: false,
+            placement: Placement::Fixed,
+            on_collision: CollisionPolicy::Reject,
+            ttl: None,
+            feed_mm: None,
+            hold_seconds: None,
+            write_policy: None,
+        }
+    }
+
+    /// Return whether the job is held until a later time.
+    pub(crate) fn is_held(&self, now: OffsetDateTime) -> bool {
+        self.start_at.is_some_and(|start_at| now < start_at)
+    }
+
+    pub(crate) fn is_expired(&self, now: OffsetDateTime) -> bool {
+        self.deadline.is_some_and(|deadline| now > deadline)
+    }
+}
+
+pub(crate) type SharedJobQueue = Arc<Mutex<JobQueue>>;
+
+#[derive(Debug)]
+pub(crate) struct JobQueue {
+    config: SchedulingConfig,
+    /// Interactive jobs by owner (e.g. tenant), served in turns.
+    interactive: FairQueue<Job>,
+    scheduled: VecDeque<Job>,
+    /// Jobs (with their source and owner) held until their start time, or
+    /// deferred scheduled jobs that wait for the time limits.
+    held: Vec<(Source, String, Job)>,
+    /// The source served last, and how many jobs in a row.
+    current: Source,
+    served: u32,
+    /// Key of the last job that was started.
+    last_key: Option<String>,
+    /// File where the key of the last job is persisted.
+    state_file: Option<PathBuf>,
+    /// Where the enqueued jobs are registered.
+    registry: SharedJobRegistry,
+    /// The board the queue belongs to.
+    board: String,
+}
+
+impl JobQueue {
+    pub(crate) fn new_shared(
+        config: SchedulingConfig,
+        state_file: Option<PathBuf>,
+        registry: SharedJobRegistry,
+        board: &str,
+    ) -> SharedJobQueue {
+        let mut queue = JobQueue::new(config);
+        queue.registry = registry;
+        queue.board = board.to_string();
+        queue.last_key = state_file
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|key| key.trim().to_string())
+            .filter(|key| !key.is_empty());
+        queue.state_file = state_file;
+        Arc::new(Mutex::new(queue))
+    }
+
+    fn new(config: SchedulingConfig) -> Self {
+        JobQueue {
+            config,
+            interactive: FairQueue::default(),
+            scheduled: VecDeque::new(),
+            held: vec![],
+            current: Source::Interactive,
+            served: 0,
+            last_key: None,
+            state_file: None,
+            registry: JobRegistry::new_shared(),
+            board: String::new(),
+        }
+    }
+
+    /// Register the job as pending, unless it is registered already.
+    fn register(&self, owner: &str, job: &mut Job) {
+        if job.id.is_some() {
+            return;
+        }
+        match self.registry.lock() {
+            Ok(mut registry) => {
+                let owner = Some(owner).filter(|owner| !owner.is_empty());
+                job.id = Some(registry.register(&self.board, owner, &job.label));
+            }
+            Err(e) => warn!("Could not unlock job registry mutex: {}", e),
+        }
+    }
+
+    fn cancel<'a>(&self, jobs: impl Iterator<Item = &'a Job>) {
+        for job in jobs {
+            jobs::transition(&self.registry, job.id, JobState::Cancelled);
+        }
+    }
+
+    pub(crate) fn policy(&self) -> SchedulingPolicy {
+        self.config.policy
+    }
+
+    /// Enqueue an interactive job. Jobs with a later start time are held
+    /// until they are released.
+    pub(crate) fn push_interactive(&mut self, owner: &str, mut job: Job) {
+        self.register(owner, &mut job);
+        match job.is_held(OffsetDateTime::now_utc()) {
+            true => self
+                .held
+                .push((Source::Interactive, owner.to_string(), job)),
+            false => self.interactive.push(owner, job),
+        }
+    }
+
+    /// Hold a scheduled job that missed the time limits until they begin,
+    /// unless another one is held already. Return whether the job was held.
+    pub(crate) fn defer_scheduled(&mut self, mut job: Job) -> bool {
+        if self.has_deferred() {
+            return false;
+        }
+        self.register("", &mut job);
+        job.start_at = Some(OffsetDateTime::now_utc());
+        self.held.push((Source::Scheduled, String::new(), job));
+        true
+    }
+
+    /// Return whether a scheduled job is held, e.g. until the time limits
+    /// begin.
+    pub(crate) fn has_deferred(&self) -> bool {
+        self.held
+            .iter()
+            .any(|(source, _, _)| *source == Source::Scheduled)
+    }
+
+    /// Enqueue the held jobs whose start time has come, if the time limits
+    /// allow it. Return the number of released jobs.
+    pub(crate) fn release(&mut self, now: OffsetDateTime, within_limits: bool) -> usize {
+        if !within_limits {
+            return 0;
+        }
+        let (due, held) = std::mem::take(&mut self.held)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(_, _, job)| !job.is_held(now));
+        self.held = held;
+        let released = due.len();
+        for (source, owner, job) in due {
+            info!("Releasing job {}: Its start time has come", job.label);
+            match source {
+                Source::Interactive => self.interactive.push(&owner, job),
+                Source::Scheduled => self.scheduled.push_back(job),
+            }
+        }
+        released
+    }
+
+    /// Enqueue a scheduled job, unless it has the same key as the job before
+    /// it. Jobs with a later start time (e.g. because of a jitter) are held
+    /// until they are released. Return whether the job was enqueued.
+    pub(crate) fn push_scheduled(&mut self, mut job: Job) -> bool {
+        let previous_key = match self.scheduled.back() {
+            Some(previous) => previous.key.as_ref(),
+            None => self.last_key.as_ref(),
+        };
+        if job.key.is_some() && job.key.as_ref() == previous_key {
+            info!("Skipping job {}: Its content was just drawn", job.label);
+            return false;
+        }
+        self.register("", &mut job);
+        match job.is_held(OffsetDateTime::now_utc()) {
+            true => self.held.push((Source::Scheduled, String::new(), job)),
+            false => self.scheduled.push_back(job),
+        }
+        true
+    }
+
+    /// Drop the scheduled jobs, e.g. when the schedule is replaced.
+    pub(crate) fn clear_scheduled(&mut self) {
+        self.cancel(self.scheduled.iter());
+        self.scheduled.clear();
+        let (deferred, held) = std::mem::take(&mut self.held)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(source, _, _)| *source == Source::Scheduled);
+        self.cancel(deferred.iter().map(|(_, _, job)| job));
+        self.held = held;
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.interactive.len() + self.scheduled.len() + self.held.len()
+    }
+
+    /// The name of the board whose jobs are queued.
+    pub(crate) fn board(&self) -> &str {
+        &self.board
+    }
+
+    /// Return a copy of the waiting jobs. The copies aren't registered.
+    pub(crate) fn snapshot(&self) -> QueueSnapshot {
+        QueueSnapshot {
+            interactive: self
+                .interactive
+                .items()
+                .into_iter()
+                .chain(
+                    self.held
+                        .iter()
+                        .filter(|(source, _, _)| *source == Source::Interactive)
+                        .map(|(_, owner, job)| (owner.as_str(), job)),
+                )
+                .map(|(owner, job)| QueuedJob {
+                    owner: owner.to_string(),
+                    job: Job {
+                        id: None,
+                        ..job.clone()
+                    },
+                })
+                .collect(),
+            scheduled: self
+                .held
+                .iter()
+                .filter(|(source, _, _)| *source == Source::Scheduled)
+                .map(|(_, _, job)| job)
+                .chain(self.scheduled.iter())
+                .map(|job| QueuedJob {
+                    owner: String::new(),
+                    job: Job {
+                        id: None,
+                        ..job.clone()
+                    },
+                })
+                .collect(),
+        }
+    }
+
+    /// Remove the waiting jobs and return them. They are cancelled in the
+    /// registry, and registered again when they are imported.
+    pub(crate) fn drain(&mut self) -> QueueSnapshot {
+        let snapshot = self.snapshot();
+        self.cancel(self.interactive.items().into_iter().map(|(_, job)| job));
+        self.cancel(self.scheduled.iter());
+        self.cancel(self.held.iter().map(|(_, _, job)| job));
+        self.interactive = FairQueue::default();
+        self.held.clear();
+        self.scheduled.clear();
+        snapshot
+    }
+
+    /// Enqueue the jobs of a snapshot after the waiting jobs. Return the
+    /// number of jobs that were enqueued.
+    pub(crate) fn restore(&mut self, snapshot: QueueSnapshot) -> usize {
+        let mut restored = 0;
+        for queued in snapshot.interactive {
+            self.push_interactive(&queued.owner, queued.job);
+            restored += 1;
+        }
+        for queued in snapshot.scheduled {
+            if self.push_scheduled(queued.job) {
+                restored += 1;
+            }
+        }
+        restored
+    }
+
+    fn weight(&self, source: Source) -> u32 {
+        match source {
+            Source::Interactive => self.config.interactive_weight.max(1),
+            Source::Scheduled => self.config.scheduled_weight.max(1),
+        }
+    }
+
+    fn pop_from(&mut self, source: Source) -> Option<Job> {
+        match source {
+            Source::Interactive => self.interactive.pop().map(|(_, job)| job),
+            Source::Scheduled => self.scheduled.pop_front(),
+        }
+    }
+
+    fn set_last_key(&mut self, key: Option<String>) {
+        if key == self.last_key {
+            return;
+        }
+        if let Some(ref path) = self.state_file {
+            if let Err(e) = fs::write(path, key.as_deref().unwrap_or_default()) {
+                warn!("Could not write {}: {}", path.display(), e);
+            }
+        }
+        self.last_key = key;
+    }
+
+    /// Return the job that should be drawn next.
+    pub(crate) fn pop(&mut self) -> Option<Job> {
+        let first = match self.config.policy {
+            SchedulingPolicy::Preempt => Source::Interactive,
+            SchedulingPolicy::Weighted if self.served < self.weight(self.current) => self.current,
+            SchedulingPolicy::Weighted => self.current.other(),
+        };
+        for source in [first, first.other()] {
+            if let Some(job) = self.pop_from(source) {
+                if source == self.current {
+                    self.served += 1;
+                } else {
+                    self.current = source;
+                    self.served = 1;
+                }
+                self.set_last_key(job.key.clone());
+                return Some(job);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(label: &str) -> Job {
+        Job::new(label.to_string(), vec![])
+    }
+
+    fn keyed(label: &str, key: &str) -> Job {
+        Job {
+            key: Some(key.to_string()),
+            ..job(label)
+        }
+    }
+
+    fn labels(queue: &mut JobQueue) -> Vec<String> {
+        std::iter::from_fn(|| queue.pop().map(|job| job.label)).collect()
+    }
+
+    fn fill(queue: &mut JobQueue) {
+        for i in 1..=4 {
+            queue.push_interactive("", job(&format!("i{}", i)));
+            queue.push_scheduled(job(&format!("s{}", i)));
+        }
+    }
+
+    #[test]
+    fn test_deadline() {
+        let now = OffsetDateTime::UNIX_EPOCH;
+        let mut job = job("a");
+        assert!(!job.is_expired(now));
+        job.deadline = Some(now);
+        assert!(!job.is_expired(now));
+        assert!(job.is_expired(now + time::Duration::seconds(1)));
+    }
+
+    #[test]
+    fn test_preempt() {
+        let mut queue = JobQueue::new(SchedulingConfig::default());
+        fill(&mut queue);
+        assert_eq!(queue.len(), 8);
+        assert_eq!(
+            labels(&mut queue),
+            vec!["i1", "i2", "i3", "i4", "s1", "s2", "s3", "s4"]
+        );
+    }
+
+    #[test]
+    fn test_weighted() {
+        let config: SchedulingConfig =
+            serde_json::from_str(r#"{"policy": "weighted", "interactive_weight": 2}"#).unwrap();
+        let mut queue = JobQueue::new(config);
+        fill(&mut queue);
+        assert_eq!(
+            labels(&mut queue),
+            vec!["i1", "i2", "s1", "i3", "i4", "s2", "s3", "s4"]
+        );
+    }
+
+    #[test]
+    fn test_weighted_single_source() {
+        let config: SchedulingConfig = serde_json::from_str(r#"{"policy": "weighted"}"#).unwrap();
+        let mut queue = JobQueue::new(config);
+        queue.push_scheduled(job("s1"));
+        queue.push_scheduled(job("s2"));
+        assert_eq!(labels(&mut queue), vec!["s1", "s2"]);
+        queue.push_interactive("", job("i1"));
+        queue.push_scheduled(job("s3"));
+        assert_eq!(labels(&mut queue), vec!["i1", "s3"]);
+    }
+
+    #[test]
+    fn test_skip_duplicate_keys() {
+        let mut queue = JobQueue::new(SchedulingConfig::default());
+        assert!(queue.push_scheduled(keyed("a", "clock:12:05")));
+        assert!(!queue.push_scheduled(keyed("b", "clock:12:05")));
+        assert!(queue.push_scheduled(job("c")));
+        assert!(queue.push_scheduled(job("d")));
+        assert_eq!(labels(&mut queue), vec!["a", "c", "d"]);
+
+        // The last drawn key is remembered
+        assert!(queue.push_scheduled(keyed("e", "clock:12:10")));
+        assert_eq!(labels(&mut queue), vec!["e"]);
+        assert!(!queue.push_scheduled(keyed("f", "clock:12:10")));
+        assert!(queue.push_scheduled(keyed("g", "clock:12:15")));
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut queue = JobQueue::new(SchedulingConfig::default());
+        queue.push_interactive("alice", job("a1"));
+        queue.push_interactive("alice", job("a2"));
+        queue.push_interactive("bob", job("b1"));
+        queue.push_scheduled(Job {
+            ttl: Some(Duration::from_secs(60)),
+            ..job("s1")
+        });
+        let snapshot = queue.drain();
+        assert_eq!(queue.len(), 0);
+        assert_eq!(snapshot.len(), 4);
+
+        // Snapshots survive a round trip through JSON
+        let json = serde_json::to_string(&snapshot).unwrap();
+        assert!(json.contains(r#""ttl_seconds":60"#));
+        let snapshot: QueueSnapshot = serde_json::from_str(&json).unwrap();
+        queue.push_interactive("bob", job("b0"));
+        assert_eq!(queue.restore(snapshot), 4);
+        assert_eq!(labels(&mut queue), vec!["b0", "a1", "b1", "a2", "s1"]);
+    }
+
+    #[test]
+    fn test_held() {
+        let now = OffsetDateTime::now_utc();
+        let mut queue = JobQueue::new(SchedulingConfig::default());
+        queue.push_interactive(
+            "",
+            Job {
+                start_at: Some(now + Duration::from_secs(3600)),
+                ..job("later")
+            },
+        );
+        queue.push_interactive(
+            "",
+            Job {
+                start_at: Some(now - Duration::from_secs(60)),
+                ..job("past")
+            },
+        );
+        assert_eq!(queue.len(), 2);
+        assert_eq!(labels(&mut queue), vec!["past"]);
+
+        // Held jobs are only released at their time, within the time limits
+        let later = now + Duration::from_secs(7200);
+        assert_eq!(queue.release(now, true), 0);
+        assert_eq!(queue.release(later, false), 0);
+        assert_eq!(queue.snapshot().len(), 1);
+        assert_eq!(queue.release(later, true), 1);
+        assert_eq!(labels(&mut queue), vec!["later"]);
+        assert_eq!(queue.len(), 0);
+    }
+
+    #[test]
+    fn test_deferred() {
+        let mut queue = JobQueue::new(SchedulingConfig::default());
+        assert!(queue.defer_scheduled(job("s1")));
+        assert!(!queue.defer_scheduled(job("s2")));
+        assert!(queue.has_deferred());
+        assert_eq!(queue.pop().map(|job| job.label), None);
+
+        let now = OffsetDateTime::now_utc();
+        assert_eq!(queue.release(now, false), 0);
+        assert_eq!(queue.release(now, true), 1);
+        assert!(!queue.has_deferred());
+        assert_eq!(labels(&mut queue), vec!["s1"]);
+
+        // Jobs with a jitter are held as well
+        queue.push_scheduled(Job {
+            start_at: Some(now + Duration::from_secs(60)),
+            ..job("s2")
+        });
+        assert!(queue.has_deferred());
+        assert_eq!(queue.release(now, true), 0);
+        assert_eq!(queue.release(now + Duration::from_secs(60), true), 1);
+        assert_eq!(labels(&mut queue), vec!["s2"]);
+
+        // Deferred jobs are dropped with the schedule
+        queue.defer_scheduled(job("s3"));
+        queue.clear_scheduled();
+        assert_eq!(queue.len(), 0);
+    }
+
+    #[test]
+    fn test_registry() {
+        let registry = JobRegistry::new_shared();
+        let queue = JobQueue::new_shared(
+            SchedulingConfig::default(),
+            None,
+            registry.clone(),
+            "default",
+        );
+        let mut queue = queue.lock().unwrap();
+        queue.push_interactive("alice", job("a1"));
+        queue.push_scheduled(job("s1"));
+        queue.clear_scheduled();
+        let snapshot = queue.drain();
+        queue.restore(snapshot);
+        let job = queue.pop().unwrap();
+        assert_eq!(job.id, Some(3));
+
+        let registry = registry.lock().unwrap();
+        let states: Vec<(&str, &JobState)> = registry
+            .list()
+            .map(|job| (job.label.as_str(), &job.state))
+            .collect();
+        assert_eq!(
+            states,
+            vec![
+                ("a1", &JobState::Pending),
+                ("s1", &JobState::Cancelled),
+                ("a1", &JobState::Cancelled),
+            ]
+        );
+        assert_eq!(registry.get(3).unwrap().owner.as_deref(), Some("alice"));
+    }
+
+    #[test]
+    fn test_last_key_persisted() {
+        let path = std::env::temp_dir().join(format!("ibb-last-key-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let queue = JobQueue::new_shared(
+            SchedulingConfig::default(),
+            Some(path.clone()),
+            JobRegistry::new_shared(),
+            "default",
+        );
+        {
+            let mut queue = queue.lock().unwrap();
+            queue.push_scheduled(keyed("a", "countdown:2022-05-01"));
+            queue.pop();
+        }
+
+        // After a restart, the same content is still skipped
+        let queue = JobQueue::new_shared(
+            SchedulingConfig::default(),
+            Some(path.clone()),
+            JobRegistry::new_shared(),
+            "default",
+        );
+        assert!(!queue
+            .lock()
+            .unwrap()
+            .push_scheduled(keyed("b", "countdown:2022-05-01")));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,362 @@
+//! In-memory registry of the jobs of all boards.
+//!
+//! Every job that enters a job queue gets an id and goes through these
+//! states:
+//!
+//! ```text
+//! Pending → Encoding → Printing → Done
+//!    │          │          ├─────→ Failed
+//!    │          │          └─────→ Cancelled
+//!    │          ├────────────────→ Done (already drawn, dry run)
+//!    │          └────────────────→ Failed / Cancelled
+//!    └───────────────────────────→ Cancelled
+//! ```
+//!
+//! Other transitions are rejected, so that e.g. a cancelled job can't be
+//! reported as done later. Finished jobs are kept for a while, so that
+//! clients can look up what happened to their job.
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use log::{debug, error, warn};
+use serde_derive::Serialize;
+use time::OffsetDateTime;
+
+use crate::feed::{self, EventFeed, FeedEvent};
+
+/// Number of finished jobs kept in the registry.
+const FINISHED_JOBS: usize = 200;
+
+pub(crate) type JobId = u64;
+
+pub(crate) type SharedJobRegistry = Arc<Mutex<JobRegistry>>;
+
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub(crate) enum JobState {
+    /// Waiting in the job queue.
+    Pending,
+    /// Being converted into blocks for the robot.
+    Encoding,
+    /// The blocks are being sent to the robot.
+    Printing,
+    /// The robot acknowledged the last block.
+    Done,
+    Failed {
+        reason: String,
+    },
+    /// Removed from the queue, or stopped while printing.
+    Cancelled,
+}
+
+impl JobState {
+    pub(crate) fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            JobState::Done | JobState::Failed { .. } | JobState::Cancelled
+        )
+    }
+
+    /// Return whether a job in this state may move to the `next` state.
+    pub(crate) fn can_transition_to(&self, next: &JobState) -> bool {
+        use JobState::*;
+        matches!(
+            (self, next),
+            (Pending, Encoding)
+                | (Pending, Cancelled)
+                | (Encoding, Printing)
+                | (Encoding, Done)
+                | (Encoding, Failed { .. })
+                | (Encoding, Cancelled)
+                | (Printing, Done)
+                | (Printing, Failed { .. })
+                | (Printing, Cancelled)
+        )
+    }
+}
+
+impl fmt::Display for JobState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JobState::Pending => write!(f, "pending"),
+            JobState::Encoding => write!(f, "encoding"),
+            JobState::Printing => write!(f, "printing"),
+            JobState::Done => write!(f, "done"),
+            JobState::Failed { reason } => write!(f, "failed ({})", reason),
+            JobState::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+/// A job, as returned over HTTP.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub(crate) struct JobEntry {
+    pub(crate) id: JobId,
+    pub(crate) board: String,
+    /// The owner (tenant) of the job, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) owner: Option<String>,
+    pub(crate) label: String,
+    #[serde(flatten)]
+    pub(crate) state: JobState,
+    #[serde(with = "time::serde::rfc3339")]
+    pub(crate) created: OffsetDateTime,
+    /// When the job entered its current state.
+    #[serde(with = "time::serde::rfc3339")]
+    pub(crate) updated: OffsetDateTime,
+    /// How the job was sent to the robot, once it is done (or dropped).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) trace: Option<JobTrace>,
+}
+
+/// Serial transmission statistics of a job.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub(crate) struct JobTrace {
+    /// Number of blocks sent (including retries).
+    pub(crate) blocks: u32,
+    /// Number of bytes sent (including retries).
+    pub(crate) bytes: usize,
+    pub(crate) seconds: u64,
+    pub(crate) bytes_per_second: f64,
+    /// Number of times the robot stalled.
+    pub(crate) stalls: u32,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct JobRegistry {
+    /// The jobs, oldest first.
+    jobs: VecDeque<JobEntry>,
+    last_id: JobId,
+    /// Where changes of the jobs are published, if anywhere.
+    feed: Option<EventFeed>,
+}
+
+impl JobRegistry {
+    pub(crate) fn new_shared() -> SharedJobRegistry {
+        Arc::new(Mutex::new(JobRegistry::default()))
+    }
+
+    /// Create a registry that publishes every change of a job to the feed.
+    pub(crate) fn with_feed(feed: EventFeed) -> SharedJobRegistry {
+        Arc::new(Mutex::new(JobRegistry {
+            feed: Some(feed),
+            ..JobRegistry::default()
+        }))
+    }
+
+    /// Register a new pending job and return its id.
+    pub(crate) fn register(&mut self, board: &str, owner: Option<&str>, label: &str) -> JobId {
+        self.last_id += 1;
+        let now = OffsetDateTime::now_utc();
+        self.jobs.push_back(JobEntry {
+            id: self.last_id,
+            board: board.to_string(),
+            owner: owner.map(str::to_string),
+            label: label.to_string(),
+            state: JobState::Pending,
+            created: now,
+            updated: now,
+            trace: None,
+        });
+        if let (Some(feed), Some(job)) = (&self.feed, self.jobs.back()) {
+            feed::send(feed, || FeedEvent::job(job));
+        }
+        self.last_id
+    }
+
+    /// Move the job to the next state, if that is a valid transition.
+    pub(crate) fn transition(&mut self, id: JobId, next: JobState) -> Result<(), String> {
+        let job = self
+            .jobs
+            .iter_mut()
+            .find(|job| job.id == id)
+            .ok_or_else(|| format!("Unknown job: {}", id))?;
+        if !job.state.can_transition_to(&next) {
+            return Err(format!(
+                "Job {} can't go from {} to {}",
+                id, job.state, next
+            ));
+        }
+        debug!("Job {}: {} -> {}", id, job.state, next);
+        job.state = next;
+        job.updated = OffsetDateTime::now_utc();
+        if let Some(ref feed) = self.feed {
+            feed::send(feed, || FeedEvent::job(job));
+        }
+        if job.state.is_finished() {
+            self.prune();
+        }
+        Ok(())
+    }
+
+    /// Forget the oldest finished jobs beyond the retention limit.
+    fn prune(&mut self) {
+        let mut excess = self
+            .jobs
+            .iter()
+            .filter(|job| job.state.is_finished())
+            .count()
+            .saturating_sub(FINISHED_JOBS);
+        self.jobs.retain(|job| {
+            if excess > 0 && job.state.is_finished() {
+                excess -= 1;
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// Fail the jobs of the board that are being encoded or printed, e.g.
+    /// after the robot thread crashed. Return their number.
+    pub(crate) fn fail_active(&mut self, board: &str, reason: &str) -> usize {
+        let active: Vec<JobId> = self
+            .jobs
+            .iter()
+            .filter(|job| {
+                job.board == board && matches!(job.state, JobState::Encoding | JobState::Printing)
+            })
+            .map(|job| job.id)
+            .collect();
+        for &id in &active {
+            let failed = JobState::Failed {
+                reason: reason.to_string(),
+            };
+            if let Err(e) = self.transition(id, failed) {
+                warn!("{}", e);
+            }
+        }
+        active.len()
+    }
+
+    /// Store the transmission statistics of the job.
+    pub(crate) fn set_trace(&mut self, id: JobId, trace: JobTrace) {
+        if let Some(job) = self.jobs.iter_mut().find(|job| job.id == id) {
+            job.trace = Some(trace);
+        }
+    }
+
+    pub(crate) fn get(&self, id: JobId) -> Option<&JobEntry> {
+        self.jobs.iter().find(|job| job.id == id)
+    }
+
+    /// Return the jobs, newest first.
+    pub(crate) fn list(&self) -> impl Iterator<Item = &JobEntry> {
+        self.jobs.iter().rev()
+    }
+}
+
+/// Move the job (if registered) to the next state, logging invalid
+/// transitions.
+pub(crate) fn transition(registry: &SharedJobRegistry, id: Option<JobId>, next: JobState) {
+    let id = match id {
+        Some(id) => id,
+        None => return,
+    };
+    match registry.lock() {
+        Ok(mut registry) => {
+            if let Err(e) = registry.transition(id, next) {
+                warn!("{}", e);
+            }
+        }
+        Err(e) => error!("Could not unlock job registry mutex: {}", e),
+    }
+}
+
+/// Store the transmission statistics of the job (if registered) and move it
+/// to the next state, which publishes them along with the state.
+pub(crate) fn finish(
+    registry: &SharedJobRegistry,
+    id: Option<JobId>,
+    next: JobState,
+    trace: JobTrace,
+) {
+    let id = match id {
+        Some(id) => id,
+        None => return,
+    };
+    match registry.lock() {
+        Ok(mut registry) => {
+            registry.set_trace(id, trace);
+            if let Err(e) = registry.transition(id, next) {
+                warn!("{}", e);
+            }
+        }
+        Err(e) => error!("Could not unlock job registry mutex: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failed() -> JobState {
+        JobState::Failed {
+            reason: "Deadline passed".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_transitions() {
+        let mut registry = JobRegistry::default();
+        let id = registry.register("default", None, "Single print");
+        assert_eq!(id, 1);
+        assert_eq!(registry.get(id).unwrap().state, JobState::Pending);
+
+        // States can't be skipped
+        assert!(registry.transition(id, JobState::Printing).is_err());
+        assert!(registry.transition(id, JobState::Encoding).is_ok());
+        assert!(registry.transition(id, JobState::Printing).is_ok());
+        assert!(registry.transition(id, JobState::Done).is_ok());
+        assert_eq!(registry.get(id).unwrap().state, JobState::Done);
+
+        // Finished jobs stay finished
+        assert!(registry.transition(id, JobState::Cancelled).is_err());
+        assert!(registry.transition(id, failed()).is_err());
+        assert_eq!(registry.get(id).unwrap().state, JobState::Done);
+
+        let id = registry.register("default", Some("alice"), "Job of alice");
+        assert!(registry.transition(id, failed()).is_err());
+        assert!(registry.transition(id, JobState::Cancelled).is_ok());
+        assert!(registry.transition(42, JobState::Encoding).is_err());
+
+        let labels: Vec<&str> = registry.list().map(|job| job.label.as_str()).collect();
+        assert_eq!(labels, vec!["Job of alice", "Single print"]);
+    }
+
+    #[test]
+    fn test_fail_active() {
+        let mut registry = JobRegistry::default();
+        let pending = registry.register("default", None, "Pending");
+        let printing = registry.register("default", None, "Printing");
+        let other = registry.register("lobby", None, "Other board");
+        for id in [printing, other] {
+            registry.transition(id, JobState::Encoding).unwrap();
+        }
+        registry.transition(printing, JobState::Printing).unwrap();
+
+        assert_eq!(registry.fail_active("default", "Crashed"), 1);
+        assert_eq!(registry.get(pending).unwrap().state, JobState::Pending);
+        assert_eq!(
+            registry.get(printing).unwrap().state,
+            JobState::Failed {
+                reason: "Crashed".to_string()
+            }
+        );
+        assert_eq!(registry.get(other).unwrap().state, JobState::Encoding);
+    }
+
+    #[test]
+    fn test_prune() {
+        let mut registry = JobRegistry::default();
+        let pending = registry.register("default", None, "Pending");
+        for _ in 0..FINISHED_JOBS + 5 {
+            let id = registry.register("default", None, "Cancelled");
+            registry.transition(id, JobState::Cancelled).unwrap();
+        }
+        assert_eq!(registry.list().count(), FINISHED_JOBS + 1);
+        assert!(registry.get(pending).is_some());
+        assert!(registry.get(pending + 1).is_none());
+    }
+}
@@ -0,0 +1,156 @@
+//! Log levels per module.
+//!
+//! A filter like `robot=debug,actix_web=warn,info` sets the level of the
+//! listed modules (and their submodules) and, with a bare level, the default
+//! level. Modules of this crate can be named without the crate prefix, so
+//! `robot` is the same as `iboardbot_web::robot`.
+use std::str::FromStr;
+use std::sync::RwLock;
+
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+const CRATE_PREFIX: &str = "iboardbot_web::";
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct LogFilter {
+    default: LevelFilter,
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl LogFilter {
+    pub(crate) fn new(default: LevelFilter) -> Self {
+        LogFilter {
+            default,
+            modules: vec![],
+        }
+    }
+
+    /// Return the level of a log target. The most specific module wins.
+    fn level(&self, target: &str) -> LevelFilter {
+        let short = target.strip_prefix(CRATE_PREFIX);
+        let matches = |module: &str, target: &str| {
+            target == module
+                || target
+                    .strip_prefix(module)
+                    .is_some_and(|rest| rest.starts_with("::"))
+        };
+        self.modules
+            .iter()
+            .filter(|(module, _)| {
+                matches(module, target) || short.is_some_and(|short| matches(module, short))
+            })
+            .max_by_key(|(module, _)| module.len())
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    /// Return the most verbose level of any module.
+    fn max_level(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+impl FromStr for LogFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = LogFilter::new(LevelFilter::Info);
+        for directive in s.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let parse_level = |level: &str| {
+                LevelFilter::from_str(level.trim())
+                    .map_err(|_| format!("Invalid log level: {}", level.trim()))
+            };
+            match directive.split_once('=') {
+                Some((module, level)) if !module.trim().is_empty() => {
+                    filter
+                        .modules
+                        .push((module.trim().to_string(), parse_level(level)?));
+                }
+                Some(_) => return Err(format!("Missing module name: {}", directive)),
+                None => filter.default = parse_level(directive)?,
+            }
+        }
+        Ok(filter)
+    }
+}
+
+/// Passes the records that pass the filter on to another logger. The filter
+/// can be replaced later, e.g. once the config file has been read.
+pub(crate) struct FilteredLogger {
+    filter: RwLock<LogFilter>,
+    inner: Box<dyn Log>,
+}
+
+impl FilteredLogger {
+    /// Install a filtered logger as the global logger.
+    pub(crate) fn init(
+        filter: LogFilter,
+        inner: Box<dyn Log>,
+    ) -> Result<&'static FilteredLogger, SetLoggerError> {
+        let logger: &'static FilteredLogger = Box::leak(Box::new(FilteredLogger {
+            filter: RwLock::new(LogFilter::new(LevelFilter::Off)),
+            inner,
+        }));
+        log::set_logger(logger)?;
+        logger.set_filter(filter);
+        Ok(logger)
+    }
+
+    pub(crate) fn set_filter(&self, filter: LogFilter) {
+        log::set_max_level(filter.max_level());
+        if let Ok(mut current) = self.filter.write() {
+            *current = filter;
+        }
+    }
+}
+
+impl Log for FilteredLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter
+            .read()
+            .is_ok_and(|filter| metadata.level() <= filter.level(metadata.target()))
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let filter: LogFilter = "robot=debug, actix_web=warn,error".parse().unwrap();
+        assert_eq!(filter.default, LevelFilter::Error);
+        assert_eq!(filter.max_level(), LevelFilter::Debug);
+        assert_eq!("".parse(), Ok(LogFilter::new(LevelFilter::Info)));
+        assert!("robot=loud".parse::<LogFilter>().is_err());
+        assert!("=debug".parse::<LogFilter>().is_err());
+    }
+
+    #[test]
+    fn test_level() {
+        let filter: LogFilter = "robot=debug,actix_web=warn,actix_web::middleware=info"
+            .parse()
+            .unwrap();
+        assert_eq!(filter.level("iboardbot_web::robot"), LevelFilter::Debug);
+        assert_eq!(filter.level("iboardbot_web::robot::x"), LevelFilter::Debug);
+        assert_eq!(filter.level("iboardbot_web::robots"), LevelFilter::Info);
+        assert_eq!(filter.level("actix_web::server"), LevelFilter::Warn);
+        assert_eq!(
+            filter.level("actix_web::middleware::logger"),
+            LevelFilter::Info
+        );
+        assert_eq!(filter.level("iboardbot_web"), LevelFilter::Info);
+    }
+}
@@ -1,72 +1,352 @@
+mod agenda;
+mod alerts;
+mod analyze;
+mod archive;
+mod barcode;
+mod board;
+mod boards;
+mod capture;
+mod chart;
+mod console;
+mod countdown;
+mod detect;
+mod drawing;
+mod dryrun;
+mod dxf;
+mod errors;
+mod expiry;
+mod fairqueue;
+mod feed;
+mod firmware;
+mod flowcontrol;
+mod gcode;
+mod generate;
+mod hatch;
+mod heatmap;
+mod history;
+mod holidays;
+mod hpgl;
+mod importers;
+mod jobqueue;
+mod jobs;
+mod logging;
+mod merge;
+mod migrate;
+mod mock;
+mod notify;
+mod optimize;
+mod order;
+mod passes;
+mod pdf;
+mod pen;
+mod placement;
+mod png;
+mod preflight;
 mod printmode;
+mod public;
+mod retry;
 mod robot;
+mod roll;
+mod rotation;
+mod rules;
 mod scaling;
+mod schedules;
+mod servo;
+mod simplify;
+mod simulate;
+mod slowmode;
+mod speed;
+mod status;
+mod svg;
+mod template;
+mod tenants;
+#[cfg(test)]
+mod test_util;
+mod text;
 mod timelimits;
+mod voting;
+mod watchdog;
 
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::From;
 use std::ffi::OsStr;
 use std::fmt;
-use std::fs::{read_dir, DirEntry, File};
+use std::fs::{self, read_dir, DirEntry, File};
 use std::io::{self, Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process;
-use std::sync::mpsc::Sender;
-use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, Sender, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use actix_web::http::StatusCode;
+use actix_web::dev::{Service, ServiceRequest};
+use actix_web::http::{header, StatusCode};
 use actix_web::HttpServer;
-use actix_web::{get, post, web, App, HttpResponse, Responder, ResponseError};
+use actix_web::{
+    delete, get, post, put, web, App, HttpRequest, HttpResponse, Responder, ResponseError,
+};
 use docopt::Docopt;
-use log::{error, info};
+use log::{error, info, warn};
 use rust_embed::RustEmbed;
+use serde::de::DeserializeOwned;
 use serde_derive::{Deserialize, Serialize};
 use serial::BaudRate;
-use simplelog::{
-    ColorChoice, Config as LogConfig, LevelFilter, SimpleLogger, TermLogger, TerminalMode,
-};
+use simplelog::{ColorChoice, Config as LogConfig, LevelFilter, TermLogger, TerminalMode};
 use svg2polylines::Polyline;
+use time::OffsetDateTime;
 
+use crate::agenda::AgendaConfig;
+use crate::alerts::AlertConfig;
+use crate::analyze::Analysis;
+use crate::archive::ArchiveConfig;
+use crate::barcode::Barcode;
+use crate::boards::{BoardConfig, CanvasConfig};
+use crate::capture::CaptureConfig;
+use crate::chart::Chart;
+use crate::console::SerialConsole;
+use crate::countdown::CountdownConfig;
+use crate::detect::AUTO_DEVICE;
+use crate::drawing::{Content, Drawing, Generator};
+use crate::dryrun::DryRunConfig;
+use crate::errors::ErrorCode;
+use crate::feed::{EventFeed, Subscription, SubscriptionQuery};
+use crate::firmware::Firmware;
+use crate::generate::GeneratorConfig;
+use crate::hatch::HatchFill;
+use crate::history::{History, SharedHistory};
+use crate::holidays::{HolidayConfig, Holidays, SharedHolidays};
+use crate::importers::ImportOptions;
+use crate::jobqueue::{Job, JobQueue, QueueSnapshot, SchedulingConfig, SharedJobQueue};
+use crate::jobs::{JobEntry, JobId, JobRegistry, SharedJobRegistry};
+use crate::logging::{FilteredLogger, LogFilter};
+use crate::mock::MOCK_DEVICE;
+use crate::notify::{Event, SinkConfig};
+use crate::optimize::OptimizeConfig;
+use crate::order::DrawOrder;
+use crate::pen::PenProfile;
+use crate::placement::{CollisionPolicy, Placement};
+use crate::preflight::Violation;
 use crate::printmode::PrintMode;
-use crate::robot::PrintTask;
-use crate::scaling::{Bounds, Range};
-use crate::timelimits::TimeLimits;
+use crate::public::{PublicConfig, RateLimiter, SharedRateLimiter};
+use crate::retry::WritePolicy;
+use crate::robot::{NamedTask, PrintTask, Reporting, RobotConfig, MAX_HOLD_SECONDS, TCP_PREFIX};
+use crate::roll::{PaperRoll, RollConfig};
+use crate::rotation::{Rotation, Rotator};
+use crate::rules::Rule;
+use crate::scaling::{Align, Bounds, Orientation, Range};
+use crate::schedules::{NamedSchedule, Schedules, SharedNamedSchedules, SharedSchedules};
+use crate::servo::{PenPosition, PenServo};
+use crate::simulate::SimulationConfig;
+use crate::slowmode::SlowWindow;
+use crate::speed::Speed;
+use crate::status::{RobotStatus, SharedStatus, Status};
+use crate::template::{Counters, SharedCounters};
+use crate::tenants::{Quotas, SharedQuotas, SharedTenants, TenantConfig};
+use crate::timelimits::{SharedTimeLimits, TimeLimits};
+use crate::voting::{SharedVotingPool, VoteError, VotingConfig, VotingPool};
+use crate::watchdog::{SharedWatchdog, Warning, Watchdog, WatchdogConfig};
 
-type RobotQueue = Arc<Mutex<Sender<PrintTask>>>;
+type RobotQueue = Arc<Mutex<SyncSender<PrintTask>>>;
 
 // Suggested value from https://docs.rs/svg2polylines/0.7.0/svg2polylines/fn.parse.html
 const SVG2POLYLINES_TOLERANCE: f64 = 0.15;
 
+/// Maximum size of uploaded PDF files.
+const PDF_SIZE_LIMIT: usize = 16 * 1024 * 1024;
+
+/// Default number of jobs that may wait for the robot of a board.
+const DEFAULT_QUEUE_DEPTH: usize = 16;
+
+/// How long untimed animation frames are shown, in seconds.
+const DEFAULT_FRAME_SECONDS: u32 = 10;
+
+/// How long tiles are shown before the next one is drawn, in seconds.
+const DEFAULT_TILE_SECONDS: u32 = 60;
+
 /// The raw configuration obtained when parsing the config file.
 #[derive(Debug, Deserialize, Clone)]
 struct RawConfig {
+    name: Option<String>,
+    log_levels: Option<String>,
     listen: Option<String>,
     device: Option<String>,
+    boards: Option<Vec<BoardConfig>>,
+    canvases: Option<Vec<CanvasConfig>>,
     svg_dir: Option<String>,
     interval_seconds: Option<u64>,
+    jitter_seconds: Option<u64>,
     time_limits: Option<TimeLimits>,
+    slow_mode: Option<Vec<SlowWindow>>,
+    stall_timeout_seconds: Option<u64>,
+    offline_after_seconds: Option<u64>,
+    redraw_after_restart: Option<bool>,
+    queue_depth: Option<usize>,
+    incremental: Option<bool>,
+    orientation: Option<Orientation>,
+    pen_servo: Option<PenServo>,
+    state_dir: Option<String>,
+    pen: Option<PenProfile>,
+    speed: Option<Speed>,
+    draw_order: Option<DrawOrder>,
+    simplify_tolerance: Option<f64>,
+    merge_distance: Option<f64>,
+    hatch: Option<HatchFill>,
+    passes: Option<u32>,
+    pass_offset: Option<f64>,
+    min_point_spacing: Option<f64>,
+    margin_mm: Option<f64>,
+    regions: Option<BTreeMap<String, EraseRegion>>,
+    dry_run: Option<DryRunConfig>,
+    serial_capture: Option<CaptureConfig>,
+    serial_console: Option<bool>,
+    write_policy: Option<WritePolicy>,
+    paper_roll: Option<RollConfig>,
+    accepted_types: Option<Vec<String>>,
+    optimize_svg: Option<OptimizeConfig>,
+    simulation: Option<SimulationConfig>,
+    scheduling: Option<SchedulingConfig>,
+    agenda: Option<AgendaConfig>,
+    holidays: Option<HolidayConfig>,
+    countdown: Option<CountdownConfig>,
+    generators: Option<Vec<GeneratorConfig>>,
+    rotation: Option<Rotation>,
+    weights: Option<BTreeMap<String, u32>>,
+    named_schedules: Option<Vec<NamedSchedule>>,
+    notifications: Option<Vec<SinkConfig>>,
+    rules: Option<Vec<Rule>>,
+    alerts: Option<AlertConfig>,
+    watchdog: Option<WatchdogConfig>,
+    archive: Option<ArchiveConfig>,
+    tenants: Option<Vec<TenantConfig>>,
+    admin_token: Option<String>,
+    voting: Option<VotingConfig>,
+    preview: Option<PublicConfig>,
 }
 
 /// Note: This struct can be queried over HTTP,
 /// so be careful with sensitive data.
 #[derive(Debug, Serialize, Clone)]
 struct Config {
+    name: String,
     listen: String,
     device: String,
+    /// The boards. Without a `boards` key, this is a single board named
+    /// `default` with the `device`.
+    boards: Vec<BoardConfig>,
+    /// Pairs of boards that form one drawing area.
+    canvases: Vec<CanvasConfig>,
     svg_dir: String,
     interval_seconds: u64,
+    /// Random offset of the scheduled prints in headless mode.
+    jitter_seconds: u64,
     time_limits: Option<TimeLimits>,
+    /// When urgent jobs are drawn slowly outside of the time limits.
+    slow_mode: Vec<SlowWindow>,
+    stall_timeout_seconds: u64,
+    /// If reconnecting fails for this long, prints are refused.
+    offline_after_seconds: Option<u64>,
+    /// Whether to redraw the board after an unexpected restart.
+    redraw_after_restart: bool,
+    /// The number of jobs that may wait for the robot of a board.
+    queue_depth: usize,
+    incremental: bool,
+    orientation: Orientation,
+    pen_servo: PenServo,
+    state_dir: Option<String>,
+    pen: Option<PenProfile>,
+    /// The default drawing speed.
+    speed: Speed,
+    /// The default order of the polylines.
+    draw_order: DrawOrder,
+    /// The default tolerance for simplifying the polylines, in mm.
+    simplify_tolerance: Option<f64>,
+    /// Polylines whose ends are closer than this (in mm) are joined.
+    merge_distance: Option<f64>,
+    /// How closed shapes are filled, if at all.
+    hatch: Option<HatchFill>,
+    /// How often every polyline is drawn.
+    passes: Option<u32>,
+    /// The offset of the repeated passes, in mm.
+    pass_offset: Option<f64>,
+    /// Consecutive points closer than this (in mm) are dropped.
+    min_point_spacing: Option<f64>,
+    /// The margin along the edges of the board that is kept free, in mm,
+    /// e.g. where the frame of the board hides the strokes.
+    margin_mm: Option<f64>,
+    /// Named regions of the board, in board coordinates, which print
+    /// requests and named schedules can draw into.
+    regions: BTreeMap<String, EraseRegion>,
+    dry_run: Option<DryRunConfig>,
+    serial_capture: Option<CaptureConfig>,
+    /// Whether the serial traffic is mirrored to `/debug/serial/` (also
+    /// enabled by `--debug`).
+    serial_console: bool,
+    /// How serial writes that time out are retried.
+    write_policy: WritePolicy,
+    /// The paper budget of the paper roll variant.
+    paper_roll: Option<RollConfig>,
+    /// The content types accepted by `POST /jobs/`.
+    accepted_types: Vec<String>,
+    optimize_svg: Option<OptimizeConfig>,
+    simulation: SimulationConfig,
+    scheduling: SchedulingConfig,
+    agenda: Option<AgendaConfig>,
+    /// Days on which scheduled prints are suppressed.
+    holidays: Option<HolidayConfig>,
+    countdown: Option<CountdownConfig>,
+    generators: Vec<GeneratorConfig>,
+    /// The order of the items of the headless rotation.
+    rotation: Rotation,
+    /// Weights of the items for the weighted rotation, by item name.
+    weights: BTreeMap<String, u32>,
+    /// Schedules that run alongside the main schedule on the default board.
+    named_schedules: Vec<NamedSchedule>,
+    /// Not serialized, since sinks may contain credentials.
+    #[serde(skip)]
+    notifications: Vec<SinkConfig>,
+    #[serde(skip)]
+    rules: Vec<Rule>,
+    alerts: AlertConfig,
+    /// Thresholds for the resources of the host, if they are checked.
+    watchdog: Option<WatchdogConfig>,
+    /// Where completed jobs are archived, if anywhere.
+    archive: Option<ArchiveConfig>,
+    /// The tenant tokens are not serialized.
+    tenants: Vec<TenantConfig>,
+    /// The token that manages the tenants over HTTP. Not serialized.
+    #[serde(skip)]
+    admin_token: Option<String>,
+    /// If set, single prints for the default board are voted on.
+    voting: Option<VotingConfig>,
 }
 
 impl Config {
     fn from(config: &RawConfig) -> Option<Self> {
+        let name = match config.name {
+            Some(ref val) => val.clone(),
+            None => "iBoardBot".to_string(),
+        };
         let listen = match config.listen {
             Some(ref val) => val.clone(),
             None => "127.0.0.1:8080".to_string(),
         };
+        let boards = config.boards.clone().unwrap_or_default();
         let device = match config.device {
+            // With several boards, the first one is the default board
+            _ if !boards.is_empty() => {
+                if config.device.is_some() {
+                    info!("Note: Ignoring device key, since boards are configured");
+                }
+                boards[0].device.clone()
+            }
             Some(ref val) => val.clone(),
+            // In an otherwise complete config, the device is detected
+            None if config.svg_dir.is_some() && config.interval_seconds.is_some() => {
+                info!("Note: Config is missing device key, detecting the robot");
+                AUTO_DEVICE.to_string()
+            }
             None => {
                 info!("Note: Config is missing device key");
                 return None;
@@ -86,20 +366,136 @@ impl Config {
                 return None;
             }
         };
+        let jitter_seconds = config.jitter_seconds.unwrap_or(0);
+        let boards = match boards.is_empty() {
+            true => vec![BoardConfig {
+                name: "default".to_string(),
+                device: device.clone(),
+            }],
+            false => boards,
+        };
+        let canvases = config.canvases.clone().unwrap_or_default();
         let time_limits = config.time_limits;
+        let slow_mode = config.slow_mode.clone().unwrap_or_default();
+        let stall_timeout_seconds = config.stall_timeout_seconds.unwrap_or(600);
+        let offline_after_seconds = config.offline_after_seconds;
+        let redraw_after_restart = config.redraw_after_restart.unwrap_or(false);
+        let queue_depth = config.queue_depth.unwrap_or(DEFAULT_QUEUE_DEPTH);
+        let incremental = config.incremental.unwrap_or(false);
+        let orientation = config.orientation.unwrap_or_default();
+        let pen_servo = config.pen_servo.unwrap_or_default();
+        let state_dir = config.state_dir.clone();
+        let pen = config.pen;
+        let speed = config.speed.unwrap_or_default();
+        let draw_order = config.draw_order.unwrap_or_default();
+        let simplify_tolerance = config.simplify_tolerance;
+        let merge_distance = config.merge_distance;
+        let hatch = config.hatch;
+        let passes = config.passes;
+        let pass_offset = config.pass_offset;
+        let min_point_spacing = config.min_point_spacing;
+        let margin_mm = config.margin_mm;
+        let regions = config.regions.clone().unwrap_or_default();
+        let dry_run = config.dry_run.clone();
+        let serial_capture = config.serial_capture.clone();
+        let serial_console = config.serial_console.unwrap_or(false);
+        let write_policy = config.write_policy.unwrap_or_default();
+        let paper_roll = config.paper_roll;
+        let accepted_types = config
+            .accepted_types
+            .clone()
+            .unwrap_or_else(importers::content_types);
+        let optimize_svg = config.optimize_svg;
+        let simulation = config.simulation.unwrap_or_default();
+        let scheduling = config.scheduling.unwrap_or_default();
+        let agenda = config.agenda.clone();
+        let holidays = config.holidays.clone();
+        let countdown = config.countdown.clone();
+        let generators = config.generators.clone().unwrap_or_default();
+        let rotation = config.rotation.unwrap_or_default();
+        let weights = config.weights.clone().unwrap_or_default();
+        let named_schedules = config.named_schedules.clone().unwrap_or_default();
+        let notifications = config.notifications.clone().unwrap_or_default();
+        let rules = config.rules.clone().unwrap_or_default();
+        let alerts = config.alerts.unwrap_or_default();
+        let watchdog = config.watchdog.clone();
+        let archive = config.archive.clone();
+        let tenants = config.tenants.clone().unwrap_or_default();
+        let admin_token = config.admin_token.clone();
+        let voting = config.voting;
         Some(Self {
+            name,
             listen,
             device,
+            boards,
+            canvases,
             svg_dir,
             interval_seconds,
+            jitter_seconds,
             time_limits,
+            slow_mode,
+            stall_timeout_seconds,
+            offline_after_seconds,
+            redraw_after_restart,
+            queue_depth,
+            incremental,
+            orientation,
+            pen_servo,
+            state_dir,
+            pen,
+            speed,
+            draw_order,
+            simplify_tolerance,
+            merge_distance,
+            hatch,
+            passes,
+            pass_offset,
+            min_point_spacing,
+            margin_mm,
+            regions,
+            dry_run,
+            serial_capture,
+            serial_console,
+            write_policy,
+            paper_roll,
+            accepted_types,
+            optimize_svg,
+            simulation,
+            scheduling,
+            agenda,
+            holidays,
+            countdown,
+            generators,
+            rotation,
+            weights,
+            named_schedules,
+            notifications,
+            rules,
+            alerts,
+            watchdog,
+            archive,
+            tenants,
+            admin_token,
+            voting,
         })
     }
+
+    /// The margin along the edges of the board in mm, 5 mm by default.
+    fn margin(&self) -> f64 {
+        self.margin_mm.unwrap_or(scaling::DEFAULT_MARGIN_MM)
+    }
+
+    /// Whether several tenants share the boards, which are configured or
+    /// managed with the admin token.
+    fn multi_tenant(&self) -> bool {
+        !self.tenants.is_empty() || self.admin_token.is_some()
+    }
 }
 
 #[derive(Debug, Clone)]
 struct PreviewConfig {
     listen: String,
+    public: PublicConfig,
 }
 
 impl PreviewConfig {
@@ -109,16 +505,54 @@ impl PreviewConfig {
                 .listen
                 .clone()
                 .unwrap_or_else(|| "listen".to_string()),
+            public: config.preview.clone().unwrap_or_default(),
         }
     }
 }
 
+/// A board with its robot thread.
+#[derive(Debug, Clone)]
+struct BoardState {
+    name: String,
+    robot_queue: RobotQueue,
+    /// The number of jobs that may wait for the robot.
+    queue_depth: usize,
+    /// The jobs waiting for the robot.
+    jobs: SharedJobQueue,
+    history: SharedHistory,
+    status: SharedStatus,
+    /// The current (possibly not yet saved) pen servo positions.
+    servo: Arc<Mutex<PenServo>>,
+    /// Where the serial traffic is mirrored to, if enabled.
+    console: Option<SerialConsole>,
+}
+
 /// Application state.
 /// Every worker will have its own copy.
 #[derive(Debug, Clone)]
 struct State {
     config: Config,
-    robot_queue: RobotQueue,
+    /// The path of the config file, where calibrated values are stored.
+    config_file: String,
+    /// The boards, the first one being the default board.
+    boards: Vec<BoardState>,
+    /// The tenants, which may be managed at runtime.
+    tenants: SharedTenants,
+    quotas: SharedQuotas,
+    counters: SharedCounters,
+    /// The jobs of all boards.
+    registry: SharedJobRegistry,
+    /// The events for the clients of `/events/`.
+    feed: EventFeed,
+    schedules: SharedSchedules,
+    /// The named schedules that are running on the default board.
+    named_schedules: SharedNamedSchedules,
+    time_limits: SharedTimeLimits,
+    holidays: SharedHolidays,
+    /// The entries that are voted on, if voting is enabled.
+    voting: Option<SharedVotingPool>,
+    /// The resources of the host.
+    watchdog: SharedWatchdog,
 }
 
 #[derive(Debug)]
@@ -136,6 +570,18 @@ impl From<io::Error> for HeadlessError {
     }
 }
 
+impl HeadlessError {
+    fn code(&self) -> ErrorCode {
+        match self {
+            HeadlessError::NoFiles => ErrorCode::NoSvgFiles,
+            HeadlessError::Io(_) => ErrorCode::Io,
+            HeadlessError::SvgParse(_) => ErrorCode::InvalidSvg,
+            HeadlessError::PolylineScale(_) => ErrorCode::ScalingFailed,
+            HeadlessError::Queue(_) => ErrorCode::RobotUnreachable,
+        }
+    }
+}
+
 impl fmt::Display for HeadlessError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -154,11 +600,13 @@ const USAGE: &str = "
 iBoardBot Web: Cloudless drawing fun.
 
 Usage:
-    iboardbot-web [-h] [-v] [-c <configfile>] [--headless] [--debug]
+    iboardbot-web [-h] [-v] [-c <configfile>] [--headless] [--debug] [--log <filter>]
+    iboardbot-web replay <capture> <device> [--debug] [--log <filter>]
 
 Example:
 
     iboardbot-web -c config.json
+    iboardbot-web replay serial-20261016-091502-dev_ttyACM0.log /dev/ttyACM0
 
 Options:
     -h --help        Show this screen.
@@ -166,13 +614,18 @@ Options:
     -c <configfile>  Path to config file [default: config.json].
     --headless       Headless mode (start drawing immediately)
     --debug          Log debug logs
+    --log <filter>   Log levels per module, e.g. robot=debug,actix_web=warn
 ";
 
 #[derive(Debug, Deserialize)]
 struct Args {
+    cmd_replay: bool,
+    arg_capture: String,
+    arg_device: String,
     flag_c: String,
     flag_headless: bool,
     flag_debug: bool,
+    flag_log: Option<String>,
     flag_version: bool,
 }
 
@@ -201,288 +654,3330 @@ async fn config_handler(data: web::Data<State>) -> String {
         .to_string()
 }
 
-/// Return a list of SVG files from the SVG dir.
-fn get_svg_files(dir: &str) -> Result<Vec<String>, io::Error> {
-    let mut svg_files = read_dir(dir)
-        // The `read_dir` function returns an iterator over results.
-        // If any iterator entry fails, fail the whole iterator.
-        .and_then(|iter| iter.collect::<Result<Vec<DirEntry>, io::Error>>())
-        // Filter directory entries
-        .map(|entries| {
-            entries
-                .iter()
-                // Get filepath for entry
-                .map(|entry| entry.path())
-                // We only want files
-                .filter(|path| path.is_file())
-                // Map to filename
-                .filter_map(|ref path| {
-                    path.file_name()
-                        .map(OsStr::to_os_string)
-                        .and_then(|oss| oss.into_string().ok())
-                })
-                // We only want .svg files
-                .filter(|filename| filename.ends_with(".svg"))
-                // Collect vector of strings
-                .collect::<Vec<String>>()
-        })?;
-    svg_files.sort();
-    Ok(svg_files)
+/// Return the history of completed jobs, with simulated-vs-actual durations.
+#[get("/history/")]
+async fn history_handler(
+    data: web::Data<State>,
+    query: web::Query<BoardQuery>,
+) -> JsonResult<HttpResponse> {
+    let board = find_board(&data, query.board.as_deref())?;
+    let history = board.history.lock().map_err(|e| {
+        JsonError::ServerError(ErrorDetails::from(format!(
+            "Could not access history: {}",
+            e
+        )))
+    })?;
+    Ok(HttpResponse::Ok().json(history.report()))
 }
 
-#[get("/list/")]
-async fn list_handler(data: web::Data<State>) -> Result<web::Json<Vec<String>>, JsonError> {
-    let svg_files = get_svg_files(&data.config.svg_dir).map_err(|_e| {
-        JsonError::ServerError(ErrorDetails::from("Could not read files in SVG directory"))
+/// Return the pen-down travel per area of the board.
+#[get("/stats/heatmap/")]
+async fn heatmap_handler(
+    data: web::Data<State>,
+    query: web::Query<BoardQuery>,
+) -> JsonResult<HttpResponse> {
+    let board = find_board(&data, query.board.as_deref())?;
+    let history = board.history.lock().map_err(|e| {
+        JsonError::ServerError(ErrorDetails::from(format!(
+            "Could not access history: {}",
+            e
+        )))
     })?;
-    Ok(web::Json(svg_files))
+    Ok(HttpResponse::Ok().json(history.heatmap.report()))
 }
 
-#[derive(Deserialize, Debug)]
-struct PreviewRequest {
-    svg: String,
+/// Return the file where an imported schedule is persisted, if any.
+fn schedules_file(config: &Config) -> Option<PathBuf> {
+    config
+        .state_dir
+        .as_ref()
+        .map(|dir| Path::new(dir).join("schedules"))
 }
 
-#[derive(Deserialize, Debug)]
-struct PrintRequest {
-    svg: String,
-    offset_x: f64,
-    offset_y: f64,
-    scale_x: f64,
-    scale_y: f64,
-    mode: PrintMode,
+/// Return the file where the named schedules are persisted, if any.
+fn named_schedules_file(config: &Config) -> Option<PathBuf> {
+    config
+        .state_dir
+        .as_ref()
+        .map(|dir| Path::new(dir).join("named_schedules"))
 }
 
-#[derive(Serialize, Debug)]
-struct ErrorDetails {
-    details: String,
+/// The schedule belongs to the whole board, so it can't be managed by
+/// tenants.
+fn check_schedules_access(data: &State) -> JsonResult<()> {
+    if !data.config.multi_tenant() {
+        Ok(())
+    } else {
+        Err(JsonError::Unauthorized(ErrorDetails::from(
+            "The schedule can't be managed in multi-tenant mode".to_string(),
+        )))
+    }
 }
 
-impl ErrorDetails {
-    fn from<S: Into<String>>(details: S) -> Self {
-        ErrorDetails {
-            details: details.into(),
+/// Export the current schedule.
+#[get("/schedules/")]
+async fn schedules_handler(data: web::Data<State>) -> JsonResult<HttpResponse> {
+    check_schedules_access(&data)?;
+    let schedules = data.schedules.lock().map_err(|e| {
+        JsonError::ServerError(ErrorDetails::from(format!(
+            "Could not access schedule: {}",
+            e
+        )))
+    })?;
+    Ok(HttpResponse::Ok().json(&*schedules))
+}
+
+/// Import a schedule, replacing the current schedule and time limits.
+#[put("/schedules/")]
+async fn schedules_import_handler(
+    data: web::Data<State>,
+    schedules: web::Json<Schedules>,
+) -> Result<HttpResponse, JsonError> {
+    check_schedules_access(&data)?;
+    let schedules = schedules.into_inner();
+    schedules
+        .validate()
+        .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+    info!(
+        "Importing schedule with {} item(s) every {} seconds",
+        schedules.len(),
+        schedules.interval_seconds
+    );
+
+    // Update the time limits before the new schedule starts
+    match data.time_limits.lock() {
+        Ok(mut time_limits) => *time_limits = schedules.time_limits,
+        Err(e) => {
+            return Err(JsonError::ServerError(ErrorDetails::from(format!(
+                "Could not access time limits: {}",
+                e
+            ))))
         }
     }
+    let board = &data.boards[0];
+    start_schedules(
+        &board.robot_queue,
+        &board.status,
+        &data.counters,
+        &data.config,
+        &schedules,
+        None,
+    )
+    .map_err(|e| JsonError::ClientError(ErrorDetails::from(e.to_string()).with_code(e.code())))?;
+    if let Some(path) = schedules_file(&data.config) {
+        schedules::save(&path, &schedules);
+    }
+
+    match data.schedules.lock() {
+        Ok(mut current) => *current = schedules,
+        Err(e) => error!("Could not access schedule: {}", e),
+    }
+    Ok(HttpResponse::new(StatusCode::NO_CONTENT))
 }
 
-#[derive(Debug)]
-enum JsonError {
-    ServerError(ErrorDetails),
-    ClientError(ErrorDetails),
+/// When the next item of the schedule is drawn, as returned by
+/// `/schedule/next/`.
+#[derive(Serialize, Debug)]
+struct NextRunResponse {
+    #[serde(with = "time::serde::rfc3339")]
+    time: OffsetDateTime,
+    item: Option<String>,
+    /// Cycles before that are skipped because of the time limits.
+    skipped_cycles: u32,
+    paused: bool,
 }
 
-impl fmt::Display for JsonError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let val = serde_json::to_value(match self {
-            JsonError::ServerError(details) => details,
-            JsonError::ClientError(details) => details,
-        });
-        write!(f, "{}", val.expect("Could not serialize error details"))
-    }
+/// Return when the next item of the schedule of the default board is drawn,
+/// taking the time limits into account.
+#[get("/schedule/next/")]
+async fn schedule_next_handler(data: web::Data<State>) -> JsonResult<HttpResponse> {
+    check_schedules_access(&data)?;
+    let status = data.boards[0].status.lock().map_err(|e| {
+        JsonError::ServerError(ErrorDetails::from(format!(
+            "Could not access status: {}",
+            e
+        )))
+    })?;
+    let (item, next, interval) = status
+        .next()
+        .ok_or_else(|| JsonError::NotFound(ErrorDetails::from("No schedule is running")))?;
+    let offset = OffsetDateTime::now_local()
+        .unwrap_or_else(|_| OffsetDateTime::now_utc())
+        .offset();
+    let time_limits = timelimits::current(&data.time_limits);
+    let (time, skipped_cycles) = schedules::next_run(
+        next.to_offset(offset),
+        interval,
+        time_limits.as_ref(),
+        &data.holidays,
+        status.skip_cycles(),
+    )
+    .ok_or_else(|| {
+        JsonError::NotFound(ErrorDetails::from(
+            "No cycle of the schedule lies within the time limits",
+        ))
+    })?;
+    Ok(HttpResponse::Ok().json(NextRunResponse {
+        time,
+        item: item.map(str::to_string),
+        skipped_cycles,
+        paused: status.schedule_paused(),
+    }))
 }
-impl std::error::Error for JsonError {}
-impl ResponseError for JsonError {
-    fn error_response(&self) -> HttpResponse {
-        let mut builder = match self {
-            JsonError::ServerError(_) => HttpResponse::InternalServerError(),
-            JsonError::ClientError(_) => HttpResponse::BadRequest(),
-        };
-        builder
-            .content_type("application/json")
-            .body(self.to_string())
+
+/// Pause or resume the schedule of the default board. While it is paused,
+/// its cycles are skipped, but the playlist is kept.
+fn pause_schedule(data: &State, paused: bool) -> JsonResult<HttpResponse> {
+    check_schedules_access(data)?;
+    let board = &data.boards[0];
+    match paused {
+        true => info!("Pausing the schedule"),
+        false => info!("Resuming the schedule"),
     }
+    status::update(&board.status, |status| status.set_schedule_paused(paused));
+    Ok(HttpResponse::new(StatusCode::NO_CONTENT))
 }
 
-type JsonResult<T> = Result<T, JsonError>;
+#[post("/schedule/pause/")]
+async fn schedule_pause_handler(data: web::Data<State>) -> JsonResult<HttpResponse> {
+    pause_schedule(&data, true)
+}
 
-#[post("/preview/")]
-async fn preview_handler(req: web::Json<PreviewRequest>) -> JsonResult<web::Json<Vec<Polyline>>> {
-    match svg2polylines::parse(&req.svg, SVG2POLYLINES_TOLERANCE) {
-        Ok(polylines) => Ok(web::Json(polylines)),
-        Err(errmsg) => Err(JsonError::ClientError(ErrorDetails::from(errmsg))),
+#[post("/schedule/resume/")]
+async fn schedule_resume_handler(data: web::Data<State>) -> JsonResult<HttpResponse> {
+    pause_schedule(&data, false)
+}
+
+/// Skip the next cycle of the schedule of the default board (or the one
+/// after the cycles that are skipped already).
+#[post("/schedule/skip/")]
+async fn schedule_skip_handler(data: web::Data<State>) -> JsonResult<HttpResponse> {
+    check_schedules_access(&data)?;
+    let mut skip_cycles = 0;
+    status::update(&data.boards[0].status, |status| {
+        status.skip_cycle();
+        skip_cycles = status.skip_cycles();
+    });
+    info!("Skipping the next {} cycle(s) of the schedule", skip_cycles);
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "skip_cycles": skip_cycles })))
+}
+
+/// Lock the named schedules.
+fn lock_named_schedules(data: &State) -> JsonResult<MutexGuard<'_, Vec<NamedSchedule>>> {
+    data.named_schedules.lock().map_err(|e| {
+        JsonError::ServerError(ErrorDetails::from(format!(
+            "Could not access named schedules: {}",
+            e
+        )))
+    })
+}
+
+/// No named schedule with that name is running.
+fn named_schedule_not_found(name: &str) -> JsonError {
+    JsonError::NotFound(ErrorDetails::from(format!("No schedule named {}", name)))
+}
+
+/// Persist the named schedules after they were edited.
+fn save_named_schedules(data: &State, named: &[NamedSchedule]) {
+    if let Some(path) = named_schedules_file(&data.config) {
+        schedules::save(&path, &named);
     }
 }
 
-#[post("/print/")]
-async fn print_handler(
+/// List the named schedules.
+#[get("/schedule/named/")]
+async fn named_schedules_handler(data: web::Data<State>) -> JsonResult<HttpResponse> {
+    check_schedules_access(&data)?;
+    let named = lock_named_schedules(&data)?;
+    Ok(HttpResponse::Ok().json(&*named))
+}
+
+/// Return a named schedule.
+#[get("/schedule/named/{name}/")]
+async fn named_schedule_handler(
     data: web::Data<State>,
-    print_request: web::Json<PrintRequest>,
-) -> Result<HttpResponse, JsonError> {
-    // Parse SVG into list of polylines
-    info!("Requested print mode: {:?}", print_request.mode);
-    let mut polylines = match svg2polylines::parse(&print_request.svg, SVG2POLYLINES_TOLERANCE) {
-        Ok(polylines) => polylines,
-        Err(e) => return Err(JsonError::ClientError(ErrorDetails::from(e))),
-    };
+    name: web::Path<String>,
+) -> JsonResult<HttpResponse> {
+    check_schedules_access(&data)?;
+    let named = lock_named_schedules(&data)?;
+    let schedule = named
+        .iter()
+        .find(|schedule| schedule.name == *name)
+        .ok_or_else(|| named_schedule_not_found(&name))?;
+    Ok(HttpResponse::Ok().json(schedule))
+}
 
-    // Scale polylines
-    scaling::scale_polylines(
-        &mut polylines,
-        (print_request.offset_x, print_request.offset_y),
-        (print_request.scale_x, print_request.scale_y),
-    );
+/// Create a named schedule, or replace the schedule of that name (e.g. to
+/// change its interval, files or time limits).
+#[put("/schedule/named/{name}/")]
+async fn named_schedule_put_handler(
+    data: web::Data<State>,
+    name: web::Path<String>,
+    schedule: web::Json<NamedSchedule>,
+) -> JsonResult<HttpResponse> {
+    check_schedules_access(&data)?;
+    let schedule = NamedSchedule {
+        name: name.into_inner(),
+        ..schedule.into_inner()
+    };
+    let mut named = lock_named_schedules(&data)?;
+    let mut updated = named.clone();
+    let created = match updated.iter_mut().find(|s| s.name == schedule.name) {
+        Some(existing) => {
+            *existing = schedule.clone();
+            false
+        }
+        None => {
+            updated.push(schedule.clone());
+            true
+        }
+    };
+    schedules::validate_named(&updated, &data.config.regions)
+        .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+    match created {
+        true => info!("Creating schedule {}", schedule.name),
+        false => info!("Replacing schedule {}", schedule.name),
+    }
+    start_named_schedule(
+        &data.boards[0].robot_queue,
+        &data.counters,
+        &data.config,
+        &schedule,
+    )
+    .map_err(|e| JsonError::ClientError(ErrorDetails::from(e.to_string()).with_code(e.code())))?;
+    save_named_schedules(&data, &updated);
+    *named = updated;
+    Ok(match created {
+        true => HttpResponse::Created().json(&schedule),
+        false => HttpResponse::Ok().json(&schedule),
+    })
+}
 
-    // Get access to queue
-    let tx = data.robot_queue.lock().map_err(|e| {
-        JsonError::ClientError(ErrorDetails::from(format!(
+/// Stop and remove a named schedule.
+#[delete("/schedule/named/{name}/")]
+async fn named_schedule_delete_handler(
+    data: web::Data<State>,
+    name: web::Path<String>,
+) -> JsonResult<HttpResponse> {
+    check_schedules_access(&data)?;
+    let name = name.into_inner();
+    let mut named = lock_named_schedules(&data)?;
+    let index = named
+        .iter()
+        .position(|schedule| schedule.name == name)
+        .ok_or_else(|| named_schedule_not_found(&name))?;
+    info!("Removing schedule {}", name);
+    let tx = data.boards[0].robot_queue.lock().map_err(|e| {
+        JsonError::ServerError(ErrorDetails::from(format!(
             "Could not communicate with robot thread: {}",
             e
         )))
     })?;
-    let task = print_request.mode.to_print_task(polylines);
-    tx.send(task).map_err(|e| {
+    tx.send(PrintTask::StopNamed(name)).map_err(|e| {
         JsonError::ServerError(ErrorDetails::from(format!(
-            "Could not send print request to robot thread: {}",
+            "Could not send task to robot thread: {}",
             e
         )))
     })?;
-
-    info!("Printing...");
+    named.remove(index);
+    save_named_schedules(&data, &named);
     Ok(HttpResponse::new(StatusCode::NO_CONTENT))
 }
 
-fn headless_start(robot_queue: RobotQueue, config: &Config) -> Result<(), HeadlessError> {
-    // Get SVG files to be printed
-    let svg_files = get_svg_files(&config.svg_dir)?;
-    if svg_files.is_empty() {
-        return Err(HeadlessError::NoFiles);
-    }
+#[derive(Deserialize, Debug)]
+struct QueueExportQuery {
+    board: Option<String>,
+    /// Whether to remove the exported jobs from the queue.
+    #[serde(default)]
+    drain: bool,
+}
 
-    // Read SVG files
-    let mut svgs = vec![];
-    let base_path = Path::new(&config.svg_dir);
-    for file in svg_files {
-        let mut svg = String::new();
-        let mut f = File::open(base_path.join(&file))?;
-        f.read_to_string(&mut svg)?;
-        svgs.push(svg);
-    }
+/// Lock the job queue of the board.
+fn lock_jobs(board: &BoardState) -> JsonResult<MutexGuard<'_, JobQueue>> {
+    board.jobs.lock().map_err(|e| {
+        JsonError::ServerError(ErrorDetails::from(format!(
+            "Could not access job queue: {}",
+            e
+        )))
+    })
+}
 
-    // Specify target area bounds
-    let mut bounds = Bounds {
-        x: Range {
-            min: 0.0,
-            max: f64::from(robot::IBB_WIDTH),
-        },
-        y: Range {
-            min: 0.0,
-            max: f64::from(robot::IBB_HEIGHT),
-        },
+/// Export the jobs waiting for the robot, and optionally remove them from
+/// the queue (e.g. before a maintenance window).
+#[get("/queue/export/")]
+async fn queue_export_handler(
+    data: web::Data<State>,
+    query: web::Query<QueueExportQuery>,
+) -> JsonResult<HttpResponse> {
+    check_queue_access(&data)?;
+    let board = find_board(&data, query.board.as_deref())?;
+    let mut jobs = lock_jobs(board)?;
+    let snapshot = match query.drain {
+        true => jobs.drain(),
+        false => jobs.snapshot(),
     };
-    bounds.add_padding(5.0);
+    if query.drain {
+        info!("Drained {} job(s) from the queue", snapshot.len());
+    }
+    Ok(HttpResponse::Ok().json(snapshot))
+}
 
-    // Parse SVG strings into lists of polylines
-    let polylines_set: Vec<Vec<Polyline>> = svgs
-        .iter()
-        .map(|ref svg| {
-            svg2polylines::parse(svg, SVG2POLYLINES_TOLERANCE)
-                .map_err(|e| HeadlessError::SvgParse(e))
-                .and_then(|mut polylines| {
-                    scaling::fit_polylines(&mut polylines, &bounds)
-                        .map_err(|e| HeadlessError::PolylineScale(e))?;
-                    Ok(polylines)
-                })
-        })
-        .collect::<Result<Vec<_>, HeadlessError>>()?;
+/// Enqueue previously exported jobs after the jobs that are waiting.
+#[post("/queue/import/")]
+async fn queue_import_handler(
+    data: web::Data<State>,
+    query: web::Query<BoardQuery>,
+    snapshot: web::Json<QueueSnapshot>,
+) -> JsonResult<HttpResponse> {
+    check_queue_access(&data)?;
+    let board = find_board(&data, query.board.as_deref())?;
+    let restored = lock_jobs(board)?.restore(snapshot.into_inner());
+    info!("Imported {} job(s) into the queue", restored);
+    Ok(HttpResponse::new(StatusCode::NO_CONTENT))
+}
 
-    // Get access to queue
-    let tx = robot_queue.lock().map_err(|e| {
-        HeadlessError::Queue(format!("Could not communicate with robot thread: {}", e))
-    })?;
+/// Lock the job registry.
+fn lock_registry(data: &State) -> JsonResult<MutexGuard<'_, JobRegistry>> {
+    data.registry.lock().map_err(|e| {
+        JsonError::ServerError(ErrorDetails::from(format!(
+            "Could not access job registry: {}",
+            e
+        )))
+    })
+}
 
-    // Create print task
-    let interval_duration = Duration::from_secs(config.interval_seconds);
-    let task = PrintTask::Scheduled(interval_duration, polylines_set);
+/// Return whether the sender of the request may see the job. Tenants only
+/// see their own jobs.
+fn can_see_job(tenant: Option<&TenantConfig>, job: &JobEntry) -> bool {
+    tenant.is_none_or(|tenant| job.owner.as_ref() == Some(&tenant.name))
+}
 
-    // Send task to robot
-    tx.send(task).map_err(|e| {
-        HeadlessError::Queue(format!(
-            "Could not send print request to robot thread: {}",
-            e
-        ))
+/// Return the jobs of the board (newest first) with their states.
+#[get("/jobs/")]
+async fn jobs_handler(
+    data: web::Data<State>,
+    req: HttpRequest,
+    query: web::Query<BoardQuery>,
+) -> JsonResult<HttpResponse> {
+    let tenant = authorize(&data, &req)?;
+    let board = find_board(&data, query.board.as_deref())?;
+    let registry = lock_registry(&data)?;
+    let jobs: Vec<&JobEntry> = registry
+        .list()
+        .filter(|job| job.board == board.name && can_see_job(tenant.as_ref(), job))
+        .collect();
+    Ok(HttpResponse::Ok().json(jobs))
+}
+
+/// Print an uploaded file of any accepted format, chosen by its content
+/// type.
+#[post("/jobs/")]
+async fn submit_job_handler(
+    req: HttpRequest,
+    data: web::Data<State>,
+    body: web::Bytes,
+) -> JsonResult<HttpResponse> {
+    let query: JobSubmitQuery = parse_query(&req)?;
+    let content_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let importer = importers::find(content_type, &data.config.accepted_types).ok_or_else(|| {
+        JsonError::UnsupportedMediaType(ErrorDetails::from(format!(
+            "Unsupported content type {:?} (accepted: {})",
+            content_type,
+            data.config.accepted_types.join(", ")
+        )))
     })?;
+    info!(
+        "Requested {} job with print mode: {:?}",
+        importer.name, query.mode
+    );
+    let polylines = importer
+        .import(&body, &query.import_options(data.config.margin()))
+        .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
 
-    info!("Printing...");
-    Ok(())
-}
+    submit(
+        &data,
+        &req,
+        &query.mode,
+        Drawing::Static(polylines),
+        query.options,
+    )?;
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    // Parse args
-    let args: Args = Docopt::new(USAGE)
-        .and_then(|d| d.deserialize())
-        .unwrap_or_else(|e| e.exit());
+    info!("Printing {} job...", importer.name);
+    Ok(HttpResponse::new(StatusCode::NO_CONTENT))
+}
 
-    // Show version and exit
-    if args.flag_version {
-        println!("{} v{}", NAME, VERSION);
-        process::exit(0);
+/// Return the state of a job.
+#[get("/jobs/{id}/")]
+async fn job_handler(
+    data: web::Data<State>,
+    req: HttpRequest,
+    id: web::Path<JobId>,
+) -> JsonResult<HttpResponse> {
+    let tenant = authorize(&data, &req)?;
+    let registry = lock_registry(&data)?;
+    match registry
+        .get(*id)
+        .filter(|job| can_see_job(tenant.as_ref(), job))
+    {
+        Some(job) => Ok(HttpResponse::Ok().json(job)),
+        None => Err(JsonError::NotFound(ErrorDetails::from(format!(
+            "Unknown job: {}",
+            id
+        )))),
     }
+}
 
-    // Init logger
-    let log_level = if args.flag_debug {
-        LevelFilter::Debug
+/// The queue holds the jobs of all tenants, so it can't be managed by
+/// tenants.
+fn check_queue_access(data: &State) -> JsonResult<()> {
+    if !data.config.multi_tenant() {
+        Ok(())
     } else {
-        LevelFilter::Info
-    };
-    if let Err(_) = TermLogger::init(
-        log_level,
-        LogConfig::default(),
-        TerminalMode::Mixed,
-        ColorChoice::Auto,
-    ) {
-        eprintln!("Could not initialize TermLogger. Falling back to SimpleLogger.");
-        SimpleLogger::init(log_level, LogConfig::default())
-            .expect("Could not initialize SimpleLogger");
+        Err(JsonError::Unauthorized(ErrorDetails::from(
+            "The queue can't be managed in multi-tenant mode".to_string(),
+        )))
     }
+}
 
-    // Headless mode
-    let headless_mode: bool = args.flag_headless;
+/// Return a compact status for companion displays.
+#[get("/kiosk.json")]
+async fn kiosk_handler(
+    data: web::Data<State>,
+    query: web::Query<BoardQuery>,
+) -> JsonResult<HttpResponse> {
+    let board = find_board(&data, query.board.as_deref())?;
+    let status = board.status.lock().map_err(|e| {
+        JsonError::ServerError(ErrorDetails::from(format!(
+            "Could not access status: {}",
+            e
+        )))
+    })?;
+    Ok(HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, "public, max-age=15"))
+        .json(status.kiosk(&kiosk_name(&data.config, board))))
+}
+
+/// Mirror the serial traffic of the board to a WebSocket client, if the
+/// serial console is enabled.
+#[get("/debug/serial/")]
+async fn serial_console_handler(
+    req: HttpRequest,
+    body: web::Payload,
+    data: web::Data<State>,
+    query: web::Query<BoardQuery>,
+) -> JsonResult<HttpResponse> {
+    if data.config.multi_tenant() {
+        return Err(JsonError::Unauthorized(ErrorDetails::from(
+            "The serial console isn't available in multi-tenant mode",
+        )));
+    }
+    let board = find_board(&data, query.board.as_deref())?;
+    let console = board.console.clone().ok_or_else(|| {
+        JsonError::NotFound(ErrorDetails::from(
+            "The serial console is disabled (enable it with --debug or serial_console)",
+        ))
+    })?;
+    let (response, session, messages) = actix_ws::handle(&req, body)
+        .map_err(|e| JsonError::ClientError(ErrorDetails::from(e.to_string())))?;
+    actix_web::rt::spawn(console::stream(console, session, messages));
+    Ok(response)
+}
+
+/// Stream the events matching the subscription of the client over a
+/// WebSocket.
+#[get("/events/")]
+async fn event_feed_handler(
+    req: HttpRequest,
+    body: web::Payload,
+    data: web::Data<State>,
+    query: web::Query<SubscriptionQuery>,
+) -> JsonResult<HttpResponse> {
+    if data.config.multi_tenant() {
+        return Err(JsonError::Unauthorized(ErrorDetails::from(
+            "The event feed isn't available in multi-tenant mode",
+        )));
+    }
+    let subscription = Subscription::try_from(&*query)
+        .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+    let (response, session, messages) = actix_ws::handle(&req, body)
+        .map_err(|e| JsonError::ClientError(ErrorDetails::from(e.to_string())))?;
+    actix_web::rt::spawn(feed::stream(
+        data.feed.clone(),
+        subscription,
+        session,
+        messages,
+    ));
+    Ok(response)
+}
+
+/// Stream the events matching the subscription of the client as
+/// newline-delimited JSON.
+#[get("/events/ndjson")]
+async fn event_ndjson_handler(
+    data: web::Data<State>,
+    query: web::Query<SubscriptionQuery>,
+) -> JsonResult<HttpResponse> {
+    if data.config.multi_tenant() {
+        return Err(JsonError::Unauthorized(ErrorDetails::from(
+            "The event feed isn't available in multi-tenant mode",
+        )));
+    }
+    let subscription = Subscription::try_from(&*query)
+        .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(feed::ndjson(&data.feed, subscription)))
+}
+
+/// Return what the robot is currently doing.
+#[get("/robot/status/")]
+async fn robot_status_handler(
+    data: web::Data<State>,
+    query: web::Query<BoardQuery>,
+) -> JsonResult<HttpResponse> {
+    let board = find_board(&data, query.board.as_deref())?;
+    let status = board.status.lock().map_err(|e| {
+        JsonError::ServerError(ErrorDetails::from(format!(
+            "Could not access status: {}",
+            e
+        )))
+    })?;
+    Ok(HttpResponse::Ok().json(RobotStatusResponse {
+        status: status.robot(&board.name),
+        warnings: data.watchdog.warnings(),
+    }))
+}
+
+/// The state of the robot, together with the warnings of the watchdog.
+#[derive(Serialize)]
+struct RobotStatusResponse<'a> {
+    #[serde(flatten)]
+    status: RobotStatus<'a>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<Warning>,
+}
+
+/// Return the latest measurements of the watchdog.
+#[get("/watchdog/")]
+async fn watchdog_handler(data: web::Data<State>) -> JsonResult<HttpResponse> {
+    if data.config.watchdog.is_none() {
+        return Err(JsonError::NotFound(ErrorDetails::from(
+            "The watchdog is disabled (enable it with a watchdog section in the config)",
+        )));
+    }
+    Ok(HttpResponse::Ok().json(data.watchdog.report()))
+}
+
+/// A region of the board to erase, in board coordinates.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct EraseRegion {
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+}
+
+impl EraseRegion {
+    fn bounds(self) -> Result<Bounds, String> {
+        if !(self.width > 0.0 && self.height > 0.0) {
+            return Err("The region to erase must have a positive width and height".to_string());
+        }
+        Ok(Bounds {
+            x: Range {
+                min: self.x,
+                max: self.x + self.width,
+            },
+            y: Range {
+                min: self.y,
+                max: self.y + self.height,
+            },
+        })
+    }
+}
+
+/// A region of the board: The name of a region in the config, or its
+/// extent.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum Region {
+    Named(String),
+    Extent(EraseRegion),
+}
+
+impl Region {
+    pub(crate) fn bounds(&self, regions: &BTreeMap<String, EraseRegion>) -> Result<Bounds, String> {
+        match self {
+            Region::Named(name) => regions
+                .get(name)
+                .ok_or_else(|| format!("Unknown region: {}", name))?
+                .bounds(),
+            Region::Extent(region) => region.bounds(),
+        }
+    }
+}
+
+/// Check that the named regions have a name and lie on the board.
+fn validate_regions(regions: &BTreeMap<String, EraseRegion>) -> Result<(), String> {
+    for (name, region) in regions {
+        if name.is_empty() {
+            return Err("Regions must have a name".to_string());
+        }
+        region
+            .bounds()
+            .and_then(|bounds| {
+                bounds
+                    .intersection(&Bounds::board())
+                    .ok_or_else(|| "The region is outside of the board".to_string())
+            })
+            .map_err(|e| format!("{}: {}", name, e))?;
+    }
+    Ok(())
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct EraseRequest {
+    /// The region to erase, by name or extent (the whole board if unset).
+    region: Option<Region>,
+    /// The board or canvas to erase (the default board if unset).
+    board: Option<String>,
+}
+
+/// Erase the board, or a region of it, before the next job is started.
+///
+/// An empty request body erases the whole default board.
+#[post("/erase/")]
+async fn erase_handler(data: web::Data<State>, body: web::Bytes) -> JsonResult<HttpResponse> {
+    if data.config.multi_tenant() {
+        return Err(JsonError::Unauthorized(ErrorDetails::from(
+            "The board can't be erased in multi-tenant mode",
+        )));
+    }
+    let request: EraseRequest = match body.is_empty() {
+        true => EraseRequest::default(),
+        false => serde_json::from_slice(&body)
+            .map_err(|e| JsonError::ClientError(ErrorDetails::from(e.to_string())))?,
+    };
+    let region = request
+        .region
+        .map(|region| region.bounds(&data.config.regions))
+        .transpose()
+        .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+    let canvas = data
+        .config
+        .canvases
+        .iter()
+        .find(|canvas| request.board.as_ref() == Some(&canvas.name));
+    if let Some(canvas) = canvas {
+        for (i, name) in canvas.boards.iter().enumerate() {
+            let board = find_board(&data, Some(name))?;
+            match region {
+                Some(ref region) => {
+                    if let Some(part) = canvas.layout.board_region(i, region) {
+                        send_task(board, PrintTask::Erase(Some(part)))?;
+                    }
+                }
+                None => send_task(board, PrintTask::Erase(None))?,
+            }
+        }
+    } else {
+        let board = find_board(&data, request.board.as_deref())?;
+        let region = match region {
+            Some(region) => Some(region.intersection(&Bounds::board()).ok_or_else(|| {
+                JsonError::ClientError(ErrorDetails::from("The region is outside of the board"))
+            })?),
+            None => None,
+        };
+        send_task(board, PrintTask::Erase(region))?;
+    }
+    info!("Erasing...");
+    Ok(HttpResponse::new(StatusCode::NO_CONTENT))
+}
+
+#[derive(Deserialize, Debug)]
+struct FeedRequest {
+    /// How far to advance the paper.
+    mm: u32,
+    /// The board whose paper is fed (the default board if unset).
+    board: Option<String>,
+}
+
+/// Advance the paper roll of a board, e.g. to tear off a drawing. The paper
+/// is fed before the next job starts.
+#[post("/robot/feed/")]
+async fn feed_handler(
+    data: web::Data<State>,
+    request: web::Json<FeedRequest>,
+) -> JsonResult<HttpResponse> {
+    check_roll_access(&data)?;
+    if request.mm == 0 {
+        return Err(JsonError::ClientError(ErrorDetails::from(
+            "The feed length must be positive",
+        )));
+    }
+    let board = find_board(&data, request.board.as_deref())?;
+    send_task(board, PrintTask::Feed(request.mm))?;
+    info!("Feeding {} mm of paper", request.mm);
+    Ok(HttpResponse::new(StatusCode::NO_CONTENT))
+}
+
+/// The paper roll belongs to the whole board, so it can't be managed by
+/// tenants. Boards without a paper roll can't feed paper.
+fn check_roll_access(data: &State) -> JsonResult<()> {
+    if data.config.multi_tenant() {
+        return Err(JsonError::Unauthorized(ErrorDetails::from(
+            "The paper roll can't be managed in multi-tenant mode",
+        )));
+    }
+    if data.config.paper_roll.is_none() {
+        return Err(JsonError::ClientError(ErrorDetails::from(
+            "The paper roll requires a paper_roll section in the config",
+        )));
+    }
+    Ok(())
+}
+
+/// Reset the paper budget of the board after a new paper roll was inserted.
+#[post("/robot/roll/replace/")]
+async fn replace_roll_handler(
+    data: web::Data<State>,
+    query: web::Query<BoardQuery>,
+) -> JsonResult<HttpResponse> {
+    check_roll_access(&data)?;
+    let board = find_board(&data, query.board.as_deref())?;
+    send_task(board, PrintTask::ReplaceRoll)?;
+    Ok(HttpResponse::new(StatusCode::NO_CONTENT))
+}
+
+#[derive(Deserialize, Debug)]
+struct CalibratePenRequest {
+    /// The servo position to calibrate.
+    position: PenPosition,
+    /// The new servo position (the current one if unset).
+    value: Option<u16>,
+    /// Change of the servo position.
+    #[serde(default)]
+    step: i16,
+    /// Whether to store the calibrated positions in the config file.
+    #[serde(default)]
+    save: bool,
+    /// The board to calibrate (the default board if unset).
+    board: Option<String>,
+}
+
+/// Move the pen servo of a board to a new position, so that the position can
+/// be checked, and optionally store the calibrated positions in the config.
+/// Return the current positions.
+#[post("/robot/calibrate-pen/")]
+async fn calibrate_pen_handler(
+    data: web::Data<State>,
+    request: web::Json<CalibratePenRequest>,
+) -> JsonResult<HttpResponse> {
+    if data.config.multi_tenant() {
+        return Err(JsonError::Unauthorized(ErrorDetails::from(
+            "The pen can't be calibrated in multi-tenant mode",
+        )));
+    }
+    let board = find_board(&data, request.board.as_deref())?;
+    let mut servo = board.servo.lock().map_err(|e| {
+        JsonError::ServerError(ErrorDetails::from(format!(
+            "Could not access pen servo: {}",
+            e
+        )))
+    })?;
+    let value = servo
+        .adjust(request.position, request.value, request.step)
+        .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+    info!("Calibrating pen servo ({:?}: {})", request.position, value);
+    send_task(board, PrintTask::CalibratePen(*servo, request.position))?;
+    if request.save {
+        save_pen_servo(&data.config_file, &servo).map_err(|e| {
+            JsonError::ServerError(ErrorDetails::from(format!(
+                "Could not save config file: {}",
+                e
+            )))
+        })?;
+        info!("Saved pen servo positions to {}", data.config_file);
+    }
+    Ok(HttpResponse::Ok().json(*servo))
+}
+
+/// Store the pen servo positions in the config file.
+fn save_pen_servo(config_file: &str, servo: &PenServo) -> io::Result<()> {
+    let mut config: serde_json::Value = serde_json::from_reader(File::open(config_file)?)?;
+    let object = config
+        .as_object_mut()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Not a JSON object"))?;
+    object.insert("pen_servo".to_string(), serde_json::to_value(servo)?);
+    let json = serde_json::to_string_pretty(&config).map_err(io::Error::from)?;
+    fs::write(config_file, json + "\n")
+}
+
+/// Return the name shown on companion displays: The configured name, and
+/// the board name if there are several boards.
+fn kiosk_name(config: &Config, board: &BoardState) -> String {
+    match config.boards.len() {
+        1 => config.name.clone(),
+        _ => format!("{} ({})", config.name, board.name),
+    }
+}
+
+/// Return a list of SVG files from the SVG dir.
+fn get_svg_files(dir: &str) -> Result<Vec<String>, io::Error> {
+    let mut svg_files = read_dir(dir)
+        // The `read_dir` function returns an iterator over results.
+        // If any iterator entry fails, fail the whole iterator.
+        .and_then(|iter| iter.collect::<Result<Vec<DirEntry>, io::Error>>())
+        // Filter directory entries
+        .map(|entries| {
+            entries
+                .iter()
+                // Get filepath for entry
+                .map(|entry| entry.path())
+                // We only want files
+                .filter(|path| path.is_file())
+                // Map to filename
+                .filter_map(|ref path| {
+                    path.file_name()
+                        .map(OsStr::to_os_string)
+                        .and_then(|oss| oss.into_string().ok())
+                })
+                // We only want .svg files
+                .filter(|filename| filename.ends_with(".svg"))
+                // Collect vector of strings
+                .collect::<Vec<String>>()
+        })?;
+    svg_files.sort();
+    Ok(svg_files)
+}
+
+/// Return the SVG directory of the sender of the request. Tenants only see
+/// their own library.
+fn library_dir(data: &State, req: &HttpRequest) -> JsonResult<PathBuf> {
+    let svg_dir = Path::new(&data.config.svg_dir);
+    Ok(match authorize(data, req)? {
+        Some(tenant) => svg_dir.join(tenant.library()),
+        None => svg_dir.to_path_buf(),
+    })
+}
+
+/// Return whether the name is a plain file name, without any directories.
+fn is_plain_file_name(name: &str) -> bool {
+    Path::new(name).file_name().and_then(OsStr::to_str) == Some(name)
+}
+
+/// Store an SVG file in the library of the sender of the request. If
+/// configured, the file is optimized first.
+#[post("/library/{name}")]
+async fn library_upload_handler(
+    req: HttpRequest,
+    data: web::Data<State>,
+    name: web::Path<String>,
+    svg: String,
+) -> Result<HttpResponse, JsonError> {
+    let name = name.into_inner();
+    if !is_plain_file_name(&name) || !name.ends_with(".svg") {
+        return Err(JsonError::ClientError(ErrorDetails::from(format!(
+            "Invalid file name: {}",
+            name
+        ))));
+    }
+    let original_size = svg.len();
+    let svg = match data.config.optimize_svg {
+        Some(ref config) => optimize::optimize(&svg, config, SVG2POLYLINES_TOLERANCE),
+        None => svg::parse(&svg, SVG2POLYLINES_TOLERANCE).map(|_| svg),
+    }
+    .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+
+    if data.watchdog.writes_paused() {
+        return Err(JsonError::Unavailable(
+            ErrorDetails::from("The disk is nearly full, uploads are paused")
+                .with_code(ErrorCode::DiskFull),
+        ));
+    }
+    let dir = library_dir(&data, &req)?;
+    fs::create_dir_all(&dir)
+        .and_then(|_| fs::write(dir.join(&name), &svg))
+        .map_err(|e| {
+            JsonError::ServerError(ErrorDetails::from(format!(
+                "Could not store {}: {}",
+                name, e
+            )))
+        })?;
+    info!(
+        "Stored {} in the library ({} bytes, uploaded {} bytes)",
+        name,
+        svg.len(),
+        original_size
+    );
+    Ok(HttpResponse::new(StatusCode::NO_CONTENT))
+}
+
+#[get("/list/")]
+async fn list_handler(
+    req: HttpRequest,
+    data: web::Data<State>,
+) -> Result<web::Json<Vec<String>>, JsonError> {
+    let dir = library_dir(&data, &req)?;
+    let svg_files = get_svg_files(&dir.to_string_lossy()).map_err(|_e| {
+        JsonError::ServerError(ErrorDetails::from("Could not read files in SVG directory"))
+    })?;
+    Ok(web::Json(svg_files))
+}
+
+#[derive(Deserialize, Debug)]
+struct PreviewRequest {
+    svg: String,
+    /// The layers to draw (all if empty).
+    #[serde(default)]
+    layers: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AnalyzeRequest {
+    svg: String,
+    /// The layers to draw (all if empty).
+    #[serde(default)]
+    layers: Vec<String>,
+    #[serde(default)]
+    offset_x: f64,
+    #[serde(default)]
+    offset_y: f64,
+    #[serde(default = "default_scale")]
+    scale_x: f64,
+    #[serde(default = "default_scale")]
+    scale_y: f64,
+    /// Overrides the drawing speed of the config.
+    speed: Option<Speed>,
+    /// Overrides the draw order of the config.
+    draw_order: Option<DrawOrder>,
+}
+
+/// Options of a single print job, shared by the print requests.
+#[derive(Deserialize, Debug, Clone, Default)]
+struct JobOptions {
+    /// Overrides the pen profile of the config.
+    pen: Option<PenProfile>,
+    /// Overrides the drawing speed of the config.
+    speed: Option<Speed>,
+    /// Overrides the draw order of the config.
+    draw_order: Option<DrawOrder>,
+    /// Overrides the simplification tolerance of the config, in mm.
+    simplify_tolerance: Option<f64>,
+    /// Overrides the merge distance of the config, in mm.
+    merge_distance: Option<f64>,
+    /// Overrides the hatch fill of the config.
+    hatch: Option<HatchFill>,
+    /// Overrides the number of passes of the config.
+    passes: Option<u32>,
+    /// Overrides the offset of the passes of the config, in mm.
+    pass_offset: Option<f64>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    deadline: Option<OffsetDateTime>,
+    /// Write the blocks to the dry run file instead of drawing them.
+    #[serde(default)]
+    dry_run: bool,
+    /// Where to place the drawing on the board.
+    #[serde(default)]
+    placement: Placement,
+    /// What to do if an appended drawing overlaps existing content.
+    #[serde(default)]
+    on_collision: CollisionPolicy,
+    /// Erase the drawing again after this many seconds.
+    ttl_seconds: Option<u64>,
+    /// Paper to feed before drawing, instead of the default of the paper
+    /// roll.
+    feed_mm: Option<u32>,
+    /// Keep the drawing on the board for this many seconds before the next
+    /// job is drawn.
+    hold_seconds: Option<u32>,
+    /// Overrides how serial writes that time out are retried.
+    write_policy: Option<WritePolicy>,
+    /// Draw the job in slow mode if it is submitted outside of the time
+    /// limits.
+    #[serde(default)]
+    urgent: bool,
+    /// The board to draw on (the default board if unset).
+    board: Option<String>,
+    /// Strokes wiped with the eraser before drawing, in board coordinates
+    /// (taken from the eraser layers of the SVG).
+    #[serde(skip)]
+    eraser: Vec<Polyline>,
+    /// A region erased right before drawing, instead of the whole board.
+    erase_region: Option<Region>,
+}
+
+/// Selects a board (the default board if unset).
+#[derive(Deserialize, Debug)]
+struct BoardQuery {
+    board: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PrintRequest {
+    svg: String,
+    /// The layers to draw (all if empty).
+    #[serde(default)]
+    layers: Vec<String>,
+    /// The layers to wipe with the eraser before drawing the other layers.
+    #[serde(default)]
+    eraser_layers: Vec<String>,
+    #[serde(default)]
+    offset_x: f64,
+    #[serde(default)]
+    offset_y: f64,
+    #[serde(default = "default_scale")]
+    scale_x: f64,
+    #[serde(default = "default_scale")]
+    scale_y: f64,
+    /// Draw the drawing at the physical size given by the SVG (times the
+    /// scale), instead of taking its user units as mm.
+    #[serde(default)]
+    native_size: bool,
+    /// Fit the drawing to the board instead of scaling it, keeping its
+    /// aspect ratio.
+    #[serde(default)]
+    fit: bool,
+    /// The margin around a fitted drawing in mm, the configured margin by
+    /// default.
+    #[serde(default)]
+    padding: Option<f64>,
+    /// Fit the drawing into a region of the board (by name or in board
+    /// coordinates) instead of the whole board. With a fixed placement,
+    /// only this region is erased before drawing.
+    region: Option<Region>,
+    /// Rotate the drawing clockwise by this many degrees around its center,
+    /// after scaling it (before fitting it).
+    #[serde(default)]
+    rotation: f64,
+    /// Mirror the drawing horizontally, e.g. for boards behind glass.
+    #[serde(default)]
+    flip_x: bool,
+    /// Mirror the drawing vertically.
+    #[serde(default)]
+    flip_y: bool,
+    /// Place the drawing within the board (after fitting it), ignoring the
+    /// offsets.
+    align: Option<Align>,
+    mode: PrintMode,
+    /// Draw the frames of an animated SVG one after another.
+    #[serde(default)]
+    frames: bool,
+    /// Split a drawing that is larger than the board into tiles, which are
+    /// drawn one after another.
+    #[serde(default)]
+    tiles: bool,
+    /// How much neighbouring tiles overlap, in mm.
+    #[serde(default = "default_tile_overlap")]
+    tile_overlap: f64,
+    #[serde(flatten)]
+    options: JobOptions,
+}
+
+fn default_pdf_page() -> u32 {
+    1
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+fn default_tile_overlap() -> f64 {
+    10.0
+}
+
+impl PrintRequest {
+    /// Return the offset and the scale of the drawing. At its native size,
+    /// the user units of the SVG are converted into mm first.
+    fn offset_and_scale(&self) -> JsonResult<((f64, f64), (f64, f64))> {
+        let offset = (self.offset_x, self.offset_y);
+        let scale = (self.scale_x, self.scale_y);
+        if !self.native_size {
+            return Ok((offset, scale));
+        }
+        let native = svg::native_size(&self.svg)
+            .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+        let scale = (scale.0 * native.scale.0, scale.1 * native.scale.1);
+        Ok((
+            (
+                offset.0 - scale.0 * native.origin.0,
+                offset.1 - scale.1 * native.origin.1,
+            ),
+            scale,
+        ))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct PdfPreviewQuery {
+    #[serde(default = "default_pdf_page")]
+    page: u32,
+}
+
+#[derive(Deserialize, Debug)]
+struct PdfPrintQuery {
+    #[serde(default = "default_pdf_page")]
+    page: u32,
+    mode: PrintMode,
+    #[serde(flatten)]
+    options: JobOptions,
+}
+
+#[derive(Deserialize, Debug)]
+struct JobSubmitQuery {
+    mode: PrintMode,
+    /// The page of a PDF file.
+    #[serde(default = "default_pdf_page")]
+    page: u32,
+    /// Comma separated layers of an SVG file (all if unset).
+    layers: Option<String>,
+    /// Whether to fit the drawing to the board (default depends on the
+    /// format).
+    fit: Option<bool>,
+    #[serde(flatten)]
+    options: JobOptions,
+}
+
+impl JobSubmitQuery {
+    fn import_options(&self, margin: f64) -> ImportOptions {
+        ImportOptions {
+            page: self.page,
+            layers: self
+                .layers
+                .iter()
+                .flat_map(|layers| layers.split(','))
+                .map(|layer| layer.trim().to_string())
+                .filter(|layer| !layer.is_empty())
+                .collect(),
+            fit: self.fit,
+            margin,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct PolylinesPrintQuery {
+    mode: PrintMode,
+    #[serde(flatten)]
+    options: JobOptions,
+}
+
+#[derive(Deserialize, Debug)]
+struct TemplatePrintRequest {
+    name: String,
+    #[serde(default)]
+    variables: HashMap<String, String>,
+    mode: PrintMode,
+    #[serde(flatten)]
+    options: JobOptions,
+}
+
+#[derive(Deserialize, Debug)]
+struct BarcodeRequest {
+    #[serde(flatten)]
+    barcode: Barcode,
+    mode: PrintMode,
+    #[serde(flatten)]
+    options: JobOptions,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChartRequest {
+    #[serde(flatten)]
+    chart: Chart,
+    mode: PrintMode,
+    #[serde(flatten)]
+    options: JobOptions,
+}
+
+#[derive(Deserialize, Debug)]
+struct GenerateRequest {
+    #[serde(flatten)]
+    generator: GeneratorConfig,
+    mode: PrintMode,
+    #[serde(flatten)]
+    options: JobOptions,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorDetails {
+    details: String,
+    /// Overrides the error code of the kind of error.
+    #[serde(skip)]
+    code: Option<ErrorCode>,
+}
+
+impl ErrorDetails {
+    fn from<S: Into<String>>(details: S) -> Self {
+        ErrorDetails {
+            details: details.into(),
+            code: None,
+        }
+    }
+
+    fn with_code(self, code: ErrorCode) -> Self {
+        ErrorDetails {
+            code: Some(code),
+            ..self
+        }
+    }
+}
+
+/// The queue of a board is full, with how many jobs are waiting.
+#[derive(Serialize, Debug)]
+struct QueueFull {
+    details: String,
+    queued: usize,
+    capacity: usize,
+}
+
+/// A job that was rejected before submission, with every constraint that it
+/// violates.
+#[derive(Serialize, Debug)]
+struct Rejection {
+    details: String,
+    violations: Vec<Violation>,
+}
+
+#[derive(Debug)]
+enum JsonError {
+    ServerError(ErrorDetails),
+    ClientError(ErrorDetails),
+    NotFound(ErrorDetails),
+    Unauthorized(ErrorDetails),
+    PasswordRequired(ErrorDetails),
+    TooManyRequests(ErrorDetails),
+    UnsupportedMediaType(ErrorDetails),
+    Unavailable(ErrorDetails),
+    QueueFull(QueueFull),
+    Rejected(Rejection),
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let val = match self {
+            JsonError::ServerError(details) => serde_json::to_value(details),
+            JsonError::ClientError(details) => serde_json::to_value(details),
+            JsonError::NotFound(details) => serde_json::to_value(details),
+            JsonError::Unauthorized(details) => serde_json::to_value(details),
+            JsonError::PasswordRequired(details) => serde_json::to_value(details),
+            JsonError::TooManyRequests(details) => serde_json::to_value(details),
+            JsonError::UnsupportedMediaType(details) => serde_json::to_value(details),
+            JsonError::Unavailable(details) => serde_json::to_value(details),
+            JsonError::QueueFull(queue) => serde_json::to_value(queue),
+            JsonError::Rejected(rejection) => serde_json::to_value(rejection),
+        };
+        let mut val = val.expect("Could not serialize error details");
+        if let Some(object) = val.as_object_mut() {
+            object.insert("code".to_string(), self.code().as_str().into());
+        }
+        write!(f, "{}", val)
+    }
+}
+impl JsonError {
+    fn code(&self) -> ErrorCode {
+        match self {
+            JsonError::ServerError(details) => details.code.unwrap_or(ErrorCode::Internal),
+            JsonError::ClientError(details) => details.code.unwrap_or(ErrorCode::InvalidRequest),
+            JsonError::NotFound(details) => details.code.unwrap_or(ErrorCode::NotFound),
+            JsonError::Unauthorized(details) => details.code.unwrap_or(ErrorCode::Unauthorized),
+            JsonError::PasswordRequired(details) => {
+                details.code.unwrap_or(ErrorCode::PasswordRequired)
+            }
+            JsonError::TooManyRequests(details) => details.code.unwrap_or(ErrorCode::RateLimited),
+            JsonError::UnsupportedMediaType(details) => {
+                details.code.unwrap_or(ErrorCode::UnsupportedMediaType)
+            }
+            JsonError::Unavailable(details) => details.code.unwrap_or(ErrorCode::Unavailable),
+            JsonError::QueueFull(_) => ErrorCode::QueueFull,
+            JsonError::Rejected(_) => ErrorCode::Rejected,
+        }
+    }
+}
+impl std::error::Error for JsonError {}
+impl ResponseError for JsonError {
+    fn error_response(&self) -> HttpResponse {
+        let mut builder = match self {
+            JsonError::ServerError(_) => HttpResponse::InternalServerError(),
+            JsonError::ClientError(_) => HttpResponse::BadRequest(),
+            JsonError::NotFound(_) => HttpResponse::NotFound(),
+            JsonError::Unauthorized(_) => HttpResponse::Unauthorized(),
+            JsonError::PasswordRequired(_) => {
+                // Make browsers ask for the password
+                let mut builder = HttpResponse::Unauthorized();
+                builder.insert_header((header::WWW_AUTHENTICATE, "Basic realm=\"iBoardBot\""));
+                builder
+            }
+            JsonError::UnsupportedMediaType(_) => HttpResponse::UnsupportedMediaType(),
+            JsonError::Unavailable(_) => HttpResponse::ServiceUnavailable(),
+            JsonError::TooManyRequests(_) | JsonError::QueueFull(_) => {
+                HttpResponse::TooManyRequests()
+            }
+            JsonError::Rejected(_) => HttpResponse::UnprocessableEntity(),
+        };
+        builder
+            .content_type("application/json")
+            .body(self.to_string())
+    }
+}
+
+type JsonResult<T> = Result<T, JsonError>;
+
+#[post("/preview/")]
+async fn preview_handler(req: web::Json<PreviewRequest>) -> JsonResult<web::Json<Vec<Polyline>>> {
+    match svg::parse_layers(&req.svg, SVG2POLYLINES_TOLERANCE, &req.layers) {
+        Ok(polylines) => Ok(web::Json(polylines)),
+        Err(errmsg) => Err(JsonError::ClientError(ErrorDetails::from(errmsg))),
+    }
+}
+
+/// Return statistics of an SVG drawing as it would be printed with the
+/// configured options (e.g. the draw order and the speed), without printing
+/// it.
+#[post("/analyze/")]
+async fn analyze_handler(
+    data: web::Data<State>,
+    analyze_request: web::Json<AnalyzeRequest>,
+) -> JsonResult<web::Json<Analysis>> {
+    let mut polylines = svg::parse_layers(
+        &analyze_request.svg,
+        SVG2POLYLINES_TOLERANCE,
+        &analyze_request.layers,
+    )
+    .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+    scaling::scale_polylines(
+        &mut polylines,
+        (analyze_request.offset_x, analyze_request.offset_y),
+        (analyze_request.scale_x, analyze_request.scale_y),
+    );
+    let config = &data.config;
+    let drawing = Drawing::Static(polylines);
+    let drawing = apply_merge(config.merge_distance, drawing);
+    let drawing = apply_hatch(config.hatch, drawing);
+    let drawing = apply_simplify(config.simplify_tolerance, drawing);
+    let drawing = apply_passes(config.passes, config.pass_offset, drawing);
+    let drawing = apply_order(
+        analyze_request.draw_order.unwrap_or(config.draw_order),
+        drawing,
+    );
+    let drawing = apply_pen(config.pen, drawing);
+    let drawing = apply_speed(analyze_request.speed.unwrap_or(config.speed), drawing);
+    let drawing = apply_resample(config.min_point_spacing, drawing);
+    let polylines = drawing
+        .into_polylines()
+        .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+    let estimate = preflight::estimate(&polylines, true, config.orientation, config.simulation);
+    Ok(web::Json(analyze::analyze(&polylines, estimate)))
+}
+
+/// Convert a page of an uploaded PDF file into polylines.
+#[post("/preview-pdf/")]
+async fn preview_pdf_handler(
+    query: web::Query<PdfPreviewQuery>,
+    body: web::Bytes,
+) -> JsonResult<web::Json<Vec<Polyline>>> {
+    match pdf::parse(&body, query.page) {
+        Ok(polylines) => Ok(web::Json(polylines)),
+        Err(errmsg) => Err(JsonError::ClientError(ErrorDetails::from(errmsg))),
+    }
+}
+
+/// Print a page of an uploaded PDF file, fitted to the board.
+#[post("/print-pdf/")]
+async fn print_pdf_handler(
+    req: HttpRequest,
+    data: web::Data<State>,
+    body: web::Bytes,
+) -> Result<HttpResponse, JsonError> {
+    let query: PdfPrintQuery = parse_query(&req)?;
+    info!(
+        "Requested PDF page {} with print mode: {:?}",
+        query.page, query.mode
+    );
+    let mut polylines =
+        pdf::parse(&body, query.page).map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+
+    scaling::fit_polylines(&mut polylines, &Bounds::drawable(data.config.margin()))
+        .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+
+    submit(
+        &data,
+        &req,
+        &query.mode,
+        Drawing::Static(polylines),
+        query.options,
+    )?;
+
+    info!("Printing PDF...");
+    Ok(HttpResponse::new(StatusCode::NO_CONTENT))
+}
+
+/// Parse the query string of a request. Query strings only carry strings,
+/// which the flattened job options can't take: Values that are valid JSON
+/// (e.g. `passes=2`, `dry_run=true` or `hatch={"spacing":2}`) are taken as
+/// such, all other values as strings.
+fn parse_query<T: DeserializeOwned>(req: &HttpRequest) -> JsonResult<T> {
+    let pairs = web::Query::<Vec<(String, String)>>::from_query(req.query_string())
+        .map_err(|e| JsonError::ClientError(ErrorDetails::from(format!("Invalid query: {}", e))))?
+        .into_inner();
+    let query: serde_json::Map<String, serde_json::Value> = pairs
+        .into_iter()
+        .map(|(key, value)| {
+            let value = serde_json::from_str(&value).unwrap_or(serde_json::Value::String(value));
+            (key, value)
+        })
+        .collect();
+    serde_json::from_value(query.into())
+        .map_err(|e| JsonError::ClientError(ErrorDetails::from(format!("Invalid query: {}", e))))
+}
+
+/// Parse a print request, filling in the options of the preset of the
+/// tenant that it selects.
+fn parse_print_request(
+    data: &State,
+    req: &HttpRequest,
+    mut request: serde_json::Map<String, serde_json::Value>,
+) -> JsonResult<PrintRequest> {
+    if request.contains_key("preset") {
+        let tenant = authorize(data, req)?.ok_or_else(|| {
+            JsonError::ClientError(ErrorDetails::from(
+                "Presets are only available in multi-tenant mode",
+            ))
+        })?;
+        tenant
+            .apply_preset(&mut request)
+            .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+    }
+    serde_json::from_value(serde_json::Value::Object(request))
+        .map_err(|e| JsonError::ClientError(ErrorDetails::from(e.to_string())))
+}
+
+/// Return the presets of the tenant that sent the request.
+#[get("/presets/")]
+async fn presets_handler(req: HttpRequest, data: web::Data<State>) -> JsonResult<HttpResponse> {
+    match authorize(&data, &req)? {
+        Some(tenant) => Ok(HttpResponse::Ok().json(tenant.presets())),
+        None => Err(JsonError::NotFound(ErrorDetails::from(
+            "Presets are only available in multi-tenant mode",
+        ))),
+    }
+}
+
+#[post("/print/")]
+async fn print_handler(
+    req: HttpRequest,
+    data: web::Data<State>,
+    request: web::Json<serde_json::Map<String, serde_json::Value>>,
+) -> Result<HttpResponse, JsonError> {
+    let print_request = parse_print_request(&data, &req, request.into_inner())?;
+    if !print_request.rotation.is_finite() {
+        return Err(JsonError::ClientError(ErrorDetails::from(
+            "The rotation must be a number of degrees",
+        )));
+    }
+    let padding = print_request.padding.unwrap_or(data.config.margin());
+    let max_padding = f64::from(robot::IBB_HEIGHT) / 2.0;
+    let region = print_request
+        .region
+        .as_ref()
+        .map(|region| region.bounds(&data.config.regions))
+        .transpose()
+        .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+    let fit = print_request.fit || region.is_some();
+    if fit && print_request.native_size {
+        return Err(JsonError::ClientError(ErrorDetails::from(
+            "A drawing can't be fitted to the board at its native size",
+        )));
+    }
+    if print_request.fit && !(0.0..max_padding).contains(&padding) {
+        return Err(JsonError::ClientError(ErrorDetails::from(format!(
+            "The padding must be between 0 and {} mm",
+            max_padding
+        ))));
+    }
+    if print_request.frames {
+        return print_frames(&data, &req, &print_request);
+    }
+    if print_request.tiles {
+        return print_tiles(&data, &req, &print_request);
+    }
+
+    // Parse SVG into list of polylines
+    info!("Requested print mode: {:?}", print_request.mode);
+    let parsed = match (&print_request.layers, &print_request.eraser_layers) {
+        (layers, eraser) if layers.is_empty() && !eraser.is_empty() => {
+            svg::parse_without_layers(&print_request.svg, SVG2POLYLINES_TOLERANCE, eraser)
+        }
+        (layers, _) => svg::parse_layers(&print_request.svg, SVG2POLYLINES_TOLERANCE, layers),
+    };
+    let mut polylines = match parsed {
+        Ok(polylines) => polylines,
+        Err(e) => return Err(JsonError::ClientError(ErrorDetails::from(e))),
+    };
+    let mut eraser = match print_request.eraser_layers.is_empty() {
+        true => vec![],
+        false => svg::parse_layers(
+            &print_request.svg,
+            SVG2POLYLINES_TOLERANCE,
+            &print_request.eraser_layers,
+        )
+        .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?,
+    };
+
+    // Scale, rotate and mirror polylines (or fit them to the board) and align them
+    if !fit {
+        let (offset, scale) = print_request.offset_and_scale()?;
+        for polylines in [&mut polylines, &mut eraser] {
+            scaling::scale_polylines(polylines, offset, scale);
+        }
+    }
+    if print_request.rotation != 0.0 {
+        scaling::rotate_polylines(&mut [&mut polylines, &mut eraser], print_request.rotation);
+    }
+    if print_request.flip_x || print_request.flip_y {
+        scaling::flip_polylines(
+            &mut [&mut polylines, &mut eraser],
+            print_request.flip_x,
+            print_request.flip_y,
+        );
+    }
+    let bounds = match (region, print_request.fit) {
+        (Some(region), _) => region,
+        (None, true) => Bounds::drawable(padding),
+        (None, false) => Bounds::drawable(data.config.margin()),
+    };
+    if fit {
+        // Fit the eraser strokes along with the drawing
+        let count = polylines.len();
+        polylines.append(&mut eraser);
+        scaling::fit_polylines(&mut polylines, &bounds)
+            .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+        eraser = polylines.split_off(count);
+    }
+    if let Some(align) = print_request.align {
+        scaling::align_polylines(&mut [&mut polylines, &mut eraser], align, &bounds);
+    }
+    // Keep the rest of the board, unless another region is to be erased
+    let mut options = print_request.options.clone();
+    if options.placement == Placement::Fixed && options.erase_region.is_none() {
+        options.erase_region = print_request.region.clone();
+    }
+
+    submit(
+        &data,
+        &req,
+        &print_request.mode,
+        Drawing::Static(polylines),
+        JobOptions { eraser, ..options },
+    )?;
+
+    info!("Printing...");
+    Ok(HttpResponse::new(StatusCode::NO_CONTENT))
+}
+
+/// Print the frames of an animated SVG as a sequence of jobs. Every frame
+/// is drawn on the erased board and held until the next one is drawn.
+fn print_frames(
+    data: &State,
+    req: &HttpRequest,
+    print_request: &PrintRequest,
+) -> Result<HttpResponse, JsonError> {
+    if !matches!(print_request.mode, PrintMode::Once) {
+        return Err(JsonError::ClientError(ErrorDetails::from(
+            "Frames can only be printed with the print mode \"once\"",
+        )));
+    }
+    if !print_request.layers.is_empty()
+        || !print_request.eraser_layers.is_empty()
+        || print_request.options.placement != Placement::Fixed
+        || print_request.fit
+        || print_request.region.is_some()
+        || print_request.align.is_some()
+        || print_request.tiles
+    {
+        return Err(JsonError::ClientError(ErrorDetails::from(
+            "Frames can't be combined with layers, placements, fitting, regions, alignment or tiles",
+        )));
+    }
+    let frames = svg::frames(&print_request.svg)
+        .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+    info!("Requested animation with {} frames", frames.len());
+    let (offset, scale) = print_request.offset_and_scale()?;
+    let last = frames.len() - 1;
+    let mut drawings = Vec::with_capacity(frames.len());
+    for (i, frame) in frames.iter().enumerate() {
+        let mut polylines = svg::parse_layers(
+            &print_request.svg,
+            SVG2POLYLINES_TOLERANCE,
+            std::slice::from_ref(&frame.name),
+        )
+        .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+        scaling::scale_polylines(&mut polylines, offset, scale);
+        if print_request.rotation != 0.0 {
+            scaling::rotate_polylines(&mut [&mut polylines], print_request.rotation);
+        }
+        if print_request.flip_x || print_request.flip_y {
+            scaling::flip_polylines(
+                &mut [&mut polylines],
+                print_request.flip_x,
+                print_request.flip_y,
+            );
+        }
+        // The last frame stays on the board anyway
+        let hold_seconds = match i == last {
+            true => print_request.options.hold_seconds,
+            false => Some(print_request.options.hold_seconds.unwrap_or_else(|| {
+                frame.hold.map_or(DEFAULT_FRAME_SECONDS, |hold| {
+                    hold.as_secs_f64().ceil().min(f64::from(MAX_HOLD_SECONDS)) as u32
+                })
+            })),
+        };
+        let options = JobOptions {
+            hold_seconds,
+            ..print_request.options.clone()
+        };
+        drawings.push((Drawing::Static(polylines), options));
+    }
+    // Either all frames are printed or none
+    submit_all(data, req, &print_request.mode, drawings)?;
+
+    info!("Printing frames...");
+    Ok(HttpResponse::new(StatusCode::NO_CONTENT))
+}
+
+/// Print a drawing that is larger than the board as a sequence of tiles.
+/// Every tile is drawn on the erased board and held until the next one is
+/// drawn, e.g. to photograph it.
+fn print_tiles(
+    data: &State,
+    req: &HttpRequest,
+    print_request: &PrintRequest,
+) -> Result<HttpResponse, JsonError> {
+    if !matches!(print_request.mode, PrintMode::Once) {
+        return Err(JsonError::ClientError(ErrorDetails::from(
+            "Tiles can only be printed with the print mode \"once\"",
+        )));
+    }
+    if !print_request.eraser_layers.is_empty()
+        || print_request.options.placement != Placement::Fixed
+        || print_request.fit
+        || print_request.region.is_some()
+        || print_request.align.is_some()
+    {
+        return Err(JsonError::ClientError(ErrorDetails::from(
+            "Tiles can't be combined with eraser layers, placements, fitting, regions or alignment",
+        )));
+    }
+    let mut polylines = svg::parse_layers(
+        &print_request.svg,
+        SVG2POLYLINES_TOLERANCE,
+        &print_request.layers,
+    )
+    .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+    let (offset, scale) = print_request.offset_and_scale()?;
+    scaling::scale_polylines(&mut polylines, offset, scale);
+    if print_request.rotation != 0.0 {
+        scaling::rotate_polylines(&mut [&mut polylines], print_request.rotation);
+    }
+    if print_request.flip_x || print_request.flip_y {
+        scaling::flip_polylines(
+            &mut [&mut polylines],
+            print_request.flip_x,
+            print_request.flip_y,
+        );
+    }
+    let tiles = scaling::tile_polylines(
+        &polylines,
+        &Bounds::drawable(data.config.margin()),
+        print_request.tile_overlap,
+    )
+    .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+    info!("Requested drawing in {} tiles", tiles.len());
+    let last = tiles.len().saturating_sub(1);
+    let mut drawings = Vec::with_capacity(tiles.len());
+    for (i, tile) in tiles.into_iter().enumerate() {
+        // The last tile stays on the board anyway
+        let hold_seconds = match i == last {
+            true => print_request.options.hold_seconds,
+            false => Some(
+                print_request
+                    .options
+                    .hold_seconds
+                    .unwrap_or(DEFAULT_TILE_SECONDS),
+            ),
+        };
+        let options = JobOptions {
+            hold_seconds,
+            ..print_request.options.clone()
+        };
+        drawings.push((Drawing::Static(tile), options));
+    }
+    // Either all tiles are printed or none
+    submit_all(data, req, &print_request.mode, drawings)?;
+
+    info!("Printing tiles...");
+    Ok(HttpResponse::new(StatusCode::NO_CONTENT))
+}
+
+/// Print a list of polylines (in board coordinates), bypassing SVG parsing.
+#[post("/print-polylines/")]
+async fn print_polylines_handler(
+    req: HttpRequest,
+    data: web::Data<State>,
+    polylines: web::Json<Vec<Polyline>>,
+) -> Result<HttpResponse, JsonError> {
+    let query: PolylinesPrintQuery = parse_query(&req)?;
+    info!(
+        "Requested {} polylines with print mode: {:?}",
+        polylines.len(),
+        query.mode
+    );
+    submit(
+        &data,
+        &req,
+        &query.mode,
+        Drawing::Static(polylines.into_inner()),
+        query.options,
+    )?;
+
+    info!("Printing polylines...");
+    Ok(HttpResponse::new(StatusCode::NO_CONTENT))
+}
+
+/// Print a template SVG from the SVG dir. Scheduled templates are expanded
+/// anew for every print.
+#[post("/print-template/")]
+async fn print_template_handler(
+    req: HttpRequest,
+    data: web::Data<State>,
+    template_request: web::Json<TemplatePrintRequest>,
+) -> Result<HttpResponse, JsonError> {
+    let template_request = template_request.into_inner();
+    info!(
+        "Requested template {} with print mode: {:?}",
+        template_request.name, template_request.mode
+    );
+
+    // Only allow plain file names, to stay within the library
+    let name = template_request.name;
+    if !is_plain_file_name(&name) {
+        return Err(JsonError::ClientError(ErrorDetails::from(format!(
+            "Invalid template name: {}",
+            name
+        ))));
+    }
+    let path = library_dir(&data, &req)?.join(&name);
+    let svg = fs::read_to_string(&path).map_err(|e| {
+        JsonError::ClientError(ErrorDetails::from(format!(
+            "Could not read template {}: {}",
+            name, e
+        )))
+    })?;
+
+    let drawing = template_drawing(
+        name,
+        svg,
+        template_request.variables,
+        data.counters.clone(),
+        data.config.margin(),
+    );
+    submit(
+        &data,
+        &req,
+        &template_request.mode,
+        drawing,
+        template_request.options.clone(),
+    )?;
+
+    info!("Printing template...");
+    Ok(HttpResponse::new(StatusCode::NO_CONTENT))
+}
+
+#[post("/barcode/")]
+async fn barcode_handler(
+    req: HttpRequest,
+    data: web::Data<State>,
+    barcode_request: web::Json<BarcodeRequest>,
+) -> Result<HttpResponse, JsonError> {
+    // Render barcode into the board area
+    info!(
+        "Requested barcode with print mode: {:?}",
+        barcode_request.mode
+    );
+    let polylines = barcode_request
+        .barcode
+        .render(&Bounds::drawable(data.config.margin()))
+        .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+
+    submit(
+        &data,
+        &req,
+        &barcode_request.mode,
+        Drawing::Static(polylines),
+        barcode_request.options.clone(),
+    )?;
+
+    info!("Printing barcode...");
+    Ok(HttpResponse::new(StatusCode::NO_CONTENT))
+}
+
+#[post("/chart/")]
+async fn chart_handler(
+    req: HttpRequest,
+    data: web::Data<State>,
+    chart_request: web::Json<ChartRequest>,
+) -> Result<HttpResponse, JsonError> {
+    // Render chart into the board area
+    info!("Requested chart with print mode: {:?}", chart_request.mode);
+    let polylines = chart_request
+        .chart
+        .render(&Bounds::drawable(data.config.margin()))
+        .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+
+    submit(
+        &data,
+        &req,
+        &chart_request.mode,
+        Drawing::Static(polylines),
+        chart_request.options.clone(),
+    )?;
+
+    info!("Printing chart...");
+    Ok(HttpResponse::new(StatusCode::NO_CONTENT))
+}
+
+/// Print generative art. Scheduled drawings are generated anew for every
+/// print.
+#[post("/generate/")]
+async fn generate_handler(
+    req: HttpRequest,
+    data: web::Data<State>,
+    generate_request: web::Json<GenerateRequest>,
+) -> Result<HttpResponse, JsonError> {
+    info!(
+        "Requested generator {:?} with print mode: {:?}",
+        generate_request.generator, generate_request.mode
+    );
+
+    // Validate the parameters before submitting
+    let margin = data.config.margin();
+    generate_request
+        .generator
+        .generate(&Bounds::drawable(margin))
+        .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+
+    submit(
+        &data,
+        &req,
+        &generate_request.mode,
+        generate_request.generator.drawing(margin),
+        generate_request.options.clone(),
+    )?;
+
+    info!("Printing generated drawing...");
+    Ok(HttpResponse::new(StatusCode::NO_CONTENT))
+}
+
+/// Return the bearer token of the request, if any.
+fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+/// Return the tenant that sent the request. In multi-tenant mode, every
+/// request must carry a valid API token.
+fn authorize(data: &State, req: &HttpRequest) -> JsonResult<Option<TenantConfig>> {
+    if !data.config.multi_tenant() {
+        return Ok(None);
+    }
+    let tenants = lock_tenants(data)?;
+    let tenant = bearer_token(req).and_then(|token| tenants::find_by_token(&tenants, token));
+    match tenant {
+        Some(tenant) => Ok(Some(tenant.clone())),
+        None => Err(JsonError::Unauthorized(ErrorDetails::from(
+            "A valid API token is required",
+        ))),
+    }
+}
+
+/// Lock the tenants.
+fn lock_tenants(data: &State) -> JsonResult<MutexGuard<'_, Vec<TenantConfig>>> {
+    data.tenants.lock().map_err(|e| {
+        JsonError::ServerError(ErrorDetails::from(format!(
+            "Could not access tenants: {}",
+            e
+        )))
+    })
+}
+
+/// The tenants can only be managed with the admin token.
+fn check_tenants_access(data: &State, req: &HttpRequest) -> JsonResult<()> {
+    match (&data.config.admin_token, bearer_token(req)) {
+        (Some(admin_token), Some(token)) if admin_token == token => Ok(()),
+        (None, _) => Err(JsonError::NotFound(ErrorDetails::from(
+            "Tenants can only be managed with an admin_token in the config",
+        ))),
+        (Some(_), _) => Err(JsonError::Unauthorized(ErrorDetails::from(
+            "The admin token is required",
+        ))),
+    }
+}
+
+/// The file where the tenants managed at runtime are persisted.
+fn tenants_file(config: &Config) -> Option<PathBuf> {
+    config
+        .state_dir
+        .as_ref()
+        .map(|dir| Path::new(dir).join("tenants"))
+}
+
+/// Persist the tenants, if there is a state dir.
+fn save_tenants(data: &State, tenants: &[TenantConfig]) {
+    if let Some(path) = tenants_file(&data.config) {
+        tenants::save(&path, tenants);
+    }
+}
+
+/// Return the tenants (without their tokens).
+#[get("/tenants/")]
+async fn tenants_handler(req: HttpRequest, data: web::Data<State>) -> JsonResult<HttpResponse> {
+    check_tenants_access(&data, &req)?;
+    let tenants = lock_tenants(&data)?;
+    Ok(HttpResponse::Ok().json(&*tenants))
+}
+
+/// Create a tenant, or replace the tenant of that name (e.g. to change its
+/// tokens or quota).
+#[put("/tenants/{name}/")]
+async fn tenant_put_handler(
+    req: HttpRequest,
+    data: web::Data<State>,
+    name: web::Path<String>,
+    tenant: web::Json<serde_json::Map<String, serde_json::Value>>,
+) -> JsonResult<HttpResponse> {
+    check_tenants_access(&data, &req)?;
+    // The name is taken from the path
+    let mut tenant = tenant.into_inner();
+    tenant.insert("name".to_string(), name.into_inner().into());
+    let tenant: TenantConfig = serde_json::from_value(tenant.into()).map_err(|e| {
+        JsonError::ClientError(ErrorDetails::from(format!("Invalid tenant: {}", e)))
+    })?;
+    let mut tenants = lock_tenants(&data)?;
+    let mut updated = tenants.clone();
+    let created = match updated.iter_mut().find(|t| t.name == tenant.name) {
+        Some(existing) => {
+            *existing = tenant.clone();
+            false
+        }
+        None => {
+            updated.push(tenant.clone());
+            true
+        }
+    };
+    tenants::validate(&updated, data.config.admin_token.as_deref())
+        .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+    match created {
+        true => info!("Creating tenant {}", tenant.name),
+        false => info!("Replacing tenant {}", tenant.name),
+    }
+    save_tenants(&data, &updated);
+    *tenants = updated;
+    Ok(match created {
+        true => HttpResponse::Created().json(&tenant),
+        false => HttpResponse::Ok().json(&tenant),
+    })
+}
+
+/// Remove a tenant. Its jobs that are already queued are still drawn.
+#[delete("/tenants/{name}/")]
+async fn tenant_delete_handler(
+    req: HttpRequest,
+    data: web::Data<State>,
+    name: web::Path<String>,
+) -> JsonResult<HttpResponse> {
+    check_tenants_access(&data, &req)?;
+    let mut tenants = lock_tenants(&data)?;
+    let index = tenants
+        .iter()
+        .position(|tenant| tenant.name == *name)
+        .ok_or_else(|| {
+            JsonError::NotFound(ErrorDetails::from(format!("No tenant named {}", name)))
+        })?;
+    info!("Removing tenant {}", name);
+    tenants.remove(index);
+    save_tenants(&data, &tenants);
+    Ok(HttpResponse::new(StatusCode::NO_CONTENT))
+}
+
+/// Print the drawing on behalf of the sender of the request.
+///
+/// Drawings on a canvas are split at the seam, and each part is printed on
+/// its board. Tenants can only print once and are limited by their daily
+/// quota. Jobs with a deadline are dropped if they could not be started in
+/// time, and dry runs are written to the dry run file instead of being
+/// drawn. Single prints are checked against the board bounds, time limits
+/// and quota first, while prints at a later time are held by the robot until
+/// then.
+fn submit(
+    data: &State,
+    req: &HttpRequest,
+    mode: &PrintMode,
+    drawing: Drawing,
+    options: JobOptions,
+) -> JsonResult<()> {
+    submit_all(data, req, mode, vec![(drawing, options)])
+}
+
+/// Print several drawings one after another, e.g. the frames of an
+/// animation. Either all of them are accepted or none: All drawings are
+/// checked, and the quota of the tenant and the room in the queues are
+/// checked for all of them, before the first one is sent to the robot.
+fn submit_all(
+    data: &State,
+    req: &HttpRequest,
+    mode: &PrintMode,
+    drawings: Vec<(Drawing, JobOptions)>,
+) -> JsonResult<()> {
+    let tenant = authorize(data, req)?;
+    let tenant = tenant.as_ref();
+    let sequence = drawings.len() > 1;
+    let mut submissions = vec![];
+    for (drawing, options) in drawings {
+        prepare(data, tenant, mode, drawing, options, &mut submissions)?;
+    }
+    if sequence && submissions.iter().any(|submission| submission.voted) {
+        return Err(JsonError::ClientError(ErrorDetails::from(
+            "Sequences of drawings can't enter the voting pool",
+        )));
+    }
+    enqueue(data, tenant, submissions)
+}
+
+/// A checked task, ready to be sent to the robot thread of its board.
+struct Submission<'a> {
+    board: &'a BoardState,
+    task: PrintTask,
+    /// Whether the job enters the voting pool instead of the queue.
+    voted: bool,
+}
+
+/// Check the drawing and turn it into the tasks to send (one per board of a
+/// canvas).
+fn prepare<'a>(
+    data: &'a State,
+    tenant: Option<&TenantConfig>,
+    mode: &PrintMode,
+    drawing: Drawing,
+    options: JobOptions,
+    submissions: &mut Vec<Submission<'a>>,
+) -> JsonResult<()> {
+    let canvas = data
+        .config
+        .canvases
+        .iter()
+        .find(|canvas| options.board.as_ref() == Some(&canvas.name));
+    if let Some(canvas) = canvas {
+        return prepare_canvas(data, tenant, mode, drawing, options, canvas, submissions);
+    }
+    let JobOptions {
+        pen,
+        speed,
+        draw_order,
+        simplify_tolerance,
+        merge_distance,
+        hatch,
+        passes,
+        pass_offset,
+        deadline,
+        dry_run,
+        placement,
+        on_collision,
+        ttl_seconds,
+        feed_mm,
+        hold_seconds,
+        write_policy,
+        urgent,
+        board,
+        mut eraser,
+        erase_region,
+    } = options;
+    let board = find_board(data, board.as_deref())?;
+    if dry_run && data.config.dry_run.is_none() {
+        return Err(JsonError::ClientError(ErrorDetails::from(
+            "Dry runs require a dry_run section in the config",
+        )));
+    }
+    if feed_mm.is_some() && data.config.paper_roll.is_none() {
+        return Err(JsonError::ClientError(ErrorDetails::from(
+            "Paper feeds require a paper_roll section in the config",
+        )));
+    }
+    if hold_seconds.is_some_and(|hold| hold > MAX_HOLD_SECONDS) {
+        return Err(JsonError::ClientError(ErrorDetails::from(format!(
+            "Drawings can be held for at most {} seconds",
+            MAX_HOLD_SECONDS
+        ))));
+    }
+    // Only the region is erased, and the rest of the board is kept
+    let placement = match erase_region {
+        Some(region) => {
+            let region = region
+                .bounds(&data.config.regions)
+                .and_then(|region| {
+                    region
+                        .intersection(&Bounds::board())
+                        .ok_or_else(|| "The region to erase is outside of the board".to_string())
+                })
+                .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+            eraser.extend(robot::region_eraser(&region));
+            match placement {
+                Placement::Fixed => Placement::Append,
+                placement => placement,
+            }
+        }
+        None => placement,
+    };
+    if !eraser.is_empty() && placement == Placement::Random {
+        return Err(JsonError::ClientError(ErrorDetails::from(
+            "Eraser strokes can't be combined with random placement",
+        )));
+    }
+    let simplify_tolerance = simplify_tolerance.or(data.config.simplify_tolerance);
+    if let Some(Err(e)) = simplify_tolerance.map(simplify::validate) {
+        return Err(JsonError::ClientError(ErrorDetails::from(e)));
+    }
+    let merge_distance = merge_distance.or(data.config.merge_distance);
+    if let Some(Err(e)) = merge_distance.map(merge::validate) {
+        return Err(JsonError::ClientError(ErrorDetails::from(e)));
+    }
+    let drawing = apply_merge(merge_distance, drawing);
+    let hatch = hatch.or(data.config.hatch);
+    if let Some(Err(e)) = hatch.map(|hatch| hatch.validate()) {
+        return Err(JsonError::ClientError(ErrorDetails::from(e)));
+    }
+    let drawing = apply_hatch(hatch, drawing);
+    let drawing = apply_simplify(simplify_tolerance, drawing);
+    let passes = passes.or(data.config.passes);
+    let pass_offset = pass_offset.or(data.config.pass_offset);
+    if let Err(e) = passes::validate(passes.unwrap_or(1), pass_offset.unwrap_or(0.0)) {
+        return Err(JsonError::ClientError(ErrorDetails::from(e)));
+    }
+    let drawing = apply_passes(passes, pass_offset, drawing);
+    let drawing = apply_order(draw_order.unwrap_or(data.config.draw_order), drawing);
+    let drawing = apply_pen(pen.or(data.config.pen), drawing);
+    let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+    let slow = match urgent && matches!(mode, PrintMode::Once) {
+        true => slowmode::active(
+            &data.config.slow_mode,
+            timelimits::current(&data.time_limits).as_ref(),
+            &now.time(),
+        ),
+        false => None,
+    };
+    if let Some(slow) = slow {
+        info!("Drawing urgent job in slow mode ({})", slow.window);
+    }
+    let speed = slow.map(|slow| slow.speed).or(speed);
+    let drawing = apply_speed(speed.unwrap_or(data.config.speed), drawing);
+    let drawing = apply_resample(data.config.min_point_spacing, drawing);
+    let drawing = match mode {
+        PrintMode::Once => preflight(data, tenant, drawing, dry_run, placement, slow)?,
+        _ => {
+            check_scheduled_bounds(data, &drawing, placement)?;
+            drawing
+        }
+    };
+    // Single prints for the default board are voted on, if enabled
+    let voted = data.voting.is_some()
+        && matches!(mode, PrintMode::Once)
+        && std::ptr::eq(board, &data.boards[0]);
+    let single = voted
+        || deadline.is_some()
+        || dry_run
+        || placement != Placement::Fixed
+        || ttl_seconds.is_some()
+        || feed_mm.is_some()
+        || hold_seconds.is_some()
+        || write_policy.is_some()
+        || urgent
+        || !eraser.is_empty();
+    if tenant.is_none() && !single {
+        let task = mode
+            .to_drawing_task(drawing)
+            .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+        submissions.push(Submission {
+            board,
+            task,
+            voted: false,
+        });
+        return Ok(());
+    }
+    if mode.interval().is_some() {
+        let details = match tenant {
+            Some(_) => "Tenants can only use the print modes \"once\" and \"at\"",
+            None => {
+                "Deadlines, dry runs, placements, TTLs, paper feeds, holds, eraser strokes, write policies and urgent jobs can only be used with the print modes \"once\" and \"at\""
+            }
+        };
+        return Err(JsonError::ClientError(ErrorDetails::from(details)));
+    }
+    let polylines = drawing
+        .into_polylines()
+        .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+    let label = match tenant {
+        Some(tenant) => format!("Job of {}", tenant.name),
+        None => "Single print".to_string(),
+    };
+    let job = Job {
+        deadline,
+        start_at: mode.start_at(),
+        dry_run,
+        placement,
+        on_collision,
+        ttl: ttl_seconds.map(std::time::Duration::from_secs),
+        feed_mm,
+        hold_seconds,
+        eraser,
+        write_policy,
+        ..Job::new(label, polylines)
+    };
+    let owner = tenant.map(|t| t.name.clone());
+    submissions.push(Submission {
+        board,
+        task: PrintTask::Job(owner, job),
+        voted,
+    });
+    Ok(())
+}
+
+/// Send the checked tasks to the robot threads, unless the tenant doesn't
+/// have enough quota left or the queues don't have room for all of them.
+fn enqueue(
+    data: &State,
+    tenant: Option<&TenantConfig>,
+    submissions: Vec<Submission>,
+) -> JsonResult<()> {
+    for board in &data.boards {
+        let queued = submissions
+            .iter()
+            .filter(|submission| std::ptr::eq(submission.board, board) && !submission.voted)
+            .filter(|submission| matches!(submission.task, PrintTask::Once(_) | PrintTask::Job(..)))
+            .count();
+        if queued == 0 {
+            continue;
+        }
+        if queued > board.queue_depth {
+            return Err(JsonError::ClientError(ErrorDetails::from(format!(
+                "The {} jobs don't fit into the queue of board {} (queue_depth is {})",
+                queued, board.name, board.queue_depth
+            ))));
+        }
+        check_robot(board)?;
+        let waiting = lock_jobs(board)?.len();
+        if waiting + queued > board.queue_depth {
+            return Err(queue_full(board, waiting));
+        }
+    }
+    // Tenants only submit jobs, and each of them counts towards the quota
+    let jobs = submissions.len() as u32;
+    if let Some(tenant) = tenant {
+        check_quota(data, tenant, jobs)?;
+    }
+    for (sent, submission) in submissions.into_iter().enumerate() {
+        if let Err(e) = send_submission(data, submission) {
+            // Only jobs that were accepted count towards the quota
+            if let Some(tenant) = tenant {
+                refund_quota(data, tenant, jobs - sent as u32);
+            }
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Send a checked task to the robot thread of its board (or the voting
+/// pool).
+fn send_submission(data: &State, submission: Submission) -> JsonResult<()> {
+    let Submission { board, task, voted } = submission;
+    match (task, data.voting.as_ref().filter(|_| voted)) {
+        (PrintTask::Job(owner, job), Some(pool)) => {
+            let id = lock_voting(pool)?
+                .submit(owner, job, OffsetDateTime::now_utc())
+                .map_err(|e| JsonError::TooManyRequests(ErrorDetails::from(e)))?;
+            info!("Added entry {} to the voting pool", id);
+            Ok(())
+        }
+        (task, _) => {
+            // A scheduled drawing replaces the imported schedule for good
+            let replaces_schedule =
+                matches!(task, PrintTask::Scheduled(..)) && std::ptr::eq(board, &data.boards[0]);
+            send_task(board, task)?;
+            if let Some(path) = schedules_file(&data.config).filter(|_| replaces_schedule) {
+                if let Err(e) = fs::remove_file(&path) {
+                    if e.kind() != io::ErrorKind::NotFound {
+                        warn!("Could not remove {}: {}", path.display(), e);
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Lock the voting pool.
+fn lock_voting(pool: &SharedVotingPool) -> JsonResult<MutexGuard<'_, VotingPool>> {
+    pool.lock().map_err(|e| {
+        JsonError::ServerError(ErrorDetails::from(format!(
+            "Could not access voting pool: {}",
+            e
+        )))
+    })
+}
+
+/// Return the voting pool, if voting is enabled.
+fn voting_pool(data: &State) -> JsonResult<&SharedVotingPool> {
+    data.voting.as_ref().ok_or_else(|| {
+        JsonError::NotFound(ErrorDetails::from(
+            "Voting is disabled (enable it with a voting section in the config)",
+        ))
+    })
+}
+
+/// Return the entries of the voting pool, the one drawn next first.
+#[get("/vote/")]
+async fn vote_list_handler(data: web::Data<State>) -> JsonResult<HttpResponse> {
+    let pool = lock_voting(voting_pool(&data)?)?;
+    Ok(HttpResponse::Ok().json(pool.ranking()))
+}
+
+#[derive(Serialize, Debug)]
+struct VoteResponse {
+    id: u64,
+    votes: usize,
+}
+
+/// Vote for an entry of the voting pool. Every client (by IP address) can
+/// vote once per entry.
+#[post("/vote/{id}/")]
+async fn vote_handler(
+    req: HttpRequest,
+    data: web::Data<State>,
+    id: web::Path<u64>,
+) -> JsonResult<HttpResponse> {
+    let id = id.into_inner();
+    let voter = req
+        .connection_info()
+        .realip_remote_addr()
+        .unwrap_or_default()
+        .to_string();
+    let votes = lock_voting(voting_pool(&data)?)?
+        .vote(id, &voter)
+        .map_err(|e| match e {
+            VoteError::NotFound => {
+                JsonError::NotFound(ErrorDetails::from(format!("No entry with id {}", id)))
+            }
+            VoteError::AlreadyVoted => {
+                JsonError::ClientError(ErrorDetails::from("You have already voted for this entry"))
+            }
+        })?;
+    Ok(HttpResponse::Ok().json(VoteResponse { id, votes }))
+}
+
+/// Split the drawing on a canvas at the seam and print each part on its
+/// board. Parts without any strokes are not printed.
+fn prepare_canvas<'a>(
+    data: &'a State,
+    tenant: Option<&TenantConfig>,
+    mode: &PrintMode,
+    drawing: Drawing,
+    options: JobOptions,
+    canvas: &CanvasConfig,
+    submissions: &mut Vec<Submission<'a>>,
+) -> JsonResult<()> {
+    if options.placement == Placement::Random || options.on_collision == CollisionPolicy::Shift {
+        return Err(JsonError::ClientError(ErrorDetails::from(
+            "Drawings on a canvas can't be moved",
+        )));
+    }
+    if !options.eraser.is_empty() || options.erase_region.is_some() {
+        return Err(JsonError::ClientError(ErrorDetails::from(
+            "Eraser strokes can't be used on a canvas",
+        )));
+    }
+    let layout = canvas.layout;
+    for (i, board) in canvas.boards.iter().enumerate() {
+        let part = drawing.clone().map_polylines(move |polylines| {
+            let (first, second) = scaling::split_at_seam(&polylines, layout);
+            if i == 0 {
+                first
+            } else {
+                second
+            }
+        });
+        if matches!(part, Drawing::Static(ref polylines) if polylines.is_empty()) {
+            continue;
+        }
+        let options = JobOptions {
+            board: Some(board.clone()),
+            ..options.clone()
+        };
+        prepare(data, tenant, mode, part, options, submissions)?;
+    }
+    Ok(())
+}
+
+/// Sort the polylines of the drawing by the draw order.
+fn apply_order(order: DrawOrder, drawing: Drawing) -> Drawing {
+    match order {
+        DrawOrder::Document => drawing,
+        order => drawing.map_polylines(move |polylines| order.apply(polylines)),
+    }
+}
+
+/// Join the polylines of the drawing whose ends coincide, if a merge
+/// distance is set.
+fn apply_merge(max_distance: Option<f64>, drawing: Drawing) -> Drawing {
+    match max_distance {
+        Some(max_distance) => {
+            drawing.map_polylines(move |polylines| merge::merge(polylines, max_distance))
+        }
+        None => drawing,
+    }
+}
+
+/// Fill the closed shapes of the drawing with hatch lines, if configured.
+fn apply_hatch(hatch: Option<HatchFill>, drawing: Drawing) -> Drawing {
+    match hatch {
+        Some(hatch) => drawing.map_polylines(move |polylines| hatch.apply(polylines)),
+        None => drawing,
+    }
+}
+
+/// Simplify the polylines of the drawing, if a tolerance is set.
+fn apply_simplify(tolerance: Option<f64>, drawing: Drawing) -> Drawing {
+    match tolerance {
+        Some(tolerance) => {
+            drawing.map_polylines(move |polylines| simplify::simplify(polylines, tolerance))
+        }
+        None => drawing,
+    }
+}
+
+/// Draw every polyline of the drawing several times, if configured.
+fn apply_passes(passes: Option<u32>, offset: Option<f64>, drawing: Drawing) -> Drawing {
+    match passes {
+        Some(passes) if passes > 1 => drawing.map_polylines(move |polylines| {
+            passes::overdraw(polylines, passes, offset.unwrap_or(0.0))
+        }),
+        _ => drawing,
+    }
+}
+
+/// Apply the pen profile (if any) to the drawing.
+fn apply_pen(pen: Option<PenProfile>, drawing: Drawing) -> Drawing {
+    match pen {
+        Some(pen) => drawing.map_polylines(move |polylines| pen.apply(polylines)),
+        None => drawing,
+    }
+}
+
+/// Adjust the density of the points of the drawing to the speed.
+fn apply_speed(speed: Speed, drawing: Drawing) -> Drawing {
+    match speed {
+        Speed::Normal => drawing,
+        speed => drawing.map_polylines(move |polylines| speed.apply(polylines)),
+    }
+}
+
+/// Drop the points of the drawing that are too close to each other, if a
+/// minimum spacing is configured.
+fn apply_resample(min_spacing: Option<f64>, drawing: Drawing) -> Drawing {
+    match min_spacing {
+        Some(min_spacing) => {
+            drawing.map_polylines(move |polylines| speed::resample(polylines, min_spacing))
+        }
+        None => drawing,
+    }
+}
+
+/// Check a single print before accepting it, and reject it with all
+/// violated constraints. Generated drawings are generated here, so that the
+/// printed drawing is the checked one. Urgent jobs drawn in slow mode are
+/// checked against the slow mode window instead of the time limits.
+fn preflight(
+    data: &State,
+    tenant: Option<&TenantConfig>,
+    drawing: Drawing,
+    dry_run: bool,
+    placement: Placement,
+    slow: Option<&SlowWindow>,
+) -> JsonResult<Drawing> {
+    let content = drawing
+        .content()
+        .map_err(|e| JsonError::ClientError(ErrorDetails::from(e)))?;
+    let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+    let quota_remaining = match tenant {
+        Some(tenant) => data
+            .quotas
+            .lock()
+            .map_err(|e| {
+                JsonError::ServerError(ErrorDetails::from(format!(
+                    "Could not access quotas: {}",
+                    e
+                )))
+            })?
+            .remaining(tenant, now.date()),
+        None => None,
+    };
+    let time_limits = match slow {
+        Some(slow) => Some(slow.window),
+        None => timelimits::current(&data.time_limits),
+    };
+    let always_dry_run = data.config.dry_run.as_ref().is_some_and(|c| c.always);
+    let estimate = match time_limits {
+        Some(_) if !dry_run && !always_dry_run => Some(preflight::estimate(
+            &content.polylines,
+            placement == Placement::Fixed,
+            data.config.orientation,
+            data.config.simulation,
+        )),
+        _ => None,
+    };
+    let limits = preflight::Limits {
+        time_limits,
+        now: now.time(),
+        quota_remaining,
+        // Without a configured margin, the whole board may be used
+        margin: data.config.margin_mm.unwrap_or(0.0),
+    };
+    let violations = preflight::check(&content.polylines, placement, estimate, &limits);
+    if !violations.is_empty() {
+        return Err(reject(violations));
+    }
+    match content.polylines {
+        // Static drawings are returned as they are, without copying them
+        Cow::Borrowed(_) => Ok(drawing),
+        Cow::Owned(_) => Ok(Drawing::from_content(content)),
+    }
+}
+
+/// Check that a static drawing lies within the board before it is scheduled.
+/// Generated drawings are generated anew every time they are drawn.
+fn check_scheduled_bounds(data: &State, drawing: &Drawing, placement: Placement) -> JsonResult<()> {
+    let Drawing::Static(polylines) = drawing else {
+        return Ok(());
+    };
+    let margin = data.config.margin_mm.unwrap_or(0.0);
+    match preflight::check_bounds(polylines, placement, margin) {
+        Some(violation) => Err(reject(vec![violation])),
+        None => Ok(()),
+    }
+}
+
+/// Return the error for a job that violates the constraints.
+fn reject(violations: Vec<Violation>) -> JsonError {
+    let details = violations
+        .iter()
+        .map(|violation| violation.message.as_str())
+        .collect::<Vec<_>>()
+        .join(" ");
+    JsonError::Rejected(Rejection {
+        details: format!("The job was rejected: {}", details),
+        violations,
+    })
+}
+
+/// Count print jobs of the tenant, failing if its daily quota doesn't allow
+/// all of them.
+fn check_quota(data: &State, tenant: &TenantConfig, jobs: u32) -> JsonResult<()> {
+    let today = OffsetDateTime::now_local()
+        .unwrap_or_else(|_| OffsetDateTime::now_utc())
+        .date();
+    let allowed = data
+        .quotas
+        .lock()
+        .map_err(|e| {
+            JsonError::ServerError(ErrorDetails::from(format!(
+                "Could not access quotas: {}",
+                e
+            )))
+        })?
+        .try_use(tenant, today, jobs);
+    if !allowed {
+        return Err(JsonError::TooManyRequests(
+            ErrorDetails::from(format!(
+                "Tenant {} has exhausted its daily quota",
+                tenant.name
+            ))
+            .with_code(ErrorCode::QuotaExhausted),
+        ));
+    }
+    Ok(())
+}
+
+/// Give back print jobs of the tenant that were not accepted after all.
+fn refund_quota(data: &State, tenant: &TenantConfig, jobs: u32) {
+    let today = OffsetDateTime::now_local()
+        .unwrap_or_else(|_| OffsetDateTime::now_utc())
+        .date();
+    match data.quotas.lock() {
+        Ok(mut quotas) => quotas.refund(tenant, today, jobs),
+        Err(e) => error!("Could not unlock quotas mutex: {}", e),
+    }
+}
+
+/// Create a drawing from a template SVG. The template is expanded and fitted
+/// to the board every time it is drawn.
+fn template_drawing(
+    name: String,
+    svg: String,
+    variables: HashMap<String, String>,
+    counters: SharedCounters,
+    margin: f64,
+) -> Drawing {
+    Drawing::Generated(Generator::new(move || {
+        let counter = counters
+            .lock()
+            .map_err(|e| format!("Could not access template counters: {}", e))?
+            .next(&name);
+        let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+        let expanded = template::expand(&svg, &variables, counter, now)?;
+        let mut polylines = svg::parse(&expanded, SVG2POLYLINES_TOLERANCE)?;
+        scaling::fit_polylines(&mut polylines, &Bounds::drawable(margin))?;
+        Ok(Content {
+            key: Some(template::key(&name, &expanded)),
+            polylines: polylines.into(),
+        })
+    }))
+}
+
+/// Return the board with the specified name, or the default board.
+fn find_board<'a>(data: &'a State, name: Option<&str>) -> JsonResult<&'a BoardState> {
+    match name {
+        Some(name) => data
+            .boards
+            .iter()
+            .find(|board| board.name == name)
+            .ok_or_else(|| {
+                JsonError::ClientError(ErrorDetails::from(format!("Unknown board: {}", name)))
+            }),
+        None => Ok(&data.boards[0]),
+    }
+}
+
+/// Check that the robot of the board is online and can draw jobs.
+fn check_robot(board: &BoardState) -> JsonResult<()> {
+    let status = board.status.lock().map_err(|e| {
+        JsonError::ServerError(ErrorDetails::from(format!(
+            "Could not access status: {}",
+            e
+        )))
+    })?;
+    if status.offline() {
+        return Err(JsonError::Unavailable(
+            ErrorDetails::from(format!(
+                "Board {}: The robot is offline, only previews are available",
+                board.name
+            ))
+            .with_code(ErrorCode::RobotOffline),
+        ));
+    }
+    if let Some(reason) = status.firmware().and_then(Firmware::incompatibility) {
+        return Err(JsonError::Unavailable(ErrorDetails::from(format!(
+            "Board {}: {}",
+            board.name, reason
+        ))));
+    }
+    Ok(())
+}
+
+/// The error for a full queue of the board.
+fn queue_full(board: &BoardState, queued: usize) -> JsonError {
+    JsonError::QueueFull(QueueFull {
+        details: format!("The queue of board {} is full, try again later", board.name),
+        queued,
+        capacity: board.queue_depth,
+    })
+}
+
+/// Send a task to the robot thread of the board. If the jobs waiting for the
+/// robot already fill the queue, new jobs are refused instead of piling up
+/// in memory. Tasks for a robot whose firmware can't draw them are refused.
+fn send_task(board: &BoardState, task: PrintTask) -> JsonResult<()> {
+    if !matches!(task, PrintTask::Shutdown(_)) {
+        check_robot(board)?;
+    }
+    if matches!(task, PrintTask::Once(_) | PrintTask::Job(..)) {
+        let queued = lock_jobs(board)?.len();
+        if queued >= board.queue_depth {
+            return Err(queue_full(board, queued));
+        }
+    }
+
+    // Get access to queue
+    let tx = board.robot_queue.lock().map_err(|e| {
+        JsonError::ClientError(ErrorDetails::from(format!(
+            "Could not communicate with robot thread: {}",
+            e
+        )))
+    })?;
+    tx.try_send(task).map_err(|e| match e {
+        TrySendError::Full(_) => queue_full(board, board.queue_depth),
+        TrySendError::Disconnected(_) => JsonError::ServerError(ErrorDetails::from(
+            "Could not send print request to robot thread: The thread is gone",
+        )),
+    })
+}
+
+/// Return the schedule configured for headless mode: All SVG files, the
+/// countdown and the generators.
+fn headless_schedules(config: &Config) -> Result<Schedules, HeadlessError> {
+    let playlist = get_svg_files(&config.svg_dir)?;
+    if playlist.is_empty() && config.generators.is_empty() {
+        return Err(HeadlessError::NoFiles);
+    }
+    Ok(Schedules {
+        interval_seconds: config.interval_seconds,
+        jitter_seconds: config.jitter_seconds,
+        time_limits: config.time_limits,
+        playlist,
+        generators: config.generators.clone(),
+        countdown: config.countdown.clone(),
+        rotation: config.rotation,
+        weights: config.weights.clone(),
+    })
+}
+
+/// Return the drawings of a schedule: The SVG files of the playlist, the
+/// countdown and the generators. The drawings are fitted into the region, if
+/// any.
+fn schedule_drawings(
+    config: &Config,
+    counters: &SharedCounters,
+    schedules: &Schedules,
+    region: Option<&Bounds>,
+) -> Result<Vec<Drawing>, HeadlessError> {
+    // Read SVG files
+    let mut svgs = vec![];
+    let base_path = Path::new(&config.svg_dir);
+    for file in &schedules.playlist {
+        let mut svg = String::new();
+        let mut f = File::open(base_path.join(file))?;
+        f.read_to_string(&mut svg)?;
+        svgs.push(svg);
+    }
+
+    // Specify target area bounds
+    let bounds = match region {
+        Some(region) => region.clone(),
+        None => Bounds::drawable(config.margin()),
+    };
+
+    // Parse SVG strings into lists of polylines. Templates are expanded
+    // every time they are drawn.
+    let mut drawings: Vec<Drawing> = schedules
+        .playlist
+        .iter()
+        .zip(svgs)
+        .map(|(file, svg)| {
+            if template::is_template(&svg) {
+                return Ok(template_drawing(
+                    file.clone(),
+                    svg,
+                    HashMap::new(),
+                    counters.clone(),
+                    config.margin(),
+                ));
+            }
+            svg::parse(&svg, SVG2POLYLINES_TOLERANCE)
+                .map_err(HeadlessError::SvgParse)
+                .and_then(|mut polylines| {
+                    scaling::fit_polylines(&mut polylines, &bounds)
+                        .map_err(HeadlessError::PolylineScale)?;
+                    Ok(Drawing::Static(polylines))
+                })
+        })
+        .collect::<Result<Vec<_>, HeadlessError>>()?;
+
+    // The countdown is part of the rotation
+    if let Some(ref countdown) = schedules.countdown {
+        drawings.push(countdown.drawing(config.margin()));
+    }
+
+    // Generated art is part of the rotation as well
+    for generator in &schedules.generators {
+        drawings.push(generator.drawing(config.margin()));
+    }
+
+    Ok(drawings
+        .into_iter()
+        .map(|drawing| match (region, drawing) {
+            // Generated drawings fill the board, so they are fitted into the
+            // region every time they are drawn
+            (Some(_), drawing @ Drawing::Generated(_)) => {
+                let bounds = bounds.clone();
+                drawing.map_polylines(move |mut polylines| {
+                    if let Err(e) = scaling::fit_polylines(&mut polylines, &bounds) {
+                        warn!("Could not fit drawing into the region: {}", e);
+                    }
+                    polylines
+                })
+            }
+            (_, drawing) => drawing,
+        })
+        .map(|drawing| apply_merge(config.merge_distance, drawing))
+        .map(|drawing| apply_hatch(config.hatch, drawing))
+        .map(|drawing| apply_simplify(config.simplify_tolerance, drawing))
+        .map(|drawing| apply_passes(config.passes, config.pass_offset, drawing))
+        .map(|drawing| apply_order(config.draw_order, drawing))
+        .map(|drawing| apply_speed(config.speed, apply_pen(config.pen, drawing)))
+        .map(|drawing| apply_resample(config.min_point_spacing, drawing))
+        .collect())
+}
+
+/// Return the rotator of a schedule, which weighs the items and orders the
+/// SVG files by their modification time.
+fn schedule_rotator(config: &Config, schedules: &Schedules, item_names: &[String]) -> Rotator {
+    let base_path = Path::new(&config.svg_dir);
+    let items = item_names
+        .iter()
+        .map(|name| rotation::Item {
+            weight: schedules.weight(name),
+            modified: schedules
+                .playlist
+                .contains(name)
+                .then(|| {
+                    fs::metadata(base_path.join(name))
+                        .and_then(|m| m.modified())
+                        .ok()
+                })
+                .flatten(),
+        })
+        .collect();
+    Rotator::new(schedules.rotation, items)
+}
+
+/// Start the rotation of a schedule, replacing the current schedule. The
+/// rotation can be resumed with the item named `resume`.
+fn start_schedules(
+    robot_queue: &RobotQueue,
+    status: &SharedStatus,
+    counters: &SharedCounters,
+    config: &Config,
+    schedules: &Schedules,
+    resume: Option<&str>,
+) -> Result<(), HeadlessError> {
+    let drawings = schedule_drawings(config, counters, schedules, None)?;
+
+    // Get access to queue
+    let tx = robot_queue.lock().map_err(|e| {
+        HeadlessError::Queue(format!("Could not communicate with robot thread: {}", e))
+    })?;
+
+    // Name the scheduled items after their files, and weigh them
+    let item_names = schedules.item_names();
+    let mut rotator = schedule_rotator(config, schedules, &item_names);
+    if let Some(index) = resume.and_then(|name| item_names.iter().position(|n| n == name)) {
+        info!("Resuming the rotation with {}", item_names[index]);
+        rotator.resume_at(index);
+    }
+    status::update(status, |status| status.set_item_names(item_names));
+
+    // Create print task
+    let interval_duration = Duration::from_secs(schedules.interval_seconds);
+    let jitter = Duration::from_secs(schedules.jitter_seconds);
+    let task = PrintTask::Scheduled(interval_duration, drawings, rotator, jitter);
+
+    // Send task to robot
+    tx.send(task).map_err(|e| {
+        HeadlessError::Queue(format!(
+            "Could not send print request to robot thread: {}",
+            e
+        ))
+    })?;
+
+    info!("Printing...");
+    Ok(())
+}
+
+/// Start a named schedule alongside the main schedule.
+fn start_named_schedule(
+    robot_queue: &RobotQueue,
+    counters: &SharedCounters,
+    config: &Config,
+    named: &NamedSchedule,
+) -> Result<(), HeadlessError> {
+    let schedules = &named.schedule;
+    let region = named
+        .region
+        .as_ref()
+        .map(|region| region.bounds(&config.regions))
+        .transpose()
+        .map_err(HeadlessError::PolylineScale)?;
+    let drawings = schedule_drawings(config, counters, schedules, region.as_ref())?;
+    let item_names = schedules.item_names();
+    let rotator = schedule_rotator(config, schedules, &item_names);
+    let task = PrintTask::Named(NamedTask {
+        name: named.name.clone(),
+        interval: Duration::from_secs(schedules.interval_seconds),
+        jitter: Duration::from_secs(schedules.jitter_seconds),
+        drawings,
+        item_names,
+        rotator,
+        time_limits: schedules.time_limits,
+        region,
+    });
+    let tx = robot_queue.lock().map_err(|e| {
+        HeadlessError::Queue(format!("Could not communicate with robot thread: {}", e))
+    })?;
+    tx.send(task).map_err(|e| {
+        HeadlessError::Queue(format!(
+            "Could not send print request to robot thread: {}",
+            e
+        ))
+    })
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    // Parse args
+    let args: Args = Docopt::new(USAGE)
+        .and_then(|d| d.deserialize())
+        .unwrap_or_else(|e| e.exit());
+
+    // Show version and exit
+    if args.flag_version {
+        println!("{} v{}", NAME, VERSION);
+        process::exit(0);
+    }
+
+    // Init logger
+    let log_filter = match args.flag_log {
+        Some(ref filter) => filter.parse().unwrap_or_else(|e| {
+            eprintln!("Invalid log filter ({}): {}", filter, e);
+            process::exit(1);
+        }),
+        None if args.flag_debug => LogFilter::new(LevelFilter::Debug),
+        None => LogFilter::new(LevelFilter::Info),
+    };
+    let term_logger = TermLogger::new(
+        LevelFilter::Trace,
+        LogConfig::default(),
+        TerminalMode::Mixed,
+        ColorChoice::Auto,
+    );
+    let logger = FilteredLogger::init(log_filter, term_logger).unwrap_or_else(|e| {
+        eprintln!("Could not initialize logger: {}", e);
+        process::exit(1);
+    });
+
+    // Replay a serial capture
+    if args.cmd_replay {
+        match capture::replay(&args.arg_capture, &args.arg_device) {
+            Ok(sent) => {
+                info!("Replayed {} record(s)", sent);
+                process::exit(0);
+            }
+            Err(e) => {
+                error!("Could not replay {}: {}", &args.arg_capture, e);
+                abort(ErrorCode::ReplayFailed);
+            }
+        }
+    }
+
+    // Headless mode
+    let headless_mode: bool = args.flag_headless;
 
     // Parse config
     let configfile = File::open(&args.flag_c).unwrap_or_else(|e| {
         error!("Could not open configfile ({}): {}", &args.flag_c, e);
-        abort(1);
+        abort(ErrorCode::ConfigUnreadable);
+    });
+    let mut config: serde_json::Value = serde_json::from_reader(configfile).unwrap_or_else(|e| {
+        error!("Could not parse configfile ({}): {}", &args.flag_c, e);
+        abort(ErrorCode::ConfigUnreadable);
+    });
+
+    // Upgrade configs of older versions in place
+    let migrated = migrate::migrate(&mut config).unwrap_or_else(|e| {
+        error!("Could not migrate configfile ({}): {}", &args.flag_c, e);
+        abort(ErrorCode::ConfigUnreadable);
     });
-    let config: RawConfig = serde_json::from_reader(configfile).unwrap_or_else(|e| {
+    if migrated {
+        match migrate::save(&args.flag_c, &config) {
+            Ok(backup) => info!("Migrated configfile {} (backup: {})", &args.flag_c, backup),
+            Err(e) => error!(
+                "Could not save migrated configfile ({}): {}",
+                &args.flag_c, e
+            ),
+        }
+    }
+    let config: RawConfig = serde_json::from_value(config).unwrap_or_else(|e| {
         error!("Could not parse configfile ({}): {}", &args.flag_c, e);
-        abort(1);
+        abort(ErrorCode::ConfigUnreadable);
     });
 
+    // The log levels of the command line take precedence
+    if let Some(ref levels) = config.log_levels {
+        match levels.parse() {
+            _ if args.flag_log.is_some() || args.flag_debug => {
+                info!("Note: Ignoring log_levels key, since log levels are set on the command line")
+            }
+            Ok(filter) => logger.set_filter(filter),
+            Err(e) => {
+                error!("Invalid log_levels: {}", e);
+                abort(ErrorCode::ConfigUnreadable);
+            }
+        }
+    }
+
     // Check if this is an active config
     match Config::from(&config) {
-        Some(c) => main_active(c, headless_mode).await,
+        Some(mut c) => {
+            c.serial_console |= args.flag_debug;
+            main_active(c, &args.flag_c, headless_mode).await
+        }
         None => main_preview(PreviewConfig::from(&config)).await,
     }
 }
 
 /// Start the web server in active (printing) mode.
-async fn main_active(config: Config, headless_mode: bool) -> std::io::Result<()> {
+async fn main_active(
+    config: Config,
+    config_file: &str,
+    headless_mode: bool,
+) -> std::io::Result<()> {
     info!("Starting server in active mode (with robot attached)");
 
     // Check for presence of relevant paths
-    let device_path = Path::new(&config.device);
-    if !device_path.exists() {
-        error!("Device {} does not exist", &config.device);
-        abort(2);
+    if let Err(e) = boards::validate(&config.boards) {
+        error!("Invalid boards: {}", e);
+        abort(ErrorCode::InvalidConfig);
+    }
+    if let Err(e) = boards::validate_canvases(&config.canvases, &config.boards) {
+        error!("Invalid canvases: {}", e);
+        abort(ErrorCode::InvalidConfig);
+    }
+    if let Err(e) = config.orientation.validate() {
+        error!("Invalid orientation: {}", e);
+        abort(ErrorCode::InvalidConfig);
+    }
+    if let Err(e) = config.pen_servo.validate() {
+        error!("Invalid pen_servo: {}", e);
+        abort(ErrorCode::InvalidConfig);
+    }
+    if let Some(Err(e)) = config.paper_roll.map(|roll| roll.validate()) {
+        error!("Invalid paper_roll: {}", e);
+        abort(ErrorCode::InvalidConfig);
+    }
+    if let Err(e) = importers::validate(&config.accepted_types) {
+        error!("Invalid accepted_types: {}", e);
+        abort(ErrorCode::InvalidConfig);
+    }
+    let max_margin = f64::from(robot::IBB_HEIGHT) / 2.0;
+    if config
+        .margin_mm
+        .is_some_and(|margin| !(0.0..max_margin).contains(&margin))
+    {
+        error!(
+            "Invalid margin_mm: The margin must be between 0 and {} mm",
+            max_margin
+        );
+        abort(ErrorCode::InvalidConfig);
+    }
+    if let Some(Err(e)) = config.merge_distance.map(merge::validate) {
+        error!("Invalid merge_distance: {}", e);
+        abort(ErrorCode::InvalidConfig);
+    }
+    if let Some(Err(e)) = config.hatch.map(|hatch| hatch.validate()) {
+        error!("Invalid hatch: {}", e);
+        abort(ErrorCode::InvalidConfig);
+    }
+    if let Err(e) = passes::validate(
+        config.passes.unwrap_or(1),
+        config.pass_offset.unwrap_or(0.0),
+    ) {
+        error!("Invalid passes: {}", e);
+        abort(ErrorCode::InvalidConfig);
+    }
+    if let Some(Err(e)) = config.min_point_spacing.map(speed::validate_spacing) {
+        error!("Invalid min_point_spacing: {}", e);
+        abort(ErrorCode::InvalidConfig);
+    }
+    if let Some(Err(e)) = config.simplify_tolerance.map(simplify::validate) {
+        error!("Invalid simplify_tolerance: {}", e);
+        abort(ErrorCode::InvalidConfig);
+    }
+    if let Some(Err(e)) = config.voting.map(|voting| voting.validate()) {
+        error!("Invalid voting: {}", e);
+        abort(ErrorCode::InvalidConfig);
+    }
+    if config.jitter_seconds * 2 >= config.interval_seconds && config.jitter_seconds > 0 {
+        error!("Invalid jitter_seconds: The jitter must be less than half of the interval");
+        abort(ErrorCode::InvalidConfig);
+    }
+    if config.admin_token.as_deref() == Some("") {
+        error!("Invalid admin_token: The token must not be empty");
+        abort(ErrorCode::InvalidConfig);
+    }
+    if let Err(e) = tenants::validate(&config.tenants, config.admin_token.as_deref()) {
+        error!("Invalid tenants: {}", e);
+        abort(ErrorCode::InvalidConfig);
+    }
+    if let Err(e) = validate_regions(&config.regions) {
+        error!("Invalid regions: {}", e);
+        abort(ErrorCode::InvalidConfig);
+    }
+    if let Err(e) = schedules::validate_named(&config.named_schedules, &config.regions) {
+        error!("Invalid named_schedules: {}", e);
+        abort(ErrorCode::InvalidConfig);
+    }
+    if config.queue_depth == 0 {
+        error!("Invalid queue_depth: The queue must hold at least one job");
+        abort(ErrorCode::InvalidConfig);
+    }
+    for board in &config.boards {
+        let device_path = Path::new(&board.device);
+        let is_file = board.device != AUTO_DEVICE
+            && board.device != MOCK_DEVICE
+            && !board.device.starts_with(TCP_PREFIX);
+        if is_file && !device_path.exists() {
+            warn!(
+                "Device {} does not exist (yet), waiting for it to appear",
+                &board.device
+            );
+        }
     }
     let svg_dir_path = Path::new(&config.svg_dir);
     if !svg_dir_path.exists() || !svg_dir_path.is_dir() {
         error!("SVG dir {} does not exist", &config.svg_dir);
-        abort(2);
+        abort(ErrorCode::InvalidConfig);
     }
 
-    // Launch robot thread
-    let baud_rate = BaudRate::Baud115200;
-    let tx = robot::communicate(&config.device, baud_rate, config.time_limits);
+    let holidays = Holidays::new_shared(config.holidays.as_ref());
+    if let Some(ref holiday_config) = config.holidays {
+        holidays::spawn(holiday_config, holidays.clone());
+    }
 
-    // Initialize server state
-    let robot_queue = Arc::new(Mutex::new(tx));
-    let state = web::Data::new(State {
-        config: config.clone(),
-        robot_queue: robot_queue.clone(),
-    });
+    // Launch a robot thread for every board
+    let baud_rate = BaudRate::Baud115200;
+    let robot_config = RobotConfig {
+        stall_timeout: Duration::from_secs(config.stall_timeout_seconds),
+        queue_depth: config.queue_depth,
+        simulation: config.simulation,
+        incremental: config.incremental,
+        orientation: config.orientation,
+        servo: config.pen_servo,
+        feed_mm: config.paper_roll.map_or(0, |roll| roll.feed_mm),
+        dry_run: config.dry_run.clone(),
+        capture: config.serial_capture.clone(),
+        alerts: config.alerts,
+        write_policy: config.write_policy,
+        offline_after: config.offline_after_seconds.map(Duration::from_secs),
+        redraw_after_restart: config.redraw_after_restart,
+        holidays: holidays.clone(),
+    };
+    let feed = feed::new();
+    let events = notify::spawn(
+        config.notifications.clone(),
+        config.rules.clone(),
+        feed.clone(),
+    );
+    let watchdog = Watchdog::new_shared();
+    if let Some(watchdog_config) = config.watchdog.clone() {
+        let path = watchdog_config
+            .path
+            .clone()
+            .unwrap_or_else(|| config.svg_dir.clone());
+        watchdog::spawn(watchdog_config, path, watchdog.clone(), events.clone());
+    }
+    let archive = config.archive.clone().map(archive::spawn);
+    let counters = Counters::new_shared();
+    if let Some(ref dir) = config.state_dir {
+        if let Err(e) = fs::create_dir_all(dir) {
+            error!("Could not create state dir {}: {}", dir, e);
+        }
+    }
+    let time_limits: SharedTimeLimits = Arc::new(Mutex::new(config.time_limits));
+    let registry = JobRegistry::with_feed(feed.clone());
+    let board_states: Vec<BoardState> = config
+        .boards
+        .iter()
+        .enumerate()
+        .map(|(i, board)| {
+            info!("Starting robot thread of board {}", board.name);
+            let history = History::new_shared(
+                config
+                    .state_dir
+                    .as_ref()
+                    .map(|dir| Path::new(dir).join(boards::state_file("heatmap", i, board))),
+            );
+            let status = Status::new_shared();
+            let console = config.serial_console.then(console::new);
+            let reporting = Reporting {
+                events: events.clone(),
+                history: history.clone(),
+                registry: registry.clone(),
+                status: status.clone(),
+                console: console.clone(),
+                watchdog: watchdog.clone(),
+                archive: archive.clone(),
+                board_file: config
+                    .state_dir
+                    .as_ref()
+                    .map(|dir| Path::new(dir).join(boards::state_file("board", i, board))),
+                rotation_file: config
+                    .state_dir
+                    .as_ref()
+                    .map(|dir| Path::new(dir).join(boards::state_file("rotation", i, board))),
+            };
+            let last_key_file = config
+                .state_dir
+                .as_ref()
+                .map(|dir| Path::new(dir).join(boards::state_file("last_key", i, board)));
+            let roll = config.paper_roll.map(|roll_config| {
+                let roll_file = config
+                    .state_dir
+                    .as_ref()
+                    .map(|dir| Path::new(dir).join(boards::state_file("paper_roll", i, board)));
+                PaperRoll::new(roll_config, roll_file)
+            });
+            let jobs = JobQueue::new_shared(
+                config.scheduling,
+                last_key_file,
+                registry.clone(),
+                &board.name,
+            );
+            let tx = robot::communicate(
+                &board.device,
+                baud_rate,
+                time_limits.clone(),
+                robot_config.clone(),
+                jobs.clone(),
+                roll,
+                reporting,
+            );
+            BoardState {
+                name: board.name.clone(),
+                robot_queue: Arc::new(Mutex::new(tx)),
+                queue_depth: config.queue_depth,
+                jobs,
+                history,
+                status,
+                servo: Arc::new(Mutex::new(config.pen_servo)),
+                console,
+            }
+        })
+        .collect();
+    // The schedules and the agenda are drawn on the default board
+    let robot_queue = board_states[0].robot_queue.clone();
+    let status = board_states[0].status.clone();
 
     // Print mode
     match headless_mode {
@@ -490,25 +3985,176 @@ async fn main_active(config: Config, headless_mode: bool) -> std::io::Result<()>
         false => info!("Starting in normal mode"),
     };
 
-    // If we're in headless mode, start the print jobs
-    if headless_mode {
-        headless_start(robot_queue.clone(), &config).unwrap_or_else(|e| {
+    // Resume an imported schedule after a restart. Otherwise, in headless
+    // mode, draw all SVG files, or only draw the countdown every day.
+    let restored = schedules_file(&config).and_then(|path| schedules::load(&path));
+    let schedules = if let Some(restored) = restored {
+        info!("Restoring the imported schedule");
+        match time_limits.lock() {
+            Ok(mut time_limits) => *time_limits = restored.time_limits,
+            Err(e) => error!("Could not access time limits: {}", e),
+        }
+        restored
+    } else if headless_mode {
+        headless_schedules(&config).unwrap_or_else(|e| {
             error!("Could not start headless mode: {}", e);
-            abort(3);
+            abort(e.code());
+        })
+    } else {
+        Schedules {
+            interval_seconds: match config.countdown {
+                Some(_) => 24 * 60 * 60,
+                None => config.interval_seconds,
+            },
+            jitter_seconds: 0,
+            time_limits: config.time_limits,
+            playlist: vec![],
+            countdown: config.countdown.clone(),
+            generators: vec![],
+            rotation: config.rotation,
+            weights: BTreeMap::new(),
+        }
+    };
+    if schedules.len() > 0 {
+        let resume = config
+            .state_dir
+            .as_ref()
+            .and_then(|dir| fs::read_to_string(Path::new(dir).join("rotation")).ok());
+        start_schedules(
+            &robot_queue,
+            &status,
+            &counters,
+            &config,
+            &schedules,
+            resume.as_deref(),
+        )
+        .unwrap_or_else(|e| {
+            error!("Could not start schedule: {}", e);
+            abort(e.code());
+        });
+    }
+
+    // The named schedules run alongside the main schedule. Schedules that
+    // were edited at runtime replace the ones of the config.
+    let named_schedules = named_schedules_file(&config)
+        .and_then(|path| schedules::load_named(&path, &config.regions))
+        .unwrap_or_else(|| config.named_schedules.clone());
+    for named in &named_schedules {
+        start_named_schedule(&robot_queue, &counters, &config, named).unwrap_or_else(|e| {
+            error!("Could not start schedule {}: {}", named.name, e);
+            abort(e.code());
         });
     }
 
+    // Tenants that were managed at runtime replace the ones of the config
+    let tenants = tenants_file(&config)
+        .and_then(|path| tenants::load(&path, config.admin_token.as_deref()))
+        .unwrap_or_else(|| config.tenants.clone());
+
+    // Initialize server state
+    let voting = config.voting.map(VotingPool::new_shared);
+    let state = web::Data::new(State {
+        config: config.clone(),
+        config_file: config_file.to_string(),
+        boards: board_states,
+        quotas: Quotas::new_shared(),
+        counters: counters.clone(),
+        registry,
+        feed,
+        schedules: schedules.into_shared(),
+        named_schedules: Arc::new(Mutex::new(named_schedules)),
+        tenants: Arc::new(Mutex::new(tenants)),
+        time_limits: time_limits.clone(),
+        holidays: holidays.clone(),
+        voting: voting.clone(),
+        watchdog,
+    });
+
+    // Draw the top-voted entry every interval
+    if let (Some(pool), Some(voting_config)) = (voting, config.voting) {
+        let interval = Duration::from_secs(voting_config.interval_seconds);
+        voting::spawn(pool, interval, robot_queue.clone());
+    }
+
+    // Draw the agenda every morning
+    if let Some(agenda_config) = config.agenda.clone() {
+        agenda::spawn(
+            agenda_config,
+            config.margin(),
+            time_limits,
+            holidays.clone(),
+            robot_queue.clone(),
+        );
+    }
+
     // Start web server
     let interface = config.listen.clone();
     info!("Listening on {}", interface);
-    HttpServer::new(move || {
+    let alerts = config.alerts;
+    let boards = state.boards.clone();
+    let result = HttpServer::new(move || {
+        let events = events.clone();
         let mut app = App::new()
             .app_data(state.clone())
+            .app_data(web::PayloadConfig::new(PDF_SIZE_LIMIT))
+            .wrap_fn(move |req, srv| {
+                let started = Instant::now();
+                let path = req.path().to_string();
+                let response = srv.call(req);
+                let events = events.clone();
+                async move {
+                    let response = response.await;
+                    check_request_duration(&alerts, &events, path, started.elapsed());
+                    response
+                }
+            })
             .service(static_files_handler)
             .service(config_handler)
+            .service(history_handler)
+            .service(heatmap_handler)
+            .service(jobs_handler)
+            .service(submit_job_handler)
+            .service(job_handler)
+            .service(kiosk_handler)
+            .service(robot_status_handler)
+            .service(watchdog_handler)
+            .service(serial_console_handler)
+            .service(event_feed_handler)
+            .service(event_ndjson_handler)
+            .service(erase_handler)
+            .service(calibrate_pen_handler)
+            .service(feed_handler)
+            .service(replace_roll_handler)
             .service(list_handler)
+            .service(library_upload_handler)
             .service(preview_handler)
-            .service(print_handler);
+            .service(preview_pdf_handler)
+            .service(analyze_handler)
+            .service(presets_handler)
+            .service(print_handler)
+            .service(print_pdf_handler)
+            .service(print_polylines_handler)
+            .service(print_template_handler)
+            .service(schedules_handler)
+            .service(schedules_import_handler)
+            .service(schedule_next_handler)
+            .service(schedule_pause_handler)
+            .service(schedule_resume_handler)
+            .service(schedule_skip_handler)
+            .service(named_schedules_handler)
+            .service(named_schedule_handler)
+            .service(named_schedule_put_handler)
+            .service(named_schedule_delete_handler)
+            .service(tenants_handler)
+            .service(tenant_put_handler)
+            .service(tenant_delete_handler)
+            .service(queue_export_handler)
+            .service(queue_import_handler)
+            .service(barcode_handler)
+            .service(chart_handler)
+            .service(generate_handler)
+            .service(vote_list_handler)
+            .service(vote_handler);
         if headless_mode {
             app = app.route(
                 "/",
@@ -529,20 +4175,91 @@ async fn main_active(config: Config, headless_mode: bool) -> std::io::Result<()>
     })
     .bind(interface)?
     .run()
-    .await
+    .await;
+
+    // The server stopped, e.g. because of SIGTERM
+    shutdown_robots(&boards);
+    result
+}
+
+/// Stop the robot threads, so that the pens are lifted and the carriages
+/// parked before the process exits. Robots that are offline or stalled
+/// can't be parked, so they aren't waited for.
+fn shutdown_robots(boards: &[BoardState]) {
+    let stopped: Vec<Receiver<()>> = boards
+        .iter()
+        .filter(|board| {
+            let error = board
+                .status
+                .lock()
+                .ok()
+                .and_then(|status| status.robot_error().map(str::to_string));
+            match error {
+                Some(error) => {
+                    warn!("Not parking the robot of board {}: {}", board.name, error);
+                    false
+                }
+                None => true,
+            }
+        })
+        .filter_map(|board| {
+            let (tx, rx) = channel();
+            // Unlike requests, the shutdown waits for room in the queue
+            let robot_queue = board.robot_queue.lock().ok()?;
+            robot_queue.send(PrintTask::Shutdown(tx)).ok().map(|_| rx)
+        })
+        .collect();
+    info!("Waiting for {} robot(s) to park...", stopped.len());
+    let deadline = Instant::now() + robot::SHUTDOWN_TIMEOUT + Duration::from_secs(5);
+    for rx in stopped {
+        if rx
+            .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            .is_err()
+        {
+            warn!("A robot thread did not stop in time");
+        }
+    }
+}
+
+/// Report requests that took longer than the configured threshold.
+fn check_request_duration(
+    alerts: &AlertConfig,
+    events: &Sender<Event>,
+    path: String,
+    elapsed: Duration,
+) {
+    if alerts.is_slow_request(elapsed) {
+        let millis = elapsed.as_millis() as u64;
+        warn!("Slow request: {} took {}ms", path, millis);
+        let _ = events.send(Event::SlowRequest { path, millis });
+    }
 }
 
 /// Start the web server in preview-only mode.
 async fn main_preview(config: PreviewConfig) -> std::io::Result<()> {
     info!("Starting server in preview-only mode");
 
+    let public = config.public.clone();
+    let payload_limit = public.max_payload_kb.map_or(PDF_SIZE_LIMIT, |kb| kb * 1024);
+    let rate_limiter = public.requests_per_minute.map(RateLimiter::new_shared);
+
     // Start web server
     let interface = config.listen.clone();
     info!("Listening on {}", interface);
     HttpServer::new(move || {
+        let public = public.clone();
+        let rate_limiter = rate_limiter.clone();
         App::new()
+            .app_data(web::PayloadConfig::new(payload_limit))
+            .app_data(web::JsonConfig::default().limit(payload_limit))
+            .wrap_fn(move |req, srv| {
+                let checked = check_public_access(&public, rate_limiter.as_ref(), &req);
+                let response = checked.map(|()| srv.call(req));
+                async move { response?.await }
+            })
             .service(static_files_handler)
             .service(preview_handler)
+            .service(preview_pdf_handler)
             .route(
                 "/",
                 web::get().to(|| async { handle_embedded_file("index-preview.html") }),
@@ -553,12 +4270,51 @@ async fn main_preview(config: PreviewConfig) -> std::io::Result<()> {
     .await
 }
 
-fn abort(exit_code: i32) -> ! {
+/// Check the password and the rate limit of a request to the public
+/// preview server. Only preview requests count towards the rate limit.
+fn check_public_access(
+    public: &PublicConfig,
+    rate_limiter: Option<&SharedRateLimiter>,
+    req: &ServiceRequest,
+) -> JsonResult<()> {
+    let authorization = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    if !public.is_authorized(authorization) {
+        return Err(JsonError::PasswordRequired(ErrorDetails::from(
+            "Password required",
+        )));
+    }
+    let (limiter, client) = match (rate_limiter, req.peer_addr()) {
+        (Some(limiter), Some(addr)) if req.path().starts_with("/preview") => (limiter, addr.ip()),
+        _ => return Ok(()),
+    };
+    let allowed = limiter
+        .lock()
+        .map_err(|e| {
+            JsonError::ServerError(ErrorDetails::from(format!(
+                "Could not access rate limiter: {}",
+                e
+            )))
+        })?
+        .try_request(client, Instant::now());
+    if !allowed {
+        return Err(JsonError::TooManyRequests(ErrorDetails::from(
+            "Too many preview requests, please try again in a minute",
+        )));
+    }
+    Ok(())
+}
+
+/// Exit with the exit code of the error.
+fn abort(code: ErrorCode) -> ! {
+    error!("Exiting ({})", code);
     io::stdout().flush().expect("Could not flush stdout");
     io::stderr().flush().expect("Could not flush stderr");
 
     // No idea why this is required, but otherwise the error log doesn't show up :(
     sleep(Duration::from_millis(100));
 
-    process::exit(exit_code);
+    process::exit(code.exit_code());
 }
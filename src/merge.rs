@@ -0,0 +1,130 @@
+//! Joining polylines whose end points coincide.
+//!
+//! CAD and Inkscape exports often fragment a single outline into dozens of
+//! paths, and the pen is lifted between every two of them. Polylines that
+//! start or end within the merge distance (in mm) of the end of another one
+//! are joined into a single polyline (reversed, if necessary).
+use svg2polylines::{CoordinatePair, Polyline};
+
+fn distance(a: &CoordinatePair, b: &CoordinatePair) -> f64 {
+    (b.x - a.x).hypot(b.y - a.y)
+}
+
+/// Append the polyline to the chain, without repeating a shared point.
+fn append(chain: &mut Polyline, polyline: Polyline) {
+    let mut points = polyline.into_iter().peekable();
+    if chain.last() == points.peek() {
+        points.next();
+    }
+    chain.extend(points);
+}
+
+/// Join the polylines whose end points are closer than `max_distance`. The
+/// polylines keep the order of their first part.
+pub(crate) fn merge(polylines: Vec<Polyline>, max_distance: f64) -> Vec<Polyline> {
+    let mut remaining: Vec<Option<Polyline>> = polylines
+        .into_iter()
+        .filter(|polyline| !polyline.is_empty())
+        .map(Some)
+        .collect();
+    let mut merged = vec![];
+    for i in 0..remaining.len() {
+        let Some(mut chain) = remaining[i].take() else {
+            continue;
+        };
+        // Extend the end of the chain, then its start, as long as another
+        // polyline continues it
+        for at_start in [false, true] {
+            if at_start {
+                chain.reverse();
+            }
+            loop {
+                let end = chain[chain.len() - 1];
+                let next = remaining.iter().enumerate().find_map(|(j, polyline)| {
+                    let polyline = polyline.as_ref()?;
+                    if distance(&end, &polyline[0]) <= max_distance {
+                        Some((j, false))
+                    } else if distance(&end, &polyline[polyline.len() - 1]) <= max_distance {
+                        Some((j, true))
+                    } else {
+                        None
+                    }
+                });
+                let Some((j, reverse)) = next else {
+                    break;
+                };
+                let mut polyline = remaining[j].take().unwrap_or_default();
+                if reverse {
+                    polyline.reverse();
+                }
+                append(&mut chain, polyline);
+            }
+            if at_start {
+                chain.reverse();
+            }
+        }
+        merged.push(chain);
+    }
+    merged
+}
+
+/// Check that the merge distance can be used.
+pub(crate) fn validate(max_distance: f64) -> Result<(), String> {
+    match max_distance.is_finite() && max_distance >= 0.0 {
+        true => Ok(()),
+        false => Err("The merge distance must not be negative".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::polyline;
+
+    #[test]
+    fn test_merge() {
+        // The sides of a square, fragmented and partly reversed, and a
+        // separate line
+        let polylines = vec![
+            polyline(&[(10.0, 0.0), (10.0, 10.0)]),
+            polyline(&[(50.0, 50.0), (60.0, 50.0)]),
+            polyline(&[(0.0, 10.0), (10.0, 10.05)]),
+            polyline(&[(0.0, 0.0), (10.0, 0.0)]),
+            polyline(&[(0.0, 0.0), (0.0, 10.0)]),
+        ];
+        let merged = merge(polylines.clone(), 0.1);
+        assert_eq!(
+            merged,
+            vec![
+                polyline(&[
+                    (10.0, 0.0),
+                    (10.0, 10.0),
+                    (10.0, 10.05),
+                    (0.0, 10.0),
+                    (0.0, 0.0),
+                    (10.0, 0.0),
+                ]),
+                polyline(&[(50.0, 50.0), (60.0, 50.0)]),
+            ]
+        );
+
+        // The gap is not bridged with a smaller distance
+        assert_eq!(
+            merge(polylines, 0.01)[0],
+            polyline(&[
+                (10.0, 10.05),
+                (0.0, 10.0),
+                (0.0, 0.0),
+                (10.0, 0.0),
+                (10.0, 10.0),
+            ])
+        );
+        assert!(merge(vec![], 1.0).is_empty());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(0.1).is_ok());
+        assert!(validate(-0.1).is_err());
+    }
+}
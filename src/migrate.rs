@@ -0,0 +1,210 @@
+//! Migration of config files written for older versions of the server.
+//!
+//! Every config carries a `version` (configs without it are version 0).
+//! When the schema changes, a migration is appended to `MIGRATIONS`, which
+//! upgrades a config from the previous version, so that existing configs
+//! keep working without hand-editing.
+use std::fs;
+use std::io;
+
+use serde_json::{json, Map, Value};
+
+/// A migration upgrades the config object from the previous version.
+type Migration = fn(&mut Map<String, Value>) -> Result<(), String>;
+
+/// The migrations, in order. The current config version is the number of
+/// migrations.
+const MIGRATIONS: &[Migration] = &[device_into_boards];
+
+/// Version 1: The `device` of a single robot moves into the `boards` list,
+/// as the board named `default`. The playlist (`svg_dir` and
+/// `interval_seconds`), the time limits and the other keys are kept.
+fn device_into_boards(config: &mut Map<String, Value>) -> Result<(), String> {
+    let device = match config.remove("device") {
+        Some(Value::String(device)) => device,
+        Some(Value::Null) | None => return Ok(()),
+        Some(_) => return Err("The device must be a string".to_string()),
+    };
+    // Next to a list of boards, the device was ignored
+    if !config.contains_key("boards") {
+        config.insert(
+            "boards".to_string(),
+            json!([{"name": "default", "device": device}]),
+        );
+    }
+    Ok(())
+}
+
+/// Upgrade the config to the current version. Return whether the config was
+/// changed.
+pub(crate) fn migrate(config: &mut Value) -> Result<bool, String> {
+    migrate_with(config, MIGRATIONS)
+}
+
+/// Save a migrated config to `path`. The original file is copied to a backup
+/// with the suffix `.bak` first, and only replaced if that succeeded. Return
+/// the path of the backup.
+pub(crate) fn save(path: &str, config: &Value) -> io::Result<String> {
+    let backup = format!("{}.bak", path);
+    fs::copy(path, &backup)?;
+    let json = serde_json::to_string_pretty(config).map_err(io::Error::from)?;
+    fs::write(path, json + "\n")?;
+    Ok(backup)
+}
+
+fn migrate_with(config: &mut Value, migrations: &[Migration]) -> Result<bool, String> {
+    let object = config
+        .as_object_mut()
+        .ok_or("The config must be a JSON object")?;
+    let version = match object.get("version") {
+        Some(version) => version.as_u64().ok_or("Invalid config version")? as usize,
+        None => 0,
+    };
+    if version > migrations.len() {
+        return Err(format!(
+            "Config version {} is newer than the supported version {}",
+            version,
+            migrations.len()
+        ));
+    }
+    if version == migrations.len() {
+        return Ok(false);
+    }
+    for (i, migration) in migrations.iter().enumerate().skip(version) {
+        migration(object)
+            .map_err(|e| format!("Could not migrate config to version {}: {}", i + 1, e))?;
+    }
+    object.insert("version".to_string(), Value::from(migrations.len()));
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, RawConfig};
+
+    fn rename_port(config: &mut Map<String, Value>) -> Result<(), String> {
+        if let Some(port) = config.remove("port") {
+            config.insert("device".to_string(), port);
+        }
+        Ok(())
+    }
+
+    fn add_name(config: &mut Map<String, Value>) -> Result<(), String> {
+        config.insert("name".to_string(), json!("Board"));
+        Ok(())
+    }
+
+    /// The config of the README before the config was versioned.
+    const LEGACY_CONFIG: &str = r#"{
+        "listen": "127.0.0.1:8080",
+        "device": "/dev/ttyACM0",
+        "svg_dir": "/path/to/svgdir",
+        "interval_seconds": 900,
+        "time_limits": {
+            "start_time": "06:00",
+            "end_time": "00:30"
+        }
+    }"#;
+
+    #[test]
+    fn test_current_config() {
+        let mut config = json!({
+            "version": 1,
+            "boards": [{"name": "default", "device": "/dev/ttyACM0"}]
+        });
+        let current = config.clone();
+        assert_eq!(migrate(&mut config), Ok(false));
+        assert_eq!(config, current);
+    }
+
+    #[test]
+    fn test_legacy_config() {
+        let mut config: Value = serde_json::from_str(LEGACY_CONFIG).unwrap();
+        assert_eq!(migrate(&mut config), Ok(true));
+        assert_eq!(
+            config,
+            json!({
+                "version": 1,
+                "listen": "127.0.0.1:8080",
+                "boards": [{"name": "default", "device": "/dev/ttyACM0"}],
+                "svg_dir": "/path/to/svgdir",
+                "interval_seconds": 900,
+                "time_limits": {"start_time": "06:00", "end_time": "00:30"}
+            })
+        );
+        assert_eq!(migrate(&mut config), Ok(false));
+
+        // The migrated config still starts the same single robot
+        let raw: RawConfig = serde_json::from_value(config).unwrap();
+        let config = Config::from(&raw).unwrap();
+        assert_eq!(config.device, "/dev/ttyACM0");
+        assert_eq!(config.boards.len(), 1);
+        assert_eq!(config.svg_dir, "/path/to/svgdir");
+        assert_eq!(config.interval_seconds, 900);
+        assert!(config.time_limits.is_some());
+    }
+
+    #[test]
+    fn test_device_into_boards() {
+        // Preview-only configs have no device
+        let mut config = json!({"listen": "127.0.0.1:8080"});
+        assert_eq!(migrate(&mut config), Ok(true));
+        assert_eq!(config, json!({"listen": "127.0.0.1:8080", "version": 1}));
+
+        // Configured boards are kept
+        let boards = json!([{"name": "lobby", "device": "/dev/ttyACM1"}]);
+        let mut config = json!({"device": "/dev/ttyACM0", "boards": boards});
+        assert_eq!(migrate(&mut config), Ok(true));
+        assert_eq!(config, json!({"boards": boards, "version": 1}));
+
+        let mut config = json!({"device": 0});
+        assert!(migrate(&mut config).is_err());
+    }
+
+    #[test]
+    fn test_save() {
+        let dir = std::env::temp_dir().join(format!("ibb-migrate-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.json");
+        let path = path.to_str().unwrap();
+        fs::write(path, LEGACY_CONFIG).unwrap();
+
+        let mut config: Value = serde_json::from_str(LEGACY_CONFIG).unwrap();
+        migrate(&mut config).unwrap();
+        let backup = save(path, &config).unwrap();
+        assert_eq!(fs::read_to_string(&backup).unwrap(), LEGACY_CONFIG);
+        let saved: Value = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(saved, config);
+
+        // Without the original file, nothing is written
+        let missing = dir.join("missing.json");
+        let missing = missing.to_str().unwrap();
+        assert!(save(missing, &config).is_err());
+        assert!(fs::metadata(missing).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_migrate() {
+        let migrations: &[Migration] = &[rename_port, add_name];
+
+        let mut config = json!({"port": "/dev/ttyACM0"});
+        assert_eq!(migrate_with(&mut config, migrations), Ok(true));
+        assert_eq!(
+            config,
+            json!({"device": "/dev/ttyACM0", "name": "Board", "version": 2})
+        );
+
+        // Only newer migrations are applied
+        let mut config = json!({"port": "/dev/ttyACM0", "version": 1});
+        assert_eq!(migrate_with(&mut config, migrations), Ok(true));
+        assert_eq!(
+            config,
+            json!({"port": "/dev/ttyACM0", "name": "Board", "version": 2})
+        );
+
+        assert_eq!(migrate_with(&mut config, migrations), Ok(false));
+        assert!(migrate_with(&mut json!({"version": 3}), migrations).is_err());
+    }
+}
@@ -0,0 +1,154 @@
+//! An in-memory fake robot for development without hardware.
+//!
+//! The mock robot speaks the serial protocol of the real robot: It requests
+//! blocks, logs the blocks it receives and acknowledges them after the
+//! simulated drawing duration. It identifies itself as the `mock` firmware.
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::info;
+
+use crate::robot::Command;
+use crate::simulate::{SimulationConfig, Simulator};
+
+/// The device name that selects the mock robot.
+pub(crate) const MOCK_DEVICE: &str = "mock";
+
+/// How often the idle robot requests a block.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Reads time out after this duration, like reads from the serial port.
+const READ_TIMEOUT: Duration = Duration::from_millis(1000);
+
+#[derive(Debug)]
+pub(crate) struct MockRobot {
+    simulator: Simulator,
+    /// Bytes received since the last flush.
+    input: Vec<u8>,
+    /// Bytes to be read by the server.
+    output: VecDeque<u8>,
+    /// The number of the block that is being drawn, and when it is done.
+    drawing: Option<(u16, Instant)>,
+    /// When the idle robot requests the next block.
+    next_poll: Instant,
+}
+
+impl MockRobot {
+    pub(crate) fn new(simulation: SimulationConfig) -> Self {
+        info!("Using mock robot");
+        MockRobot {
+            simulator: Simulator::new(simulation),
+            input: vec![],
+            output: VecDeque::new(),
+            drawing: None,
+            next_poll: Instant::now(),
+        }
+    }
+
+    /// Wait for the next message of the robot (up to the read timeout).
+    fn advance(&mut self) {
+        let due = match self.drawing {
+            Some((_, done)) => done,
+            None => self.next_poll,
+        };
+        let wait = due.saturating_duration_since(Instant::now());
+        if wait > READ_TIMEOUT {
+            thread::sleep(READ_TIMEOUT);
+            return;
+        }
+        thread::sleep(wait);
+        let message = match self.drawing.take() {
+            Some((number, _)) => format!("CL STATUS=ACK&NUM={}\n", number),
+            None => "CL STATUS=READY\n".to_string(),
+        };
+        self.output.extend(message.bytes());
+        self.next_poll = Instant::now() + POLL_INTERVAL;
+    }
+
+    /// Start drawing a received block.
+    fn receive_block(&mut self, block: &[u8]) {
+        // A block starts with the block start and block number commands
+        if block.len() < 6 || block[..3] != [0xfa, 0x9f, 0xa1] {
+            info!("Mock robot: Ignoring invalid block ({} bytes)", block.len());
+            return;
+        }
+        let number = (u16::from(block[4] & 0x0f) << 8) | u16::from(block[5]);
+        let before = self.simulator.elapsed();
+        self.simulator.feed(block);
+        let duration = self.simulator.elapsed() - before;
+        info!(
+            "Mock robot: Drawing block {} ({} bytes) for {:.1}s",
+            number,
+            block.len(),
+            duration.as_secs_f64()
+        );
+        self.drawing = Some((number, Instant::now() + duration));
+    }
+}
+
+impl Read for MockRobot {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.output.is_empty() {
+            self.advance();
+        }
+        if self.output.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Operation timed out",
+            ));
+        }
+        let n = buf.len().min(self.output.len());
+        for (target, byte) in buf.iter_mut().zip(self.output.drain(..n)) {
+            *target = byte;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for MockRobot {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.input.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    /// Every flush completes a block (or the identify command).
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.input.is_empty() {
+            let block = std::mem::take(&mut self.input);
+            if block == Command::Identify.to_bytes() {
+                let identity = format!("CL FIRMWARE=mock&VERSION={}\n", env!("CARGO_PKG_VERSION"));
+                self.output.extend(identity.bytes());
+            } else {
+                self.receive_block(&block);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+
+    fn read_line(robot: &mut BufReader<MockRobot>) -> String {
+        let mut line = String::new();
+        robot.read_line(&mut line).unwrap();
+        line.trim().to_string()
+    }
+
+    #[test]
+    fn test_protocol() {
+        let mut robot = BufReader::new(MockRobot::new(SimulationConfig::default()));
+        assert_eq!(read_line(&mut robot), "CL STATUS=READY");
+
+        // Block 2, containing a pen lift
+        let block = [0xfa, 0x9f, 0xa1, 0xfa, 0x90, 0x02, 0xfa, 0x30, 0x00];
+        robot.get_mut().write_all(&block).unwrap();
+        robot.get_mut().flush().unwrap();
+        assert_eq!(read_line(&mut robot), "CL STATUS=ACK&NUM=2");
+        assert_eq!(read_line(&mut robot), "CL STATUS=READY");
+    }
+}
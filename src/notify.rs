@@ -0,0 +1,496 @@
+//! Notifications about robot events.
+//!
+//! Events are sent to a dispatcher thread, which forwards them to all
+//! configured sinks (webhook, MQTT, Telegram, email or shell command) whose
+//! event filter matches, or which are targeted by a matching rule (see the
+//! `rules` module). All events are also published to the live event feed
+//! (see the `feed` module).
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, error, info, warn};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::errors::ErrorCode;
+use crate::feed::{self, EventFeed, FeedEvent};
+use crate::rules::{self, Rule};
+use crate::watchdog::Resource;
+
+const TIMEOUT_SECONDS_NETWORK: u64 = 10;
+
+/// An event that may trigger notifications.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(crate) enum Event {
+    /// The robot thread received a new print task.
+    TaskReceived,
+    /// All blocks of a job were sent to the robot.
+    JobFinished {
+        blocks: u32,
+        bytes: usize,
+        seconds: u64,
+    },
+    /// The robot did not acknowledge a block in time.
+    RobotStalled { block: u32 },
+    /// A job was dropped before it was completely sent to the robot.
+    JobDropped { code: ErrorCode, reason: String },
+    /// The serial connection to the robot was lost.
+    RobotDisconnected { reason: String },
+    /// The serial connection to the robot was restored.
+    RobotReconnected { attempts: u32 },
+    /// Reconnecting to the robot failed for too long, so only previews are
+    /// available until it is back.
+    RobotOffline { seconds: u64 },
+    /// An HTTP request took longer than the configured threshold.
+    SlowRequest { path: String, millis: u64 },
+    /// A job is taking longer than estimated by more than the configured
+    /// threshold.
+    JobOverrun {
+        label: String,
+        estimated_seconds: u64,
+        elapsed_seconds: u64,
+    },
+    /// A job was refused because the paper roll is running out.
+    ReplaceRoll {
+        label: String,
+        required_mm: u32,
+        remaining_mm: u32,
+    },
+    /// The watchdog found a resource running low (see the `watchdog`
+    /// module).
+    ResourceWarning { resource: Resource, message: String },
+}
+
+impl Event {
+    /// The name of the event, as used in event filters.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Event::TaskReceived => "task_received",
+            Event::JobFinished { .. } => "job_finished",
+            Event::RobotStalled { .. } => "robot_stalled",
+            Event::JobDropped { .. } => "job_dropped",
+            Event::RobotDisconnected { .. } => "robot_disconnected",
+            Event::RobotReconnected { .. } => "robot_reconnected",
+            Event::RobotOffline { .. } => "robot_offline",
+            Event::SlowRequest { .. } => "slow_request",
+            Event::JobOverrun { .. } => "job_overrun",
+            Event::ReplaceRoll { .. } => "replace_roll",
+            Event::ResourceWarning { .. } => "resource_warning",
+        }
+    }
+
+    fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Could not serialize event")
+    }
+
+    pub(crate) fn to_value(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("Could not serialize event")
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::TaskReceived => write!(f, "Received print task"),
+            Event::JobFinished {
+                blocks,
+                bytes,
+                seconds,
+            } => write!(
+                f,
+                "Job finished: Sent {} block(s) ({} bytes) in {}s",
+                blocks, bytes, seconds
+            ),
+            Event::RobotStalled { block } => write!(f, "Robot stalled after block {}", block),
+            Event::JobDropped { reason, .. } => write!(f, "Job dropped: {}", reason),
+            Event::RobotDisconnected { reason } => write!(f, "Robot disconnected: {}", reason),
+            Event::RobotReconnected { attempts } => {
+                write!(f, "Robot reconnected after {} attempt(s)", attempts)
+            }
+            Event::RobotOffline { seconds } => write!(
+                f,
+                "Robot offline for {}s, only previews are available",
+                seconds
+            ),
+            Event::SlowRequest { path, millis } => {
+                write!(f, "Slow request: {} took {}ms", path, millis)
+            }
+            Event::JobOverrun {
+                label,
+                estimated_seconds,
+                elapsed_seconds,
+            } => write!(
+                f,
+                "Job overrun: {} is running for {}s, estimated {}s",
+                label, elapsed_seconds, estimated_seconds
+            ),
+            Event::ReplaceRoll {
+                label,
+                required_mm,
+                remaining_mm,
+            } => write!(
+                f,
+                "Replace the paper roll: {} needs {} mm, {} mm left",
+                label, required_mm, remaining_mm
+            ),
+            Event::ResourceWarning { message, .. } => write!(f, "Resource warning: {}", message),
+        }
+    }
+}
+
+/// Something that can deliver notifications.
+pub(crate) trait Notifier {
+    fn notify(&self, event: &Event) -> Result<(), String>;
+}
+
+/// POST the event as JSON to a URL.
+#[derive(Debug, Deserialize, Clone)]
+pub(crate) struct Webhook {
+    url: String,
+}
+
+impl Notifier for Webhook {
+    fn notify(&self, event: &Event) -> Result<(), String> {
+        ureq::post(&self.url)
+            .timeout(Duration::from_secs(TIMEOUT_SECONDS_NETWORK))
+            .set("Content-Type", "application/json")
+            .send_string(&event.to_json())
+            .map(|_| ())
+            .map_err(|e| format!("Webhook request failed: {}", e))
+    }
+}
+
+/// Publish the event as JSON to an MQTT topic (QoS 0).
+#[derive(Debug, Deserialize, Clone)]
+pub(crate) struct Mqtt {
+    host: String,
+    #[serde(default = "default_mqtt_port")]
+    port: u16,
+    topic: String,
+    #[serde(default = "default_mqtt_client_id")]
+    client_id: String,
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_mqtt_client_id() -> String {
+    "iboardbot-web".to_string()
+}
+
+/// Encode a length-prefixed MQTT string.
+fn mqtt_string(buf: &mut Vec<u8>, value: &str) {
+    buf.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    buf.extend_from_slice(value.as_bytes());
+}
+
+/// Encode an MQTT control packet with the specified header byte.
+fn mqtt_packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![header];
+    let mut remaining = body.len();
+    loop {
+        let mut byte = (remaining % 128) as u8;
+        remaining /= 128;
+        if remaining > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if remaining == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+impl Mqtt {
+    fn connect_packet(&self) -> Vec<u8> {
+        let mut body = vec![];
+        mqtt_string(&mut body, "MQTT");
+        body.push(0x04); // Protocol level 3.1.1
+        body.push(0x02); // Clean session
+        body.extend_from_slice(&60u16.to_be_bytes()); // Keep alive
+        mqtt_string(&mut body, &self.client_id);
+        mqtt_packet(0x10, &body)
+    }
+
+    fn publish_packet(&self, payload: &str) -> Vec<u8> {
+        let mut body = vec![];
+        mqtt_string(&mut body, &self.topic);
+        body.extend_from_slice(payload.as_bytes());
+        mqtt_packet(0x30, &body)
+    }
+
+    fn publish(&self, payload: &str) -> io::Result<()> {
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port))?;
+        stream.set_read_timeout(Some(Duration::from_secs(TIMEOUT_SECONDS_NETWORK)))?;
+        stream.write_all(&self.connect_packet())?;
+        let mut connack = [0u8; 4];
+        stream.read_exact(&mut connack)?;
+        if connack[0] != 0x20 || connack[3] != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("Connection refused (return code {})", connack[3]),
+            ));
+        }
+        stream.write_all(&self.publish_packet(payload))?;
+        stream.write_all(&mqtt_packet(0xe0, &[]))?; // Disconnect
+        stream.flush()
+    }
+}
+
+impl Notifier for Mqtt {
+    fn notify(&self, event: &Event) -> Result<(), String> {
+        self.publish(&event.to_json())
+            .map_err(|e| format!("MQTT publish failed: {}", e))
+    }
+}
+
+/// Send the event as a message through a Telegram bot.
+#[derive(Debug, Deserialize, Clone)]
+pub(crate) struct Telegram {
+    token: String,
+    chat_id: String,
+}
+
+impl Notifier for Telegram {
+    fn notify(&self, event: &Event) -> Result<(), String> {
+        let url = format!("https://api.telegram.org/bot{}/sendMessage", self.token);
+        ureq::post(&url)
+            .timeout(Duration::from_secs(TIMEOUT_SECONDS_NETWORK))
+            .send_form(&[
+                ("chat_id", &self.chat_id),
+                ("text", &format!("iBoardBot: {}", event)),
+            ])
+            .map(|_| ())
+            .map_err(|e| format!("Telegram request failed: {}", e))
+    }
+}
+
+/// Send the event by email, using the local `sendmail` command.
+#[derive(Debug, Deserialize, Clone)]
+pub(crate) struct Email {
+    to: String,
+    #[serde(default = "default_email_from")]
+    from: String,
+    #[serde(default = "default_sendmail")]
+    sendmail: String,
+}
+
+fn default_email_from() -> String {
+    "iboardbot-web".to_string()
+}
+
+fn default_sendmail() -> String {
+    "/usr/sbin/sendmail".to_string()
+}
+
+impl Email {
+    fn message(&self, event: &Event) -> String {
+        format!(
+            "From: {}\r\nTo: {}\r\nSubject: iBoardBot: {}\r\n\r\n{}\r\n",
+            self.from,
+            self.to,
+            event.name(),
+            event
+        )
+    }
+}
+
+impl Notifier for Email {
+    fn notify(&self, event: &Event) -> Result<(), String> {
+        let mut child = Command::new(&self.sendmail)
+            .arg("-t")
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Could not start {}: {}", self.sendmail, e))?;
+        child
+            .stdin
+            .take()
+            .expect("Could not access sendmail stdin")
+            .write_all(self.message(event).as_bytes())
+            .map_err(|e| format!("Could not write to sendmail: {}", e))?;
+        match child.wait() {
+            Ok(status) if status.success() => Ok(()),
+            Ok(status) => Err(format!("sendmail failed: {}", status)),
+            Err(e) => Err(format!("sendmail failed: {}", e)),
+        }
+    }
+}
+
+/// Run a shell command. The event is passed in the `IBB_EVENT` (name) and
+/// `IBB_EVENT_JSON` environment variables.
+#[derive(Debug, Deserialize, Clone)]
+pub(crate) struct ShellCommand {
+    command: String,
+}
+
+impl Notifier for ShellCommand {
+    fn notify(&self, event: &Event) -> Result<(), String> {
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .env("IBB_EVENT", event.name())
+            .env("IBB_EVENT_JSON", event.to_json())
+            .status()
+            .map_err(|e| format!("Could not run command: {}", e))?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(format!("Command failed: {}", status))
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub(crate) enum NotifierConfig {
+    Webhook(Webhook),
+    Mqtt(Mqtt),
+    Telegram(Telegram),
+    Email(Email),
+    Command(ShellCommand),
+}
+
+impl NotifierConfig {
+    fn notifier(&self) -> &dyn Notifier {
+        match self {
+            NotifierConfig::Webhook(n) => n,
+            NotifierConfig::Mqtt(n) => n,
+            NotifierConfig::Telegram(n) => n,
+            NotifierConfig::Email(n) => n,
+            NotifierConfig::Command(n) => n,
+        }
+    }
+}
+
+/// A configured notification sink.
+#[derive(Debug, Deserialize, Clone)]
+pub(crate) struct SinkConfig {
+    /// Name of the sink, used to reference it from rules.
+    name: Option<String>,
+    #[serde(flatten)]
+    notifier: NotifierConfig,
+    /// Names of the events to notify about. If missing, all events are sent.
+    /// Use an empty list for sinks that should only be triggered by rules.
+    events: Option<Vec<String>>,
+}
+
+impl SinkConfig {
+    fn accepts(&self, event: &Event) -> bool {
+        match self.events {
+            Some(ref events) => events.iter().any(|e| e == event.name()),
+            None => true,
+        }
+    }
+}
+
+/// Spawn the notification dispatcher thread.
+///
+/// The return value is the sending end of a channel, over which events can
+/// be sent.
+pub(crate) fn spawn(sinks: Vec<SinkConfig>, rules: Vec<Rule>, feed: EventFeed) -> Sender<Event> {
+    if !sinks.is_empty() {
+        info!("{} notification sink(s) configured", sinks.len());
+    }
+    for name in rules.iter().flat_map(|rule| rule.notify.iter()) {
+        if !sinks.iter().any(|sink| sink.name.as_ref() == Some(name)) {
+            warn!("Rules reference unknown notification sink \"{}\"", name);
+        }
+    }
+    let (tx, rx) = channel::<Event>();
+    thread::spawn(move || {
+        for event in rx {
+            debug!("Event: {}", event.name());
+            feed::send(&feed, || FeedEvent::robot(&event));
+            let targets = rules::targets(&rules, &event);
+            let selected = sinks.iter().filter(|sink| {
+                sink.accepts(&event)
+                    || sink
+                        .name
+                        .as_deref()
+                        .is_some_and(|name| targets.contains(&name))
+            });
+            for sink in selected {
+                if let Err(e) = sink.notifier.notifier().notify(&event) {
+                    error!("Could not send notification: {}", e);
+                }
+            }
+        }
+    });
+    tx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_json() {
+        assert_eq!(
+            Event::TaskReceived.to_json(),
+            r#"{"event":"task_received"}"#
+        );
+        assert_eq!(
+            Event::RobotStalled { block: 3 }.to_json(),
+            r#"{"event":"robot_stalled","block":3}"#
+        );
+    }
+
+    #[test]
+    fn test_sink_filter() {
+        let sink: SinkConfig = serde_json::from_str(
+            r#"{"type": "command", "command": "true", "events": ["robot_stalled"]}"#,
+        )
+        .unwrap();
+        assert!(sink.accepts(&Event::RobotStalled { block: 1 }));
+        assert!(!sink.accepts(&Event::TaskReceived));
+
+        let sink: SinkConfig =
+            serde_json::from_str(r#"{"type": "webhook", "url": "http://localhost/"}"#).unwrap();
+        assert!(sink.accepts(&Event::TaskReceived));
+
+        let sink: SinkConfig = serde_json::from_str(
+            r#"{"name": "chat", "type": "webhook", "url": "http://localhost/", "events": []}"#,
+        )
+        .unwrap();
+        assert!(!sink.accepts(&Event::TaskReceived));
+    }
+
+    #[test]
+    fn test_shell_command() {
+        let notifier = ShellCommand {
+            command: r#"test "$IBB_EVENT" = "task_received""#.to_string(),
+        };
+        assert!(notifier.notify(&Event::TaskReceived).is_ok());
+        assert!(notifier.notify(&Event::RobotStalled { block: 1 }).is_err());
+    }
+
+    #[test]
+    fn test_mqtt_packet_length() {
+        assert_eq!(mqtt_packet(0xe0, &[]), vec![0xe0, 0x00]);
+        let packet = mqtt_packet(0x30, &[0; 200]);
+        assert_eq!(packet[..3], [0x30, 0xc8, 0x01]);
+        assert_eq!(packet.len(), 203);
+    }
+
+    #[test]
+    fn test_mqtt_publish_packet() {
+        let mqtt = Mqtt {
+            host: "localhost".into(),
+            port: 1883,
+            topic: "ibb".into(),
+            client_id: "test".into(),
+        };
+        assert_eq!(
+            mqtt.publish_packet("hi"),
+            vec![0x30, 7, 0, 3, b'i', b'b', b'b', b'h', b'i']
+        );
+    }
+}
@@ -0,0 +1,328 @@
+//! Optimization of SVG files before they are stored in the library.
+//!
+//! Files exported from design tools are often large: Curves with many
+//! control points, coordinates with lots of decimals, and nested groups with
+//! transforms. Since the robot only draws polylines, every path is flattened
+//! into polylines with the transforms applied, coordinates are rounded, and
+//! consecutive paths are merged into one. Groups (and thus layers) are kept.
+use std::io::Cursor;
+
+use quick_xml::escape::escape;
+use quick_xml::events::{BytesEnd, BytesStart, Event};
+use quick_xml::{Reader, Writer};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::svg;
+
+fn default_precision() -> usize {
+    2
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub(crate) struct OptimizeConfig {
+    /// Number of decimals of the coordinates.
+    #[serde(default = "default_precision")]
+    pub(crate) precision: usize,
+}
+
+/// An affine transformation `[a, b, c, d, e, f]`, as in the SVG `matrix()`.
+type Matrix = [f64; 6];
+
+const IDENTITY: Matrix = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+
+/// Return the transformation that applies `inner` first, then `outer`.
+fn multiply(outer: &Matrix, inner: &Matrix) -> Matrix {
+    let [a1, b1, c1, d1, e1, f1] = *outer;
+    let [a2, b2, c2, d2, e2, f2] = *inner;
+    [
+        a1 * a2 + c1 * b2,
+        b1 * a2 + d1 * b2,
+        a1 * c2 + c1 * d2,
+        b1 * c2 + d1 * d2,
+        a1 * e2 + c1 * f2 + e1,
+        b1 * e2 + d1 * f2 + f1,
+    ]
+}
+
+/// Parse an SVG `transform` attribute.
+fn parse_transform(transform: &str) -> Result<Matrix, String> {
+    let mut matrix = IDENTITY;
+    let mut rest = transform.trim();
+    while !rest.is_empty() {
+        let (name, after) = rest
+            .split_once('(')
+            .ok_or_else(|| format!("Invalid transform: {}", transform))?;
+        let (args, after) = after
+            .split_once(')')
+            .ok_or_else(|| format!("Invalid transform: {}", transform))?;
+        let args = args
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|arg| !arg.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|_| format!("Invalid transform: {}", transform))?;
+        let next = match (name.trim(), args.as_slice()) {
+            ("matrix", &[a, b, c, d, e, f]) => [a, b, c, d, e, f],
+            ("translate", &[x]) => [1.0, 0.0, 0.0, 1.0, x, 0.0],
+            ("translate", &[x, y]) => [1.0, 0.0, 0.0, 1.0, x, y],
+            ("scale", &[s]) => [s, 0.0, 0.0, s, 0.0, 0.0],
+            ("scale", &[x, y]) => [x, 0.0, 0.0, y, 0.0, 0.0],
+            ("rotate", &[angle]) => rotation(angle),
+            ("rotate", &[angle, x, y]) => multiply(
+                &[1.0, 0.0, 0.0, 1.0, x, y],
+                &multiply(&rotation(angle), &[1.0, 0.0, 0.0, 1.0, -x, -y]),
+            ),
+            ("skewX", &[angle]) => [1.0, 0.0, angle.to_radians().tan(), 1.0, 0.0, 0.0],
+            ("skewY", &[angle]) => [1.0, angle.to_radians().tan(), 0.0, 1.0, 0.0, 0.0],
+            _ => return Err(format!("Invalid transform: {}", transform)),
+        };
+        matrix = multiply(&matrix, &next);
+        rest = after.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+    }
+    Ok(matrix)
+}
+
+fn rotation(angle: f64) -> Matrix {
+    let (sin, cos) = angle.to_radians().sin_cos();
+    [cos, sin, -sin, cos, 0.0, 0.0]
+}
+
+/// Format a number with at most `precision` decimals, without trailing
+/// zeros.
+fn format_number(value: f64, precision: usize) -> String {
+    let formatted = format!("{:.*}", precision, value);
+    let trimmed = match formatted.contains('.') {
+        true => formatted.trim_end_matches('0').trim_end_matches('.'),
+        false => &formatted,
+    };
+    match trimmed {
+        "-0" => "0".to_string(),
+        _ => trimmed.to_string(),
+    }
+}
+
+/// Return the value of an attribute of the element.
+fn attribute(element: &BytesStart, name: &[u8]) -> Result<Option<String>, String> {
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(|e| format!("Invalid SVG attribute: {}", e))?;
+        if attribute.key == name {
+            let value = attribute
+                .unescaped_value()
+                .map_err(|e| format!("Invalid SVG attribute value: {}", e))?;
+            return Ok(Some(String::from_utf8_lossy(&value).into_owned()));
+        }
+    }
+    Ok(None)
+}
+
+/// Return the transformation of the element, relative to its parent.
+fn transform(element: &BytesStart) -> Result<Matrix, String> {
+    match attribute(element, b"transform")? {
+        Some(transform) => parse_transform(&transform),
+        None => Ok(IDENTITY),
+    }
+}
+
+/// Return a copy of the element without its `transform` attribute.
+fn without_transform(element: &BytesStart) -> BytesStart<'static> {
+    let mut copy = BytesStart::owned_name(element.name().to_vec());
+    copy.extend_attributes(
+        element
+            .attributes()
+            .flatten()
+            .filter(|attribute| attribute.key != b"transform"),
+    );
+    copy.into_owned()
+}
+
+/// Flatten the path data into polylines and return it as transformed path
+/// data with rounded coordinates.
+fn flatten_path(
+    data: &str,
+    matrix: &Matrix,
+    tolerance: f64,
+    precision: usize,
+) -> Result<String, String> {
+    let wrapped = format!(
+        "<svg><path d=\"{}\"/></svg>",
+        String::from_utf8_lossy(&escape(data.as_bytes()))
+    );
+    let [a, b, c, d, e, f] = *matrix;
+    let polylines = svg2polylines::parse(&wrapped, tolerance)?;
+    Ok(polylines
+        .iter()
+        .map(|polyline| {
+            polyline
+                .iter()
+                .enumerate()
+                .map(|(i, point)| {
+                    format!(
+                        "{}{},{}",
+                        if i == 0 { 'M' } else { 'L' },
+                        format_number(a * point.x + c * point.y + e, precision),
+                        format_number(b * point.x + d * point.y + f, precision)
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect::<Vec<_>>()
+        .join(" "))
+}
+
+/// Write the merged path data (if any) as a single path.
+fn flush(writer: &mut Writer<Cursor<Vec<u8>>>, merged: &mut String) -> Result<(), String> {
+    if merged.is_empty() {
+        return Ok(());
+    }
+    let mut path = BytesStart::borrowed_name(b"path");
+    path.push_attribute(("d", merged.as_str()));
+    path.push_attribute(("fill", "none"));
+    path.push_attribute(("stroke", "black"));
+    writer
+        .write_event(Event::Empty(path))
+        .map_err(|e| format!("Could not write SVG: {}", e))?;
+    merged.clear();
+    Ok(())
+}
+
+/// Optimize an SVG file: Text is converted to paths, paths are flattened
+/// into polylines with all transforms applied, coordinates are rounded and
+/// consecutive paths are merged. Comments are removed.
+pub(crate) fn optimize(
+    svg: &str,
+    config: &OptimizeConfig,
+    tolerance: f64,
+) -> Result<String, String> {
+    let svg = svg::replace_text(svg)?;
+    let mut reader = Reader::from_str(&svg);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    let mut buf = Vec::new();
+
+    // The transformation of every open element
+    let mut matrices = vec![IDENTITY];
+    // The path data of consecutive paths that are merged
+    let mut merged = String::new();
+    // The depth of the path element whose content is currently skipped
+    let mut skipped_depth = 0;
+
+    loop {
+        let event = reader
+            .read_event(&mut buf)
+            .map_err(|e| format!("Could not parse SVG: {}", e))?;
+        let current = *matrices.last().expect("Root matrix must exist");
+        match event {
+            Event::Eof => break,
+            _ if skipped_depth > 0 => match event {
+                Event::Start(_) => skipped_depth += 1,
+                Event::End(_) => skipped_depth -= 1,
+                _ => {}
+            },
+            Event::Start(ref e) | Event::Empty(ref e) if e.local_name() == b"path" => {
+                let matrix = multiply(&current, &transform(e)?);
+                if let Some(data) = attribute(e, b"d")? {
+                    let data = flatten_path(&data, &matrix, tolerance, config.precision)?;
+                    if !data.is_empty() && !merged.is_empty() {
+                        merged.push(' ');
+                    }
+                    merged.push_str(&data);
+                }
+                if matches!(event, Event::Start(_)) {
+                    skipped_depth = 1;
+                }
+            }
+            Event::Start(ref e) => {
+                flush(&mut writer, &mut merged)?;
+                matrices.push(multiply(&current, &transform(e)?));
+                writer
+                    .write_event(Event::Start(without_transform(e)))
+                    .map_err(|e| format!("Could not write SVG: {}", e))?;
+            }
+            Event::End(ref e) => {
+                flush(&mut writer, &mut merged)?;
+                matrices.pop();
+                writer
+                    .write_event(Event::End(BytesEnd::owned(e.name().to_vec())))
+                    .map_err(|e| format!("Could not write SVG: {}", e))?;
+            }
+            Event::Empty(ref e) => {
+                flush(&mut writer, &mut merged)?;
+                writer
+                    .write_event(Event::Empty(without_transform(e)))
+                    .map_err(|e| format!("Could not write SVG: {}", e))?;
+            }
+            Event::Comment(_) => {}
+            // Whitespace between paths doesn't prevent merging
+            Event::Text(ref e) if e.iter().all(u8::is_ascii_whitespace) => {}
+            event => {
+                flush(&mut writer, &mut merged)?;
+                writer
+                    .write_event(event)
+                    .map_err(|e| format!("Could not write SVG: {}", e))?;
+            }
+        }
+        buf.clear();
+    }
+
+    String::from_utf8(writer.into_inner().into_inner())
+        .map_err(|e| format!("Invalid UTF-8 in SVG: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: OptimizeConfig = OptimizeConfig { precision: 2 };
+
+    #[test]
+    fn test_parse_transform() {
+        assert_eq!(parse_transform("").unwrap(), IDENTITY);
+        assert_eq!(
+            parse_transform("translate(10, 20) scale(2)").unwrap(),
+            [2.0, 0.0, 0.0, 2.0, 10.0, 20.0]
+        );
+        let rotated = parse_transform("rotate(90 10 10)").unwrap();
+        let point = multiply(&rotated, &[1.0, 0.0, 0.0, 1.0, 20.0, 10.0]);
+        assert!((point[4] - 10.0).abs() < 1e-9 && (point[5] - 20.0).abs() < 1e-9);
+        assert!(parse_transform("scale(a)").is_err());
+        assert!(parse_transform("wobble(1)").is_err());
+    }
+
+    #[test]
+    fn test_format_number() {
+        assert_eq!(format_number(1.23456, 2), "1.23");
+        assert_eq!(format_number(2.0, 2), "2");
+        assert_eq!(format_number(-0.001, 2), "0");
+        assert_eq!(format_number(10.5, 0), "10");
+    }
+
+    #[test]
+    fn test_optimize() {
+        let svg = concat!(
+            r#"<svg width="100" height="100"><!-- Exported -->"#,
+            r#"<g id="layer" transform="translate(10,0)">"#,
+            r#"<path d="M0,0 L1.23456,2" style="stroke:red"/>"#,
+            "\n",
+            r#"<path transform="scale(2)" d="M1,1 L2,2"><title>Line</title></path>"#,
+            r#"</g></svg>"#
+        );
+        assert_eq!(
+            optimize(svg, &CONFIG, 0.15).unwrap(),
+            concat!(
+                r#"<svg width="100" height="100"><g id="layer">"#,
+                r#"<path d="M10,0 L11.23,2 M12,2 L14,4" fill="none" stroke="black"/>"#,
+                r#"</g></svg>"#
+            )
+        );
+        assert!(optimize(r#"<svg><path d="M0,0 Lx"/></svg>"#, &CONFIG, 0.15).is_err());
+    }
+
+    #[test]
+    fn test_optimize_text() {
+        let svg = r#"<svg><g transform="translate(5,0)"><text x="10" y="30">T</text></g></svg>"#;
+        let optimized = optimize(svg, &CONFIG, 0.15).unwrap();
+        let polylines = svg::parse(&optimized, 0.15).unwrap();
+        assert_eq!(polylines.len(), 2);
+        assert!(polylines.iter().flatten().all(|point| point.x >= 15.0));
+    }
+}
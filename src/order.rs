@@ -0,0 +1,289 @@
+//! The order in which the polylines of a drawing are drawn.
+//!
+//! By default, polylines are drawn in the order of the document. When
+//! spectators watch the board, a different build-up can be more interesting:
+//! `inside_out` starts at the center of the drawing and grows outwards,
+//! `top_down` draws from the top of the board to the bottom and
+//! `largest_first` draws the outlines before the details.
+//!
+//! Complex drawings spend much time moving the lifted pen between strokes.
+//! `nearest_neighbor` always continues with the closest polyline (drawing it
+//! backwards if its end is closer), and `two_opt` improves that order further
+//! by reversing runs of polylines as long as the travel gets shorter.
+use std::cmp::Ordering;
+
+use log::debug;
+use serde_derive::{Deserialize, Serialize};
+use svg2polylines::{CoordinatePair, Polyline};
+
+use crate::scaling::{self, Bounds};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum DrawOrder {
+    #[default]
+    Document,
+    InsideOut,
+    TopDown,
+    LargestFirst,
+    NearestNeighbor,
+    TwoOpt,
+}
+
+/// Above this number of polylines, `two_opt` only orders by nearest
+/// neighbor, since every pass takes quadratic time.
+const TWO_OPT_MAX_POLYLINES: usize = 2000;
+
+/// The maximum number of passes of the 2-opt improvement.
+const TWO_OPT_MAX_PASSES: usize = 8;
+
+fn center(bounds: &Bounds) -> (f64, f64) {
+    (
+        (bounds.x.min + bounds.x.max) / 2.0,
+        (bounds.y.min + bounds.y.max) / 2.0,
+    )
+}
+
+/// Return the length of the polyline, in mm.
+pub(crate) fn length(polyline: &Polyline) -> f64 {
+    polyline
+        .windows(2)
+        .map(|segment| (segment[1].x - segment[0].x).hypot(segment[1].y - segment[0].y))
+        .sum()
+}
+
+fn distance(a: &CoordinatePair, b: &CoordinatePair) -> f64 {
+    (b.x - a.x).hypot(b.y - a.y)
+}
+
+/// Return the distance the pen travels lifted between the polylines.
+pub(crate) fn travel(polylines: &[Polyline]) -> f64 {
+    polylines
+        .windows(2)
+        .filter_map(|pair| Some(distance(pair[0].last()?, pair[1].first()?)))
+        .sum()
+}
+
+/// Order the polylines so that every polyline starts close to the end of the
+/// previous one, starting with the first polyline of the document.
+fn nearest_neighbor(polylines: Vec<Polyline>) -> Vec<Polyline> {
+    let mut remaining: Vec<Polyline> = polylines.into_iter().filter(|p| !p.is_empty()).collect();
+    if remaining.is_empty() {
+        return remaining;
+    }
+    let mut ordered = vec![remaining.remove(0)];
+    while !remaining.is_empty() {
+        let end = ordered[ordered.len() - 1][ordered[ordered.len() - 1].len() - 1];
+        // The closest start or end of the remaining polylines
+        let (index, reverse, _) = remaining
+            .iter()
+            .enumerate()
+            .flat_map(|(i, polyline)| {
+                [
+                    (i, false, distance(&end, &polyline[0])),
+                    (i, true, distance(&end, &polyline[polyline.len() - 1])),
+                ]
+            })
+            .fold((0, false, f64::MAX), |best, candidate| {
+                match candidate.2 < best.2 {
+                    true => candidate,
+                    false => best,
+                }
+            });
+        let mut polyline = remaining.swap_remove(index);
+        if reverse {
+            polyline.reverse();
+        }
+        ordered.push(polyline);
+    }
+    ordered
+}
+
+/// Shorten the travel by reversing runs of polylines (including the
+/// direction of every polyline of the run), as long as that helps.
+fn two_opt(mut polylines: Vec<Polyline>) -> Vec<Polyline> {
+    let n = polylines.len();
+    let start = |p: &Polyline| p[0];
+    let end = |p: &Polyline| p[p.len() - 1];
+    for _ in 0..TWO_OPT_MAX_PASSES {
+        let mut improved = false;
+        for i in 0..n.saturating_sub(1) {
+            for j in i + 1..n {
+                // Reversing the run i+1..=j (or just the direction of one
+                // polyline) only changes the travel into and out of the run
+                let before = distance(&end(&polylines[i]), &start(&polylines[i + 1]))
+                    + polylines
+                        .get(j + 1)
+                        .map_or(0.0, |next| distance(&end(&polylines[j]), &start(next)));
+                let after = distance(&end(&polylines[i]), &end(&polylines[j]))
+                    + polylines.get(j + 1).map_or(0.0, |next| {
+                        distance(&start(&polylines[i + 1]), &start(next))
+                    });
+                if after < before - 1e-9 {
+                    polylines[i + 1..=j].reverse();
+                    for polyline in &mut polylines[i + 1..=j] {
+                        polyline.reverse();
+                    }
+                    improved = true;
+                }
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+    polylines
+}
+
+impl DrawOrder {
+    /// The sort key of a polyline (ascending), given the bounds of the whole
+    /// drawing.
+    fn key(self, polyline: &Polyline, drawing: &Bounds) -> (f64, f64) {
+        let Some(bounds) = scaling::get_bounds(std::slice::from_ref(polyline)) else {
+            return (f64::MAX, f64::MAX);
+        };
+        match self {
+            DrawOrder::Document => (0.0, 0.0),
+            // The polyline is drawn once the drawing has grown up to its
+            // farthest point
+            DrawOrder::InsideOut => {
+                let (center_x, center_y) = center(drawing);
+                let farthest = polyline
+                    .iter()
+                    .map(|point| (point.x - center_x).hypot(point.y - center_y))
+                    .fold(0.0, f64::max);
+                (farthest, 0.0)
+            }
+            DrawOrder::TopDown => (bounds.y.min, bounds.x.min),
+            DrawOrder::LargestFirst => {
+                (-(bounds.x.spread() * bounds.y.spread()), -length(polyline))
+            }
+            DrawOrder::NearestNeighbor | DrawOrder::TwoOpt => {
+                unreachable!("Not ordered by key")
+            }
+        }
+    }
+
+    /// Sort the polylines by the draw order. Polylines that are equal in the
+    /// order keep their order of the document.
+    pub(crate) fn apply(self, polylines: Vec<Polyline>) -> Vec<Polyline> {
+        if matches!(self, DrawOrder::NearestNeighbor | DrawOrder::TwoOpt) {
+            let before = travel(&polylines);
+            let ordered = match self {
+                DrawOrder::TwoOpt if polylines.len() <= TWO_OPT_MAX_POLYLINES => {
+                    two_opt(nearest_neighbor(polylines))
+                }
+                _ => nearest_neighbor(polylines),
+            };
+            debug!(
+                "Reduced the pen travel from {:.0} to {:.0}",
+                before,
+                travel(&ordered)
+            );
+            return ordered;
+        }
+        let drawing = match (self, scaling::get_bounds(&polylines)) {
+            (DrawOrder::Document, _) | (_, None) => return polylines,
+            (_, Some(drawing)) => drawing,
+        };
+        let mut keyed: Vec<((f64, f64), Polyline)> = polylines
+            .into_iter()
+            .map(|polyline| (self.key(&polyline, &drawing), polyline))
+            .collect();
+        keyed.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        keyed.into_iter().map(|(_, polyline)| polyline).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::polyline;
+
+    fn drawing() -> Vec<Polyline> {
+        vec![
+            // A small square at the bottom left
+            polyline(&[(0.0, 90.0), (10.0, 90.0), (10.0, 100.0), (0.0, 100.0)]),
+            // A large frame
+            polyline(&[(0.0, 0.0), (100.0, 0.0), (100.0, 100.0), (0.0, 100.0)]),
+            // A short line in the center
+            polyline(&[(50.0, 50.0), (51.0, 50.0)]),
+            // A line at the top
+            polyline(&[(20.0, 5.0), (80.0, 5.0)]),
+        ]
+    }
+
+    fn order(order: DrawOrder) -> Vec<Polyline> {
+        let original = drawing();
+        let sorted = order.apply(original.clone());
+        assert_eq!(sorted.len(), original.len());
+        sorted
+    }
+
+    #[test]
+    fn test_document() {
+        assert_eq!(order(DrawOrder::Document), drawing());
+    }
+
+    #[test]
+    fn test_inside_out() {
+        let sorted = order(DrawOrder::InsideOut);
+        let d = drawing();
+        // The frame is as far out as the square in its corner
+        assert_eq!(
+            sorted,
+            vec![d[2].clone(), d[3].clone(), d[0].clone(), d[1].clone()]
+        );
+    }
+
+    #[test]
+    fn test_top_down() {
+        let sorted = order(DrawOrder::TopDown);
+        let d = drawing();
+        assert_eq!(
+            sorted,
+            vec![d[1].clone(), d[3].clone(), d[2].clone(), d[0].clone()]
+        );
+    }
+
+    #[test]
+    fn test_largest_first() {
+        let sorted = order(DrawOrder::LargestFirst);
+        let d = drawing();
+        // Lines without area are ordered by their length
+        assert_eq!(
+            sorted,
+            vec![d[1].clone(), d[0].clone(), d[3].clone(), d[2].clone()]
+        );
+        assert!(DrawOrder::LargestFirst.apply(vec![]).is_empty());
+    }
+
+    #[test]
+    fn test_nearest_neighbor() {
+        let d = drawing();
+        let sorted = order(DrawOrder::NearestNeighbor);
+        // Starting with the square, the frame is drawn backwards from its
+        // corner at the square
+        assert_eq!(sorted[0], d[0]);
+        let mut frame = d[1].clone();
+        frame.reverse();
+        assert_eq!(sorted[1], frame);
+        assert!(travel(&sorted) < travel(&d));
+    }
+
+    #[test]
+    fn test_two_opt() {
+        // Lines on a row, in an order that crosses back and forth
+        let lines: Vec<Polyline> = [0.0, 30.0, 10.0, 40.0, 20.0]
+            .iter()
+            .map(|&x| polyline(&[(x, 0.0), (x + 5.0, 0.0)]))
+            .collect();
+        assert_eq!(travel(&lines), 100.0);
+        let improved = two_opt(lines.clone());
+        assert_eq!(improved.len(), lines.len());
+        assert_eq!(travel(&improved), 20.0);
+        let nearest = DrawOrder::NearestNeighbor.apply(lines.clone());
+        assert!(travel(&DrawOrder::TwoOpt.apply(lines)) <= travel(&nearest));
+        assert!(DrawOrder::TwoOpt.apply(vec![]).is_empty());
+    }
+}
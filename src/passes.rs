@@ -0,0 +1,124 @@
+//! Drawing every polyline several times.
+//!
+//! Worn markers leave faint lines. With `passes` greater than 1, every
+//! polyline is drawn again and again, back and forth without lifting the
+//! pen. With a `pass_offset` (in mm, below 1), the repeated passes are
+//! shifted up and down alternately, which also makes the lines thicker.
+use svg2polylines::{CoordinatePair, Polyline};
+
+/// The most passes a polyline is drawn with.
+pub(crate) const MAX_PASSES: u32 = 5;
+
+/// Return the vertical offset of the pass with the index: 0, +1, -1, +2, …
+/// times the offset.
+fn pass_offset(pass: u32, offset: f64) -> f64 {
+    let step = f64::from(pass.div_ceil(2));
+    match pass % 2 {
+        1 => step * offset,
+        _ => -step * offset,
+    }
+}
+
+/// Draw every polyline `passes` times in a single stroke, every other pass
+/// backwards.
+pub(crate) fn overdraw(polylines: Vec<Polyline>, passes: u32, offset: f64) -> Vec<Polyline> {
+    if passes <= 1 {
+        return polylines;
+    }
+    polylines
+        .into_iter()
+        .map(|polyline| {
+            let mut stroke = Vec::with_capacity(polyline.len() * passes as usize);
+            for pass in 0..passes {
+                let dy = pass_offset(pass, offset);
+                let shifted = polyline.iter().map(|point| CoordinatePair {
+                    x: point.x,
+                    y: point.y + dy,
+                });
+                match pass % 2 {
+                    0 => stroke.extend(shifted),
+                    _ => stroke.extend(shifted.rev()),
+                }
+            }
+            // Don't repeat the turning points without an offset
+            stroke.dedup();
+            stroke
+        })
+        .collect()
+}
+
+/// Check that the number of passes and the offset can be used.
+pub(crate) fn validate(passes: u32, offset: f64) -> Result<(), String> {
+    if !(1..=MAX_PASSES).contains(&passes) {
+        return Err(format!(
+            "The number of passes must be between 1 and {}",
+            MAX_PASSES
+        ));
+    }
+    if !(0.0..1.0).contains(&offset) {
+        return Err("The pass offset must be at least 0 and below 1 mm".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::polyline;
+
+    #[test]
+    fn test_overdraw() {
+        let line = polyline(&[(0.0, 0.0), (10.0, 0.0)]);
+        assert_eq!(overdraw(vec![line.clone()], 1, 0.0), vec![line.clone()]);
+        assert_eq!(
+            overdraw(vec![line.clone()], 3, 0.0),
+            vec![polyline(&[
+                (0.0, 0.0),
+                (10.0, 0.0),
+                (0.0, 0.0),
+                (10.0, 0.0)
+            ])]
+        );
+        assert_eq!(
+            overdraw(vec![line], 3, 0.5),
+            vec![polyline(&[
+                (0.0, 0.0),
+                (10.0, 0.0),
+                (10.0, 0.5),
+                (0.0, 0.5),
+                (0.0, -0.5),
+                (10.0, -0.5),
+            ])]
+        );
+    }
+
+    #[test]
+    fn test_pass_offset() {
+        let offsets: Vec<f64> = (0..5).map(|pass| pass_offset(pass, 0.2)).collect();
+        assert_eq!(offsets, vec![0.0, 0.2, -0.2, 0.4, -0.4]);
+    }
+
+    #[test]
+    fn test_separate_strokes() {
+        // Every polyline is repeated on its own, the pen is lifted in between
+        let a = polyline(&[(0.0, 0.0), (5.0, 0.0)]);
+        let b = polyline(&[(0.0, 10.0), (0.0, 20.0)]);
+        let strokes = overdraw(vec![a, b], 2, 0.0);
+        assert_eq!(
+            strokes,
+            vec![
+                polyline(&[(0.0, 0.0), (5.0, 0.0), (0.0, 0.0)]),
+                polyline(&[(0.0, 10.0), (0.0, 20.0), (0.0, 10.0)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(2, 0.3).is_ok());
+        assert!(validate(0, 0.0).is_err());
+        assert!(validate(MAX_PASSES + 1, 0.0).is_err());
+        assert!(validate(2, 1.0).is_err());
+        assert!(validate(2, f64::NAN).is_err());
+    }
+}
@@ -0,0 +1,348 @@
+//! Extraction of vector paths from PDF pages.
+//!
+//! The content stream of the selected page is interpreted and all painted
+//! paths (stroked or filled) are converted into polylines. Curves are
+//! flattened into line segments. Text, images and form XObjects are ignored.
+//!
+//! The resulting coordinates are in PDF points, with the origin in the top
+//! left corner of the page and the y axis pointing downwards (like SVG).
+use std::collections::HashSet;
+
+use lopdf::content::{Content, Operation};
+use lopdf::{Document, Object, ObjectId};
+use svg2polylines::{CoordinatePair, Polyline};
+
+/// Number of line segments used to approximate a bezier curve.
+const CURVE_SEGMENTS: usize = 16;
+
+/// A transformation matrix `[a b c d e f]`, as used by the PDF `cm` operator.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Matrix([f64; 6]);
+
+impl Matrix {
+    const IDENTITY: Matrix = Matrix([1.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+
+    fn apply(&self, x: f64, y: f64) -> CoordinatePair {
+        let [a, b, c, d, e, f] = self.0;
+        CoordinatePair {
+            x: a * x + c * y + e,
+            y: b * x + d * y + f,
+        }
+    }
+
+    /// Return the matrix `self × other`.
+    fn multiply(&self, other: &Matrix) -> Matrix {
+        let [a, b, c, d, e, f] = self.0;
+        let [oa, ob, oc, od, oe, of] = other.0;
+        Matrix([
+            a * oa + b * oc,
+            a * ob + b * od,
+            c * oa + d * oc,
+            c * ob + d * od,
+            e * oa + f * oc + oe,
+            e * ob + f * od + of,
+        ])
+    }
+}
+
+/// Evaluate a cubic bezier curve at `t`.
+fn bezier(
+    p0: CoordinatePair,
+    p1: CoordinatePair,
+    p2: CoordinatePair,
+    p3: CoordinatePair,
+    t: f64,
+) -> CoordinatePair {
+    let mt = 1.0 - t;
+    let (a, b, c, d) = (mt * mt * mt, 3.0 * mt * mt * t, 3.0 * mt * t * t, t * t * t);
+    CoordinatePair {
+        x: a * p0.x + b * p1.x + c * p2.x + d * p3.x,
+        y: a * p0.y + b * p1.y + c * p2.y + d * p3.y,
+    }
+}
+
+/// Interpreter state while walking through the content stream.
+#[derive(Default)]
+struct PathBuilder {
+    /// The subpaths of the path that is currently being constructed.
+    subpaths: Vec<Polyline>,
+    /// The painted paths.
+    polylines: Vec<Polyline>,
+}
+
+impl PathBuilder {
+    fn current_point(&self) -> Option<CoordinatePair> {
+        self.subpaths
+            .last()
+            .and_then(|subpath| subpath.last())
+            .cloned()
+    }
+
+    fn move_to(&mut self, point: CoordinatePair) {
+        self.subpaths.push(vec![point]);
+    }
+
+    fn line_to(&mut self, point: CoordinatePair) {
+        match self.subpaths.last_mut() {
+            Some(subpath) => subpath.push(point),
+            None => self.move_to(point),
+        }
+    }
+
+    fn curve_to(&mut self, c1: CoordinatePair, c2: CoordinatePair, end: CoordinatePair) {
+        let start = match self.current_point() {
+            Some(point) => point,
+            None => return self.move_to(end),
+        };
+        for i in 1..=CURVE_SEGMENTS {
+            self.line_to(bezier(start, c1, c2, end, i as f64 / CURVE_SEGMENTS as f64));
+        }
+    }
+
+    fn close(&mut self) {
+        if let Some(subpath) = self.subpaths.last_mut() {
+            if let Some(&first) = subpath.first() {
+                subpath.push(first);
+            }
+        }
+    }
+
+    /// Paint the current path.
+    fn paint(&mut self) {
+        self.polylines
+            .extend(self.subpaths.drain(..).filter(|s| s.len() > 1));
+    }
+
+    /// Discard the current path without painting it (e.g. clipping paths).
+    fn discard(&mut self) {
+        self.subpaths.clear();
+    }
+}
+
+/// Return the numeric operands of an operation, if there are enough of them.
+fn operands(operation: &Operation, count: usize) -> Option<Vec<f64>> {
+    if operation.operands.len() < count {
+        return None;
+    }
+    operation.operands[..count]
+        .iter()
+        .map(|o| o.as_float().ok().map(f64::from))
+        .collect()
+}
+
+/// Convert the operations of a content stream into polylines.
+fn polylines_from_operations(operations: &[Operation]) -> Vec<Polyline> {
+    let mut builder = PathBuilder::default();
+    let mut ctm = Matrix::IDENTITY;
+    let mut stack: Vec<Matrix> = vec![];
+
+    for operation in operations {
+        let op = operation.operator.as_str();
+        let count = match op {
+            "cm" | "c" => 6,
+            "v" | "y" | "re" => 4,
+            "m" | "l" => 2,
+            _ => 0,
+        };
+        let args = match operands(operation, count) {
+            Some(args) => args,
+            None => continue,
+        };
+        match op {
+            "q" => stack.push(ctm),
+            "Q" => ctm = stack.pop().unwrap_or(Matrix::IDENTITY),
+            "cm" => {
+                let matrix = Matrix([args[0], args[1], args[2], args[3], args[4], args[5]]);
+                ctm = matrix.multiply(&ctm);
+            }
+            "m" => builder.move_to(ctm.apply(args[0], args[1])),
+            "l" => builder.line_to(ctm.apply(args[0], args[1])),
+            "c" => builder.curve_to(
+                ctm.apply(args[0], args[1]),
+                ctm.apply(args[2], args[3]),
+                ctm.apply(args[4], args[5]),
+            ),
+            "v" => {
+                if let Some(current) = builder.current_point() {
+                    builder.curve_to(
+                        current,
+                        ctm.apply(args[0], args[1]),
+                        ctm.apply(args[2], args[3]),
+                    );
+                }
+            }
+            "y" => {
+                let end = ctm.apply(args[2], args[3]);
+                builder.curve_to(ctm.apply(args[0], args[1]), end, end);
+            }
+            "re" => {
+                let (x, y, w, h) = (args[0], args[1], args[2], args[3]);
+                builder.move_to(ctm.apply(x, y));
+                builder.line_to(ctm.apply(x + w, y));
+                builder.line_to(ctm.apply(x + w, y + h));
+                builder.line_to(ctm.apply(x, y + h));
+                builder.close();
+            }
+            "h" => builder.close(),
+            "S" | "f" | "F" | "f*" | "B" | "B*" => builder.paint(),
+            "s" | "b" | "b*" => {
+                builder.close();
+                builder.paint();
+            }
+            "n" => builder.discard(),
+            _ => {}
+        }
+    }
+    builder.polylines
+}
+
+/// Return the media box (`[llx lly urx ury]`) of a page, which may be
+/// inherited from a parent node in the page tree.
+fn media_box(doc: &Document, page_id: ObjectId) -> Result<[f64; 4], String> {
+    let mut node_id = page_id;
+    let mut visited = HashSet::new();
+    loop {
+        // A crafted page tree may point back to itself
+        if !visited.insert(node_id) {
+            return Err("Invalid page tree: The page is its own ancestor".to_string());
+        }
+        let node = doc
+            .get_dictionary(node_id)
+            .map_err(|e| format!("Invalid page tree: {}", e))?;
+        if let Ok(media_box) = node.get_deref(b"MediaBox", doc).and_then(Object::as_array) {
+            let values: Vec<f64> = media_box
+                .iter()
+                .filter_map(|v| v.as_float().ok().map(f64::from))
+                .collect();
+            if values.len() == 4 {
+                return Ok([values[0], values[1], values[2], values[3]]);
+            }
+        }
+        node_id = node
+            .get(b"Parent")
+            .and_then(Object::as_reference)
+            .map_err(|_| "Page has no media box".to_string())?;
+    }
+}
+
+/// Extract the polylines of the specified page (starting at 1) of a PDF file.
+pub(crate) fn parse(data: &[u8], page: u32) -> Result<Vec<Polyline>, String> {
+    let doc = Document::load_mem(data).map_err(|e| format!("Could not read PDF: {}", e))?;
+    let pages = doc.get_pages();
+    let page_id = *pages.get(&page).ok_or_else(|| {
+        format!(
+            "Page {} does not exist (the document has {} pages)",
+            page,
+            pages.len()
+        )
+    })?;
+    let content = doc
+        .get_page_content(page_id)
+        .map_err(|e| format!("Could not read page content: {}", e))?;
+    let operations = Content::decode(&content)
+        .map_err(|e| format!("Could not decode page content: {}", e))?
+        .operations;
+
+    // Convert to a coordinate system with the origin in the top left corner
+    let [left, _, _, top] = media_box(&doc, page_id)?;
+    let mut polylines = polylines_from_operations(&operations);
+    for polyline in polylines.iter_mut() {
+        for point in polyline.iter_mut() {
+            point.x -= left;
+            point.y = top - point.y;
+        }
+    }
+    Ok(polylines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn polylines(content: &[u8]) -> Vec<Polyline> {
+        polylines_from_operations(&Content::decode(content).unwrap().operations)
+    }
+
+    fn point(x: f64, y: f64) -> CoordinatePair {
+        CoordinatePair { x, y }
+    }
+
+    #[test]
+    fn test_lines() {
+        assert_eq!(
+            polylines(b"10 20 m 30 20 l 30 40 l S"),
+            vec![vec![
+                point(10.0, 20.0),
+                point(30.0, 20.0),
+                point(30.0, 40.0)
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_unpainted_paths_are_ignored() {
+        assert!(polylines(b"0 0 m 10 10 l n").is_empty());
+        assert!(polylines(b"0 0 m 10 10 l").is_empty());
+    }
+
+    #[test]
+    fn test_rectangle_with_transform() {
+        assert_eq!(
+            polylines(b"q 2 0 0 2 5 5 cm 0 0 1 1 re f Q 0 0 m 1 0 l S"),
+            vec![
+                vec![
+                    point(5.0, 5.0),
+                    point(7.0, 5.0),
+                    point(7.0, 7.0),
+                    point(5.0, 7.0),
+                    point(5.0, 5.0),
+                ],
+                vec![point(0.0, 0.0), point(1.0, 0.0)],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_curve() {
+        let result = polylines(b"0 0 m 0 10 10 10 10 0 c S");
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].len(), CURVE_SEGMENTS + 1);
+        assert_eq!(result[0][CURVE_SEGMENTS / 2], point(5.0, 7.5));
+        assert_eq!(result[0][CURVE_SEGMENTS], point(10.0, 0.0));
+    }
+
+    #[test]
+    fn test_matrix_multiply() {
+        let translate = Matrix([1.0, 0.0, 0.0, 1.0, 10.0, 0.0]);
+        let scale = Matrix([2.0, 0.0, 0.0, 2.0, 0.0, 0.0]);
+        // First translate, then scale
+        assert_eq!(translate.multiply(&scale).apply(1.0, 1.0), point(22.0, 2.0));
+    }
+
+    #[test]
+    fn test_media_box_cycle() {
+        let mut doc = Document::with_version("1.5");
+        let page_id = doc.new_object_id();
+        let parent_id = doc.new_object_id();
+        doc.objects.insert(
+            page_id,
+            Object::Dictionary(lopdf::dictionary! { "Parent" => parent_id }),
+        );
+        doc.objects.insert(
+            parent_id,
+            Object::Dictionary(lopdf::dictionary! { "Parent" => page_id }),
+        );
+        assert!(media_box(&doc, page_id).is_err());
+
+        doc.objects.insert(
+            parent_id,
+            Object::Dictionary(lopdf::dictionary! { "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()] }),
+        );
+        assert_eq!(media_box(&doc, page_id), Ok([0.0, 0.0, 595.0, 842.0]));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(parse(b"not a pdf", 1).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+//! Pen profiles, compensating for worn boards and dry markers.
+//!
+//! On worn boards, horizontal strokes tend to come out faint. With the
+//! `double_stroke` option, near-horizontal lines are drawn a second time in
+//! the opposite direction, slightly offset to make them thicker.
+use serde_derive::{Deserialize, Serialize};
+use svg2polylines::{CoordinatePair, Polyline};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub(crate) struct PenProfile {
+    /// Whether to draw near-horizontal lines twice.
+    #[serde(default)]
+    double_stroke: bool,
+    /// Maximum angle to the horizontal (in degrees) of lines that are drawn
+    /// twice.
+    #[serde(default = "default_max_angle")]
+    max_angle: f64,
+    /// Vertical offset of the second stroke in mm.
+    #[serde(default = "default_offset")]
+    offset: f64,
+}
+
+fn default_max_angle() -> f64 {
+    15.0
+}
+
+fn default_offset() -> f64 {
+    0.4
+}
+
+impl PenProfile {
+    fn is_horizontal(&self, a: &CoordinatePair, b: &CoordinatePair) -> bool {
+        let (dx, dy) = ((b.x - a.x).abs(), (b.y - a.y).abs());
+        dx > 0.0 && dy.atan2(dx).to_degrees() <= self.max_angle
+    }
+
+    /// Return the second strokes of the runs of near-horizontal segments in
+    /// the polyline.
+    fn second_strokes(&self, polyline: &[CoordinatePair]) -> Vec<Polyline> {
+        let mut strokes = vec![];
+        let mut run: Polyline = vec![];
+        for segment in polyline.windows(2) {
+            if self.is_horizontal(&segment[0], &segment[1]) {
+                if run.is_empty() {
+                    run.push(segment[0]);
+                }
+                run.push(segment[1]);
+            } else if !run.is_empty() {
+                strokes.push(std::mem::take(&mut run));
+            }
+        }
+        if !run.is_empty() {
+            strokes.push(run);
+        }
+        for stroke in &mut strokes {
+            stroke.reverse();
+            for point in stroke.iter_mut() {
+                point.y += self.offset;
+            }
+        }
+        strokes
+    }
+
+    /// Apply the profile to the polylines. Second strokes directly follow
+    /// their polyline, to keep pen travel short.
+    pub(crate) fn apply(&self, polylines: Vec<Polyline>) -> Vec<Polyline> {
+        if !self.double_stroke {
+            return polylines;
+        }
+        polylines
+            .into_iter()
+            .flat_map(|polyline| {
+                let strokes = self.second_strokes(&polyline);
+                std::iter::once(polyline).chain(strokes)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: f64, y: f64) -> CoordinatePair {
+        CoordinatePair { x, y }
+    }
+
+    fn profile(json: &str) -> PenProfile {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_disabled() {
+        let polylines = vec![vec![point(0.0, 0.0), point(10.0, 0.0)]];
+        assert_eq!(profile("{}").apply(polylines.clone()), polylines);
+    }
+
+    #[test]
+    fn test_double_stroke() {
+        // A horizontal run, a vertical line and a slightly sloped line
+        let polyline = vec![
+            point(0.0, 0.0),
+            point(10.0, 0.0),
+            point(20.0, 1.0),
+            point(20.0, 10.0),
+            point(10.0, 11.0),
+        ];
+        let polylines = profile(r#"{"double_stroke": true, "offset": 0.5}"#).apply(vec![polyline]);
+        assert_eq!(polylines.len(), 3);
+        assert_eq!(
+            polylines[1],
+            vec![point(20.0, 1.5), point(10.0, 0.5), point(0.0, 0.5)]
+        );
+        assert_eq!(polylines[2], vec![point(10.0, 11.5), point(20.0, 10.5)]);
+    }
+
+    #[test]
+    fn test_max_angle() {
+        let polylines = vec![vec![point(0.0, 0.0), point(10.0, 5.0)]];
+        let steep = profile(r#"{"double_stroke": true}"#);
+        assert_eq!(steep.apply(polylines.clone()).len(), 1);
+        let lenient = profile(r#"{"double_stroke": true, "max_angle": 30}"#);
+        assert_eq!(lenient.apply(polylines).len(), 2);
+    }
+}
@@ -0,0 +1,230 @@
+//! Placement of drawings on the board.
+//!
+//! With random placement, a small drawing is moved to a random location that
+//! doesn't overlap anything that is already drawn, so that drawings
+//! accumulate like stickers. Appended drawings are checked for collisions
+//! with existing content.
+use serde_derive::{Deserialize, Serialize};
+use svg2polylines::Polyline;
+
+use crate::generate::Rng;
+use crate::scaling::{self, Bounds};
+
+/// How many random locations are tried before the board is considered full.
+const MAX_ATTEMPTS: usize = 500;
+
+/// Minimum distance in mm between a placed drawing and existing content.
+const SPACING: f64 = 2.0;
+
+/// Grid size in mm of the locations that a colliding drawing is shifted to.
+const SHIFT_STEP: f64 = 5.0;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Placement {
+    /// The drawing is drawn where it is, after erasing the board.
+    #[default]
+    Fixed,
+    /// The drawing is drawn where it is, without erasing the board.
+    Append,
+    /// The drawing is moved to a random free location and drawn without
+    /// erasing the board.
+    Random,
+}
+
+/// What to do if an appended drawing overlaps existing content.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum CollisionPolicy {
+    /// Drop the job.
+    #[default]
+    Reject,
+    /// Move the drawing to the nearest free location.
+    Shift,
+    /// Draw over the existing content.
+    Overlap,
+}
+
+fn overlaps(a: &Bounds, b: &Bounds) -> bool {
+    a.x.min - SPACING < b.x.max
+        && b.x.min - SPACING < a.x.max
+        && a.y.min - SPACING < b.y.max
+        && b.y.min - SPACING < a.y.max
+}
+
+/// Return the bounds of every occupied polyline.
+fn occupied_bounds(occupied: &[Polyline]) -> Vec<Bounds> {
+    occupied
+        .iter()
+        .filter_map(|polyline| scaling::get_bounds(std::slice::from_ref(polyline)))
+        .collect()
+}
+
+/// Return whether a drawing of the specified size at `(x, y)` doesn't
+/// overlap any of the occupied bounds.
+fn is_free(occupied: &[Bounds], (x, y): (f64, f64), (width, height): (f64, f64)) -> bool {
+    let candidate = Bounds {
+        x: scaling::Range {
+            min: x,
+            max: x + width,
+        },
+        y: scaling::Range {
+            min: y,
+            max: y + height,
+        },
+    };
+    !occupied.iter().any(|other| overlaps(&candidate, other))
+}
+
+fn translate(polylines: &mut [Polyline], dx: f64, dy: f64) {
+    for polyline in polylines.iter_mut() {
+        for point in polyline.iter_mut() {
+            point.x += dx;
+            point.y += dy;
+        }
+    }
+}
+
+/// Return whether the polylines overlap the `occupied` polylines.
+pub(crate) fn collides(polylines: &[Polyline], occupied: &[Polyline]) -> bool {
+    match scaling::get_bounds(polylines) {
+        Some(bounds) => !is_free(
+            &occupied_bounds(occupied),
+            (bounds.x.min, bounds.y.min),
+            (bounds.x.spread(), bounds.y.spread()),
+        ),
+        None => false,
+    }
+}
+
+/// Move the polylines to the nearest location on the board that doesn't
+/// overlap the `occupied` polylines. Return `false` if there is no free
+/// location, in which case the polylines are not moved.
+pub(crate) fn shift_to_free(polylines: &mut [Polyline], occupied: &[Polyline]) -> bool {
+    let bounds = match scaling::get_bounds(polylines) {
+        Some(bounds) => bounds,
+        None => return true,
+    };
+    let board = Bounds::board();
+    let size = (bounds.x.spread(), bounds.y.spread());
+    let occupied = occupied_bounds(occupied);
+
+    // Candidate locations on a grid, nearest first
+    let steps = |min: f64, max: f64| {
+        let count = ((max - min) / SHIFT_STEP).floor().max(0.0) as usize;
+        (0..=count).map(move |i| min + i as f64 * SHIFT_STEP)
+    };
+    let mut candidates: Vec<(f64, f64)> = steps(board.x.min, board.x.max - size.0)
+        .flat_map(|x| steps(board.y.min, board.y.max - size.1).map(move |y| (x, y)))
+        .collect();
+    let distance = |&(x, y): &(f64, f64)| (x - bounds.x.min).hypot(y - bounds.y.min);
+    candidates.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+
+    match candidates
+        .into_iter()
+        .find(|&location| is_free(&occupied, location, size))
+    {
+        Some((x, y)) => {
+            translate(polylines, x - bounds.x.min, y - bounds.y.min);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Move the polylines to a random location on the board that doesn't
+/// overlap the `occupied` polylines. Return `false` if there is no free
+/// location, in which case the polylines are moved to a random location
+/// regardless of the occupied polylines.
+pub(crate) fn place_randomly(
+    polylines: &mut [Polyline],
+    occupied: &[Polyline],
+    rng: &mut Rng,
+) -> bool {
+    let bounds = match scaling::get_bounds(polylines) {
+        Some(bounds) => bounds,
+        None => return true,
+    };
+    let board = Bounds::board();
+    let size = (bounds.x.spread(), bounds.y.spread());
+    let (free_x, free_y) = (board.x.spread() - size.0, board.y.spread() - size.1);
+    if free_x < 0.0 || free_y < 0.0 {
+        // The drawing doesn't fit anywhere else
+        return occupied.is_empty();
+    }
+    let occupied = occupied_bounds(occupied);
+
+    let mut random_location = || {
+        let x = board.x.min + rng.next_f64() * free_x;
+        let y = board.y.min + rng.next_f64() * free_y;
+        (x, y)
+    };
+    let free = (0..MAX_ATTEMPTS)
+        .map(|_| random_location())
+        .find(|&location| is_free(&occupied, location, size));
+    let ((x, y), placed) = match free {
+        Some(location) => (location, true),
+        None => (random_location(), false),
+    };
+    translate(polylines, x - bounds.x.min, y - bounds.y.min);
+    placed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::square;
+
+    #[test]
+    fn test_place_randomly() {
+        let mut rng = Rng::new(Some(42));
+        let mut occupied = vec![];
+        for _ in 0..10 {
+            let mut sticker = vec![square(0.0, 0.0, 20.0)];
+            assert!(place_randomly(&mut sticker, &occupied, &mut rng));
+            let bounds = scaling::get_bounds(&sticker).unwrap();
+            let board = Bounds::board();
+            assert!(bounds.x.min >= board.x.min && bounds.x.max <= board.x.max);
+            assert!(bounds.y.min >= board.y.min && bounds.y.max <= board.y.max);
+            for other in &occupied {
+                let other = scaling::get_bounds(std::slice::from_ref(other)).unwrap();
+                assert!(!overlaps(&bounds, &other));
+            }
+            occupied.extend(sticker);
+        }
+    }
+
+    #[test]
+    fn test_board_full() {
+        let board = Bounds::board();
+        let occupied = vec![square(0.0, 0.0, board.x.max.max(board.y.max))];
+        let mut sticker = vec![square(0.0, 0.0, 20.0)];
+        assert!(!place_randomly(
+            &mut sticker,
+            &occupied,
+            &mut Rng::new(Some(1))
+        ));
+    }
+
+    #[test]
+    fn test_collides() {
+        let occupied = vec![square(10.0, 10.0, 20.0)];
+        assert!(collides(&[square(25.0, 25.0, 10.0)], &occupied));
+        assert!(!collides(&[square(40.0, 10.0, 10.0)], &occupied));
+        assert!(!collides(&[square(25.0, 25.0, 10.0)], &[]));
+    }
+
+    #[test]
+    fn test_shift_to_free() {
+        let occupied = vec![square(10.0, 10.0, 20.0)];
+        let mut note = vec![square(20.0, 10.0, 10.0)];
+        assert!(shift_to_free(&mut note, &occupied));
+        let bounds = scaling::get_bounds(&note).unwrap();
+        // Moved right next to the occupied square (plus spacing)
+        assert_eq!((bounds.x.min, bounds.y.min), (35.0, 10.0));
+
+        let board = Bounds::board();
+        let full = vec![square(0.0, 0.0, board.x.max.max(board.y.max))];
+        assert!(!shift_to_free(&mut note, &full));
+    }
+}
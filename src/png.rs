@@ -0,0 +1,342 @@
+//! Conversion of PNG images into polylines.
+//!
+//! The robot can only draw lines, so the dark parts of the image are hatched
+//! with horizontal lines: Every few pixel rows (at most `MAX_ROWS` rows in
+//! total), each run of dark pixels becomes a line. Transparent pixels count
+//! as white. The coordinates are in pixels, so the result is usually fitted
+//! to the board. Interlaced images are not supported.
+use std::io::Read;
+
+use flate2::read::ZlibDecoder;
+use svg2polylines::{CoordinatePair, Polyline};
+
+const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Maximum number of hatched rows, about one per mm of board height.
+const MAX_ROWS: u32 = 120;
+
+/// Pixels with a lower luminance (0–255) are dark.
+const THRESHOLD: u16 = 128;
+
+/// Maximum number of pixels, to limit the memory used for decoding.
+const MAX_PIXELS: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug)]
+struct Header {
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    color_type: u8,
+}
+
+impl Header {
+    fn channels(&self) -> Result<usize, String> {
+        match self.color_type {
+            0 | 3 => Ok(1),
+            2 => Ok(3),
+            4 => Ok(2),
+            6 => Ok(4),
+            other => Err(format!("Unknown PNG color type {}", other)),
+        }
+    }
+
+    fn bits_per_pixel(&self) -> Result<usize, String> {
+        Ok(self.channels()? * usize::from(self.bit_depth))
+    }
+
+    /// The number of bytes of a row, without the filter type byte.
+    fn row_bytes(&self) -> Result<usize, String> {
+        Ok((self.width as usize * self.bits_per_pixel()?).div_ceil(8))
+    }
+}
+
+/// A decoded image, with the luminance of every pixel.
+#[derive(Debug)]
+struct Image {
+    width: u32,
+    height: u32,
+    luminance: Vec<u8>,
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = i16::from(a) + i16::from(b) - i16::from(c);
+    let (pa, pb, pc) = (
+        (p - i16::from(a)).abs(),
+        (p - i16::from(b)).abs(),
+        (p - i16::from(c)).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Undo the filter of a row, given the (already unfiltered) previous row.
+fn unfilter(filter: u8, row: &mut [u8], previous: &[u8], bpp: usize) -> Result<(), String> {
+    for i in 0..row.len() {
+        let a = if i >= bpp { row[i - bpp] } else { 0 };
+        let b = previous[i];
+        let c = if i >= bpp { previous[i - bpp] } else { 0 };
+        row[i] = row[i].wrapping_add(match filter {
+            0 => 0,
+            1 => a,
+            2 => b,
+            3 => ((u16::from(a) + u16::from(b)) / 2) as u8,
+            4 => paeth(a, b, c),
+            other => return Err(format!("Unknown PNG filter type {}", other)),
+        });
+    }
+    Ok(())
+}
+
+/// Return the samples of a row, scaled to 0–255.
+fn samples(row: &[u8], bit_depth: u8, count: usize) -> Vec<u8> {
+    match bit_depth {
+        8 => row[..count].to_vec(),
+        16 => row.chunks_exact(2).take(count).map(|s| s[0]).collect(),
+        depth => {
+            let per_byte = 8 / usize::from(depth);
+            let mask = (1u16 << depth) - 1;
+            (0..count)
+                .map(|i| {
+                    let byte = row[i / per_byte];
+                    let shift = 8 - usize::from(depth) * (i % per_byte + 1);
+                    let value = (u16::from(byte) >> shift) & mask;
+                    (value * 255 / mask) as u8
+                })
+                .collect()
+        }
+    }
+}
+
+fn decode(data: &[u8]) -> Result<Image, String> {
+    if !data.starts_with(SIGNATURE) {
+        return Err("Not a PNG image".to_string());
+    }
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut transparency: &[u8] = &[];
+    let mut compressed = vec![];
+    let mut rest = &data[SIGNATURE.len()..];
+    while rest.len() >= 12 {
+        let length = read_u32(rest) as usize;
+        if rest.len() < length + 12 {
+            return Err("Truncated PNG chunk".to_string());
+        }
+        let (kind, chunk) = (&rest[4..8], &rest[8..8 + length]);
+        match kind {
+            b"IHDR" if chunk.len() == 13 => {
+                if chunk[12] != 0 {
+                    return Err("Interlaced PNG images are not supported".to_string());
+                }
+                header = Some(Header {
+                    width: read_u32(&chunk[0..4]),
+                    height: read_u32(&chunk[4..8]),
+                    bit_depth: chunk[8],
+                    color_type: chunk[9],
+                });
+            }
+            b"PLTE" => palette = chunk,
+            b"tRNS" => transparency = chunk,
+            b"IDAT" => compressed.extend_from_slice(chunk),
+            b"IEND" => break,
+            _ => {}
+        }
+        rest = &rest[length + 12..];
+    }
+    let header = header.ok_or("PNG image without header")?;
+    if !matches!(header.bit_depth, 1 | 2 | 4 | 8 | 16) {
+        return Err(format!("Unknown PNG bit depth {}", header.bit_depth));
+    }
+    if u64::from(header.width) * u64::from(header.height) > MAX_PIXELS {
+        return Err(format!(
+            "PNG image too large ({}×{} pixels)",
+            header.width, header.height
+        ));
+    }
+
+    let row_bytes = header.row_bytes()?;
+    let mut raw = Vec::with_capacity((row_bytes + 1) * header.height as usize);
+    ZlibDecoder::new(&compressed[..])
+        .take(((row_bytes + 1) * header.height as usize) as u64)
+        .read_to_end(&mut raw)
+        .map_err(|e| format!("Invalid PNG image data: {}", e))?;
+    if raw.len() < (row_bytes + 1) * header.height as usize {
+        return Err("Truncated PNG image data".to_string());
+    }
+
+    let channels = header.channels()?;
+    let bpp = header.bits_per_pixel()?.div_ceil(8);
+    let width = header.width as usize;
+    let mut previous = vec![0; row_bytes];
+    let mut luminance = Vec::with_capacity(width * header.height as usize);
+    for line in raw.chunks_exact(row_bytes + 1) {
+        let mut row = line[1..].to_vec();
+        unfilter(line[0], &mut row, &previous, bpp)?;
+        let samples = samples(&row, header.bit_depth, width * channels);
+        for pixel in samples.chunks_exact(channels) {
+            let (gray, alpha) = match (header.color_type, pixel) {
+                (0, [v]) => (u16::from(*v), 255),
+                (2, [r, g, b]) => (rgb_luminance(*r, *g, *b), 255),
+                (3, [index]) => {
+                    // Palette indices are not scaled
+                    let index = usize::from(row_index(*index, header.bit_depth));
+                    let color = palette
+                        .get(index * 3..index * 3 + 3)
+                        .ok_or("PNG palette index out of range")?;
+                    let alpha = transparency.get(index).copied().unwrap_or(255);
+                    (
+                        rgb_luminance(color[0], color[1], color[2]),
+                        u16::from(alpha),
+                    )
+                }
+                (4, [v, a]) => (u16::from(*v), u16::from(*a)),
+                (6, [r, g, b, a]) => (rgb_luminance(*r, *g, *b), u16::from(*a)),
+                _ => unreachable!("Pixel size matches the color type"),
+            };
+            // Composite on white
+            luminance.push(((gray * alpha + 255 * (255 - alpha)) / 255) as u8);
+        }
+        previous = row;
+    }
+    Ok(Image {
+        width: header.width,
+        height: header.height,
+        luminance,
+    })
+}
+
+/// Undo the scaling of `samples` for palette indices.
+fn row_index(sample: u8, bit_depth: u8) -> u8 {
+    match bit_depth {
+        1 | 2 | 4 => {
+            let mask = (1u16 << bit_depth) - 1;
+            (u16::from(sample) * mask / 255) as u8
+        }
+        _ => sample,
+    }
+}
+
+fn rgb_luminance(r: u8, g: u8, b: u8) -> u16 {
+    ((u32::from(r) * 299 + u32::from(g) * 587 + u32::from(b) * 114) / 1000) as u16
+}
+
+/// Hatch the dark parts of the image with horizontal lines (in pixels).
+fn hatch(image: &Image) -> Vec<Polyline> {
+    let step = image.height.div_ceil(MAX_ROWS).max(1);
+    let width = image.width as usize;
+    let mut polylines = vec![];
+    for y in (0..image.height).step_by(step as usize) {
+        let row = &image.luminance[y as usize * width..(y as usize + 1) * width];
+        let mut start = None;
+        for x in 0..=width {
+            let dark = row.get(x).is_some_and(|&v| u16::from(v) < THRESHOLD);
+            match (dark, start) {
+                (true, None) => start = Some(x),
+                (false, Some(from)) => {
+                    let y = f64::from(y) + 0.5;
+                    polylines.push(vec![
+                        CoordinatePair { x: from as f64, y },
+                        CoordinatePair { x: x as f64, y },
+                    ]);
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+    polylines
+}
+
+/// Convert a PNG image into hatched polylines (in pixels).
+pub(crate) fn parse(data: &[u8]) -> Result<Vec<Polyline>, String> {
+    decode(data).map(|image| hatch(&image))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(data);
+        chunk.extend_from_slice(&[0; 4]); // The CRC is not checked
+        chunk
+    }
+
+    /// Encode an 8 bit grayscale image, with a different filter per row.
+    fn png(width: u32, rows: &[&[u8]]) -> Vec<u8> {
+        let mut header = width.to_be_bytes().to_vec();
+        header.extend_from_slice(&(rows.len() as u32).to_be_bytes());
+        header.extend_from_slice(&[8, 0, 0, 0, 0]);
+        let mut raw = vec![];
+        let mut previous = vec![0; width as usize];
+        for (i, row) in rows.iter().enumerate() {
+            // Alternate between no filter and the "up" filter
+            let filter = (i % 2 * 2) as u8;
+            raw.push(filter);
+            for (x, value) in row.iter().enumerate() {
+                raw.push(match filter {
+                    0 => *value,
+                    _ => value.wrapping_sub(previous[x]),
+                });
+            }
+            previous = row.to_vec();
+        }
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(&raw).unwrap();
+        let mut png = SIGNATURE.to_vec();
+        png.extend(chunk(b"IHDR", &header));
+        png.extend(chunk(b"IDAT", &encoder.finish().unwrap()));
+        png.extend(chunk(b"IEND", &[]));
+        png
+    }
+
+    #[test]
+    fn test_hatch() {
+        let image = png(
+            5,
+            &[
+                &[255, 0, 0, 255, 0],
+                &[0, 0, 255, 255, 255],
+                &[200, 200, 200, 200, 200],
+            ],
+        );
+        let polylines = parse(&image).unwrap();
+        let lines: Vec<(f64, f64, f64)> = polylines
+            .iter()
+            .map(|line| (line[0].x, line[1].x, line[0].y))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![(1.0, 3.0, 0.5), (4.0, 5.0, 0.5), (0.0, 2.0, 1.5)]
+        );
+    }
+
+    #[test]
+    fn test_samples() {
+        assert_eq!(samples(&[0b1011_0000], 1, 4), vec![255, 0, 255, 255]);
+        assert_eq!(samples(&[0b1101_0000], 2, 2), vec![255, 85]);
+        assert_eq!(row_index(85, 2), 1);
+        assert_eq!(paeth(10, 20, 15), 15);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(parse(b"GIF89a").is_err());
+        let mut image = png(2, &[&[0, 0]]);
+        image.truncate(image.len() - 20);
+        assert!(parse(&image).is_err());
+    }
+}
@@ -0,0 +1,334 @@
+//! Checks of single prints before they are accepted.
+//!
+//! A job that would not fit on the board, could not be finished within the
+//! time limits, or exceeds the daily quota of its tenant is rejected right
+//! away instead of failing (or being cut off) on the robot. All violated
+//! constraints are reported at once, each with a hint on how to fix it.
+use std::time::Duration;
+
+use serde_derive::Serialize;
+use svg2polylines::Polyline;
+use time::Time;
+
+use crate::placement::Placement;
+use crate::robot::Sketch;
+use crate::scaling::{self, Bounds, Orientation};
+use crate::simulate::{SimulationConfig, Simulator};
+use crate::timelimits::TimeLimits;
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Constraint {
+    Bounds,
+    TimeLimits,
+    Quota,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub(crate) struct Violation {
+    pub(crate) constraint: Constraint,
+    pub(crate) message: String,
+    /// Details of a violation of the bounds.
+    #[serde(flatten)]
+    pub(crate) bounds: Option<BoundsDetails>,
+}
+
+/// A rectangular extent in board coordinates (in mm).
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+pub(crate) struct Extent {
+    pub(crate) x_min: f64,
+    pub(crate) x_max: f64,
+    pub(crate) y_min: f64,
+    pub(crate) y_max: f64,
+}
+
+impl From<&Bounds> for Extent {
+    fn from(bounds: &Bounds) -> Self {
+        Extent {
+            x_min: bounds.x.min,
+            x_max: bounds.x.max,
+            y_min: bounds.y.min,
+            y_max: bounds.y.max,
+        }
+    }
+}
+
+/// Where a drawing lies, and where it has to lie.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub(crate) struct BoundsDetails {
+    /// The extent of the drawing.
+    pub(crate) extent: Extent,
+    /// The area of the board that the drawing has to lie within.
+    pub(crate) board: Extent,
+    /// The largest factor that the drawing can be scaled by to fit the
+    /// board, if it is too large.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) suggested_scale: Option<f64>,
+}
+
+/// What a job is checked against.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Limits {
+    pub(crate) time_limits: Option<TimeLimits>,
+    /// The current local time.
+    pub(crate) now: Time,
+    /// The number of jobs the tenant may still print today, if it has a
+    /// quota.
+    pub(crate) quota_remaining: Option<u32>,
+    /// The margin along the edges of the board that must stay free, in mm.
+    pub(crate) margin: f64,
+}
+
+/// Estimate how long the robot takes to draw the polylines.
+pub(crate) fn estimate(
+    polylines: &[Polyline],
+    erase: bool,
+    orientation: Orientation,
+    simulation: SimulationConfig,
+) -> Duration {
+    let mut simulator = Simulator::new(simulation);
+    Sketch::new(polylines)
+        .with_orientation(orientation)
+        .into_blocks(erase)
+        .iter()
+        .for_each(|block| simulator.feed(block));
+    simulator.elapsed()
+}
+
+/// Return the constraints violated by a job. Without an `estimate` (e.g. for
+/// dry runs), the time limits are not checked.
+pub(crate) fn check(
+    polylines: &[Polyline],
+    placement: Placement,
+    estimate: Option<Duration>,
+    limits: &Limits,
+) -> Vec<Violation> {
+    let mut violations = vec![];
+    violations.extend(check_bounds(polylines, placement, limits.margin));
+    if let (Some(estimate), Some(time_limits)) = (estimate, limits.time_limits) {
+        violations.extend(check_time(estimate, &time_limits, &limits.now));
+    }
+    if limits.quota_remaining == Some(0) {
+        violations.push(Violation {
+            constraint: Constraint::Quota,
+            message: "The daily quota is exhausted. Try again tomorrow.".to_string(),
+            bounds: None,
+        });
+    }
+    violations
+}
+
+/// Return the factor (rounded down to hundredths) that the bounds have to be
+/// scaled by to fit the board, if they are too large.
+fn suggested_scale(bounds: &Bounds, board: &Bounds) -> Option<f64> {
+    let factor = [
+        board.x.spread() / bounds.x.spread(),
+        board.y.spread() / bounds.y.spread(),
+    ]
+    .into_iter()
+    .filter(|factor| factor.is_finite())
+    .fold(f64::INFINITY, f64::min);
+    (factor < 1.0).then(|| (factor * 100.0).floor() / 100.0)
+}
+
+/// Check that the drawing lies within the board, or for random placements,
+/// that it is small enough to be moved onto it.
+pub(crate) fn check_bounds(
+    polylines: &[Polyline],
+    placement: Placement,
+    margin: f64,
+) -> Option<Violation> {
+    let bounds = scaling::get_bounds(polylines)?;
+    let board = Bounds::drawable(margin);
+    let suggested_scale = suggested_scale(&bounds, &board);
+    let hint = match suggested_scale {
+        Some(scale) => format!("Scale it by {:.2} or less", scale),
+        None => "Move it".to_string(),
+    };
+    let message = match placement {
+        // Random placements move the drawing, so only its size matters
+        Placement::Random => {
+            suggested_scale?;
+            format!(
+                "The drawing is {:.0}×{:.0} mm, but the board is only {:.0}×{:.0} mm. {}.",
+                bounds.x.spread(),
+                bounds.y.spread(),
+                board.x.spread(),
+                board.y.spread(),
+                hint
+            )
+        }
+        Placement::Fixed | Placement::Append => {
+            if bounds.x.min >= board.x.min
+                && bounds.x.max <= board.x.max
+                && bounds.y.min >= board.y.min
+                && bounds.y.max <= board.y.max
+            {
+                return None;
+            }
+            format!(
+                "The drawing spans x {:.0}–{:.0} mm and y {:.0}–{:.0} mm, outside of the board \
+                 ({:.0}–{:.0} × {:.0}–{:.0} mm). {} onto the board.",
+                bounds.x.min,
+                bounds.x.max,
+                bounds.y.min,
+                bounds.y.max,
+                board.x.min,
+                board.x.max,
+                board.y.min,
+                board.y.max,
+                match suggested_scale {
+                    Some(_) => format!("{} and move it", hint),
+                    None => hint,
+                }
+            )
+        }
+    };
+    Some(Violation {
+        constraint: Constraint::Bounds,
+        message,
+        bounds: Some(BoundsDetails {
+            extent: Extent::from(&bounds),
+            board: Extent::from(&board),
+            suggested_scale,
+        }),
+    })
+}
+
+fn check_time(estimate: Duration, time_limits: &TimeLimits, now: &Time) -> Option<Violation> {
+    let message = match time_limits.remaining(now) {
+        None => format!(
+            "The board only draws within the time limits ({}). Submit the job then.",
+            time_limits
+        ),
+        Some(remaining) if estimate > remaining => format!(
+            "Drawing takes about {} min, but the time limits ({}) end in {} min. \
+             Simplify the drawing, use the speed \"fast\", or submit it later.",
+            minutes(estimate),
+            time_limits,
+            remaining.as_secs() / 60
+        ),
+        Some(_) => return None,
+    };
+    Some(Violation {
+        constraint: Constraint::TimeLimits,
+        message,
+        bounds: None,
+    })
+}
+
+/// Round up to whole minutes.
+fn minutes(duration: Duration) -> u64 {
+    duration.as_secs().div_ceil(60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use svg2polylines::CoordinatePair;
+    use time::macros::time;
+
+    fn line(from: (f64, f64), to: (f64, f64)) -> Polyline {
+        vec![
+            CoordinatePair {
+                x: from.0,
+                y: from.1,
+            },
+            CoordinatePair { x: to.0, y: to.1 },
+        ]
+    }
+
+    fn limits(quota_remaining: Option<u32>) -> Limits {
+        Limits {
+            time_limits: serde_json::from_str(r#"{"start_time": "08:00", "end_time": "20:00"}"#)
+                .unwrap(),
+            now: time!(19:50),
+            quota_remaining,
+            margin: 0.0,
+        }
+    }
+
+    fn constraints(violations: &[Violation]) -> Vec<Constraint> {
+        violations.iter().map(|v| v.constraint).collect()
+    }
+
+    #[test]
+    fn test_accepted() {
+        let polylines = vec![line((10.0, 10.0), (100.0, 100.0))];
+        let estimate = Some(Duration::from_secs(60));
+        assert!(check(&polylines, Placement::Fixed, estimate, &limits(Some(1))).is_empty());
+        assert!(check(&polylines, Placement::Fixed, estimate, &limits(None)).is_empty());
+        assert!(check(&[], Placement::Fixed, estimate, &limits(None)).is_empty());
+    }
+
+    #[test]
+    fn test_bounds() {
+        // Too far right for a fixed placement, but small enough to be moved
+        let polylines = vec![line((300.0, 10.0), (400.0, 100.0))];
+        let violations = check(&polylines, Placement::Fixed, None, &limits(None));
+        assert_eq!(constraints(&violations), vec![Constraint::Bounds]);
+        assert!(check(&polylines, Placement::Random, None, &limits(None)).is_empty());
+
+        let details = violations[0].bounds.as_ref().unwrap();
+        assert_eq!(details.extent.x_min, 300.0);
+        assert_eq!(details.board.x_max, 358.0);
+        assert_eq!(details.suggested_scale, None);
+        assert!(violations[0].message.ends_with("Move it onto the board."));
+
+        let polylines = vec![line((0.0, 0.0), (500.0, 100.0))];
+        let violations = check(&polylines, Placement::Random, None, &limits(None));
+        assert_eq!(constraints(&violations), vec![Constraint::Bounds]);
+        let details = violations[0].bounds.as_ref().unwrap();
+        assert_eq!(details.suggested_scale, Some(0.71));
+        assert!(violations[0].message.contains("Scale it by 0.71 or less"));
+        let json = serde_json::to_value(&violations[0]).unwrap();
+        assert_eq!(json["extent"]["x_max"], 500.0);
+        assert_eq!(json["suggested_scale"], 0.71);
+
+        // The margin along the edges must stay free
+        let polylines = vec![line((5.0, 5.0), (100.0, 100.0))];
+        let margin = Limits {
+            margin: 10.0,
+            ..limits(None)
+        };
+        let violations = check(&polylines, Placement::Fixed, None, &margin);
+        assert_eq!(constraints(&violations), vec![Constraint::Bounds]);
+        assert!(violations[0].message.contains("10–348 × 10–113 mm"));
+        assert!(check(&polylines, Placement::Fixed, None, &limits(None)).is_empty());
+    }
+
+    #[test]
+    fn test_all_violations_reported() {
+        let polylines = vec![line((-10.0, 0.0), (100.0, 100.0))];
+        let estimate = Some(Duration::from_secs(15 * 60));
+        let violations = check(&polylines, Placement::Fixed, estimate, &limits(Some(0)));
+        assert_eq!(
+            constraints(&violations),
+            vec![
+                Constraint::Bounds,
+                Constraint::TimeLimits,
+                Constraint::Quota
+            ]
+        );
+        assert!(violations[1].message.contains("15 min"));
+        assert!(violations[1].message.contains("end in 10 min"));
+
+        // Outside of the time limits, even short jobs are rejected
+        let outside = Limits {
+            now: time!(21:00),
+            ..limits(None)
+        };
+        let polylines = vec![line((0.0, 0.0), (1.0, 1.0))];
+        let violations = check(&polylines, Placement::Fixed, Some(Duration::ZERO), &outside);
+        assert_eq!(constraints(&violations), vec![Constraint::TimeLimits]);
+    }
+
+    #[test]
+    fn test_estimate() {
+        let simulation = SimulationConfig::default();
+        let polylines = vec![line((0.0, 0.0), (100.0, 0.0))];
+        let drawing = estimate(&polylines, false, Orientation::default(), simulation);
+        assert!(drawing > Duration::ZERO);
+        assert!(estimate(&polylines, true, Orientation::default(), simulation) > drawing);
+    }
+}
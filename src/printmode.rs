@@ -3,41 +3,128 @@ use std::time::Duration;
 use serde_derive::Deserialize;
 
 use svg2polylines::Polyline;
+use time::OffsetDateTime;
 
+use crate::drawing::Drawing;
+use crate::jobqueue::Job;
 use crate::robot::PrintTask;
+use crate::rotation::Rotator;
 
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "lowercase")]
+/// How often a drawing is printed: `"once"`, every few minutes with
+/// `{"schedule": {"minutes": 10}}`, or once at a later time with
+/// `{"at": "2026-10-18T07:45:00+02:00"}`. The names `"schedule<minutes>"`
+/// (e.g. `"schedule15"`) are accepted as well, also in query strings.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "RawPrintMode")]
 pub(crate) enum PrintMode {
     Once,
-    Schedule5,
-    Schedule15,
-    Schedule30,
-    Schedule60,
+    Schedule {
+        minutes: u64,
+    },
+    /// Print once, as soon as the time has come (and the time limits allow
+    /// it).
+    At(OffsetDateTime),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TaggedPrintMode {
+    Once,
+    Schedule { minutes: u64 },
+    At(#[serde(with = "time::serde::rfc3339")] OffsetDateTime),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawPrintMode {
+    Tagged(TaggedPrintMode),
+    Name(String),
+}
+
+impl TryFrom<RawPrintMode> for PrintMode {
+    type Error = String;
+
+    fn try_from(raw: RawPrintMode) -> Result<Self, Self::Error> {
+        let mode = match raw {
+            RawPrintMode::Tagged(TaggedPrintMode::Once) => PrintMode::Once,
+            RawPrintMode::Tagged(TaggedPrintMode::Schedule { minutes }) => {
+                PrintMode::Schedule { minutes }
+            }
+            RawPrintMode::Tagged(TaggedPrintMode::At(time)) => PrintMode::At(time),
+            RawPrintMode::Name(name) => match name.strip_prefix("schedule") {
+                Some(minutes) => PrintMode::Schedule {
+                    minutes: minutes
+                        .parse()
+                        .map_err(|_| format!("Invalid print mode: {}", name))?,
+                },
+                None => return Err(format!("Invalid print mode: {}", name)),
+            },
+        };
+        if mode == (PrintMode::Schedule { minutes: 0 }) {
+            return Err("The schedule interval must be at least one minute".to_string());
+        }
+        Ok(mode)
+    }
 }
 
 impl PrintMode {
-    pub(crate) fn to_print_task(&self, polylines: Vec<Polyline>) -> PrintTask {
+    /// Return the interval of scheduled print modes.
+    pub(crate) fn interval(&self) -> Option<Duration> {
         match *self {
-            PrintMode::Once => PrintTask::Once(polylines),
-            PrintMode::Schedule5 => {
-                PrintTask::Scheduled(Duration::from_secs(5 * 60), vec![polylines])
-            }
-            PrintMode::Schedule15 => {
-                PrintTask::Scheduled(Duration::from_secs(15 * 60), vec![polylines])
-            }
-            PrintMode::Schedule30 => {
-                PrintTask::Scheduled(Duration::from_secs(30 * 60), vec![polylines])
-            }
-            PrintMode::Schedule60 => {
-                PrintTask::Scheduled(Duration::from_secs(60 * 60), vec![polylines])
-            }
+            PrintMode::Once | PrintMode::At(_) => None,
+            PrintMode::Schedule { minutes } => Some(Duration::from_secs(minutes * 60)),
+        }
+    }
+
+    /// Return the time that a single print is held until, if any.
+    pub(crate) fn start_at(&self) -> Option<OffsetDateTime> {
+        match *self {
+            PrintMode::At(time) => Some(time),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn to_print_task(&self, polylines: Vec<Polyline>) -> PrintTask {
+        match (self.interval(), self.start_at()) {
+            (None, None) => PrintTask::Once(polylines),
+            (None, start_at) => PrintTask::Job(
+                None,
+                Job {
+                    start_at,
+                    ..Job::new("Single print".to_string(), polylines)
+                },
+            ),
+            (Some(interval), _) => PrintTask::Scheduled(
+                interval,
+                vec![Drawing::Static(polylines)],
+                Rotator::sequential(1),
+                Duration::ZERO,
+            ),
+        }
+    }
+
+    /// Create a print task for a drawing. When printing once, generated
+    /// drawings are generated immediately.
+    pub(crate) fn to_drawing_task(&self, drawing: Drawing) -> Result<PrintTask, String> {
+        match drawing {
+            Drawing::Static(polylines) => Ok(self.to_print_task(polylines)),
+            Drawing::Generated(generator) => match self.interval() {
+                None => Ok(self.to_print_task(generator.generate()?.polylines.into_owned())),
+                Some(interval) => Ok(PrintTask::Scheduled(
+                    interval,
+                    vec![Drawing::Generated(generator)],
+                    Rotator::sequential(1),
+                    Duration::ZERO,
+                )),
+            },
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use time::macros::datetime;
+
     use super::*;
 
     #[test]
@@ -46,20 +133,53 @@ mod tests {
         let polylines = vec![];
         match mode.to_print_task(polylines.clone()) {
             PrintTask::Once(p) => assert_eq!(p, polylines),
-            t @ _ => panic!("Task was {:?}", t),
+            t => panic!("Task was {:?}", t),
         }
     }
 
     #[test]
     fn print_mode_to_print_task_every() {
-        let mode = PrintMode::Schedule5;
+        let mode = PrintMode::Schedule { minutes: 5 };
         let polylines = vec![];
         match mode.to_print_task(polylines.clone()) {
-            PrintTask::Scheduled(d, p) => {
+            PrintTask::Scheduled(d, p, _, _) => {
                 assert_eq!(d, Duration::from_secs(60 * 5));
-                assert_eq!(p, vec![polylines]);
+                assert_eq!(p, vec![Drawing::Static(polylines)]);
             }
-            t @ _ => panic!("Task was {:?}", t),
+            t => panic!("Task was {:?}", t),
         }
     }
+
+    #[test]
+    fn print_mode_to_print_task_at() {
+        let mode = PrintMode::At(datetime!(2026-10-18 07:45 +02:00));
+        match mode.to_print_task(vec![]) {
+            PrintTask::Job(None, job) => {
+                assert_eq!(job.start_at, Some(datetime!(2026-10-18 05:45 UTC)));
+            }
+            t => panic!("Task was {:?}", t),
+        }
+    }
+
+    #[test]
+    fn print_mode_deserialize() {
+        let parse = |json: &str| serde_json::from_str::<PrintMode>(json);
+        assert_eq!(parse(r#""once""#).unwrap(), PrintMode::Once);
+        assert_eq!(
+            parse(r#""schedule15""#).unwrap(),
+            PrintMode::Schedule { minutes: 15 }
+        );
+        assert_eq!(
+            parse(r#"{"schedule": {"minutes": 120}}"#).unwrap(),
+            PrintMode::Schedule { minutes: 120 }
+        );
+        assert_eq!(
+            parse(r#"{"at": "2026-10-18T07:45:00+02:00"}"#).unwrap(),
+            PrintMode::At(datetime!(2026-10-18 07:45 +02:00))
+        );
+        assert!(parse(r#"{"at": "tomorrow"}"#).is_err());
+        assert!(parse(r#""schedule""#).is_err());
+        assert!(parse(r#""twice""#).is_err());
+        assert!(parse(r#"{"schedule": {"minutes": 0}}"#).is_err());
+    }
 }
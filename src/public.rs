@@ -0,0 +1,119 @@
+//! Protections for preview servers that are accessible publicly: Rate
+//! limiting, payload caps and an optional shared password.
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde_derive::Deserialize;
+
+/// Length of the rate limiting window.
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Prune expired clients when this many clients are tracked.
+const MAX_CLIENTS: usize = 1024;
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub(crate) struct PublicConfig {
+    /// Maximum number of preview requests per client and minute.
+    pub(crate) requests_per_minute: Option<u32>,
+    /// Maximum size of request bodies in KiB.
+    pub(crate) max_payload_kb: Option<usize>,
+    /// Password that is required through HTTP basic authentication (with
+    /// any user name).
+    password: Option<String>,
+}
+
+impl PublicConfig {
+    /// Return whether the `Authorization` header carries the password, or
+    /// no password is required.
+    pub(crate) fn is_authorized(&self, authorization: Option<&str>) -> bool {
+        let password = match self.password {
+            Some(ref password) => password,
+            None => return true,
+        };
+        authorization
+            .and_then(|value| value.strip_prefix("Basic "))
+            .and_then(|encoded| STANDARD.decode(encoded.trim()).ok())
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .and_then(|credentials| {
+                credentials
+                    .split_once(':')
+                    .map(|(_, given)| given == password)
+            })
+            .unwrap_or(false)
+    }
+}
+
+pub(crate) type SharedRateLimiter = Arc<Mutex<RateLimiter>>;
+
+/// Counts the requests of every client in fixed windows of one minute.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    limit: u32,
+    clients: HashMap<IpAddr, (Instant, u32)>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new_shared(limit: u32) -> SharedRateLimiter {
+        Arc::new(Mutex::new(RateLimiter {
+            limit,
+            clients: HashMap::new(),
+        }))
+    }
+
+    /// Count a request of the client. Return `false` if the client has
+    /// exceeded the limit.
+    pub(crate) fn try_request(&mut self, client: IpAddr, now: Instant) -> bool {
+        if self.clients.len() >= MAX_CLIENTS {
+            self.clients
+                .retain(|_, (start, _)| now.duration_since(*start) < WINDOW);
+        }
+        let (start, count) = self.clients.entry(client).or_insert((now, 0));
+        if now.duration_since(*start) >= WINDOW {
+            *start = now;
+            *count = 0;
+        }
+        if *count >= self.limit {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(json: &str) -> PublicConfig {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_is_authorized() {
+        assert!(config("{}").is_authorized(None));
+
+        let protected = config(r#"{"password": "sesame"}"#);
+        assert!(!protected.is_authorized(None));
+        // "visitor:sesame" and "visitor:wrong"
+        assert!(protected.is_authorized(Some("Basic dmlzaXRvcjpzZXNhbWU=")));
+        assert!(!protected.is_authorized(Some("Basic dmlzaXRvcjp3cm9uZw==")));
+        assert!(!protected.is_authorized(Some("Bearer sesame")));
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let limiter = RateLimiter::new_shared(2);
+        let mut limiter = limiter.lock().unwrap();
+        let (a, b): (IpAddr, IpAddr) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+        let now = Instant::now();
+        assert!(limiter.try_request(a, now));
+        assert!(limiter.try_request(a, now));
+        assert!(!limiter.try_request(a, now + Duration::from_secs(59)));
+        assert!(limiter.try_request(b, now));
+        assert!(limiter.try_request(a, now + WINDOW));
+    }
+}
@@ -0,0 +1,198 @@
+//! Retrying writes to the robot.
+//!
+//! Writes to the serial device time out after a second (e.g. when a USB
+//! adapter is wedged) instead of blocking the robot thread. A block whose
+//! write timed out is written again, up to `retries` times and as long as
+//! `timeout_ms` haven't passed since the first attempt. If it still can't be
+//! written, the job is failed and the device is reopened.
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::warn;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::robot::Connection;
+
+/// How long to wait before writing a block again.
+const RETRY_DELAY: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct WritePolicy {
+    /// How long to keep trying to write a block, in milliseconds.
+    #[serde(default = "default_timeout_ms")]
+    pub(crate) timeout_ms: u64,
+    /// How often to write a block again after a timeout.
+    #[serde(default = "default_retries")]
+    pub(crate) retries: u32,
+}
+
+fn default_timeout_ms() -> u64 {
+    10_000
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+impl Default for WritePolicy {
+    fn default() -> Self {
+        WritePolicy {
+            timeout_ms: default_timeout_ms(),
+            retries: default_retries(),
+        }
+    }
+}
+
+/// A block that could not be written.
+#[derive(Debug)]
+pub(crate) struct WriteError {
+    /// The error of the last attempt.
+    pub(crate) error: io::Error,
+    /// Number of attempts made.
+    pub(crate) attempts: u32,
+    /// Whether the write timed out (as opposed to the device being gone).
+    pub(crate) timed_out: bool,
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (after {} attempt(s))", self.error, self.attempts)
+    }
+}
+
+fn is_transient(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        ErrorKind::TimedOut | ErrorKind::WouldBlock | ErrorKind::Interrupted
+    )
+}
+
+/// Write a block to the robot, retrying timeouts according to the policy.
+/// Other errors (e.g. a disconnected device) are returned right away.
+pub(crate) fn write_block(
+    connection: &mut dyn Connection,
+    block: &[u8],
+    policy: WritePolicy,
+) -> Result<(), WriteError> {
+    let deadline = Instant::now() + Duration::from_millis(policy.timeout_ms);
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let error = match connection.write_all(block).and_then(|_| connection.flush()) {
+            Ok(()) => return Ok(()),
+            Err(error) => error,
+        };
+        let timed_out = is_transient(&error);
+        if !timed_out || attempts > policy.retries || Instant::now() + RETRY_DELAY > deadline {
+            return Err(WriteError {
+                error,
+                attempts,
+                timed_out,
+            });
+        }
+        warn!(
+            "Could not write block (attempt {}/{}): {}",
+            attempts,
+            policy.retries + 1,
+            error
+        );
+        thread::sleep(RETRY_DELAY);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, Read, Write};
+
+    use super::*;
+
+    /// A connection whose writes fail with the queued errors first.
+    #[derive(Default)]
+    struct Flaky {
+        errors: Vec<ErrorKind>,
+        written: Vec<u8>,
+    }
+
+    impl Read for Flaky {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Ok(0)
+        }
+    }
+
+    impl BufRead for Flaky {
+        fn fill_buf(&mut self) -> io::Result<&[u8]> {
+            Ok(&[])
+        }
+
+        fn consume(&mut self, _: usize) {}
+    }
+
+    impl Write for Flaky {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if !self.errors.is_empty() {
+                return Err(self.errors.remove(0).into());
+            }
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn policy(retries: u32) -> WritePolicy {
+        WritePolicy {
+            timeout_ms: 5_000,
+            retries,
+        }
+    }
+
+    #[test]
+    fn test_retry_timeouts() {
+        let mut connection = Flaky {
+            errors: vec![ErrorKind::TimedOut, ErrorKind::TimedOut],
+            ..Flaky::default()
+        };
+        assert!(write_block(&mut connection, &[1, 2, 3], policy(2)).is_ok());
+        assert_eq!(connection.written, vec![1, 2, 3]);
+
+        let mut connection = Flaky {
+            errors: vec![ErrorKind::TimedOut; 3],
+            ..Flaky::default()
+        };
+        let error = write_block(&mut connection, &[1, 2, 3], policy(2)).unwrap_err();
+        assert_eq!(error.attempts, 3);
+        assert!(error.timed_out);
+        assert!(connection.written.is_empty());
+    }
+
+    #[test]
+    fn test_no_retry_when_gone() {
+        let mut connection = Flaky {
+            errors: vec![ErrorKind::BrokenPipe],
+            ..Flaky::default()
+        };
+        let error = write_block(&mut connection, &[1], policy(3)).unwrap_err();
+        assert_eq!(error.attempts, 1);
+        assert!(!error.timed_out);
+
+        // The timeout limits the retries
+        let mut connection = Flaky {
+            errors: vec![ErrorKind::TimedOut; 3],
+            ..Flaky::default()
+        };
+        let policy = WritePolicy {
+            timeout_ms: 0,
+            retries: 3,
+        };
+        assert_eq!(
+            write_block(&mut connection, &[1], policy)
+                .unwrap_err()
+                .attempts,
+            1
+        );
+    }
+}
@@ -1,33 +1,114 @@
-use std::collections::VecDeque;
-use std::io::{self, BufRead, Write};
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
-use std::sync::{Arc, Mutex};
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::{self, BufRead, ErrorKind, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, Sender, SyncSender};
+use std::sync::Mutex;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use bufstream::BufStream;
 use log::{debug, error, info, warn};
-use regex::Regex;
 use scheduled_executor::executor::TaskHandle;
 use scheduled_executor::CoreExecutor;
-use serial::{self, BaudRate, PortSettings, SerialPort};
-use svg2polylines::Polyline;
+use serial::{self, BaudRate, PortSettings, SerialPort, SystemPort};
+use svg2polylines::{CoordinatePair, Polyline};
 use time::OffsetDateTime;
 
-use crate::TimeLimits;
+use crate::alerts::AlertConfig;
+use crate::archive::{Archive, PendingEntry};
+use crate::board::{self, Board};
+use crate::capture::{self, CaptureConfig};
+use crate::console::SerialConsole;
+use crate::detect;
+use crate::drawing::Drawing;
+use crate::dryrun::DryRunConfig;
+use crate::errors::ErrorCode;
+use crate::expiry::{self, Expiries};
+use crate::firmware::{self, Firmware};
+use crate::flowcontrol::{self, Action, FlowControl};
+use crate::generate::Rng;
+use crate::history::{JobRecord, SharedHistory};
+use crate::holidays::SharedHolidays;
+use crate::jobqueue::{Job, SchedulingPolicy, SharedJobQueue};
+use crate::jobs::{self, JobId, JobState, JobTrace, SharedJobRegistry};
+use crate::mock::{MockRobot, MOCK_DEVICE};
+use crate::notify::Event;
+use crate::placement::{self, CollisionPolicy, Placement};
+use crate::retry::{self, WriteError, WritePolicy};
+use crate::roll::{self, PaperRoll};
+use crate::rotation::Rotator;
+use crate::scaling::{Bounds, Orientation};
+use crate::servo::{PenPosition, PenServo};
+use crate::simulate::{SimulationConfig, Simulator};
+use crate::status::{self, SharedStatus, Status};
+use crate::timelimits::{self, MissedRuns, SharedTimeLimits, TimeLimits};
+use crate::watchdog::SharedWatchdog;
 
 pub(crate) const IBB_WIDTH: u16 = 358;
 pub(crate) const IBB_HEIGHT: u16 = 123;
 const TIMEOUT_MS_SERIAL: u64 = 1000;
 const TIMEOUT_MS_CHANNEL: u64 = 50;
+const TIMEOUT_MS_TCP_CONNECT: u64 = 5000;
+/// How long a lowered pen stays down while calibrating.
+const CALIBRATION_WAIT_SECONDS: u8 = 5;
+/// The longest paper feed that fits into a command.
+const MAX_FEED_MM: u32 = 0x0fff;
+/// The longest wait that fits into a command.
+const MAX_WAIT_SECONDS: u32 = 30;
+/// The longest a drawing may be kept on the board before the next job.
+pub(crate) const MAX_HOLD_SECONDS: u32 = 3600;
+/// How long to wait for the robot to finish its current block and park
+/// when shutting down.
+pub(crate) const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(75);
+/// Prefix of devices that are serial bridges on the network (e.g. ser2net
+/// or ESP-Link), e.g. `tcp://192.168.1.20:2000`.
+pub(crate) const TCP_PREFIX: &str = "tcp://";
+const MAX_STALL_RETRIES: u32 = 3;
+/// Delay before the first attempt to reconnect. It is doubled after every
+/// failed attempt, up to `MAX_RECONNECT_DELAY_SECS`.
+const RECONNECT_DELAY_SECS: u64 = 1;
+const MAX_RECONNECT_DELAY_SECS: u64 = 60;
+
+/// After running this long without crashing, the robot thread is restarted
+/// right away again after the next crash.
+const STABLE_RUN: Duration = Duration::from_secs(600);
 
 type Block = Vec<u8>;
 
+/// A connection to the robot, e.g. a serial port.
+pub(crate) trait Connection: BufRead + Write + Send {}
+
+impl<T: BufRead + Write + Send> Connection for T {}
+
+/// What to erase before drawing a sketch.
+enum Erase<'a> {
+    Nothing,
+    All,
+    Region(&'a Bounds),
+}
+
+/// The tool that a stroke is drawn with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tool {
+    Pen,
+    Eraser,
+}
+
 pub struct Sketch<'a> {
     buf: Vec<u8>,
     block_size: usize,
     polylines: &'a [Polyline],
+    /// Strokes wiped with the eraser before the polylines are drawn.
+    eraser: &'a [Polyline],
+    orientation: Orientation,
+    servo: PenServo,
+    /// Paper to feed before drawing, in mm (paper roll variant only).
+    feed_mm: u32,
+    /// How long to wait after drawing, in seconds.
+    hold_seconds: u32,
 }
 
 #[derive(Debug)]
@@ -36,13 +117,238 @@ pub enum PrintTask {
     Once(Vec<Polyline>),
     /// Schedule one or more print tasks every `Duration`.
     /// If multiple tasks are specified, then another one is printed for every
-    /// iteration.
-    Scheduled(Duration, Vec<Vec<Polyline>>),
+    /// iteration, picked by the rotator. Every print is moved by a random
+    /// jitter of up to the second `Duration`, earlier or later.
+    Scheduled(Duration, Vec<Drawing>, Rotator, Duration),
+    /// Start a named schedule alongside the main schedule, replacing the
+    /// named schedule of the same name. Its jobs are queued like the jobs of
+    /// the main schedule.
+    Named(NamedTask),
+    /// Stop the named schedule of that name. Its jobs that are already
+    /// queued are still drawn.
+    StopNamed(String),
+    /// Print a single job. Jobs with an owner (a tenant) are drawn in turns
+    /// with the jobs of other owners and don't cancel the current schedule.
+    /// Jobs without an owner are treated like `Once`.
+    Job(Option<String>, Job),
+    /// Queue a single job along with the jobs of the current schedule (e.g.
+    /// the daily agenda), without replacing the schedule.
+    OneOff(Job),
+    /// Erase a region of the board, or the whole board if no region is
+    /// specified. Erasing doesn't cancel the current schedule.
+    Erase(Option<Bounds>),
+    /// Use new pen servo positions, and move the servo to one of them so
+    /// that it can be checked.
+    CalibratePen(PenServo, PenPosition),
+    /// Reset the paper budget after a new paper roll was inserted.
+    ReplaceRoll,
+    /// Advance the paper roll by n mm.
+    Feed(u32),
+    /// Abort the current job after the block that is being drawn, lift the
+    /// pen, park the carriage and stop the robot thread. The sender is
+    /// notified once the robot thread has stopped.
+    Shutdown(Sender<()>),
+}
+
+/// A schedule that runs alongside the main schedule, e.g. a clock in a
+/// corner of the board.
+#[derive(Debug)]
+pub struct NamedTask {
+    pub(crate) name: String,
+    pub(crate) interval: Duration,
+    /// Every print is moved by a random jitter of up to this duration.
+    pub(crate) jitter: Duration,
+    pub(crate) drawings: Vec<Drawing>,
+    pub(crate) item_names: Vec<String>,
+    pub(crate) rotator: Rotator,
+    /// The time limits of the schedule, instead of the global ones.
+    pub(crate) time_limits: Option<TimeLimits>,
+    /// The region the drawings are placed in (already fitted). Only this
+    /// region is erased before drawing. The whole board if unset.
+    pub(crate) region: Option<Bounds>,
+}
+
+/// Work between jobs that was requested through the API.
+#[derive(Debug)]
+enum Maintenance {
+    Erase(Option<Bounds>),
+    CalibratePen(PenPosition),
+    Feed(u32),
+}
+
+/// Options of the robot thread.
+#[derive(Debug, Clone)]
+pub(crate) struct RobotConfig {
+    /// If the robot does not acknowledge a block within this timeout, the
+    /// robot is considered stalled and the block is sent again.
+    pub(crate) stall_timeout: Duration,
+    /// The number of tasks that may wait to be received by the robot
+    /// thread.
+    pub(crate) queue_depth: usize,
+    /// Physical parameters for simulating the drawing duration.
+    pub(crate) simulation: SimulationConfig,
+    /// Whether to only draw the new strokes of a job if everything else is
+    /// already on the board, without erasing it.
+    pub(crate) incremental: bool,
+    /// The coordinate system of the robot.
+    pub(crate) orientation: Orientation,
+    /// Calibrated positions of the pen servo.
+    pub(crate) servo: PenServo,
+    /// Paper fed before every job without its own feed length, in mm
+    /// (paper roll variant only).
+    pub(crate) feed_mm: u32,
+    /// Where dry runs are written to, if enabled.
+    pub(crate) dry_run: Option<DryRunConfig>,
+    /// Where the serial traffic is recorded, if enabled.
+    pub(crate) capture: Option<CaptureConfig>,
+    /// Thresholds for reporting jobs that take too long.
+    pub(crate) alerts: AlertConfig,
+    /// How writes that time out are retried, unless the job has its own
+    /// policy.
+    pub(crate) write_policy: WritePolicy,
+    /// If reconnecting fails for this long, the board only offers previews
+    /// until the robot is back.
+    pub(crate) offline_after: Option<Duration>,
+    /// Whether to redraw the persisted content of the board after an
+    /// unexpected restart.
+    pub(crate) redraw_after_restart: bool,
+    /// Days on which scheduled prints are suppressed.
+    pub(crate) holidays: SharedHolidays,
+}
+
+/// Channels and shared state through which the robot thread reports on
+/// its work.
+#[derive(Debug, Clone)]
+pub(crate) struct Reporting {
+    /// Robot events, e.g. for notifications.
+    pub(crate) events: Sender<Event>,
+    /// Completed jobs, together with their simulated duration.
+    pub(crate) history: SharedHistory,
+    /// The states of the jobs.
+    pub(crate) registry: SharedJobRegistry,
+    /// What is currently being drawn and what comes next.
+    pub(crate) status: SharedStatus,
+    /// Where the serial traffic is mirrored to, if enabled.
+    pub(crate) console: Option<SerialConsole>,
+    /// Counts the serial errors and pauses captures while the disk is full.
+    pub(crate) watchdog: SharedWatchdog,
+    /// Where completed jobs are archived, if enabled.
+    pub(crate) archive: Option<Archive>,
+    /// Where the content of the board is persisted, if anywhere. The file
+    /// is removed on shutdown, so it only exists after an unexpected
+    /// restart.
+    pub(crate) board_file: Option<PathBuf>,
+    /// Where the name of the next item of the schedule is persisted, so that
+    /// the rotation is resumed after a restart.
+    pub(crate) rotation_file: Option<PathBuf>,
+}
+
+/// Serial transmission statistics of the job that is currently being sent to
+/// the robot.
+#[derive(Debug)]
+struct JobStats {
+    /// When the first block of the job was sent.
+    started: Instant,
+    /// Number of blocks sent (including retries).
+    blocks_sent: u32,
+    /// Number of bytes sent (including retries).
+    bytes_sent: usize,
+    /// When the last block was sent, if it has not been acknowledged yet.
+    awaiting_ack_since: Option<Instant>,
+    /// Number of times the robot stalled during this job.
+    stalls: u32,
+    /// Number of stalls since the robot last responded.
+    retries: u32,
+    /// Simulation of the drawing duration of the blocks sent.
+    simulator: Simulator,
+    /// Simulated duration of all blocks of the job, if known.
+    estimate: Option<Duration>,
+    /// Whether the job was reported for taking longer than estimated.
+    overrun_reported: bool,
+    /// The write policy of the job, if it has its own.
+    write_policy: Option<WritePolicy>,
+    /// The job to archive once it is completed, if archival is enabled.
+    archive: Option<PendingEntry>,
+}
+
+impl JobStats {
+    fn new(now: Instant, simulation: SimulationConfig) -> Self {
+        JobStats {
+            started: now,
+            blocks_sent: 0,
+            bytes_sent: 0,
+            awaiting_ack_since: None,
+            stalls: 0,
+            retries: 0,
+            simulator: Simulator::new(simulation),
+            estimate: None,
+            overrun_reported: false,
+            write_policy: None,
+            archive: None,
+        }
+    }
+
+    /// Return the simulated duration of the job and how long it is running,
+    /// the first time that the job takes too much longer than estimated.
+    fn check_overrun(
+        &mut self,
+        now: Instant,
+        alerts: &AlertConfig,
+    ) -> Option<(Duration, Duration)> {
+        let estimate = self.estimate?;
+        let elapsed = now.duration_since(self.started);
+        if self.overrun_reported || !alerts.is_job_overrun(elapsed, estimate) {
+            return None;
+        }
+        self.overrun_reported = true;
+        Some((estimate, elapsed))
+    }
+
+    fn block_sent(&mut self, bytes: usize, now: Instant) {
+        self.blocks_sent += 1;
+        self.bytes_sent += bytes;
+        self.awaiting_ack_since = Some(now);
+    }
+
+    fn response_received(&mut self) {
+        self.awaiting_ack_since = None;
+        self.retries = 0;
+    }
+
+    /// Return whether the robot did not respond to the last block within the
+    /// specified timeout.
+    fn is_stalled(&self, now: Instant, timeout: Duration) -> bool {
+        match self.awaiting_ack_since {
+            Some(since) => now.duration_since(since) >= timeout,
+            None => false,
+        }
+    }
+
+    /// Return the effective throughput in bytes per second.
+    fn throughput(&self, now: Instant) -> f64 {
+        let elapsed = now.duration_since(self.started).as_secs_f64();
+        if elapsed > 0.0 {
+            self.bytes_sent as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+
+    /// Return the statistics that are stored with the job.
+    fn trace(&self, now: Instant) -> JobTrace {
+        JobTrace {
+            blocks: self.blocks_sent,
+            bytes: self.bytes_sent,
+            seconds: now.duration_since(self.started).as_secs(),
+            bytes_per_second: self.throughput(now),
+            stalls: self.stalls,
+        }
+    }
 }
 
 #[derive(Debug)]
 #[allow(dead_code)]
-enum Command {
+pub(crate) enum Command {
     /// Start of block
     BlockStart,
     /// This is block number n
@@ -51,16 +357,22 @@ enum Command {
     StartDrawing,
     /// Stop drawing
     StopDrawing,
-    /// Lift up the pen with the servo
-    PenLift,
-    /// Lower down the pen with the servo. This will disable the eraser.
-    PenDown,
+    /// Lift up the pen with the servo to the specified position (0 for the
+    /// default position)
+    PenLift(u16),
+    /// Lower down the pen with the servo to the specified position (0 for
+    /// the default position). This will disable the eraser.
+    PenDown(u16),
     /// Enable the eraser. This will also lift up the pen.
     EnableEraser,
     /// Move to the specified coordinates
     Move(u16, u16),
     /// Wait n seconds
     Wait(u8),
+    /// Advance the paper by n mm (paper roll variant only)
+    Feed(u16),
+    /// Ask the firmware to identify itself (sent outside of blocks)
+    Identify,
 }
 
 impl Command {
@@ -75,73 +387,143 @@ impl Command {
             }
             Command::StartDrawing => [0xfa, 0x1f, 0xa1],
             Command::StopDrawing => [0xfa, 0x20, 0x00],
-            Command::PenLift => [0xfa, 0x30, 0x00],
-            Command::PenDown => [0xfa, 0x40, 0x00],
+            Command::PenLift(position) => [0xfa, 0x30 | (position >> 8) as u8, position as u8],
+            Command::PenDown(position) => [0xfa, 0x40 | (position >> 8) as u8, position as u8],
             Command::Move(x, y) => [
                 ((x >> 4) & 0xff) as u8,
                 (((x << 4) | (y >> 8)) & 0xff) as u8,
                 (y & 0xff) as u8,
             ],
             Command::Wait(seconds) => {
-                if seconds > 30 {
+                if u32::from(seconds) > MAX_WAIT_SECONDS {
                     panic!("May not wait longer than 30 seconds");
                 };
                 [0xfa, 0x60, seconds]
             }
             Command::EnableEraser => [0xfa, 0x50, 0x00],
+            Command::Feed(mm) => {
+                if u32::from(mm) > MAX_FEED_MM {
+                    panic!("May not feed more than {} mm at once", MAX_FEED_MM);
+                };
+                [0xfa, 0x70 | (mm >> 8) as u8, mm as u8]
+            }
+            Command::Identify => [0xfa, 0xa0, 0x00],
         }
     }
 }
 
-/// Clamp x coordinate.
-fn fix_x(x: f64) -> f64 {
-    if x < 0.0 {
-        0.0
-    } else if x > (IBB_WIDTH as f64) {
-        IBB_WIDTH as f64
-    } else {
-        x
-    }
-}
-
-/// Invert and clamp y coordinate.
-fn fix_y(y: f64) -> f64 {
-    // Invert y coordinate, since SVG uses the top left as 0 coordinate,
-    // while the IBB uses the bottom left as 0 coordinate.
-    let yy = (IBB_HEIGHT as f64) - y;
-
-    if yy < 0.0 {
-        0.0
-    } else if yy > (IBB_HEIGHT as f64) {
-        IBB_HEIGHT as f64
-    } else {
-        yy
-    }
-}
-
 impl<'a> Sketch<'a> {
     pub fn new(polylines: &'a [Polyline]) -> Self {
         Sketch {
             buf: vec![],
             block_size: 768,
             polylines,
+            eraser: &[],
+            orientation: Orientation::default(),
+            servo: PenServo::default(),
+            feed_mm: 0,
+            hold_seconds: 0,
+        }
+    }
+
+    /// Use the coordinate system of a differently assembled robot.
+    pub fn with_orientation(self, orientation: Orientation) -> Self {
+        Sketch {
+            orientation,
+            ..self
+        }
+    }
+
+    /// Use calibrated pen servo positions.
+    pub fn with_servo(self, servo: PenServo) -> Self {
+        Sketch { servo, ..self }
+    }
+
+    /// Advance the paper roll before drawing, so that the drawing lands on
+    /// fresh paper.
+    pub fn with_feed(self, feed_mm: u32) -> Self {
+        Sketch { feed_mm, ..self }
+    }
+
+    /// Keep the drawing on the board for a while before the robot accepts
+    /// the next job (e.g. for the frames of an animation).
+    pub fn with_hold(self, hold_seconds: u32) -> Self {
+        Sketch {
+            hold_seconds,
+            ..self
         }
     }
 
+    /// Wipe strokes with the eraser before drawing (e.g. to clear a region
+    /// that is drawn in).
+    pub fn with_eraser(self, eraser: &'a [Polyline]) -> Self {
+        Sketch { eraser, ..self }
+    }
+
     /// Add a command to the internal command buffer.
     fn add_command(&mut self, command: Command) {
         self.buf.extend_from_slice(&command.to_bytes());
     }
 
+    fn pen_lift(&mut self) {
+        let position = self.servo.position(PenPosition::Lift);
+        self.add_command(Command::PenLift(position));
+    }
+
+    /// Advance the paper, in several steps if it is too long for a single
+    /// command.
+    fn feed(&mut self, mut mm: u32) {
+        while mm > 0 {
+            let step = mm.min(MAX_FEED_MM);
+            self.add_command(Command::Feed(step as u16));
+            mm -= step;
+        }
+    }
+
+    /// Wait, in several steps if it is too long for a single command.
+    fn wait(&mut self, mut seconds: u32) {
+        while seconds > 0 {
+            let step = seconds.min(MAX_WAIT_SECONDS);
+            self.add_command(Command::Wait(step as u8));
+            seconds -= step;
+        }
+    }
+
+    fn pen_down(&mut self) {
+        let position = self.servo.position(PenPosition::Down);
+        self.add_command(Command::PenDown(position));
+    }
+
+    /// Move along the polyline with the tool, and lift it at the end.
+    fn stroke(&mut self, polyline: &Polyline, tool: Tool) {
+        if polyline.len() < 2 {
+            warn!("Skipping polyline with less than 2 coordinate pairs");
+            return;
+        }
+
+        let start = polyline[0];
+        let (x, y) = self.orientation.robot_coordinates(start.x, start.y);
+        self.add_command(Command::Move(x, y));
+        match tool {
+            Tool::Pen => self.pen_down(),
+            Tool::Eraser => self.add_command(Command::EnableEraser),
+        }
+        for point in polyline[1..].iter() {
+            let (x, y) = self.orientation.robot_coordinates(point.x, point.y);
+            self.add_command(Command::Move(x, y));
+        }
+        self.pen_lift();
+    }
+
     /// Erase the entire board.
     /// Note that this does not contain the `StartDrawing` and `Stop Drawing`
     /// commands!
     fn erase_all(&mut self) {
-        self.add_command(Command::PenLift);
+        self.pen_lift();
         self.add_command(Command::Move(0, IBB_HEIGHT * 10));
         self.add_command(Command::EnableEraser);
         let y_step = 10;
-        let steps = (IBB_HEIGHT + y_step - 1) / y_step; // ceiling division
+        let steps = IBB_HEIGHT.div_ceil(y_step); // ceiling division
         for step in 0..steps {
             let even_step = (step % 2) == 0;
             let y = IBB_HEIGHT.saturating_sub(step * y_step) * 10;
@@ -150,52 +532,134 @@ impl<'a> Sketch<'a> {
             // Move to left/right
             self.add_command(Command::Move(even_step as u16 * IBB_WIDTH * 10, y));
         }
-        self.add_command(Command::PenLift);
+        self.pen_lift();
         self.add_command(Command::Move(0, 0));
     }
 
+    /// Erase a region of the board (in board coordinates) line by line.
+    /// Note that this does not contain the `StartDrawing` and `Stop Drawing`
+    /// commands!
+    fn erase_region(&mut self, region: &Bounds) {
+        let (x_min, y_top) = self
+            .orientation
+            .robot_coordinates(region.x.min, region.y.min);
+        let (x_max, y_bottom) = self
+            .orientation
+            .robot_coordinates(region.x.max, region.y.max);
+        self.pen_lift();
+        self.add_command(Command::Move(x_min, y_top));
+        self.add_command(Command::EnableEraser);
+        // Step towards the bottom of the region (in robot coordinates, this is
+        // downward only if the Y axis increases downward)
+        let decreasing = y_bottom < y_top;
+        let mut x = x_max;
+        let mut y = y_top;
+        loop {
+            // Move to left/right
+            self.add_command(Command::Move(x, y));
+            if y == y_bottom {
+                break;
+            }
+            // step down
+            y = match decreasing {
+                true => y.saturating_sub(100).max(y_bottom),
+                false => (y + 100).min(y_bottom),
+            };
+            self.add_command(Command::Move(x, y));
+            x = if x == x_max { x_min } else { x_max };
+        }
+        self.pen_lift();
+    }
+
     /// Convert the sketch into one or more byte vectors (blocks), ready to be
     /// sent to the robot via serial.
-    pub fn into_blocks(mut self, erase: bool) -> Vec<Block> {
+    pub fn into_blocks(self, erase: bool) -> Vec<Block> {
+        let erase = match erase {
+            true => Erase::All,
+            false => Erase::Nothing,
+        };
+        self.into_blocks_with(erase)
+    }
+
+    /// Like `into_blocks`, but erase a region of the board (in board
+    /// coordinates) before drawing.
+    pub fn into_blocks_erasing(self, region: &Bounds) -> Vec<Block> {
+        self.into_blocks_with(Erase::Region(region))
+    }
+
+    fn into_blocks_with(mut self, erase: Erase) -> Vec<Block> {
         // Start a new drawing
         self.add_command(Command::StartDrawing);
 
-        // First, erase the entire board.
-        if erase {
-            self.erase_all();
-        } else {
-            // If we used the eraser, we're already at `(0, 0)` coordinates.
-            self.add_command(Command::PenLift);
-            self.add_command(Command::Move(0, 0));
+        // First, erase the entire board (or a region of it).
+        match erase {
+            Erase::All => self.erase_all(),
+            Erase::Region(region) => {
+                self.erase_region(region);
+                self.add_command(Command::Move(0, 0));
+            }
+            Erase::Nothing => {
+                // If we used the eraser, we're already at `(0, 0)` coordinates.
+                self.pen_lift();
+                self.add_command(Command::Move(0, 0));
+            }
         }
+        self.feed(self.feed_mm);
 
-        // Now add the drawing commands to the buffer
+        // Now add the eraser and drawing commands to the buffer
+        for polyline in self.eraser {
+            self.stroke(polyline, Tool::Eraser);
+        }
         for polyline in self.polylines {
-            if polyline.len() < 2 {
-                warn!("Skipping polyline with less than 2 coordinate pairs");
-                continue;
-            }
+            self.stroke(polyline, Tool::Pen);
+        }
+
+        // Move back to start, done
+        self.add_command(Command::Move(0, 0));
+        self.wait(self.hold_seconds);
+        self.add_command(Command::StopDrawing);
+        self.divide_into_blocks()
+    }
 
-            let start = polyline[0];
-            self.add_command(Command::Move(
-                (fix_x(start.x) * 10.0) as u16,
-                (fix_y(start.y) * 10.0) as u16,
-            ));
-            self.add_command(Command::PenDown);
-            for point in polyline[1..].iter() {
-                self.add_command(Command::Move(
-                    (fix_x(point.x) * 10.0) as u16,
-                    (fix_y(point.y) * 10.0) as u16,
-                ));
+    /// Convert the sketch into blocks that move the pen servo to a position,
+    /// so that the position can be checked. A lowered pen is lifted again
+    /// after a few seconds. The polylines are ignored.
+    pub fn into_calibration_blocks(mut self, position: PenPosition) -> Vec<Block> {
+        self.add_command(Command::StartDrawing);
+        match position {
+            PenPosition::Lift => self.pen_lift(),
+            PenPosition::Down => {
+                self.pen_down();
+                self.add_command(Command::Wait(CALIBRATION_WAIT_SECONDS));
+                self.pen_lift();
             }
-            self.add_command(Command::PenLift);
         }
+        self.add_command(Command::StopDrawing);
+        self.divide_into_blocks()
+    }
 
-        // Move back to start, done
+    /// Convert the sketch into blocks that only advance the paper by the
+    /// feed length. The polylines are ignored.
+    pub fn into_feed_blocks(mut self) -> Vec<Block> {
+        self.add_command(Command::StartDrawing);
+        self.pen_lift();
+        self.feed(self.feed_mm);
+        self.add_command(Command::StopDrawing);
+        self.divide_into_blocks()
+    }
+
+    /// Convert the sketch into blocks that lift the pen and move the
+    /// carriage to its parking position. The polylines are ignored.
+    pub fn into_park_blocks(mut self) -> Vec<Block> {
+        self.add_command(Command::StartDrawing);
+        self.pen_lift();
         self.add_command(Command::Move(0, 0));
         self.add_command(Command::StopDrawing);
+        self.divide_into_blocks()
+    }
 
-        // Then, divide up the buffer into blocks
+    /// Divide up the command buffer into blocks.
+    fn divide_into_blocks(self) -> Vec<Block> {
         let mut blocks = vec![];
         for (i, chunk) in self.buf.chunks(self.block_size - 6).enumerate() {
             let mut block = vec![];
@@ -208,6 +672,210 @@ impl<'a> Sketch<'a> {
     }
 }
 
+/// How to draw a job.
+#[derive(Debug, PartialEq)]
+struct Plan {
+    /// The polylines to draw.
+    polylines: Vec<Polyline>,
+    /// Whether to erase the board first.
+    erase: bool,
+    /// Strokes to wipe with the eraser before drawing (none if the board is
+    /// erased anyway).
+    eraser: Vec<Polyline>,
+    /// A note for the log, e.g. about the placement.
+    note: Option<String>,
+}
+
+/// Decide how to draw the job, based on its placement and on what is already
+/// drawn on the board. Return `None` if nothing needs to be drawn, or an
+/// error with the reason (e.g. "overlaps existing content") if the job must
+/// be dropped.
+fn plan_job(
+    job: &mut Job,
+    board: &Board,
+    incremental: bool,
+    rng: &mut Rng,
+) -> Result<Option<Plan>, String> {
+    let mut polylines = std::mem::take(&mut job.polylines);
+    let eraser = std::mem::take(&mut job.eraser);
+    let plan = |polylines, erase, note: Option<&str>| Plan {
+        polylines,
+        erase,
+        eraser: match erase {
+            true => vec![],
+            false => eraser.clone(),
+        },
+        note: note.map(str::to_string),
+    };
+    // Plan on the board as it is after wiping the eraser strokes
+    let wiped;
+    let board = match eraser.is_empty() {
+        true => board,
+        false => {
+            let mut board = board.clone();
+            board.wipe(&eraser);
+            wiped = board;
+            &wiped
+        }
+    };
+    match job.placement {
+        Placement::Random => {
+            // Erase the board only if there is no free location
+            let placed =
+                placement::place_randomly(&mut polylines, board.content().unwrap_or(&[]), rng);
+            Ok(Some(match placed && board.content().is_some() {
+                true => plan(polylines, false, Some("at a random free location")),
+                false => plan(
+                    polylines,
+                    true,
+                    Some("at a random location, after erasing the board"),
+                ),
+            }))
+        }
+        Placement::Append => {
+            let occupied = board.content().unwrap_or_else(|| {
+                warn!(
+                    "Appending job {} to a board with unknown content",
+                    job.label
+                );
+                &[]
+            });
+            let placed = !placement::collides(&polylines, occupied)
+                || match job.on_collision {
+                    CollisionPolicy::Reject => false,
+                    CollisionPolicy::Shift => placement::shift_to_free(&mut polylines, occupied),
+                    CollisionPolicy::Overlap => true,
+                };
+            match placed {
+                true => Ok(Some(plan(
+                    polylines,
+                    false,
+                    Some("appended without erasing"),
+                ))),
+                false => Err("overlaps existing content".to_string()),
+            }
+        }
+        // Only draw new strokes if everything else is already drawn
+        Placement::Fixed => match incremental.then(|| board.additions(&polylines)).flatten() {
+            Some(additions) if additions.is_empty() => Ok(None),
+            Some(additions) => {
+                let note = format!("{} new polylines, without erasing", additions.len());
+                Ok(Some(plan(additions, false, Some(&note))))
+            }
+            None => Ok(Some(plan(polylines, true, None))),
+        },
+    }
+}
+
+/// Distance between the eraser strokes that wipe a region, in board
+/// coordinates (like the steps of `Sketch::erase_region`).
+const ERASER_STEP: f64 = 10.0;
+
+/// Return eraser strokes that wipe a region of the board line by line.
+pub(crate) fn region_eraser(region: &Bounds) -> Vec<Polyline> {
+    let mut stroke = vec![];
+    let mut y = region.y.min;
+    let mut left = true;
+    loop {
+        let (from, to) = match left {
+            true => (region.x.min, region.x.max),
+            false => (region.x.max, region.x.min),
+        };
+        stroke.push(CoordinatePair { x: from, y });
+        stroke.push(CoordinatePair { x: to, y });
+        if y >= region.y.max {
+            break;
+        }
+        y = (y + ERASER_STEP).min(region.y.max);
+        left = !left;
+    }
+    vec![stroke]
+}
+
+/// Start a named schedule, which queues a job every interval.
+fn schedule_named(
+    executor: &CoreExecutor,
+    task: NamedTask,
+    jobs: SharedJobQueue,
+    status: SharedStatus,
+    time_limits: SharedTimeLimits,
+    holidays: SharedHolidays,
+) -> TaskHandle {
+    let NamedTask {
+        name,
+        interval,
+        jitter,
+        drawings,
+        item_names,
+        rotator,
+        time_limits: own_limits,
+        region,
+    } = task;
+    let rotator = Mutex::new(rotator);
+    let eraser = region.as_ref().map(region_eraser).unwrap_or_default();
+    executor.schedule_fixed_rate(Duration::from_secs(2), interval, move |_handle| {
+        // Named schedules are paused and suppressed like the main schedule
+        if status.lock().is_ok_and(|status| status.schedule_paused()) {
+            info!("Scheduler {}: Skipping print (schedule paused)", name);
+            return;
+        }
+        if holidays.is_today() {
+            info!("Scheduler {}: Skipping print (holiday)", name);
+            return;
+        }
+        if let Some(limits) = own_limits.or_else(|| timelimits::current(&time_limits)) {
+            let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+            if !limits.is_within_limits(&now.time()) {
+                info!(
+                    "Scheduler {}: Skipping print (outside of time limits)",
+                    name
+                );
+                return;
+            }
+        }
+
+        let index = match rotator.lock() {
+            Ok(mut rotator) => rotator.next(),
+            Err(e) => {
+                error!("Scheduler {}: Could not unlock rotator mutex: {}", name, e);
+                return;
+            }
+        };
+        let label = format!("{}: {}", name, item_names[index]);
+        let content = match drawings[index].content() {
+            Ok(content) => content,
+            Err(e) => {
+                error!("Scheduler {}: Could not generate drawing: {}", name, e);
+                status::update(&status, |status| {
+                    status.add_error(format!("Could not generate {}: {}", label, e))
+                });
+                return;
+            }
+        };
+        info!("Scheduler {}: Starting scheduled print", name);
+
+        // Draw into the region without erasing the rest of the board
+        let delay = jitter.mul_f64(2.0 * Rng::new(None).next_f64());
+        let job = Job {
+            key: content.key,
+            start_at: (!jitter.is_zero()).then(|| OffsetDateTime::now_utc() + delay),
+            eraser: eraser.clone(),
+            placement: match region {
+                Some(_) => Placement::Append,
+                None => Placement::Fixed,
+            },
+            on_collision: CollisionPolicy::Overlap,
+            ..Job::new(label, content.polylines.into_owned())
+        };
+        match jobs.lock() {
+            Ok(mut jobs) => {
+                jobs.push_scheduled(job);
+            }
+            Err(e) => error!("Could not unlock job queue mutex: {}", e),
+        }
+    })
+}
+
 /// Configure the serial port
 fn setup_serial<P: SerialPort>(port: &mut P, baud_rate: BaudRate) -> io::Result<()> {
     port.configure(&PortSettings {
@@ -221,134 +889,379 @@ fn setup_serial<P: SerialPort>(port: &mut P, baud_rate: BaudRate) -> io::Result<
     Ok(())
 }
 
+/// Open and configure the serial device.
+pub(crate) fn open_serial(device: &str, baud_rate: BaudRate) -> io::Result<BufStream<SystemPort>> {
+    let mut port = serial::open(device)?;
+    setup_serial(&mut port, baud_rate)?;
+    Ok(BufStream::new(port))
+}
+
+/// Connect to a serial bridge on the network at `address` (`host:port`).
+fn open_tcp(address: &str) -> io::Result<BufStream<TcpStream>> {
+    let addr = address.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::NotFound,
+            format!("Could not resolve {}", address),
+        )
+    })?;
+    let stream = TcpStream::connect_timeout(&addr, Duration::from_millis(TIMEOUT_MS_TCP_CONNECT))?;
+    stream.set_read_timeout(Some(Duration::from_millis(TIMEOUT_MS_SERIAL)))?;
+    stream.set_write_timeout(Some(Duration::from_millis(TIMEOUT_MS_SERIAL)))?;
+    stream.set_nodelay(true)?;
+    Ok(BufStream::new(stream))
+}
+
+/// Return whether a serial read error means that the connection was lost.
+/// Timeouts are expected whenever the robot is quiet. A serial bridge
+/// closing the connection is reported as a read of zero bytes.
+fn is_connection_lost(result: &io::Result<usize>, device: &str) -> bool {
+    match result {
+        Ok(0) => device.starts_with(TCP_PREFIX) || !Path::new(device).exists(),
+        Ok(_) => false,
+        Err(e) => !matches!(
+            e.kind(),
+            ErrorKind::TimedOut | ErrorKind::WouldBlock | ErrorKind::Interrupted
+        ),
+    }
+}
+
+/// Run the robot loop, and run it again whenever it panics, so that the
+/// tasks sent to the robot thread are still received. Returns once the loop
+/// ends (after a shutdown, or when all senders are gone).
+fn supervise(mut run: impl FnMut(), mut on_crash: impl FnMut(&str)) {
+    while let Err(payload) = panic::catch_unwind(AssertUnwindSafe(&mut run)) {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "Unknown panic".to_string());
+        on_crash(&message);
+    }
+}
+
+/// Clean up the shared state after the robot thread crashed: The mutexes
+/// that it held are usable again, and the job that it was drawing failed.
+fn recover(
+    jobs: &SharedJobQueue,
+    registry: &SharedJobRegistry,
+    status: &SharedStatus,
+    history: &SharedHistory,
+    message: &str,
+) {
+    jobs.clear_poison();
+    registry.clear_poison();
+    status.clear_poison();
+    history.clear_poison();
+    let board = match jobs.lock() {
+        Ok(jobs) => jobs.board().to_string(),
+        Err(_) => return,
+    };
+    if let Ok(mut registry) = registry.lock() {
+        let failed = registry.fail_active(&board, message);
+        if failed > 0 {
+            warn!("Failed {} job(s) of board {}", failed, board);
+        }
+    }
+    status::update(status, |status| status.add_error(message.to_string()));
+}
+
+/// Return the delay before the next attempt to reconnect.
+fn reconnect_delay(attempts: u32) -> Duration {
+    let factor = 1u64 << attempts.saturating_sub(1).min(16);
+    Duration::from_secs((RECONNECT_DELAY_SECS * factor).min(MAX_RECONNECT_DELAY_SECS))
+}
+
+/// Resolve the configured device (which may be detected automatically, a
+/// serial bridge or the mock robot) and open it. Return the connection and
+/// the device path.
+pub(crate) fn connect(
+    device: &str,
+    baud_rate: BaudRate,
+    simulation: SimulationConfig,
+) -> io::Result<(Box<dyn Connection>, String)> {
+    if device == MOCK_DEVICE {
+        let robot = BufStream::new(MockRobot::new(simulation));
+        return Ok((Box::new(robot), device.to_string()));
+    }
+    if let Some(address) = device.strip_prefix(TCP_PREFIX) {
+        return Ok((Box::new(open_tcp(address)?), device.to_string()));
+    }
+    let path = detect::resolve(device, baud_rate)?;
+    Ok((Box::new(open_serial(&path, baud_rate)?), path))
+}
+
+/// Reopen the serial device, retrying with exponential backoff until it
+/// succeeds. Return the new connection, the device path and the number of
+/// attempts. If it fails for longer than `offline_after`, the robot is
+/// reported as offline until the device is back.
+fn reconnect(
+    device: &str,
+    baud_rate: BaudRate,
+    simulation: SimulationConfig,
+    offline_after: Option<Duration>,
+    status: &SharedStatus,
+    events: &Sender<Event>,
+) -> (Box<dyn Connection>, String, u32) {
+    let started = Instant::now();
+    let mut offline = false;
+    let mut attempts = 0;
+    loop {
+        let elapsed = started.elapsed();
+        if !offline && offline_after.is_some_and(|after| elapsed >= after) {
+            offline = true;
+            warn!(
+                "Robot offline for {}s, only previews are available",
+                elapsed.as_secs()
+            );
+            status::update(status, |status| status.set_offline(true));
+            let _ = events.send(Event::RobotOffline {
+                seconds: elapsed.as_secs(),
+            });
+        }
+        attempts += 1;
+        let delay = reconnect_delay(attempts);
+        info!(
+            "Reconnecting to {} in {}s (attempt {})...",
+            device,
+            delay.as_secs(),
+            attempts
+        );
+        thread::sleep(delay);
+        match connect(device, baud_rate, simulation) {
+            Ok((ser, path)) => {
+                if offline {
+                    info!("Robot is back online, accepting prints again");
+                    status::update(status, |status| status.set_offline(false));
+                }
+                return (ser, path, attempts);
+            }
+            Err(e) => warn!("Could not reconnect to {}: {}", device, e),
+        }
+    }
+}
+
+/// Identify the firmware of a freshly connected robot and report it in the
+/// status. Incompatible firmware is reported as a robot error.
+fn identify_firmware(ser: &mut dyn Connection, status: &SharedStatus) {
+    let firmware = match firmware::handshake(ser, firmware::HANDSHAKE_TIMEOUT) {
+        Ok(firmware) => firmware,
+        Err(e) => {
+            warn!("Could not identify the firmware: {}", e);
+            Firmware::Unknown
+        }
+    };
+    info!("Robot firmware: {:?}", firmware);
+    let incompatibility = firmware.incompatibility();
+    if let Some(reason) = incompatibility {
+        error!("{}", reason);
+    }
+    status::update(status, |status| {
+        status.set_firmware(firmware);
+        if let Some(reason) = incompatibility {
+            status.set_robot_error(Some(reason.to_string()));
+            status.add_error(reason.to_string());
+        }
+    });
+}
+
 /// Spawn a thread that communicates with the robot over serial.
 ///
 /// The return value is the sending end of a channel. Over this channel, a list
 /// of polylines can be sent.
+///
+/// A block is only sent when the robot requests one or acknowledged the
+/// previous block, and a block that the robot didn't receive is sent again
+/// (see `flowcontrol`).
+///
+/// If the robot does not acknowledge a block within the stall timeout of the
+/// `config`, the robot is considered stalled and the block is sent again.
+///
+/// If the serial connection is lost, the device is reopened with backoff and
+/// the unacknowledged block is sent again. If the device is offline when the
+/// thread starts, it is opened the same way once it appears.
+///
+/// Jobs are drawn in the order determined by the scheduling policy of the
+/// `jobs` queue. The robot thread reports its progress through `reporting`.
 pub(crate) fn communicate(
     device: &str,
     baud_rate: BaudRate,
-    time_limits: Option<TimeLimits>,
-) -> Sender<PrintTask> {
-    // Connect to serial device
-    info!(
-        "Connecting to {} with baud rate {}...",
-        device,
-        baud_rate.speed()
-    );
-    let mut port = serial::open(device).expect(&format!("Could not open serial device {}", device));
-    setup_serial(&mut port, baud_rate).expect("Could not configure serial port");
-
-    // Wrap port into a buffered stream
-    let mut ser = BufStream::new(port);
-    let mut buf = String::new();
-
-    if let Some(limits) = time_limits {
+    time_limits: SharedTimeLimits,
+    config: RobotConfig,
+    jobs: SharedJobQueue,
+    roll: Option<PaperRoll>,
+    reporting: Reporting,
+) -> SyncSender<PrintTask> {
+    let policy = match jobs.lock() {
+        Ok(jobs) => jobs.policy(),
+        Err(_) => SchedulingPolicy::default(),
+    };
+
+    if let Some(limits) = timelimits::current(&time_limits) {
         info!("Limiting time between {}", limits);
     } else {
         info!("No time limits configured");
     };
 
-    // Regex for recognizing ACK messages
-    let ack_re = Regex::new(r"^CL STATUS=ACK&NUM=(\d+)$").expect("Could not compile regex");
+    status::update(&reporting.status, |status| {
+        status.set_paper_remaining(roll.as_ref().map(PaperRoll::remaining_mm))
+    });
+
+    // Restore the content of the board after an unexpected restart (e.g. a
+    // power loss). The board is erased first, since its state is unknown.
+    if let Some(content) = reporting.board_file.as_deref().and_then(board::load) {
+        match config.redraw_after_restart {
+            true => match jobs.lock() {
+                Ok(mut jobs) => {
+                    info!(
+                        "Redrawing the board after an unexpected restart ({} polylines)",
+                        content.len()
+                    );
+                    jobs.push_interactive("", Job::new("Restored board".to_string(), content));
+                }
+                Err(e) => error!("Could not unlock job queue mutex: {}", e),
+            },
+            false => info!("Not redrawing the board after an unexpected restart"),
+        }
+    }
 
     // Main loop
-    let (tx, rx) = channel();
+    let (tx, rx) = sync_channel(config.queue_depth);
+    let mut robot = Robot {
+        device: device.to_string(),
+        baud_rate,
+        time_limits,
+        config,
+        policy,
+        jobs,
+        roll,
+        reporting,
+        tasks: rx,
+    };
     thread::spawn(move || {
-        // A queue for blocks that should be printed.
-        let blocks_queue: Arc<Mutex<VecDeque<Block>>> = Arc::new(Mutex::new(VecDeque::new()));
+        let jobs = robot.jobs.clone();
+        let Reporting {
+            registry,
+            status,
+            history,
+            ..
+        } = robot.reporting.clone();
+        let mut crashes = 0;
+        let mut started = Instant::now();
+        supervise(
+            || robot.run(),
+            |message| {
+                if started.elapsed() > STABLE_RUN {
+                    crashes = 0;
+                }
+                crashes += 1;
+                let message = format!("Robot thread crashed: {}", message);
+                error!("{}", message);
+                recover(&jobs, &registry, &status, &history, &message);
+                let delay = reconnect_delay(crashes);
+                info!("Restarting robot thread in {}s", delay.as_secs());
+                thread::sleep(delay);
+                started = Instant::now();
+            },
+        );
+    });
+    tx
+}
+
+/// The robot thread, with everything that survives a crash of its loop: The
+/// settings, the state shared with the server and the tasks that were not
+/// received yet.
+struct Robot {
+    device: String,
+    baud_rate: BaudRate,
+    time_limits: SharedTimeLimits,
+    config: RobotConfig,
+    policy: SchedulingPolicy,
+    jobs: SharedJobQueue,
+    roll: Option<PaperRoll>,
+    reporting: Reporting,
+    tasks: Receiver<PrintTask>,
+}
+
+/// The state of the robot loop, which is set up anew after a crash.
+struct Session {
+    /// The connection to the robot.
+    ser: Box<dyn Connection>,
+    /// The path of the connected device.
+    path: String,
+    /// The line that is being read from the robot.
+    buf: String,
+    /// A queue for blocks that should be printed.
+    blocks: VecDeque<Block>,
+    /// The block sent but not acknowledged yet, kept for retries.
+    flow: FlowControl,
+    /// What is currently drawn on the board.
+    board: Board,
+    /// Revision of the board that was persisted last.
+    saved_revision: u64,
+    /// Random numbers for placing drawings randomly.
+    rng: Rng,
+    /// Drawings that are erased again after their TTL.
+    expiries: Expiries,
+    /// Requested maintenance, done before the next job is started.
+    maintenance: VecDeque<Maintenance>,
+    /// Whether the robot stalled and didn't respond since.
+    stalled: bool,
+    /// Transmission statistics of the current job.
+    job_stats: Option<JobStats>,
+    /// Simulated duration of the job whose blocks were just queued.
+    job_estimate: Option<Duration>,
+    /// Write policy of the job whose blocks were just queued.
+    job_write_policy: Option<WritePolicy>,
+    /// Archive entry of the job whose blocks were just queued.
+    job_archive: Option<PendingEntry>,
+    /// Registry id of the job whose blocks are queued or being sent.
+    printing: Option<JobId>,
+    /// Who to notify once the robot is parked after a shutdown request,
+    /// and when to give up waiting for it.
+    shutdown: Option<(Sender<()>, Instant)>,
+    executor: CoreExecutor,
+    current_job: Option<TaskHandle>,
+    /// The named schedules, by name.
+    named_jobs: HashMap<String, TaskHandle>,
+}
 
-        // The current block number (used for ACKs).
-        let mut current_block: u32 = 0;
+/// What went wrong while talking to the robot.
+#[derive(Debug, Default)]
+struct Trouble {
+    /// Why the connection was lost, if it was.
+    disconnected: Option<String>,
+    /// A write that timed out even after retrying.
+    write_failed: Option<WriteError>,
+}
 
-        // Initialize the job scheduler
-        let executor = CoreExecutor::with_name("iboardbot_scheduler").unwrap();
-        let mut current_job: Option<TaskHandle> = None;
-        let iteration = Arc::new(AtomicUsize::new(0));
+impl Trouble {
+    fn failed_write(&mut self, e: WriteError) {
+        error!("Could not write data to serial: {}", e);
+        self.disconnected = Some(e.to_string());
+        self.write_failed = Some(e).filter(|e| e.timed_out);
+    }
+}
 
+impl Robot {
+    /// Connect to the robot and talk to it, until the robot was parked after
+    /// a shutdown request or until all senders are gone.
+    fn run(&mut self) {
+        let mut session = self.open();
         loop {
-            // Check for a new printing task
-            let task: Result<PrintTask, RecvTimeoutError> =
-                rx.recv_timeout(Duration::from_millis(TIMEOUT_MS_CHANNEL));
-            match task {
-                Ok(task) => {
-                    if let Some(ref handle) = current_job {
-                        // Handle existing job
-                        info!("Cancelling old print job");
-                        handle.stop();
-                    }
-                    // Reset iteration count
-                    iteration.store(0, Ordering::SeqCst);
-
-                    info!("Received print task");
-                    match task {
-                        PrintTask::Once(polylines) => {
-                            info!("-> Task: Scheduling once");
-                            let sketch = Sketch::new(&polylines);
-                            match blocks_queue.lock() {
-                                Ok(mut queue) => {
-                                    for block in sketch.into_blocks(true) {
-                                        queue.push_back(block);
-                                    }
-                                }
-                                Err(e) => error!("Could not unlock blocks queue mutex: {}", e),
-                            }
-                        }
-                        PrintTask::Scheduled(interval, polylines_vec) => {
-                            if polylines_vec.is_empty() {
-                                warn!("Could not schedule print task: polylines_vec is empty");
-                                return;
-                            }
-                            info!(
-                                "-> Task: Scheduling every {} minutes",
-                                interval.as_secs() / 60
-                            );
-                            if let Some(limits) = time_limits {
-                                info!("-> Task: Time limits: {}", limits);
-                            } else {
-                                info!("-> Task: No time limits");
-                            };
-                            let blocks_queue = blocks_queue.clone();
-                            let iteration_clone = iteration.clone();
-                            current_job = Some(executor.schedule_fixed_rate(
-                                Duration::from_secs(2), // Wait 2 seconds before scheduling the first task
-                                interval, // After that, schedule in a fixed interval
-                                move |_handle| {
-                                    // Check the time limits
-                                    if let Some(limits) = time_limits {
-                                        if !limits.is_within_limits(&OffsetDateTime::now_local().unwrap().time()) {
-                                            info!("Scheduler: Skipping print (outside of time limits)");
-                                            return;
-                                        }
-                                    }
-
-                                    info!("Scheduler: Starting scheduled print");
-
-                                    // Determine which polylines to print
-                                    let i = iteration_clone.fetch_add(1, Ordering::SeqCst);
-                                    let index = i % polylines_vec.len();
-                                    let polylines = &polylines_vec[index];
-
-                                    // Create and enqueue sketch
-                                    let sketch = Sketch::new(polylines);
-                                    match blocks_queue.lock() {
-                                        Ok(mut queue) => {
-                                            for block in sketch.into_blocks(true) {
-                                                queue.push_back(block);
-                                            }
-                                        },
-                                        Err(e) => error!("Could not unlock blocks queue mutex: {}", e),
-                                    }
-                                }
-                            ));
-                        }
-                    }
-                    if let Ok(queue) = blocks_queue.lock() {
-                        info!("{} block(s) in queue", queue.len());
-                    } else {
-                        warn!("Could not unlock blocks queue mutex");
-                    }
+            // Persist the content of the board after it changed
+            if session.board.revision() != session.saved_revision {
+                if let Some(ref path) = self.reporting.board_file {
+                    session.board.save(path);
                 }
+                session.saved_revision = session.board.revision();
+            }
+
+            // Check for a new printing task
+            match self
+                .tasks
+                .recv_timeout(Duration::from_millis(TIMEOUT_MS_CHANNEL))
+            {
+                Ok(task) => self.receive(&mut session, task),
                 Err(RecvTimeoutError::Timeout) => {
                     // We didn't get a new task.
                     // Simply ignore it :)
@@ -359,93 +1272,1305 @@ pub(crate) fn communicate(
                 }
             };
 
+            // Start the next job when the robot is idle
+            if session.job_stats.is_none()
+                && session.shutdown.is_none()
+                && session.blocks.is_empty()
+            {
+                self.start_next(&mut session);
+            }
+
             // Talk to robot over serial
-            if let Ok(_) = ser.read_line(&mut buf) {
-                let line = buf.trim();
-
-                // Debug print of all serial input
-                debug!("< {}", line);
-
-                // If there are blocks to be sent and we got a new CL command
-                // from the robot...
-                match blocks_queue.lock() {
-                    Ok(mut queue) => {
-                        if queue.len() > 0 && line.starts_with("CL ") {
-                            let mut send_next = false;
-
-                            if line == "CL STATUS=READY" {
-                                info!("< Requesting block");
-                                current_block = 0;
-                                send_next = true;
-                            } else if let Some(captures) = ack_re.captures(line) {
-                                let number_str = captures.get(1).unwrap().as_str();
-                                info!("< Ack: {}", number_str);
-                                match number_str.parse::<u32>() {
-                                    Ok(number) if number == current_block => {
-                                        // Acked number is our current block, so we can safely
-                                        // send the next one.
-                                        send_next = true;
-                                    }
-                                    Ok(number) if current_block == 0 => {
-                                        // We probably started the server process after
-                                        // a few blocks were already printed. Catch up.
-                                        warn!("Ack too large, update current block number");
-                                        current_block = number;
-                                        send_next = true;
-                                    }
-                                    Ok(number) => {
-                                        warn!(
-                                            "Warning: Got ack for non-current block ({} != {})",
-                                            number, current_block
-                                        );
-                                    }
-                                    Err(_) => {
-                                        error!("Could not parse ACK number \"{}\"", number_str);
-                                    }
-                                }
-                            }
-
-                            if send_next {
-                                info!("> Print block {}", current_block + 1);
-                                let block = queue
-                                    .pop_front()
-                                    .expect("Could not pop block from non-empty queue");
-                                ser.write_all(&block).unwrap_or_else(|e| {
-                                    error!("Could not write data to serial: {}", e)
-                                });
-                                ser.flush().unwrap_or_else(|e| {
-                                    error!("Could not flush serial buffer: {}", e)
-                                });
-                                current_block += 1;
-                            }
-                        }
-                    }
-                    Err(e) => error!("Could not unlock blocks queue mutex: {}", e),
-                }
+            let mut trouble = self.exchange(&mut session);
+            self.check_progress(&mut session, &mut trouble);
+
+            // Stop once the robot is parked, or if it can't be parked
+            if self.finish_shutdown(&mut session, trouble.disconnected.is_some()) {
+                break;
+            }
+
+            // Fail the current job if its blocks can't be written
+            if let Some(e) = trouble.write_failed {
+                self.drop_job(&mut session, e);
+            }
+
+            // Reconnect when the serial connection was lost
+            if let Some(reason) = trouble.disconnected {
+                self.restore_connection(&mut session, reason);
             }
-            buf.clear();
         }
-    });
-    tx
-}
+    }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use svg2polylines::{CoordinatePair, Polyline};
+    /// Connect to the serial device. If it is offline (e.g. because the
+    /// robot is still powered off), wait for it to appear.
+    fn open(&self) -> Session {
+        let Reporting {
+            ref status,
+            ref events,
+            ref console,
+            ref watchdog,
+            ..
+        } = self.reporting;
+        let device = &self.device;
+        info!(
+            "Connecting to {} with baud rate {}...",
+            device,
+            self.baud_rate.speed()
+        );
+        let (ser, path) = match connect(device, self.baud_rate, self.config.simulation) {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!("Device {} is offline: {}", device, e);
+                status::update(status, |status| {
+                    status.set_robot_error(Some(format!("Device offline: {} ({})", device, e)))
+                });
+                let (ser, path, attempts) = reconnect(
+                    device,
+                    self.baud_rate,
+                    self.config.simulation,
+                    self.config.offline_after,
+                    status,
+                    events,
+                );
+                info!("Connected to {} after {} attempt(s)", path, attempts);
+                status::update(status, |status| status.set_robot_error(None));
+                (ser, path)
+            }
+        };
+        let mut ser = capture::record(
+            ser,
+            self.config.capture.as_ref(),
+            console.as_ref(),
+            watchdog,
+            &path,
+        );
+        identify_firmware(&mut *ser, status);
 
-    #[test]
-    fn test_empty_sketch() {
-        let polylines: Vec<Polyline> = vec![];
-        let sketch = Sketch::new(&polylines);
-        let blocks = sketch.into_blocks(false);
-        assert_eq!(blocks.len(), 1);
-        assert_eq!(
-            blocks[0],
-            vec![
-                0xfa, 0x9f, 0xa1, // Block start
-                0xfa, 0x90, 0x01, // Block number 1
-                0xfa, 0x1f, 0xa1, // Start drawing
+        let board = Board::default();
+        Session {
+            ser,
+            path,
+            buf: String::new(),
+            blocks: VecDeque::new(),
+            flow: FlowControl::default(),
+            saved_revision: board.revision(),
+            board,
+            rng: Rng::new(None),
+            expiries: Expiries::default(),
+            maintenance: VecDeque::new(),
+            stalled: false,
+            job_stats: None,
+            job_estimate: None,
+            job_write_policy: None,
+            job_archive: None,
+            printing: None,
+            shutdown: None,
+            executor: CoreExecutor::with_name("iboardbot_scheduler").unwrap(),
+            current_job: None,
+            named_jobs: HashMap::new(),
+        }
+    }
+
+    /// Handle a task sent to the robot thread.
+    fn receive(&mut self, session: &mut Session, task: PrintTask) {
+        let Reporting {
+            ref events,
+            ref status,
+            ..
+        } = self.reporting;
+        match task {
+            PrintTask::Job(Some(tenant), job) => {
+                info!("Received print task of tenant {}", tenant);
+                let _ = events.send(Event::TaskReceived);
+                match self.jobs.lock() {
+                    Ok(mut jobs) => {
+                        jobs.push_interactive(&tenant, job);
+                        info!("{} job(s) waiting", jobs.len());
+                    }
+                    Err(e) => error!("Could not unlock job queue mutex: {}", e),
+                }
+            }
+            PrintTask::OneOff(job) => {
+                info!("Received one-off job {}", job.label);
+                let _ = events.send(Event::TaskReceived);
+                match self.jobs.lock() {
+                    Ok(mut jobs) => {
+                        jobs.push_scheduled(job);
+                    }
+                    Err(e) => error!("Could not unlock job queue mutex: {}", e),
+                }
+            }
+            PrintTask::Named(task) => {
+                info!(
+                    "Received named schedule {}: Scheduling every {} minutes",
+                    task.name,
+                    task.interval.as_secs() / 60
+                );
+                let _ = events.send(Event::TaskReceived);
+                let name = task.name.clone();
+                let handle = schedule_named(
+                    &session.executor,
+                    task,
+                    self.jobs.clone(),
+                    status.clone(),
+                    self.time_limits.clone(),
+                    self.config.holidays.clone(),
+                );
+                if let Some(old) = session.named_jobs.insert(name, handle) {
+                    old.stop();
+                }
+            }
+            PrintTask::StopNamed(name) => match session.named_jobs.remove(&name) {
+                Some(handle) => {
+                    info!("Stopping named schedule {}", name);
+                    handle.stop();
+                }
+                None => warn!("Cannot stop named schedule {}: Not running", name),
+            },
+            PrintTask::Erase(region) => {
+                info!("Received erase task");
+                let _ = events.send(Event::TaskReceived);
+                session.maintenance.push_back(Maintenance::Erase(region));
+            }
+            PrintTask::CalibratePen(servo, position) => {
+                info!("Received pen calibration task");
+                self.config.servo = servo;
+                session
+                    .maintenance
+                    .push_back(Maintenance::CalibratePen(position));
+            }
+            PrintTask::Feed(mm) => {
+                info!("Received paper feed task");
+                session.maintenance.push_back(Maintenance::Feed(mm));
+            }
+            PrintTask::Shutdown(done) => self.shut_down(session, done),
+            PrintTask::ReplaceRoll => match self.roll {
+                Some(ref mut roll) => {
+                    roll.replace();
+                    info!("Paper roll replaced, {} mm left", roll.remaining_mm());
+                    status::update(status, |status| {
+                        status.set_paper_remaining(Some(roll.remaining_mm()))
+                    });
+                }
+                None => warn!("Ignoring roll replacement: No paper roll configured"),
+            },
+            PrintTask::Scheduled(_, ref drawings, ..) if drawings.is_empty() => {
+                // The current schedule is kept
+                warn!("Could not schedule print task: No drawings given");
+            }
+            task => self.receive_print(session, task),
+        }
+    }
+
+    /// Handle a print task that may replace the current schedule.
+    fn receive_print(&self, session: &mut Session, task: PrintTask) {
+        // With the preempt policy, every task replaces the schedule
+        let replaces_schedule =
+            matches!(task, PrintTask::Scheduled(..)) || self.policy == SchedulingPolicy::Preempt;
+        if replaces_schedule {
+            if let Some(ref handle) = session.current_job {
+                // Handle existing job
+                info!("Cancelling old print job");
+                handle.stop();
+            }
+            match self.jobs.lock() {
+                Ok(mut jobs) => jobs.clear_scheduled(),
+                Err(e) => error!("Could not unlock job queue mutex: {}", e),
+            }
+        }
+
+        info!("Received print task");
+        let _ = self.reporting.events.send(Event::TaskReceived);
+        let task = match task {
+            PrintTask::Once(polylines) => {
+                PrintTask::Job(None, Job::new("Single print".to_string(), polylines))
+            }
+            task => task,
+        };
+        match task {
+            PrintTask::Job(_, job) => {
+                match job.start_at {
+                    Some(start_at) => info!("-> Task: Scheduling once at {}", start_at),
+                    None => info!("-> Task: Scheduling once"),
+                }
+                if replaces_schedule {
+                    status::update(&self.reporting.status, |status| {
+                        status.set_item_names(vec![]);
+                        status.set_next(None, None);
+                        status.set_interval(None);
+                    });
+                }
+                match self.jobs.lock() {
+                    Ok(mut jobs) => jobs.push_interactive("", job),
+                    Err(e) => error!("Could not unlock job queue mutex: {}", e),
+                }
+            }
+            PrintTask::Scheduled(interval, drawings, rotator, jitter) => {
+                session.current_job =
+                    Some(self.schedule(&session.executor, interval, drawings, rotator, jitter));
+            }
+            PrintTask::Once(..) => unreachable!("Converted into a job above"),
+            PrintTask::Erase(..)
+            | PrintTask::OneOff(..)
+            | PrintTask::Named(..)
+            | PrintTask::StopNamed(..)
+            | PrintTask::CalibratePen(..)
+            | PrintTask::ReplaceRoll
+            | PrintTask::Feed(..)
+            | PrintTask::Shutdown(..) => unreachable!("Handled by receive"),
+        }
+        if let Ok(jobs) = self.jobs.lock() {
+            info!("{} job(s) waiting", jobs.len());
+        } else {
+            warn!("Could not unlock job queue mutex");
+        }
+    }
+
+    /// Start the main schedule, which queues the next of the `drawings`
+    /// every interval.
+    fn schedule(
+        &self,
+        executor: &CoreExecutor,
+        interval: Duration,
+        drawings: Vec<Drawing>,
+        mut rotator: Rotator,
+        jitter: Duration,
+    ) -> TaskHandle {
+        info!(
+            "-> Task: Scheduling every {} minutes",
+            interval.as_secs() / 60
+        );
+        if let Some(limits) = timelimits::current(&self.time_limits) {
+            info!("-> Task: Time limits: {}", limits);
+        } else {
+            info!("-> Task: No time limits");
+        };
+        status::update(&self.reporting.status, |status| {
+            status.check_item_names(drawings.len());
+            let next_time = OffsetDateTime::now_utc() + Duration::from_secs(2) + jitter;
+            status.set_next(Some(status.item_label(rotator.peek())), Some(next_time));
+            status.set_interval(Some(interval));
+        });
+        let jobs = self.jobs.clone();
+        let rotator = Mutex::new(rotator);
+        let status = self.reporting.status.clone();
+        let time_limits = self.time_limits.clone();
+        let holidays = self.config.holidays.clone();
+        let rotation_file = self.reporting.rotation_file.clone();
+        // With a jitter, the cycles start early by the jitter, and every job
+        // is held for up to twice the jitter
+        executor.schedule_fixed_rate(
+            Duration::from_secs(2), // Wait 2 seconds before scheduling the first task
+            interval,               // After that, schedule in a fixed interval
+            move |_handle| {
+                let next_time = OffsetDateTime::now_utc() + interval + jitter;
+
+                // Skip the cycle while the schedule is paused, so that it
+                // continues with the next item once it is resumed
+                if status.lock().is_ok_and(|status| status.schedule_paused()) {
+                    info!("Scheduler: Skipping print (schedule paused)");
+                    status::update(&status, |status| {
+                        status.postpone(next_time);
+                    });
+                    return;
+                }
+
+                // Scheduled prints are suppressed on holidays
+                if holidays.is_today() {
+                    info!("Scheduler: Skipping print (holiday)");
+                    status::update(&status, |status| {
+                        status.postpone(next_time);
+                    });
+                    return;
+                }
+
+                // Check the time limits. Missed runs are either skipped or
+                // deferred until the time limits begin (once).
+                let mut defer = false;
+                if let Some(limits) = timelimits::current(&time_limits) {
+                    if !limits.is_within_limits(&OffsetDateTime::now_local().unwrap().time()) {
+                        defer = limits.missed_runs() == MissedRuns::CatchUp
+                            && jobs.lock().is_ok_and(|jobs| !jobs.has_deferred());
+                        if !defer {
+                            info!("Scheduler: Skipping print (outside of time limits)");
+                            status::update(&status, |status| {
+                                status.postpone(next_time);
+                            });
+                            return;
+                        }
+                    }
+                }
+
+                // Skip the cycle if requested, keeping the item
+                let mut skip = false;
+                if !defer {
+                    status::update(&status, |status| skip = status.take_skip());
+                }
+                if skip {
+                    info!("Scheduler: Skipping print (on request)");
+                    status::update(&status, |status| {
+                        status.postpone(next_time);
+                    });
+                    return;
+                }
+
+                match defer {
+                    true => info!("Scheduler: Deferring print until the time limits begin"),
+                    false => info!("Scheduler: Starting scheduled print"),
+                }
+
+                // Determine which polylines to print
+                let (index, next) = match rotator.lock() {
+                    Ok(mut rotator) => (rotator.next(), rotator.peek()),
+                    Err(e) => {
+                        error!("Scheduler: Could not unlock rotator mutex: {}", e);
+                        return;
+                    }
+                };
+                let content = match drawings[index].content() {
+                    Ok(content) => content,
+                    Err(e) => {
+                        error!("Scheduler: Could not generate drawing: {}", e);
+                        status::update(&status, |status| {
+                            status.add_error(format!(
+                                "Could not generate {}: {}",
+                                status.item_label(index),
+                                e
+                            ))
+                        });
+                        return;
+                    }
+                };
+                let mut label = String::new();
+                let mut next_label = String::new();
+                status::update(&status, |status| {
+                    label = status.item_label(index);
+                    next_label = status.item_label(next);
+                    status.set_next(Some(next_label.clone()), Some(next_time));
+                });
+                if let Some(ref path) = rotation_file {
+                    if let Err(e) = fs::write(path, &next_label) {
+                        warn!("Could not write {}: {}", path.display(), e);
+                    }
+                }
+
+                // Enqueue the job, unless the same content was just drawn
+                let delay = jitter.mul_f64(2.0 * Rng::new(None).next_f64());
+                let job = Job {
+                    key: content.key,
+                    start_at: (!jitter.is_zero() && !defer)
+                        .then(|| OffsetDateTime::now_utc() + delay),
+                    ..Job::new(label, content.polylines.into_owned())
+                };
+                match jobs.lock() {
+                    Ok(mut jobs) if defer => {
+                        jobs.defer_scheduled(job);
+                    }
+                    Ok(mut jobs) => {
+                        jobs.push_scheduled(job);
+                    }
+                    Err(e) => error!("Could not unlock job queue mutex: {}", e),
+                }
+            },
+        )
+    }
+
+    /// Abort the current job and park the robot. The thread stops once the
+    /// robot is parked (see `finish_shutdown`).
+    fn shut_down(&self, session: &mut Session, done: Sender<()>) {
+        info!("Shutting down: Parking the robot");
+        if let Some(ref handle) = session.current_job {
+            handle.stop();
+        }
+        for handle in session.named_jobs.values() {
+            handle.stop();
+        }
+        if !session.blocks.is_empty() || session.job_stats.is_some() {
+            warn!(
+                "Aborting the current job after block {}",
+                session.flow.current_block()
+            );
+            session.board.forget();
+            jobs::transition(
+                &self.reporting.registry,
+                session.printing.take(),
+                JobState::Cancelled,
+            );
+        }
+        session.blocks.clear();
+        session.blocks.extend(
+            Sketch::new(&[])
+                .with_servo(self.config.servo)
+                .into_park_blocks(),
+        );
+        session.job_stats = None;
+        session.job_estimate = None;
+        session.job_write_policy = None;
+        session.job_archive = None;
+        session.shutdown = Some((done, Instant::now() + SHUTDOWN_TIMEOUT));
+    }
+
+    /// Queue the blocks of what the idle robot should do next: Requested
+    /// maintenance comes first, then the cleanup of expired content, then
+    /// the next job.
+    fn start_next(&self, session: &mut Session) {
+        if let Some(task) = session.maintenance.pop_front() {
+            self.start_maintenance(session, task);
+        } else if let Some(expired) = session.expiries.pop_due(Instant::now()) {
+            self.cleanup(session, &expired);
+        } else if let Some(job) = self.pop_job() {
+            self.start_job(session, job);
+        }
+
+        // Estimate the duration of the queued blocks, to detect jobs that
+        // take too long
+        if !session.blocks.is_empty() {
+            let mut simulator = Simulator::new(self.config.simulation);
+            session
+                .blocks
+                .iter()
+                .for_each(|block| simulator.feed(block));
+            session.job_estimate = Some(simulator.elapsed());
+        }
+    }
+
+    /// Queue the blocks of requested maintenance.
+    fn start_maintenance(&self, session: &mut Session, task: Maintenance) {
+        let RobotConfig {
+            orientation, servo, ..
+        } = self.config;
+        let status = &self.reporting.status;
+        match task {
+            Maintenance::Erase(Some(ref region)) => {
+                info!("Erasing region {:?}", region);
+                session.blocks.extend(
+                    Sketch::new(&[])
+                        .with_orientation(orientation)
+                        .with_servo(servo)
+                        .into_blocks_erasing(region),
+                );
+                session.board.erase_region(region);
+                status::update(status, |status| status.start_erasing("Erase".to_string()));
+            }
+            Maintenance::Erase(None) => {
+                info!("Erasing the board");
+                session.blocks.extend(
+                    Sketch::new(&[])
+                        .with_orientation(orientation)
+                        .with_servo(servo)
+                        .into_blocks(true),
+                );
+                session.board.draw(&[], true);
+                session.expiries.clear();
+                status::update(status, |status| status.start_erasing("Erase".to_string()));
+            }
+            Maintenance::CalibratePen(position) => {
+                info!(
+                    "Moving the pen servo ({:?}: {})",
+                    position,
+                    servo.position(position)
+                );
+                session.blocks.extend(
+                    Sketch::new(&[])
+                        .with_servo(servo)
+                        .into_calibration_blocks(position),
+                );
+                status::update(status, |status| {
+                    status.start_drawing("Pen calibration".to_string())
+                });
+            }
+            Maintenance::Feed(mm) => {
+                info!("Feeding {} mm of paper", mm);
+                session
+                    .blocks
+                    .extend(Sketch::new(&[]).with_feed(mm).into_feed_blocks());
+                status::update(status, |status| {
+                    status.start_drawing("Paper feed".to_string())
+                });
+            }
+        }
+    }
+
+    /// Erase expired content, and redraw what overlaps with it.
+    fn cleanup(&self, session: &mut Session, expired: &[Polyline]) {
+        session.board.remove(expired);
+        if let Some((region, redraw)) = expiry::cleanup(expired, session.board.content()) {
+            info!(
+                "Erasing expired content ({} polylines, redrawing {})",
+                expired.len(),
+                redraw.len()
+            );
+            session.blocks.extend(
+                Sketch::new(&redraw)
+                    .with_orientation(self.config.orientation)
+                    .with_servo(self.config.servo)
+                    .into_blocks_erasing(&region),
+            );
+            status::update(&self.reporting.status, |status| {
+                status.start_erasing("Cleanup".to_string())
+            });
+        }
+    }
+
+    /// Take the next job from the queue.
+    fn pop_job(&self) -> Option<Job> {
+        let mut jobs = match self.jobs.lock() {
+            Ok(jobs) => jobs,
+            Err(e) => {
+                error!("Could not unlock job queue mutex: {}", e);
+                return None;
+            }
+        };
+        // Held jobs start when their time has come, within the time limits
+        let within_limits = timelimits::current(&self.time_limits).is_none_or(|limits| {
+            limits.is_within_limits(
+                &OffsetDateTime::now_local()
+                    .unwrap_or_else(|_| OffsetDateTime::now_utc())
+                    .time(),
+            )
+        });
+        jobs.release(OffsetDateTime::now_utc(), within_limits);
+        jobs.pop()
+    }
+
+    /// Queue the blocks of a job, or write them to the dry run file.
+    fn start_job(&self, session: &mut Session, job: Job) {
+        let Reporting {
+            ref registry,
+            ref events,
+            ref status,
+            ..
+        } = self.reporting;
+        jobs::transition(registry, job.id, JobState::Encoding);
+        if job.is_expired(OffsetDateTime::now_utc()) {
+            warn!("Dropping job {}: Its deadline has passed", job.label);
+            let reason = format!("Deadline of {} passed", job.label);
+            jobs::transition(
+                registry,
+                job.id,
+                JobState::Failed {
+                    reason: "Deadline passed".to_string(),
+                },
+            );
+            let _ = events.send(Event::JobDropped {
+                code: ErrorCode::DeadlinePassed,
+                reason: reason.clone(),
+            });
+            status::update(status, |status| status.add_error(reason));
+        } else if let Some(config) = self
+            .config
+            .dry_run
+            .as_ref()
+            .filter(|config| job.dry_run || config.always)
+        {
+            self.dry_run(config, &job);
+        } else {
+            self.plan(session, job);
+        }
+    }
+
+    /// Write the blocks of a job to the dry run file instead of the robot.
+    fn dry_run(&self, config: &DryRunConfig, job: &Job) {
+        let registry = &self.reporting.registry;
+        let blocks = Sketch::new(&job.polylines)
+            .with_eraser(&job.eraser)
+            .with_orientation(self.config.orientation)
+            .with_servo(self.config.servo)
+            .with_feed(job.feed_mm.unwrap_or(self.config.feed_mm))
+            .with_hold(job.hold_seconds.unwrap_or(0))
+            .into_blocks(true);
+        match config.write(&job.label, &blocks) {
+            Ok(()) => {
+                info!(
+                    "Dry run: Wrote {} block(s) of job {} to {}",
+                    blocks.len(),
+                    job.label,
+                    config.file
+                );
+                jobs::transition(registry, job.id, JobState::Done);
+            }
+            Err(e) => {
+                jobs::transition(
+                    registry,
+                    job.id,
+                    JobState::Failed {
+                        reason: format!("Dry run failed: {}", e),
+                    },
+                );
+                error!("Dry run: Could not write to {}: {}", config.file, e);
+                status::update(&self.reporting.status, |status| {
+                    status.add_error(format!("Dry run of {} failed: {}", job.label, e))
+                });
+            }
+        }
+    }
+
+    /// Place a job on the board and queue its blocks, unless it is already
+    /// drawn or can't be drawn.
+    fn plan(&self, session: &mut Session, mut job: Job) {
+        let Reporting {
+            ref registry,
+            ref events,
+            ref status,
+            ref history,
+            ref archive,
+            ..
+        } = self.reporting;
+        let plan = match plan_job(
+            &mut job,
+            &session.board,
+            self.config.incremental,
+            &mut session.rng,
+        ) {
+            Ok(Some(plan)) => plan,
+            Ok(None) => {
+                info!("Skipping job {}: It is already drawn", job.label);
+                jobs::transition(registry, job.id, JobState::Done);
+                return;
+            }
+            Err(reason) => {
+                jobs::transition(
+                    registry,
+                    job.id,
+                    JobState::Failed {
+                        reason: reason.clone(),
+                    },
+                );
+                let reason = format!("{} {}", job.label, reason);
+                warn!("Dropping job: {}", reason);
+                let _ = events.send(Event::JobDropped {
+                    code: ErrorCode::PlacementFailed,
+                    reason: reason.clone(),
+                });
+                status::update(status, |status| status.add_error(reason));
+                return;
+            }
+        };
+        let blocks = Sketch::new(&plan.polylines)
+            .with_eraser(&plan.eraser)
+            .with_orientation(self.config.orientation)
+            .with_servo(self.config.servo)
+            .with_feed(job.feed_mm.unwrap_or(self.config.feed_mm))
+            .with_hold(job.hold_seconds.unwrap_or(0))
+            .into_blocks(plan.erase);
+        let required_mm = roll::feed_length(&blocks);
+        let short_roll = self.roll.as_ref().filter(|roll| !roll.fits(required_mm));
+        if let Some(short_roll) = short_roll {
+            let remaining_mm = short_roll.remaining_mm();
+            warn!(
+                "Refusing job {}: It needs {} mm of paper, {} mm left",
+                job.label, required_mm, remaining_mm
+            );
+            jobs::transition(
+                registry,
+                job.id,
+                JobState::Failed {
+                    reason: "Not enough paper left on the roll".to_string(),
+                },
+            );
+            status::update(status, |status| {
+                status.add_error(format!("Replace the paper roll ({} refused)", job.label))
+            });
+            let _ = events.send(Event::ReplaceRoll {
+                label: job.label,
+                required_mm,
+                remaining_mm,
+            });
+            return;
+        }
+
+        match plan.note {
+            Some(note) => info!("Starting job: {} ({})", job.label, note),
+            None => info!("Starting job: {}", job.label),
+        }
+        session.blocks.extend(blocks);
+        session.board.wipe(&plan.eraser);
+        session.board.draw(&plan.polylines, plan.erase);
+        match history.lock() {
+            Ok(mut history) => history.heatmap.add(&plan.polylines),
+            Err(e) => error!("Could not unlock history mutex: {}", e),
+        }
+        session.job_archive = archive.as_ref().map(|_| PendingEntry {
+            job_id: job.id,
+            label: job.label.clone(),
+            erased: plan.erase,
+            polylines: plan.polylines.clone(),
+        });
+        if plan.erase {
+            session.expiries.clear();
+        }
+        if let Some(ttl) = job.ttl {
+            session.expiries.add(Instant::now() + ttl, plan.polylines);
+        }
+        session.printing = job.id;
+        session.job_write_policy = job.write_policy;
+        status::update(status, |status| match plan.erase {
+            true => status.start_erasing(job.label),
+            false => status.start_drawing(job.label),
+        });
+    }
+
+    /// Read a line from the robot, and send a block if it requests one.
+    fn exchange(&mut self, session: &mut Session) -> Trouble {
+        let mut trouble = Trouble::default();
+        let read = session.ser.read_line(&mut session.buf);
+        trouble.disconnected = match read {
+            Err(ref e) if is_connection_lost(&read, &session.path) => Some(e.to_string()),
+            Ok(_) if is_connection_lost(&read, &session.path) => {
+                Some("Device disappeared".to_string())
+            }
+            _ => None,
+        };
+        if read.is_ok() && trouble.disconnected.is_none() {
+            let line = session.buf.trim();
+
+            // Debug print of all serial input
+            debug!("< {}", line);
+
+            // If there are blocks to be sent and we got a new CL command
+            // from the robot...
+            let response = flowcontrol::parse(line);
+            if response.is_some() {
+                if let Some(ref mut stats) = session.job_stats {
+                    stats.response_received();
+                }
+                if session.stalled {
+                    session.stalled = false;
+                    status::update(&self.reporting.status, |status| status.set_stall(None));
+                }
+            }
+            let action = match response {
+                Some(response)
+                    if session.blocks.is_empty() && session.flow.in_flight().is_none() =>
+                {
+                    session.flow.respond(response)
+                }
+                Some(response) => {
+                    match response {
+                        flowcontrol::Response::Ready => info!("< Requesting block"),
+                        flowcontrol::Response::Ack(number) => info!("< Ack: {}", number),
+                    }
+                    session.flow.respond(response)
+                }
+                None => Action::Wait,
+            };
+
+            match action {
+                Action::Resend => {
+                    let block = session.flow.in_flight().cloned().unwrap_or_default();
+                    info!("> Print block {} again", session.flow.current_block());
+                    let policy = session
+                        .job_stats
+                        .as_ref()
+                        .and_then(|stats| stats.write_policy)
+                        .unwrap_or(self.config.write_policy);
+                    if let Err(e) = retry::write_block(&mut *session.ser, &block, policy) {
+                        trouble.failed_write(e);
+                    }
+                    if let Some(ref mut stats) = session.job_stats {
+                        stats.block_sent(block.len(), Instant::now());
+                    }
+                }
+                Action::SendNext if !session.blocks.is_empty() => {
+                    self.send_next(session, &mut trouble);
+                }
+                Action::SendNext if session.shutdown.is_some() => {
+                    // The robot is parked
+                    session.job_stats = None;
+                }
+                Action::SendNext => {
+                    // The robot acknowledged the last block of the job
+                    if let Some(stats) = session.job_stats.take() {
+                        self.finish_job(session, stats);
+                    }
+                }
+                Action::Wait => {}
+            }
+        }
+        session.buf.clear();
+        trouble
+    }
+
+    /// Send the next queued block to the robot.
+    fn send_next(&mut self, session: &mut Session, trouble: &mut Trouble) {
+        let status = &self.reporting.status;
+        // The first block of a job erases the board
+        if session.job_stats.is_some() {
+            status::update(status, Status::finish_erasing);
+        }
+        let block = session
+            .blocks
+            .pop_front()
+            .expect("Could not pop block from non-empty queue");
+        if let Some(ref mut roll) = self.roll {
+            let fed_mm = roll::feed_length([&block]);
+            if fed_mm > 0 {
+                roll.consume(fed_mm);
+                status::update(status, |status| {
+                    status.set_paper_remaining(Some(roll.remaining_mm()))
+                });
+            }
+        }
+        info!(
+            "> Print block {}",
+            flowcontrol::block_number(&block).unwrap_or_default()
+        );
+        let policy = match session.job_stats {
+            Some(ref stats) => stats.write_policy,
+            None => session.job_write_policy,
+        };
+        if let Err(e) = retry::write_block(
+            &mut *session.ser,
+            &block,
+            policy.unwrap_or(self.config.write_policy),
+        ) {
+            trouble.failed_write(e);
+        }
+
+        let now = Instant::now();
+        if session.job_stats.is_none() {
+            jobs::transition(
+                &self.reporting.registry,
+                session.printing,
+                JobState::Printing,
+            );
+        }
+        let stats = session.job_stats.get_or_insert_with(|| JobStats {
+            estimate: session.job_estimate.take(),
+            write_policy: session.job_write_policy.take(),
+            archive: session.job_archive.take(),
+            ..JobStats::new(now, self.config.simulation)
+        });
+        stats.block_sent(block.len(), now);
+        stats.simulator.feed(&block);
+        debug!(
+            "Job trace: {} block(s), {} bytes sent, {:.1} bytes/s",
+            stats.blocks_sent,
+            stats.bytes_sent,
+            stats.throughput(now)
+        );
+        session.flow.sent(block);
+    }
+
+    /// Record a job whose last block the robot acknowledged.
+    fn finish_job(&self, session: &mut Session, stats: JobStats) {
+        let job_id = session.printing.take();
+        let now = Instant::now();
+        jobs::finish(
+            &self.reporting.registry,
+            job_id,
+            JobState::Done,
+            stats.trace(now),
+        );
+        let actual = now.duration_since(stats.started);
+        let simulated = stats.simulator.elapsed();
+        info!(
+            "Job trace: Sent {} block(s) ({} bytes) in {}s, {:.1} bytes/s, {} stall(s)",
+            stats.blocks_sent,
+            stats.bytes_sent,
+            actual.as_secs(),
+            stats.throughput(now),
+            stats.stalls
+        );
+        info!(
+            "Job trace: Simulated duration {}s, actual duration {}s",
+            simulated.as_secs(),
+            actual.as_secs()
+        );
+        let _ = self.reporting.events.send(Event::JobFinished {
+            blocks: stats.blocks_sent,
+            bytes: stats.bytes_sent,
+            seconds: actual.as_secs(),
+        });
+        match self.reporting.history.lock() {
+            Ok(mut history) => history.push(JobRecord {
+                job_id,
+                finished: OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc()),
+                blocks: stats.blocks_sent,
+                bytes: stats.bytes_sent,
+                actual_seconds: actual.as_secs_f64(),
+                simulated_seconds: simulated.as_secs_f64(),
+            }),
+            Err(e) => error!("Could not unlock history mutex: {}", e),
+        }
+        if let (Some(archive), Some(entry)) = (self.reporting.archive.as_ref(), stats.archive) {
+            let board = match self.jobs.lock() {
+                Ok(jobs) => jobs.board().to_string(),
+                Err(_) => String::new(),
+            };
+            let entry = entry.complete(&board, actual.as_secs_f64());
+            let _ = archive.send(entry);
+        }
+        status::update(&self.reporting.status, Status::finish_drawing);
+    }
+
+    /// Report jobs that take longer than estimated, and detect stalls: The
+    /// robot did not acknowledge the last block in time.
+    fn check_progress(&self, session: &mut Session, trouble: &mut Trouble) {
+        let Reporting {
+            ref events,
+            ref status,
+            ref watchdog,
+            ref registry,
+            ..
+        } = self.reporting;
+        let stall_timeout = self.config.stall_timeout;
+        let stats = match session.job_stats {
+            Some(ref mut stats) => stats,
+            None => return,
+        };
+        let now = Instant::now();
+        if let Some((estimate, elapsed)) = stats.check_overrun(now, &self.config.alerts) {
+            let mut label = String::new();
+            status::update(status, |status| {
+                label = status.drawing().unwrap_or("Job").to_string();
+                status.add_error(format!("{} is taking longer than estimated", label))
+            });
+            warn!(
+                "Job {} is running for {}s, estimated {}s",
+                label,
+                elapsed.as_secs(),
+                estimate.as_secs()
+            );
+            let _ = events.send(Event::JobOverrun {
+                label,
+                estimated_seconds: estimate.as_secs(),
+                elapsed_seconds: elapsed.as_secs(),
+            });
+        }
+        if !stats.is_stalled(now, stall_timeout) {
+            return;
+        }
+        stats.stalls += 1;
+        stats.retries += 1;
+        let current_block = session.flow.current_block();
+        error!(
+            "Robot stalled: No response for {}s after block {}",
+            stall_timeout.as_secs(),
+            current_block
+        );
+        let _ = events.send(Event::RobotStalled {
+            block: current_block,
+        });
+        watchdog.serial_error();
+        let retries = stats.retries.min(MAX_STALL_RETRIES);
+        status::update(status, |status| {
+            status.set_stall(Some((current_block, retries)));
+            status.add_error(format!("Robot stalled after block {}", current_block))
+        });
+        session.stalled = true;
+        match session.flow.in_flight() {
+            Some(block) if stats.retries <= MAX_STALL_RETRIES => {
+                info!(
+                    "> Retrying block {} (attempt {}/{})",
+                    current_block, stats.retries, MAX_STALL_RETRIES
+                );
+                let policy = stats.write_policy.unwrap_or(self.config.write_policy);
+                if let Err(e) = retry::write_block(&mut *session.ser, block, policy) {
+                    trouble.failed_write(e);
+                }
+                stats.block_sent(block.len(), now);
+            }
+            _ => {
+                error!("Robot stalled too often, dropping the current job");
+                let _ = events.send(Event::JobDropped {
+                    code: ErrorCode::RobotStalled,
+                    reason: "Robot stalled too often".to_string(),
+                });
+                status::update(status, |status| {
+                    status.finish_drawing();
+                    status.add_error("Job dropped: Robot stalled too often".to_string());
+                });
+                session.blocks.clear();
+                session.board.forget();
+                jobs::finish(
+                    registry,
+                    session.printing.take(),
+                    JobState::Failed {
+                        reason: "Robot stalled too often".to_string(),
+                    },
+                    stats.trace(now),
+                );
+                session.job_stats = None;
+                session.flow.reset();
+            }
+        }
+    }
+
+    /// After a shutdown request, check whether the robot is parked (or can't
+    /// be parked). If so, notify the requester and return true.
+    fn finish_shutdown(&self, session: &mut Session, disconnected: bool) -> bool {
+        let (done, deadline) = match session.shutdown {
+            Some((ref done, deadline)) => (done, deadline),
+            None => return false,
+        };
+        let parked = session.flow.in_flight().is_none() && session.blocks.is_empty();
+        if !parked && !disconnected && Instant::now() <= deadline {
+            return false;
+        }
+        match parked {
+            true => info!("Robot parked"),
+            false => warn!("Could not park the robot before shutting down"),
+        }
+        // The content is only redrawn after an unexpected restart
+        if let Some(ref path) = self.reporting.board_file {
+            if let Err(e) = fs::remove_file(path) {
+                if e.kind() != ErrorKind::NotFound {
+                    warn!("Could not remove {}: {}", path.display(), e);
+                }
+            }
+        }
+        if let Err(e) = session.ser.flush() {
+            error!("Could not flush serial connection: {}", e);
+        }
+        let _ = done.send(());
+        true
+    }
+
+    /// Fail the current job, since its blocks can't be written.
+    fn drop_job(&self, session: &mut Session, e: WriteError) {
+        let reason = format!("Could not write to the robot: {}", e);
+        error!("{}, dropping the current job", reason);
+        let _ = self.reporting.events.send(Event::JobDropped {
+            code: ErrorCode::WriteFailed,
+            reason: reason.clone(),
+        });
+        status::update(&self.reporting.status, |status| {
+            status.finish_drawing();
+            status.add_error(format!("Job dropped: {}", reason));
+        });
+        session.blocks.clear();
+        session.board.forget();
+        session.job_stats = None;
+        session.job_write_policy = None;
+        session.job_archive = None;
+        jobs::transition(
+            &self.reporting.registry,
+            session.printing.take(),
+            JobState::Failed { reason },
+        );
+        session.flow.reset();
+    }
+
+    /// Reopen the serial device after the connection was lost.
+    fn restore_connection(&self, session: &mut Session, reason: String) {
+        let Reporting {
+            ref events,
+            ref status,
+            ref watchdog,
+            ref console,
+            ..
+        } = self.reporting;
+        error!("Lost connection to robot: {}", reason);
+        let _ = events.send(Event::RobotDisconnected {
+            reason: reason.clone(),
+        });
+        watchdog.serial_error();
+        status::update(status, |status| {
+            let message = format!("Robot disconnected: {}", reason);
+            status.set_robot_error(Some(message.clone()));
+            status.add_error(message)
+        });
+        let (reconnected, path, attempts) = reconnect(
+            &self.device,
+            self.baud_rate,
+            self.config.simulation,
+            self.config.offline_after,
+            status,
+            events,
+        );
+        session.ser = capture::record(
+            reconnected,
+            self.config.capture.as_ref(),
+            console.as_ref(),
+            watchdog,
+            &path,
+        );
+        info!("Reconnected to {} after {} attempt(s)", path, attempts);
+        session.path = path;
+        let _ = events.send(Event::RobotReconnected { attempts });
+        status::update(status, |status| status.set_robot_error(None));
+        identify_firmware(&mut *session.ser, status);
+
+        // The unacknowledged block is still in flight, so it is sent again
+        // once the robot requests a block
+        if let Some(ref mut stats) = session.job_stats {
+            stats.response_received();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_supervise() {
+        let mut runs = 0;
+        let mut crashes = vec![];
+        supervise(
+            || {
+                runs += 1;
+                match runs {
+                    1 => panic!("Serial port exploded"),
+                    2 => panic!("{} times", 2),
+                    _ => {}
+                }
+            },
+            |message| crashes.push(message.to_string()),
+        );
+        assert_eq!(runs, 3);
+        assert_eq!(crashes, vec!["Serial port exploded", "2 times"]);
+    }
+
+    #[test]
+    fn test_reconnect_delay() {
+        assert_eq!(reconnect_delay(1), Duration::from_secs(1));
+        assert_eq!(reconnect_delay(2), Duration::from_secs(2));
+        assert_eq!(reconnect_delay(4), Duration::from_secs(8));
+        assert_eq!(reconnect_delay(100), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_is_connection_lost() {
+        let timeout = Err(io::Error::new(ErrorKind::TimedOut, "timeout"));
+        assert!(!is_connection_lost(&timeout, "/"));
+        let broken = Err(io::Error::new(ErrorKind::BrokenPipe, "broken"));
+        assert!(is_connection_lost(&broken, "/"));
+        assert!(!is_connection_lost(&Ok(0), "/"));
+        assert!(is_connection_lost(&Ok(0), "/nonexistent/ttyACM0"));
+        assert!(!is_connection_lost(&Ok(12), "/nonexistent/ttyACM0"));
+        assert!(is_connection_lost(&Ok(0), "tcp://127.0.0.1:2000"));
+    }
+
+    #[test]
+    fn test_tcp_bridge() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let device = format!("tcp://{}", listener.local_addr().unwrap());
+        let bridge = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"CL STATUS=READY\n").unwrap();
+        });
+        let (mut ser, path) =
+            connect(&device, BaudRate::Baud115200, SimulationConfig::default()).unwrap();
+        assert_eq!(path, device);
+        let mut line = String::new();
+        ser.read_line(&mut line).unwrap();
+        assert_eq!(line, "CL STATUS=READY\n");
+        bridge.join().unwrap();
+    }
+
+    #[test]
+    fn test_job_stats() {
+        let start = Instant::now();
+        let mut stats = JobStats::new(start, SimulationConfig::default());
+        assert_eq!(stats.throughput(start), 0.0);
+        assert!(!stats.is_stalled(start + Duration::from_secs(100), Duration::from_secs(10)));
+
+        stats.block_sent(768, start);
+        stats.block_sent(256, start + Duration::from_secs(1));
+        let now = start + Duration::from_secs(2);
+        assert_eq!(stats.blocks_sent, 2);
+        assert_eq!(stats.throughput(now), 512.0);
+
+        // Waiting for an ACK
+        assert!(!stats.is_stalled(now, Duration::from_secs(10)));
+        assert!(stats.is_stalled(now + Duration::from_secs(9), Duration::from_secs(10)));
+
+        // Not stalled anymore once the robot responded, and the retries
+        // start over
+        stats.stalls = 2;
+        stats.retries = 2;
+        stats.response_received();
+        assert!(!stats.is_stalled(now + Duration::from_secs(9), Duration::from_secs(10)));
+        assert_eq!(stats.retries, 0);
+
+        let trace = stats.trace(now);
+        assert_eq!((trace.blocks, trace.bytes, trace.stalls), (2, 1024, 2));
+        assert_eq!(trace.bytes_per_second, 512.0);
+    }
+
+    #[test]
+    fn test_job_overrun() {
+        let start = Instant::now();
+        let alerts = AlertConfig {
+            slow_request_ms: None,
+            job_overrun_minutes: Some(1),
+        };
+        let mut stats = JobStats {
+            estimate: Some(Duration::from_secs(30)),
+            ..JobStats::new(start, SimulationConfig::default())
+        };
+        assert_eq!(
+            stats.check_overrun(start + Duration::from_secs(90), &alerts),
+            None
+        );
+        let late = start + Duration::from_secs(91);
+        assert_eq!(
+            stats.check_overrun(late, &alerts),
+            Some((Duration::from_secs(30), Duration::from_secs(91)))
+        );
+        // Only reported once
+        assert_eq!(stats.check_overrun(late, &alerts), None);
+    }
+
+    #[test]
+    fn test_feed_command() {
+        let command = Command::Feed(300).to_bytes();
+        assert_eq!(command, [0xfa, 0x71, 0x2c]);
+        assert_eq!(roll::feed_length([&command.to_vec()]), 300);
+    }
+
+    #[test]
+    fn test_feed_blocks() {
+        // Long feeds are split into several commands
+        let blocks = Sketch::new(&[]).with_feed(5000).into_feed_blocks();
+        assert_eq!(roll::feed_length(&blocks), 5000);
+
+        // Jobs feed before drawing
+        let polylines = vec![vec![
+            CoordinatePair { x: 10.0, y: 10.0 },
+            CoordinatePair { x: 20.0, y: 10.0 },
+        ]];
+        let blocks = Sketch::new(&polylines).with_feed(120).into_blocks(false);
+        assert_eq!(roll::feed_length(&blocks), 120);
+        let position = |command: [u8; 3]| blocks[0].windows(3).position(|c| c == command);
+        assert!(position([0xfa, 0x70, 120]).unwrap() < position([0xfa, 0x40, 0x00]).unwrap());
+        assert_eq!(
+            roll::feed_length(&Sketch::new(&polylines).into_blocks(false)),
+            0
+        );
+    }
+
+    #[test]
+    fn test_hold_blocks() {
+        // Long holds are split into several waits after drawing
+        let polylines: Vec<Polyline> = vec![];
+        let blocks = Sketch::new(&polylines).with_hold(70).into_blocks(false);
+        assert_eq!(
+            blocks[0][15..],
+            [
+                0x00, 0x00, 0x00, // Move to 0,0
+                0xfa, 0x60, 30, // Wait 30 seconds
+                0xfa, 0x60, 30, // Wait 30 seconds
+                0xfa, 0x60, 10, // Wait 10 seconds
+                0xfa, 0x20, 0x00, // Stop drawing
+            ]
+        );
+    }
+
+    #[test]
+    fn test_empty_sketch() {
+        let polylines: Vec<Polyline> = vec![];
+        let sketch = Sketch::new(&polylines);
+        let blocks = sketch.into_blocks(false);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(
+            blocks[0],
+            vec![
+                0xfa, 0x9f, 0xa1, // Block start
+                0xfa, 0x90, 0x01, // Block number 1
+                0xfa, 0x1f, 0xa1, // Start drawing
                 0xfa, 0x30, 0x00, // Pen lift
                 0x00, 0x00, 0x00, // Move to 0,0
                 0x00, 0x00, 0x00, // Move to 0,0
@@ -481,6 +2606,67 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_region_eraser() {
+        let region = Bounds {
+            x: crate::scaling::Range {
+                min: 10.0,
+                max: 30.0,
+            },
+            y: crate::scaling::Range {
+                min: 5.0,
+                max: 20.0,
+            },
+        };
+        let point = |x, y| CoordinatePair { x, y };
+        assert_eq!(
+            region_eraser(&region),
+            vec![vec![
+                point(10.0, 5.0),
+                point(30.0, 5.0),
+                point(30.0, 15.0),
+                point(10.0, 15.0),
+                point(10.0, 20.0),
+                point(30.0, 20.0),
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_eraser_strokes() {
+        let polylines: Vec<Polyline> = vec![vec![
+            CoordinatePair::from((12.3, 45.6)),
+            CoordinatePair::from((14.3, 47.6)),
+        ]];
+        let eraser: Vec<Polyline> = vec![vec![
+            CoordinatePair::from((10.0, 45.6)),
+            CoordinatePair::from((20.0, 45.6)),
+        ]];
+        let blocks = Sketch::new(&polylines)
+            .with_eraser(&eraser)
+            .into_blocks(false);
+        assert_eq!(
+            blocks[0],
+            vec![
+                0xfa, 0x9f, 0xa1, // Block start
+                0xfa, 0x90, 0x01, // Block number 1
+                0xfa, 0x1f, 0xa1, // Start drawing
+                0xfa, 0x30, 0x00, // Pen lift
+                0x00, 0x00, 0x00, // Move to 0,0
+                0x06, 0x43, 0x06, // Move to 100,456
+                0xfa, 0x50, 0x00, // Enable eraser
+                0x0c, 0x83, 0x06, // Move to 200,456
+                0xfa, 0x30, 0x00, // Pen lift
+                0x07, 0xb3, 0x06, // Move to 123,456
+                0xfa, 0x40, 0x00, // Pen down
+                0x08, 0xf2, 0xf2, // Move to 143,476
+                0xfa, 0x30, 0x00, // Pen lift
+                0x00, 0x00, 0x00, // Move to 0,0
+                0xfa, 0x20, 0x00, // Stop drawing
+            ]
+        );
+    }
+
     #[test]
     fn test_full_block() {
         let mut polyline = vec![CoordinatePair::from((1.0, 1.0))];
@@ -511,4 +2697,123 @@ mod test {
         assert_eq!(blocks[0][3..6], [0xfa, 0x90, 0x01]); // Block 1
         assert_eq!(blocks[1][3..6], [0xfa, 0x90, 0x02]); // Block 2
     }
+
+    #[test]
+    fn test_erase_region() {
+        let polylines: Vec<Polyline> = vec![];
+        let region = Bounds {
+            x: crate::scaling::Range {
+                min: 10.0,
+                max: 20.0,
+            },
+            y: crate::scaling::Range {
+                min: IBB_HEIGHT as f64 - 35.0,
+                max: IBB_HEIGHT as f64 - 10.0,
+            },
+        };
+        let blocks = Sketch::new(&polylines).into_blocks_erasing(&region);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(
+            blocks[0],
+            vec![
+                0xfa, 0x9f, 0xa1, // Block start
+                0xfa, 0x90, 0x01, // Block number 1
+                0xfa, 0x1f, 0xa1, // Start drawing
+                0xfa, 0x30, 0x00, // Pen lift
+                0x06, 0x41, 0x5e, // Move to 100,350
+                0xfa, 0x50, 0x00, // Enable eraser
+                0x0c, 0x81, 0x5e, // Move to 200,350
+                0x0c, 0x80, 0xfa, // Move to 200,250
+                0x06, 0x40, 0xfa, // Move to 100,250
+                0x06, 0x40, 0x96, // Move to 100,150
+                0x0c, 0x80, 0x96, // Move to 200,150
+                0x0c, 0x80, 0x64, // Move to 200,100
+                0x06, 0x40, 0x64, // Move to 100,100
+                0xfa, 0x30, 0x00, // Pen lift
+                0x00, 0x00, 0x00, // Move to 0,0
+                0x00, 0x00, 0x00, // Move to 0,0
+                0xfa, 0x20, 0x00, // Stop drawing
+            ]
+        );
+    }
+
+    fn line(x: f64, y: f64) -> Polyline {
+        vec![
+            CoordinatePair::from((x, y)),
+            CoordinatePair::from((x + 10.0, y)),
+        ]
+    }
+
+    fn job(placement: Placement, on_collision: CollisionPolicy) -> Job {
+        Job {
+            placement,
+            on_collision,
+            ..Job::new("Test".to_string(), vec![line(10.0, 10.0)])
+        }
+    }
+
+    #[test]
+    fn test_calibration_blocks() {
+        let servo = PenServo {
+            lift: None,
+            down: Some(0x4b0),
+        };
+        let blocks = Sketch::new(&[])
+            .with_servo(servo)
+            .into_calibration_blocks(PenPosition::Down);
+        assert_eq!(
+            blocks,
+            vec![vec![
+                0xfa, 0x9f, 0xa1, // Block start
+                0xfa, 0x90, 0x01, // Block number 1
+                0xfa, 0x1f, 0xa1, // Start drawing
+                0xfa, 0x44, 0xb0, // Pen down to 1200
+                0xfa, 0x60, 0x05, // Wait 5 seconds
+                0xfa, 0x30, 0x00, // Pen lift to the default position
+                0xfa, 0x20, 0x00, // Stop drawing
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_plan_job() {
+        let mut rng = Rng::new(Some(1));
+        let mut board = Board::default();
+        let fixed = || job(Placement::Fixed, CollisionPolicy::Reject);
+
+        // The content of the board is unknown, so it must be erased
+        let plan = plan_job(&mut fixed(), &board, true, &mut rng)
+            .unwrap()
+            .unwrap();
+        assert_eq!((plan.polylines, plan.erase), (vec![line(10.0, 10.0)], true));
+        board.draw(&[line(10.0, 10.0)], true);
+        assert_eq!(plan_job(&mut fixed(), &board, true, &mut rng), Ok(None));
+
+        // Appending
+        let mut append = job(Placement::Append, CollisionPolicy::Reject);
+        assert!(plan_job(&mut append, &board, false, &mut rng).is_err());
+        let mut append = job(Placement::Append, CollisionPolicy::Overlap);
+        let plan = plan_job(&mut append, &board, false, &mut rng)
+            .unwrap()
+            .unwrap();
+        assert!(!plan.erase);
+
+        // Appending after wiping the existing content with the eraser
+        let mut append = Job {
+            eraser: vec![line(5.0, 10.0)],
+            ..job(Placement::Append, CollisionPolicy::Reject)
+        };
+        let plan = plan_job(&mut append, &board, false, &mut rng)
+            .unwrap()
+            .unwrap();
+        assert_eq!((plan.erase, plan.eraser), (false, vec![line(5.0, 10.0)]));
+
+        // Random placement
+        let mut random = job(Placement::Random, CollisionPolicy::Reject);
+        let plan = plan_job(&mut random, &board, false, &mut rng)
+            .unwrap()
+            .unwrap();
+        assert!(!plan.erase);
+        assert!(!placement::collides(&plan.polylines, &[line(10.0, 10.0)]));
+    }
 }
@@ -0,0 +1,149 @@
+//! Paper budget of the paper roll variant of the robot.
+//!
+//! The paper is advanced by feed commands, either before every job (with
+//! `feed_mm`) or on demand with `/robot/feed/`. With a `paper_roll` section
+//! in the config, the remaining length of the roll is tracked: Every feed
+//! command sent to the robot uses up paper. Jobs that would feed more paper
+//! than is left are refused, and a `replace_roll` event is sent. After a new
+//! roll is inserted, the budget is reset with `/robot/roll/replace/`. The
+//! remaining length is kept in the state dir, so that it survives restarts.
+use std::fs;
+use std::path::PathBuf;
+
+use log::warn;
+use serde_derive::{Deserialize, Serialize};
+
+/// The command code of a feed command (see `robot::Command`).
+const FEED_CODE: u8 = 0x7;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RollConfig {
+    /// The length of a new roll in mm.
+    pub(crate) length_mm: u32,
+    /// Paper fed before every job, so that each drawing lands on fresh
+    /// paper (unless the job sets its own `feed_mm`).
+    #[serde(default)]
+    pub(crate) feed_mm: u32,
+}
+
+impl RollConfig {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        match self.length_mm {
+            0 => Err("The roll length must be positive".to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct PaperRoll {
+    config: RollConfig,
+    remaining_mm: u32,
+    /// File where the remaining length is persisted.
+    state_file: Option<PathBuf>,
+}
+
+impl PaperRoll {
+    /// Track the roll, starting with the remaining length from the state
+    /// file (or a new roll).
+    pub(crate) fn new(config: RollConfig, state_file: Option<PathBuf>) -> Self {
+        let remaining_mm = state_file
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|remaining| remaining.trim().parse::<u32>().ok())
+            .map_or(config.length_mm, |remaining| {
+                remaining.min(config.length_mm)
+            });
+        PaperRoll {
+            config,
+            remaining_mm,
+            state_file,
+        }
+    }
+
+    pub(crate) fn remaining_mm(&self) -> u32 {
+        self.remaining_mm
+    }
+
+    /// Return whether feeding `length_mm` fits on the rest of the roll.
+    pub(crate) fn fits(&self, length_mm: u32) -> bool {
+        length_mm <= self.remaining_mm
+    }
+
+    /// Use up paper after it was fed.
+    pub(crate) fn consume(&mut self, length_mm: u32) {
+        if length_mm > 0 {
+            self.set_remaining(self.remaining_mm.saturating_sub(length_mm));
+        }
+    }
+
+    /// Start over with a new roll.
+    pub(crate) fn replace(&mut self) {
+        self.set_remaining(self.config.length_mm);
+    }
+
+    fn set_remaining(&mut self, remaining_mm: u32) {
+        self.remaining_mm = remaining_mm;
+        if let Some(ref path) = self.state_file {
+            if let Err(e) = fs::write(path, remaining_mm.to_string()) {
+                warn!("Could not write {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Return the length in mm fed by the feed commands in the blocks.
+pub(crate) fn feed_length<'a>(blocks: impl IntoIterator<Item = &'a Vec<u8>>) -> u32 {
+    blocks
+        .into_iter()
+        .flat_map(|block| block.chunks_exact(3))
+        .map(|command| match command {
+            [0xfa, code, low] if code >> 4 == FEED_CODE => {
+                u32::from(code & 0x0f) << 8 | u32::from(*low)
+            }
+            _ => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feed_length() {
+        let block = vec![
+            0xfa, 0x9f, 0xa1, // Block start
+            0xfa, 0x90, 0x01, // Block number 1
+            0xfa, 0x71, 0x2c, // Feed 300 mm
+            0x00, 0x00, 0x00, // Move to (0, 0)
+            0xfa, 0x70, 0x14, // Feed 20 mm
+        ];
+        assert_eq!(feed_length(&[block]), 320);
+        assert_eq!(feed_length(&[]), 0);
+    }
+
+    #[test]
+    fn test_budget() {
+        let path = std::env::temp_dir().join(format!("ibb-roll-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let config = RollConfig {
+            length_mm: 1000,
+            feed_mm: 0,
+        };
+        let mut roll = PaperRoll::new(config, Some(path.clone()));
+        assert_eq!(roll.remaining_mm(), 1000);
+        roll.consume(300);
+        assert!(roll.fits(700));
+        assert!(!roll.fits(701));
+
+        // The remaining length survives restarts
+        let mut roll = PaperRoll::new(config, Some(path.clone()));
+        assert_eq!(roll.remaining_mm(), 700);
+        roll.consume(800);
+        assert_eq!(roll.remaining_mm(), 0);
+        roll.replace();
+        assert_eq!(roll.remaining_mm(), 1000);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,222 @@
+//! The order in which the items of a scheduled rotation are drawn.
+//!
+//! - `sequential`: In the order of the playlist (the default).
+//! - `shuffle`: Every item once per round, in a new random order every round.
+//! - `weighted`: A random item every time, with a probability proportional to
+//!   its weight (1 unless configured otherwise).
+//! - `newest_first`: Like `sequential`, but the SVG files are ordered by their
+//!   modification time, newest first. Generated items come last.
+use std::collections::VecDeque;
+use std::time::SystemTime;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::generate::Rng;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Rotation {
+    #[default]
+    Sequential,
+    Shuffle,
+    Weighted,
+    NewestFirst,
+}
+
+/// An item of the rotation.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Item {
+    pub(crate) weight: u32,
+    /// The modification time of the SVG file, if the item is a file.
+    pub(crate) modified: Option<SystemTime>,
+}
+
+/// Picks the index of the item to draw in every cycle.
+#[derive(Debug)]
+pub(crate) struct Rotator {
+    rotation: Rotation,
+    items: Vec<Item>,
+    /// The indices of the items to draw next.
+    upcoming: VecDeque<usize>,
+    /// The index of the item that was drawn last.
+    last: Option<usize>,
+    rng: Rng,
+}
+
+impl Rotator {
+    pub(crate) fn new(rotation: Rotation, items: Vec<Item>) -> Self {
+        Rotator {
+            rotation,
+            items,
+            upcoming: VecDeque::new(),
+            last: None,
+            rng: Rng::new(None),
+        }
+    }
+
+    /// Draw the items in the order of the list.
+    pub(crate) fn sequential(len: usize) -> Self {
+        let item = Item {
+            weight: 1,
+            modified: None,
+        };
+        Rotator::new(Rotation::Sequential, vec![item; len])
+    }
+
+    /// Plan the next round (or pick, for the weighted rotation).
+    fn plan(&mut self) {
+        let len = self.items.len();
+        match self.rotation {
+            Rotation::Sequential => self.upcoming.extend(0..len),
+            Rotation::NewestFirst => {
+                let mut order: Vec<usize> = (0..len).collect();
+                // Files before generated items, and newer files first
+                order.sort_by_key(|&i| {
+                    let modified = self.items[i].modified;
+                    (modified.is_none(), std::cmp::Reverse(modified))
+                });
+                self.upcoming.extend(order);
+            }
+            Rotation::Shuffle => {
+                let mut order: Vec<usize> = (0..len).collect();
+                for i in (1..len).rev() {
+                    order.swap(i, self.rng.below(i + 1));
+                }
+                // Don't draw the same item twice in a row across rounds
+                if len > 1 && order.first() == self.last.as_ref() {
+                    order.swap(0, len - 1);
+                }
+                self.upcoming.extend(order);
+            }
+            Rotation::Weighted => {
+                let total: u64 = self.items.iter().map(|item| u64::from(item.weight)).sum();
+                if total == 0 {
+                    self.upcoming.push_back(self.rng.below(len));
+                    return;
+                }
+                let mut target = (self.rng.next_f64() * total as f64) as u64;
+                let index = self
+                    .items
+                    .iter()
+                    .position(|item| {
+                        let weight = u64::from(item.weight);
+                        if target < weight {
+                            return true;
+                        }
+                        target -= weight;
+                        false
+                    })
+                    .unwrap_or(len - 1);
+                self.upcoming.push_back(index);
+            }
+        }
+    }
+
+    /// Continue an ordered rotation with the item at the index, e.g. after a
+    /// restart. Random rotations start a new round anyway.
+    pub(crate) fn resume_at(&mut self, index: usize) {
+        if !matches!(self.rotation, Rotation::Sequential | Rotation::NewestFirst)
+            || index >= self.items.len()
+        {
+            return;
+        }
+        self.upcoming.clear();
+        self.plan();
+        while self.upcoming.front().is_some_and(|&i| i != index) {
+            self.upcoming.pop_front();
+        }
+    }
+
+    /// Return the index of the item that is drawn next, without drawing it.
+    pub(crate) fn peek(&mut self) -> usize {
+        if self.upcoming.is_empty() && !self.items.is_empty() {
+            self.plan();
+        }
+        self.upcoming.front().copied().unwrap_or(0)
+    }
+
+    /// Return the index of the item to draw now.
+    pub(crate) fn next(&mut self) -> usize {
+        let index = self.peek();
+        self.upcoming.pop_front();
+        self.last = Some(index);
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn items(weights: &[u32]) -> Vec<Item> {
+        weights
+            .iter()
+            .map(|&weight| Item {
+                weight,
+                modified: None,
+            })
+            .collect()
+    }
+
+    fn take(rotator: &mut Rotator, n: usize) -> Vec<usize> {
+        (0..n).map(|_| rotator.next()).collect()
+    }
+
+    #[test]
+    fn test_sequential() {
+        let mut rotator = Rotator::sequential(3);
+        assert_eq!(rotator.peek(), 0);
+        assert_eq!(take(&mut rotator, 5), vec![0, 1, 2, 0, 1]);
+        assert_eq!(rotator.peek(), 2);
+
+        let mut rotator = Rotator::sequential(3);
+        rotator.resume_at(1);
+        assert_eq!(take(&mut rotator, 3), vec![1, 2, 0]);
+    }
+
+    #[test]
+    fn test_shuffle() {
+        let mut rotator = Rotator::new(Rotation::Shuffle, items(&[1; 4]));
+        let mut previous = None;
+        for _ in 0..10 {
+            let mut round = take(&mut rotator, 4);
+            assert_ne!(Some(round[0]), previous);
+            previous = round.last().copied();
+            round.sort();
+            assert_eq!(round, vec![0, 1, 2, 3]);
+        }
+    }
+
+    #[test]
+    fn test_weighted() {
+        let mut rotator = Rotator::new(Rotation::Weighted, items(&[3, 0, 1]));
+        let picks = take(&mut rotator, 400);
+        let count = |index| picks.iter().filter(|&&i| i == index).count();
+        assert_eq!(count(1), 0);
+        assert!(count(0) > count(2));
+        assert_eq!(count(0) + count(2), 400);
+    }
+
+    #[test]
+    fn test_newest_first() {
+        let time = |seconds| Some(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds));
+        let items = vec![
+            Item {
+                weight: 1,
+                modified: time(100),
+            },
+            Item {
+                weight: 1,
+                modified: None,
+            },
+            Item {
+                weight: 1,
+                modified: time(300),
+            },
+        ];
+        let mut rotator = Rotator::new(Rotation::NewestFirst, items);
+        assert_eq!(take(&mut rotator, 4), vec![2, 0, 1, 2]);
+    }
+}
@@ -0,0 +1,102 @@
+//! User-defined rules that decide which notification sinks react to events.
+//!
+//! A rule matches an event by name (or `*` for all events) and optionally by
+//! the values of the event fields, e.g.
+//!
+//! ```json
+//! {"on": "job_dropped", "when": {"reason": "Robot stalled too often"}, "notify": ["slack"]}
+//! ```
+use std::collections::BTreeMap;
+
+use serde_derive::Deserialize;
+use serde_json::Value;
+
+use crate::notify::Event;
+
+#[derive(Debug, Deserialize, Clone)]
+pub(crate) struct Rule {
+    /// Event name, or `*` for any event.
+    on: String,
+    /// Conditions on event fields. All of them must match.
+    #[serde(default)]
+    when: BTreeMap<String, Value>,
+    /// Names of the sinks to notify.
+    pub(crate) notify: Vec<String>,
+}
+
+/// Render a JSON value for comparison, so that `"3"` and `3` are equal.
+fn normalize(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+impl Rule {
+    pub(crate) fn matches(&self, event: &Event) -> bool {
+        if self.on != "*" && self.on != event.name() {
+            return false;
+        }
+        if self.when.is_empty() {
+            return true;
+        }
+        let fields = event.to_value();
+        self.when.iter().all(|(key, expected)| {
+            fields
+                .get(key)
+                .is_some_and(|actual| normalize(actual) == normalize(expected))
+        })
+    }
+}
+
+/// Return the names of all sinks that should be notified about the event.
+pub(crate) fn targets<'a>(rules: &'a [Rule], event: &Event) -> Vec<&'a str> {
+    let mut targets: Vec<&str> = rules
+        .iter()
+        .filter(|rule| rule.matches(event))
+        .flat_map(|rule| rule.notify.iter().map(String::as_str))
+        .collect();
+    targets.sort_unstable();
+    targets.dedup();
+    targets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(json: &str) -> Rule {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_match_event_name() {
+        let r = rule(r#"{"on": "robot_stalled", "notify": ["a"]}"#);
+        assert!(r.matches(&Event::RobotStalled { block: 1 }));
+        assert!(!r.matches(&Event::TaskReceived));
+        let r = rule(r#"{"on": "*", "notify": ["a"]}"#);
+        assert!(r.matches(&Event::TaskReceived));
+    }
+
+    #[test]
+    fn test_match_conditions() {
+        let r = rule(r#"{"on": "robot_stalled", "when": {"block": "3"}, "notify": ["a"]}"#);
+        assert!(r.matches(&Event::RobotStalled { block: 3 }));
+        assert!(!r.matches(&Event::RobotStalled { block: 4 }));
+        let r = rule(r#"{"on": "*", "when": {"board": "lobby"}, "notify": ["a"]}"#);
+        assert!(!r.matches(&Event::TaskReceived));
+    }
+
+    #[test]
+    fn test_targets() {
+        let rules = vec![
+            rule(r#"{"on": "robot_stalled", "notify": ["mail", "chat"]}"#),
+            rule(r#"{"on": "*", "notify": ["chat"]}"#),
+        ];
+        assert_eq!(
+            targets(&rules, &Event::RobotStalled { block: 1 }),
+            vec!["chat", "mail"]
+        );
+        assert_eq!(targets(&rules, &Event::TaskReceived), vec!["chat"]);
+    }
+}
@@ -1,8 +1,14 @@
 //! Code for resizing, scaling and fitting polylines.
 use log::{info, warn};
-use svg2polylines::Polyline;
+use serde_derive::{Deserialize, Serialize};
+use svg2polylines::{CoordinatePair, Polyline};
 
-#[derive(Debug, PartialEq)]
+use crate::robot;
+
+/// The default margin along the edges of the board, in mm.
+pub const DEFAULT_MARGIN_MM: f64 = 5.0;
+
+#[derive(Debug, PartialEq, Clone)]
 pub struct Range {
     pub min: f64,
     pub max: f64,
@@ -14,13 +20,60 @@ impl Range {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Bounds {
     pub x: Range,
     pub y: Range,
 }
 
 impl Bounds {
+    /// The bounds of the entire drawing area of the board.
+    pub fn board() -> Self {
+        Bounds {
+            x: Range {
+                min: 0.0,
+                max: f64::from(robot::IBB_WIDTH),
+            },
+            y: Range {
+                min: 0.0,
+                max: f64::from(robot::IBB_HEIGHT),
+            },
+        }
+    }
+
+    /// The bounds of the board without a margin along the edges (in mm), e.g.
+    /// where the frame of the board hides the strokes.
+    pub fn drawable(margin: f64) -> Self {
+        let mut bounds = Bounds::board();
+        bounds.add_padding(margin);
+        bounds
+    }
+
+    /// Return whether the bounds overlap (or touch) other bounds.
+    pub(crate) fn intersects(&self, other: &Bounds) -> bool {
+        self.x.min <= other.x.max
+            && other.x.min <= self.x.max
+            && self.y.min <= other.y.max
+            && other.y.min <= self.y.max
+    }
+
+    /// Return the part of the bounds that is within other bounds.
+    pub(crate) fn intersection(&self, other: &Bounds) -> Option<Bounds> {
+        match self.intersects(other) {
+            true => Some(Bounds {
+                x: Range {
+                    min: self.x.min.max(other.x.min),
+                    max: self.x.max.min(other.x.max),
+                },
+                y: Range {
+                    min: self.y.min.max(other.y.min),
+                    max: self.y.max.min(other.y.max),
+                },
+            }),
+            false => None,
+        }
+    }
+
     /// Add padding. Panic if this results in min <= max.
     pub fn add_padding(&mut self, padding: f64) {
         self.x.min += padding;
@@ -32,8 +85,354 @@ impl Bounds {
     }
 }
 
+/// The corner of the board where the origin of the robot is.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Origin {
+    #[default]
+    BottomLeft,
+    TopLeft,
+}
+
+/// The coordinate system of the robot, as seen from the front of the board.
+///
+/// Board coordinates (like SVG coordinates) always have their origin at the
+/// top left, with Y increasing downward. They are converted into robot
+/// coordinates when the commands for the robot are encoded.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct Orientation {
+    #[serde(default)]
+    pub(crate) origin: Origin,
+    /// Whether the Y axis of the robot increases downward. By default, this
+    /// follows from the origin.
+    pub(crate) y_down: Option<bool>,
+}
+
+impl Orientation {
+    fn y_down(&self) -> bool {
+        self.y_down.unwrap_or(self.origin == Origin::TopLeft)
+    }
+
+    /// Check that the Y axis points from the origin into the board.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        match (self.origin, self.y_down()) {
+            (Origin::BottomLeft, false) | (Origin::TopLeft, true) => Ok(()),
+            (Origin::BottomLeft, true) => {
+                Err("With the origin at the bottom left, Y must increase upward".to_string())
+            }
+            (Origin::TopLeft, false) => {
+                Err("With the origin at the top left, Y must increase downward".to_string())
+            }
+        }
+    }
+
+    /// Convert a point in board coordinates (in mm) into robot coordinates
+    /// (in 1/10 mm). Points outside the board are moved to its edge.
+    pub(crate) fn robot_coordinates(self, x: f64, y: f64) -> (u16, u16) {
+        let (width, height) = (f64::from(robot::IBB_WIDTH), f64::from(robot::IBB_HEIGHT));
+        let x = x.clamp(0.0, width);
+        let y = y.clamp(0.0, height);
+        let y = match self.y_down() {
+            true => y,
+            false => height - y,
+        };
+        ((x * 10.0) as u16, (y * 10.0) as u16)
+    }
+}
+
+/// How the two boards of a canvas are mounted.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Layout {
+    /// The first board is on the left, the second one on the right.
+    SideBySide,
+    /// The first board is on top, the second one below.
+    Stacked,
+}
+
+impl Layout {
+    /// The position of the seam between the boards, along the X axis (side
+    /// by side) or the Y axis (stacked).
+    fn seam(self) -> f64 {
+        match self {
+            Layout::SideBySide => f64::from(robot::IBB_WIDTH),
+            Layout::Stacked => f64::from(robot::IBB_HEIGHT),
+        }
+    }
+
+    /// Return the part of a region of the canvas on the first (`index` 0) or
+    /// second board, in the coordinates of that board.
+    pub(crate) fn board_region(self, index: usize, region: &Bounds) -> Option<Bounds> {
+        let offset = index as f64 * self.seam();
+        let mut part = region.clone();
+        match self {
+            Layout::SideBySide => {
+                part.x.min -= offset;
+                part.x.max -= offset;
+            }
+            Layout::Stacked => {
+                part.y.min -= offset;
+                part.y.max -= offset;
+            }
+        }
+        part.intersection(&Bounds::board())
+    }
+
+    /// The coordinate of the point across the seam.
+    fn coordinate(self, point: &CoordinatePair) -> f64 {
+        match self {
+            Layout::SideBySide => point.x,
+            Layout::Stacked => point.y,
+        }
+    }
+}
+
+/// Split polylines in the coordinates of a canvas of two boards at the seam.
+/// Return the parts on the first and on the second board, each in the
+/// coordinates of its board. Strokes crossing the seam are cut at the seam.
+pub(crate) fn split_at_seam(
+    polylines: &[Polyline],
+    layout: Layout,
+) -> (Vec<Polyline>, Vec<Polyline>) {
+    let seam = layout.seam();
+    let mut parts = (vec![], vec![]);
+    let mut finish = |part: Polyline, second: bool| {
+        if part.len() < 2 {
+            return;
+        }
+        match second {
+            false => parts.0.push(part),
+            true => parts.1.push(
+                part.into_iter()
+                    .map(|point| match layout {
+                        Layout::SideBySide => CoordinatePair {
+                            x: point.x - seam,
+                            y: point.y,
+                        },
+                        Layout::Stacked => CoordinatePair {
+                            x: point.x,
+                            y: point.y - seam,
+                        },
+                    })
+                    .collect(),
+            ),
+        }
+    };
+    for polyline in polylines {
+        let mut points = polyline.iter();
+        let mut previous = match points.next() {
+            Some(&point) => point,
+            None => continue,
+        };
+        let mut second = layout.coordinate(&previous) > seam;
+        let mut part = vec![previous];
+        for &point in points {
+            if (layout.coordinate(&point) > seam) != second {
+                // Cut the segment at the seam
+                let (from, to) = (layout.coordinate(&previous), layout.coordinate(&point));
+                let t = (seam - from) / (to - from);
+                let cut = CoordinatePair {
+                    x: previous.x + t * (point.x - previous.x),
+                    y: previous.y + t * (point.y - previous.y),
+                };
+                part.push(cut);
+                finish(std::mem::replace(&mut part, vec![cut]), second);
+                second = !second;
+            }
+            part.push(point);
+            previous = point;
+        }
+        finish(part, second);
+    }
+    parts
+}
+
+/// The most tiles a drawing is split into.
+pub(crate) const MAX_TILES: usize = 24;
+
+/// The length of the arms of the registration marks of tiles, in mm.
+const MARK_SIZE: f64 = 2.0;
+
+/// Clip the segment from `a` to `b` to the bounds (Liang–Barsky). Return the
+/// part within the bounds, if any.
+fn clip_segment(
+    a: CoordinatePair,
+    b: CoordinatePair,
+    bounds: &Bounds,
+) -> Option<(CoordinatePair, CoordinatePair)> {
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    let (mut t0, mut t1) = (0.0_f64, 1.0_f64);
+    for (p, q) in [
+        (-dx, a.x - bounds.x.min),
+        (dx, bounds.x.max - a.x),
+        (-dy, a.y - bounds.y.min),
+        (dy, bounds.y.max - a.y),
+    ] {
+        if p == 0.0 {
+            // Parallel to the edge, and outside of it
+            if q < 0.0 {
+                return None;
+            }
+        } else if p < 0.0 {
+            t0 = t0.max(q / p);
+        } else {
+            t1 = t1.min(q / p);
+        }
+    }
+    if t0 > t1 {
+        return None;
+    }
+    // Keep the end points exact, so that the parts are joined again
+    let at = |t: f64| match t {
+        0.0 => a,
+        1.0 => b,
+        t => CoordinatePair {
+            x: a.x + t * dx,
+            y: a.y + t * dy,
+        },
+    };
+    Some((at(t0), at(t1)))
+}
+
+/// Clip polylines to the bounds. Strokes leaving the bounds are cut at the
+/// edge.
+pub(crate) fn clip_polylines(polylines: &[Polyline], bounds: &Bounds) -> Vec<Polyline> {
+    let mut clipped = vec![];
+    let finish = |clipped: &mut Vec<Polyline>, part: Polyline| {
+        if part.len() >= 2 {
+            clipped.push(part);
+        }
+    };
+    for polyline in polylines {
+        if let [point] = polyline[..] {
+            if clip_segment(point, point, bounds).is_some() {
+                clipped.push(vec![point]);
+            }
+            continue;
+        }
+        let mut part: Polyline = vec![];
+        for segment in polyline.windows(2) {
+            match clip_segment(segment[0], segment[1], bounds) {
+                Some((from, to)) => {
+                    if part.last() != Some(&from) {
+                        finish(&mut clipped, std::mem::replace(&mut part, vec![from]));
+                    }
+                    part.push(to);
+                }
+                None => finish(&mut clipped, std::mem::take(&mut part)),
+            }
+        }
+        finish(&mut clipped, part);
+    }
+    clipped
+}
+
+/// Return the registration marks of tiles: Crosses in the middle of the
+/// overlaps of neighbouring tiles, which appear on both tiles.
+fn registration_marks(
+    bounds: &Bounds,
+    size: (f64, f64),
+    tiles: (usize, usize),
+    overlap: f64,
+) -> Vec<Polyline> {
+    let arm = MARK_SIZE.min(overlap / 2.0);
+    if arm <= 0.0 {
+        return vec![];
+    }
+    let step = (size.0 - overlap, size.1 - overlap);
+    let cross = |x: f64, y: f64| {
+        [
+            vec![
+                CoordinatePair { x: x - arm, y },
+                CoordinatePair { x: x + arm, y },
+            ],
+            vec![
+                CoordinatePair { x, y: y - arm },
+                CoordinatePair { x, y: y + arm },
+            ],
+        ]
+    };
+    let mut marks = vec![];
+    for row in 0..tiles.1 {
+        for column in 0..tiles.0 {
+            let x = bounds.x.min + column as f64 * step.0;
+            let y = bounds.y.min + row as f64 * step.1;
+            if column + 1 < tiles.0 {
+                marks.extend(cross(x + step.0 + overlap / 2.0, y + size.1 / 2.0));
+            }
+            if row + 1 < tiles.1 {
+                marks.extend(cross(x + size.0 / 2.0, y + step.1 + overlap / 2.0));
+            }
+        }
+    }
+    marks
+}
+
+/// Split polylines that don't fit the area into tiles of the size of the
+/// area, e.g. to draw a banner in sections and photograph them. Neighbouring
+/// tiles overlap by `overlap` mm, with registration marks in the overlaps to
+/// align the photos. Return the tiles row by row, each in the coordinates of
+/// the area.
+pub(crate) fn tile_polylines(
+    polylines: &[Polyline],
+    area: &Bounds,
+    overlap: f64,
+) -> Result<Vec<Vec<Polyline>>, String> {
+    let size = (area.x.spread(), area.y.spread());
+    let max_overlap = size.0.min(size.1) / 2.0;
+    if !(0.0..max_overlap).contains(&overlap) {
+        return Err(format!(
+            "The tile overlap must be between 0 and {} mm",
+            max_overlap
+        ));
+    }
+    let Some(bounds) = get_bounds(polylines) else {
+        return Ok(vec![]);
+    };
+    let count =
+        |spread: f64, size: f64| ((spread - size) / (size - overlap)).max(0.0).ceil() as usize + 1;
+    let tiles = (
+        count(bounds.x.spread(), size.0),
+        count(bounds.y.spread(), size.1),
+    );
+    if tiles.0.saturating_mul(tiles.1) > MAX_TILES {
+        return Err(format!(
+            "The drawing would take {}×{} tiles, but at most {} tiles are drawn. Scale it down.",
+            tiles.0, tiles.1, MAX_TILES
+        ));
+    }
+    info!("Splitting polylines into {}×{} tiles", tiles.0, tiles.1);
+
+    let mut all = polylines.to_vec();
+    all.extend(registration_marks(&bounds, size, tiles, overlap));
+    let mut result = vec![];
+    for row in 0..tiles.1 {
+        for column in 0..tiles.0 {
+            let x = bounds.x.min + column as f64 * (size.0 - overlap);
+            let y = bounds.y.min + row as f64 * (size.1 - overlap);
+            let window = Bounds {
+                x: Range {
+                    min: x,
+                    max: x + size.0,
+                },
+                y: Range {
+                    min: y,
+                    max: y + size.1,
+                },
+            };
+            let mut tile = clip_polylines(&all, &window);
+            for coord in tile.iter_mut().flatten() {
+                coord.x += area.x.min - x;
+                coord.y += area.y.min - y;
+            }
+            result.push(tile);
+        }
+    }
+    Ok(result)
+}
+
 /// Get the bounds (maxima / minima) of the specified polylines.
-fn get_bounds(polylines: &Vec<Polyline>) -> Option<Bounds> {
+pub(crate) fn get_bounds(polylines: &[Polyline]) -> Option<Bounds> {
     let mut x_min = None;
     let mut x_max = None;
     let mut y_min = None;
@@ -100,6 +499,106 @@ pub fn scale_polylines(polylines: &mut Vec<Polyline>, offset: (f64, f64), scale:
     }
 }
 
+/// Rotate polylines clockwise (on the board, whose Y axis points down) by the
+/// angle in degrees around the center of their bounds. Other polylines (e.g.
+/// eraser strokes) are rotated around the same center.
+pub(crate) fn rotate_polylines(polylines: &mut [&mut Vec<Polyline>], degrees: f64) {
+    let all: Vec<Polyline> = polylines.iter().flat_map(|p| p.iter().cloned()).collect();
+    let Some(bounds) = get_bounds(&all) else {
+        return;
+    };
+    info!("Rotating polylines by {} degrees", degrees);
+    let center_x = (bounds.x.min + bounds.x.max) / 2.0;
+    let center_y = (bounds.y.min + bounds.y.max) / 2.0;
+    // Quarter turns are exact
+    let (sin, cos) = match degrees.rem_euclid(360.0) {
+        0.0 => (0.0, 1.0),
+        90.0 => (1.0, 0.0),
+        180.0 => (0.0, -1.0),
+        270.0 => (-1.0, 0.0),
+        d => d.to_radians().sin_cos(),
+    };
+    for coord in polylines.iter_mut().flat_map(|p| p.iter_mut()).flatten() {
+        let (x, y) = (coord.x - center_x, coord.y - center_y);
+        coord.x = center_x + x * cos - y * sin;
+        coord.y = center_y + x * sin + y * cos;
+    }
+}
+
+/// Where a drawing is placed within the board, as an alternative to moving it
+/// by offsets. The snake case names (like `top_left`) are accepted as well.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Align {
+    #[serde(alias = "top_left")]
+    TopLeft,
+    Top,
+    #[serde(alias = "top_right")]
+    TopRight,
+    Left,
+    Center,
+    Right,
+    #[serde(alias = "bottom_left")]
+    BottomLeft,
+    Bottom,
+    #[serde(alias = "bottom_right")]
+    BottomRight,
+}
+
+impl Align {
+    /// Return the position along the x and the y axis, from 0 (left or top)
+    /// to 1 (right or bottom).
+    fn position(self) -> (f64, f64) {
+        match self {
+            Align::TopLeft => (0.0, 0.0),
+            Align::Top => (0.5, 0.0),
+            Align::TopRight => (1.0, 0.0),
+            Align::Left => (0.0, 0.5),
+            Align::Center => (0.5, 0.5),
+            Align::Right => (1.0, 0.5),
+            Align::BottomLeft => (0.0, 1.0),
+            Align::Bottom => (0.5, 1.0),
+            Align::BottomRight => (1.0, 1.0),
+        }
+    }
+}
+
+/// Move polylines to the aligned position within the target bounds, without
+/// scaling them. Other polylines (e.g. eraser strokes) are moved along.
+pub(crate) fn align_polylines(polylines: &mut [&mut Vec<Polyline>], align: Align, target: &Bounds) {
+    let all: Vec<Polyline> = polylines.iter().flat_map(|p| p.iter().cloned()).collect();
+    let Some(bounds) = get_bounds(&all) else {
+        return;
+    };
+    info!("Aligning polylines ({:?})", align);
+    let (x, y) = align.position();
+    let dx = target.x.min + (target.x.spread() - bounds.x.spread()) * x - bounds.x.min;
+    let dy = target.y.min + (target.y.spread() - bounds.y.spread()) * y - bounds.y.min;
+    for coord in polylines.iter_mut().flat_map(|p| p.iter_mut()).flatten() {
+        coord.x += dx;
+        coord.y += dy;
+    }
+}
+
+/// Mirror polylines horizontally (`flip_x`) and/or vertically (`flip_y`)
+/// around the center of their bounds, e.g. for boards behind glass. Other
+/// polylines (e.g. eraser strokes) are mirrored around the same center.
+pub(crate) fn flip_polylines(polylines: &mut [&mut Vec<Polyline>], flip_x: bool, flip_y: bool) {
+    let all: Vec<Polyline> = polylines.iter().flat_map(|p| p.iter().cloned()).collect();
+    let Some(bounds) = get_bounds(&all) else {
+        return;
+    };
+    info!("Mirroring polylines (x: {}, y: {})", flip_x, flip_y);
+    for coord in polylines.iter_mut().flat_map(|p| p.iter_mut()).flatten() {
+        if flip_x {
+            coord.x = bounds.x.min + bounds.x.max - coord.x;
+        }
+        if flip_y {
+            coord.y = bounds.y.min + bounds.y.max - coord.y;
+        }
+    }
+}
+
 /// Fit polylines within the specified bounds.
 pub fn fit_polylines(polylines: &mut Vec<Polyline>, target_bounds: &Bounds) -> Result<(), String> {
     info!("Fitting polylines into specified bounds");
@@ -111,7 +610,7 @@ pub fn fit_polylines(polylines: &mut Vec<Polyline>, target_bounds: &Bounds) -> R
     }
 
     // Calculate current bounds
-    let current_bounds = get_bounds(&polylines).ok_or("Could not calculate bounds".to_string())?;
+    let current_bounds = get_bounds(polylines).ok_or("Could not calculate bounds".to_string())?;
 
     // Calculate scale factor
     let x_factor = target_bounds.x.spread() / current_bounds.x.spread();
@@ -144,6 +643,123 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_orientation() {
+        let default = Orientation::default();
+        assert!(default.validate().is_ok());
+        assert_eq!(default.robot_coordinates(12.3, 45.6), (123, 774));
+        assert_eq!(default.robot_coordinates(-1.0, 500.0), (0, 0));
+
+        let top_left = Orientation {
+            origin: Origin::TopLeft,
+            y_down: None,
+        };
+        assert!(top_left.validate().is_ok());
+        assert_eq!(top_left.robot_coordinates(12.3, 45.6), (123, 456));
+
+        let inconsistent = Orientation {
+            origin: Origin::TopLeft,
+            y_down: Some(false),
+        };
+        assert!(inconsistent.validate().is_err());
+    }
+
+    #[test]
+    fn test_rotate_polylines() {
+        let point = |x, y| CoordinatePair { x, y };
+        let mut polylines = vec![vec![point(0.0, 0.0), point(20.0, 0.0)]];
+        let mut eraser = vec![vec![point(20.0, 10.0)]];
+        rotate_polylines(&mut [&mut polylines, &mut eraser], 90.0);
+        // Around the center (10, 5), clockwise
+        assert_eq!(polylines, vec![vec![point(15.0, -5.0), point(15.0, 15.0)]]);
+        assert_eq!(eraser, vec![vec![point(5.0, 15.0)]]);
+
+        rotate_polylines(&mut [&mut polylines, &mut eraser], -90.0);
+        assert_eq!(polylines, vec![vec![point(0.0, 0.0), point(20.0, 0.0)]]);
+        assert_eq!(eraser, vec![vec![point(20.0, 10.0)]]);
+        rotate_polylines(&mut [&mut polylines], 45.0);
+        let (a, b) = (polylines[0][0], polylines[0][1]);
+        assert!(((b.y - a.y).atan2(b.x - a.x).to_degrees() - 45.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_flip_polylines() {
+        let point = |x, y| CoordinatePair { x, y };
+        let mut polylines = vec![vec![point(0.0, 0.0), point(20.0, 5.0)]];
+        let mut eraser = vec![vec![point(2.0, 10.0)]];
+        flip_polylines(&mut [&mut polylines, &mut eraser], true, false);
+        assert_eq!(polylines, vec![vec![point(20.0, 0.0), point(0.0, 5.0)]]);
+        assert_eq!(eraser, vec![vec![point(18.0, 10.0)]]);
+        flip_polylines(&mut [&mut polylines, &mut eraser], false, true);
+        assert_eq!(polylines, vec![vec![point(20.0, 10.0), point(0.0, 5.0)]]);
+        assert_eq!(eraser, vec![vec![point(18.0, 0.0)]]);
+    }
+
+    #[test]
+    fn test_split_at_seam() {
+        let width = f64::from(robot::IBB_WIDTH);
+        let polylines = vec![
+            vec![
+                CoordinatePair { x: 10.0, y: 10.0 },
+                CoordinatePair {
+                    x: width + 10.0,
+                    y: 30.0,
+                },
+                CoordinatePair {
+                    x: width + 20.0,
+                    y: 30.0,
+                },
+            ],
+            vec![
+                CoordinatePair { x: 20.0, y: 20.0 },
+                CoordinatePair { x: 30.0, y: 20.0 },
+            ],
+        ];
+        let (first, second) = split_at_seam(&polylines, Layout::SideBySide);
+        let seam_y = 10.0 + 20.0 * (width - 10.0) / width;
+        assert_eq!(first.len(), 2);
+        assert_eq!(
+            first[0][1],
+            CoordinatePair {
+                x: width,
+                y: seam_y
+            }
+        );
+        assert_eq!(
+            second,
+            vec![vec![
+                CoordinatePair { x: 0.0, y: seam_y },
+                CoordinatePair { x: 10.0, y: 30.0 },
+                CoordinatePair { x: 20.0, y: 30.0 },
+            ]]
+        );
+
+        let (first, second) = split_at_seam(&polylines, Layout::Stacked);
+        assert_eq!(first, polylines);
+        assert!(second.is_empty());
+    }
+
+    #[test]
+    fn test_board_region() {
+        let width = f64::from(robot::IBB_WIDTH);
+        let region = Bounds {
+            x: Range {
+                min: width - 10.0,
+                max: width + 20.0,
+            },
+            y: Range {
+                min: 5.0,
+                max: 15.0,
+            },
+        };
+        let first = Layout::SideBySide.board_region(0, &region).unwrap();
+        assert_eq!((first.x.min, first.x.max), (width - 10.0, width));
+        let second = Layout::SideBySide.board_region(1, &region).unwrap();
+        assert_eq!((second.x.min, second.x.max), (0.0, 20.0));
+        assert_eq!(second.y, region.y);
+        assert!(Layout::Stacked.board_region(1, &region).is_none());
+    }
+
     #[test]
     fn test_get_bounds_empty() {
         let polylines = vec![];
@@ -224,6 +840,102 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_align_polylines() {
+        let point = |x, y| CoordinatePair { x, y };
+        let target = Bounds::drawable(5.0);
+        let aligned = |align| {
+            let mut polylines = vec![vec![point(10.0, 10.0), point(30.0, 20.0)]];
+            let mut eraser = vec![vec![point(20.0, 15.0)]];
+            align_polylines(&mut [&mut polylines, &mut eraser], align, &target);
+            (polylines, eraser)
+        };
+        assert_eq!(
+            aligned(Align::TopLeft).0,
+            vec![vec![point(5.0, 5.0), point(25.0, 15.0)]]
+        );
+        let (polylines, eraser) = aligned(Align::Center);
+        assert_eq!(
+            polylines,
+            vec![vec![point(169.0, 56.5), point(189.0, 66.5)]]
+        );
+        assert_eq!(eraser, vec![vec![point(179.0, 61.5)]]);
+        assert_eq!(
+            aligned(Align::BottomRight).0,
+            vec![vec![point(333.0, 108.0), point(353.0, 118.0)]]
+        );
+
+        let align: Align = serde_json::from_str(r#""top-left""#).unwrap();
+        assert_eq!(align, Align::TopLeft);
+        let align: Align = serde_json::from_str(r#""bottom_right""#).unwrap();
+        assert_eq!(align, Align::BottomRight);
+    }
+
+    #[test]
+    fn test_clip_polylines() {
+        let bounds = Bounds {
+            x: Range {
+                min: 0.0,
+                max: 10.0,
+            },
+            y: Range {
+                min: 0.0,
+                max: 10.0,
+            },
+        };
+        let point = |x, y| CoordinatePair { x, y };
+        // Leaves the bounds and comes back in: Two parts
+        let polylines = vec![
+            vec![
+                point(5.0, 5.0),
+                point(15.0, 5.0),
+                point(15.0, 8.0),
+                point(5.0, 8.0),
+            ],
+            vec![point(20.0, 20.0), point(30.0, 30.0)],
+            vec![point(1.0, 1.0)],
+        ];
+        assert_eq!(
+            clip_polylines(&polylines, &bounds),
+            vec![
+                vec![point(5.0, 5.0), point(10.0, 5.0)],
+                vec![point(10.0, 8.0), point(5.0, 8.0)],
+                vec![point(1.0, 1.0)],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tile_polylines() {
+        let area = Bounds::board();
+        let point = |x, y| CoordinatePair { x, y };
+        // A banner of 700 mm takes two tiles, overlapping by 10 mm
+        let polylines = vec![vec![point(0.0, 0.0), point(700.0, 100.0)]];
+        let tiles = tile_polylines(&polylines, &area, 10.0).unwrap();
+        assert_eq!(tiles.len(), 2);
+        for tile in &tiles {
+            let bounds = get_bounds(tile).unwrap();
+            assert!(bounds.x.min >= 0.0 && bounds.x.max <= 358.0);
+            assert!(bounds.y.min >= 0.0 && bounds.y.max <= 123.0);
+            // The line and the two arms of the registration mark
+            assert_eq!(tile.len(), 3);
+        }
+        assert_eq!(tiles[0][0][0], point(0.0, 0.0));
+        assert_eq!(tiles[1][0][1], point(352.0, 100.0));
+
+        // Drawings that fit take a single tile without marks
+        let small = vec![vec![point(10.0, 10.0), point(20.0, 20.0)]];
+        assert_eq!(
+            tile_polylines(&small, &area, 10.0).unwrap(),
+            vec![vec![vec![point(0.0, 0.0), point(10.0, 10.0)]]]
+        );
+        assert!(tile_polylines(&[], &area, 10.0).unwrap().is_empty());
+
+        let huge = vec![vec![point(0.0, 0.0), point(5000.0, 5000.0)]];
+        assert!(tile_polylines(&huge, &area, 10.0).is_err());
+        assert!(tile_polylines(&small, &area, 100.0).is_err());
+    }
+
     #[test]
     fn test_fit_polylines_single_point() {
         let mut polylines = vec![vec![CoordinatePair { x: 7.0, y: 12.0 }]];
@@ -0,0 +1,444 @@
+//! The scheduled rotation as a single JSON document, which can be exported,
+//! version-controlled and imported on other boards.
+//!
+//! With a state dir, an imported schedule is persisted, so that it is
+//! resumed after a restart (see `save` and `load`).
+//!
+//! Named schedules run alongside the main schedule, each with its own
+//! content, time limits and region of the board (see `NamedSchedule`). They
+//! can be edited at runtime, and are persisted like the imported schedule.
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::warn;
+use serde_derive::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::countdown::CountdownConfig;
+use crate::generate::GeneratorConfig;
+use crate::holidays::Holidays;
+use crate::rotation::Rotation;
+use crate::timelimits::{MissedRuns, TimeLimits};
+use crate::{EraseRegion, Region};
+
+pub(crate) type SharedSchedules = Arc<Mutex<Schedules>>;
+pub(crate) type SharedNamedSchedules = Arc<Mutex<Vec<NamedSchedule>>>;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct Schedules {
+    /// Interval between two items of the rotation.
+    pub(crate) interval_seconds: u64,
+    /// Every item is drawn up to this many seconds earlier or later, at
+    /// random.
+    #[serde(default)]
+    pub(crate) jitter_seconds: u64,
+    #[serde(default)]
+    pub(crate) time_limits: Option<TimeLimits>,
+    /// SVG files from the SVG dir, in the order they are drawn.
+    #[serde(default)]
+    pub(crate) playlist: Vec<String>,
+    /// A countdown drawn after the playlist.
+    #[serde(default)]
+    pub(crate) countdown: Option<CountdownConfig>,
+    /// Generators drawn after the countdown.
+    #[serde(default)]
+    pub(crate) generators: Vec<GeneratorConfig>,
+    /// The order in which the items are drawn.
+    #[serde(default)]
+    pub(crate) rotation: Rotation,
+    /// Weights of the items for the weighted rotation, by item name (1 if
+    /// missing).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) weights: BTreeMap<String, u32>,
+}
+
+impl Schedules {
+    pub(crate) fn into_shared(self) -> SharedSchedules {
+        Arc::new(Mutex::new(self))
+    }
+
+    /// Return the number of items in the rotation.
+    pub(crate) fn len(&self) -> usize {
+        self.playlist.len() + self.generators.len() + usize::from(self.countdown.is_some())
+    }
+
+    /// Return the names of the items, in the order of the playlist.
+    pub(crate) fn item_names(&self) -> Vec<String> {
+        let mut names = self.playlist.clone();
+        if self.countdown.is_some() {
+            names.push("Countdown".to_string());
+        }
+        names.extend(self.generators.iter().map(|g| g.name().to_string()));
+        names
+    }
+
+    /// Return the weight of an item for the weighted rotation.
+    pub(crate) fn weight(&self, name: &str) -> u32 {
+        self.weights.get(name).copied().unwrap_or(1)
+    }
+
+    /// Check that the schedule can be started.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.interval_seconds == 0 {
+            return Err("The interval must be positive".to_string());
+        }
+        if self.jitter_seconds * 2 >= self.interval_seconds {
+            return Err("The jitter must be less than half of the interval".to_string());
+        }
+        if self.len() == 0 {
+            return Err("The schedule must contain at least one item".to_string());
+        }
+        let names = self.item_names();
+        if let Some(name) = self.weights.keys().find(|name| !names.contains(name)) {
+            return Err(format!("Weight for unknown item: {}", name));
+        }
+        if self.rotation == Rotation::Weighted && names.iter().all(|name| self.weight(name) == 0) {
+            return Err("At least one item must have a positive weight".to_string());
+        }
+        // Only allow plain file names, to stay within the SVG dir
+        for name in &self.playlist {
+            if Path::new(name).file_name().and_then(|n| n.to_str()) != Some(name.as_str()) {
+                return Err(format!("Invalid file name in playlist: {}", name));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A schedule that runs alongside the main schedule, e.g. a clock in a
+/// corner of the board.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct NamedSchedule {
+    /// The name, which is taken from the path when editing the schedule.
+    #[serde(default)]
+    pub(crate) name: String,
+    /// The region of the board the items are drawn into, by name or in
+    /// board coordinates. Only this region is erased before drawing. The
+    /// whole board if unset.
+    #[serde(default)]
+    pub(crate) region: Option<Region>,
+    /// The interval, content and time limits. Without time limits, the
+    /// global time limits apply.
+    #[serde(flatten)]
+    pub(crate) schedule: Schedules,
+}
+
+/// Check that the named schedules can be started.
+pub(crate) fn validate_named(
+    named: &[NamedSchedule],
+    regions: &BTreeMap<String, EraseRegion>,
+) -> Result<(), String> {
+    for (i, named_schedule) in named.iter().enumerate() {
+        let name = &named_schedule.name;
+        if name.is_empty() {
+            return Err("Named schedules must have a name".to_string());
+        }
+        if named[..i].iter().any(|other| &other.name == name) {
+            return Err(format!("Duplicate schedule name: {}", name));
+        }
+        if let Some(region) = &named_schedule.region {
+            region
+                .bounds(regions)
+                .map_err(|e| format!("{}: {}", name, e))?;
+        }
+        named_schedule
+            .schedule
+            .validate()
+            .map_err(|e| format!("{}: {}", name, e))?;
+    }
+    Ok(())
+}
+
+/// Persist the schedule (or the named schedules).
+pub(crate) fn save<T: serde::Serialize>(path: &Path, schedules: &T) {
+    match serde_json::to_string(schedules) {
+        Ok(json) => {
+            if let Err(e) = fs::write(path, json) {
+                warn!("Could not write {}: {}", path.display(), e);
+            }
+        }
+        Err(e) => warn!("Could not serialize the schedule: {}", e),
+    }
+}
+
+/// Load a persisted schedule, if there is a valid one.
+pub(crate) fn load(path: &Path) -> Option<Schedules> {
+    let json = fs::read_to_string(path).ok()?;
+    match serde_json::from_str::<Schedules>(&json)
+        .map_err(|e| e.to_string())
+        .and_then(|schedules| schedules.validate().map(|()| schedules))
+    {
+        Ok(schedules) => Some(schedules),
+        Err(e) => {
+            warn!("Ignoring the schedule in {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Load persisted named schedules, if they are valid.
+pub(crate) fn load_named(
+    path: &Path,
+    regions: &BTreeMap<String, EraseRegion>,
+) -> Option<Vec<NamedSchedule>> {
+    let json = fs::read_to_string(path).ok()?;
+    match serde_json::from_str::<Vec<NamedSchedule>>(&json)
+        .map_err(|e| e.to_string())
+        .and_then(|named| validate_named(&named, regions).map(|()| named))
+    {
+        Ok(named) => Some(named),
+        Err(e) => {
+            warn!("Ignoring the named schedules in {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// How far ahead the next run within the time limits is searched.
+const MAX_LOOKAHEAD: Duration = Duration::from_secs(7 * 24 * 3600);
+
+/// Return when the next item is drawn, i.e. the first cycle from `next` on
+/// that lies within the time limits and not on a holiday (other cycles are
+/// skipped),
+/// after skipping `skip` more cycles within the time limits. If missed runs
+/// are caught up, a cycle outside of the time limits is drawn when they
+/// begin instead. Return it with the number of skipped cycles, or `None` if
+/// there is no such cycle within the next week.
+pub(crate) fn next_run(
+    next: OffsetDateTime,
+    interval: Duration,
+    time_limits: Option<&TimeLimits>,
+    holidays: &Holidays,
+    mut skip: u32,
+) -> Option<(OffsetDateTime, u32)> {
+    let mut time = next;
+    let mut skipped = 0;
+    while time - next <= MAX_LOOKAHEAD {
+        match time_limits {
+            _ if holidays.is_holiday(time.date()) => {}
+            Some(limits) if !limits.is_within_limits(&time.time()) => {
+                let start = limits.next_start(time);
+                if limits.missed_runs() == MissedRuns::CatchUp && !holidays.is_holiday(start.date())
+                {
+                    return Some((start, skipped));
+                }
+            }
+            _ if skip == 0 => return Some((time, skipped)),
+            _ => skip -= 1,
+        }
+        time += interval;
+        skipped += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedules(json: &str) -> Schedules {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let json = r#"{
+            "interval_seconds": 900,
+            "time_limits": {"start_time": "06:00", "end_time": "00:30"},
+            "playlist": ["b.svg", "a.svg"],
+            "generators": [{"generator": "maze", "seed": 1}],
+            "countdown": {"date": "2026-12-24", "label": "launch"}
+        }"#;
+        let parsed = schedules(json);
+        assert_eq!(parsed.len(), 4);
+        let exported = serde_json::to_string(&parsed).unwrap();
+        let reimported = schedules(&exported);
+        assert_eq!(reimported.playlist, vec!["b.svg", "a.svg"]);
+        assert_eq!(reimported.generators, parsed.generators);
+        assert_eq!(serde_json::to_string(&reimported).unwrap(), exported);
+    }
+
+    #[test]
+    fn test_validate_named() {
+        let named = |json: &str| serde_json::from_str::<Vec<NamedSchedule>>(json).unwrap();
+        let clock = named(
+            r#"[{
+                "name": "clock",
+                "interval_seconds": 300,
+                "generators": [{"generator": "maze"}],
+                "region": {"x": 300, "y": 0, "width": 58, "height": 40}
+            }]"#,
+        );
+        assert!(validate_named(&clock, &BTreeMap::new()).is_ok());
+        assert_eq!(clock[0].schedule.len(), 1);
+
+        let mut duplicate = clock.clone();
+        duplicate.extend(clock.clone());
+        assert!(validate_named(&duplicate, &BTreeMap::new()).is_err());
+        let empty_region = named(
+            r#"[{"name": "a", "interval_seconds": 60, "playlist": ["a.svg"],
+                 "region": {"x": 0, "y": 0, "width": 0, "height": 10}}]"#,
+        );
+        assert!(validate_named(&empty_region, &BTreeMap::new()).is_err());
+        let no_items = named(r#"[{"name": "a", "interval_seconds": 60}]"#);
+        assert!(validate_named(&no_items, &BTreeMap::new()).is_err());
+
+        // Regions of the config are referenced by name
+        let notes = named(
+            r#"[{"name": "a", "interval_seconds": 60, "playlist": ["a.svg"],
+                 "region": "notes"}]"#,
+        );
+        let regions: BTreeMap<String, EraseRegion> =
+            serde_json::from_str(r#"{"notes": {"x": 0, "y": 40, "width": 100, "height": 60}}"#)
+                .unwrap();
+        assert!(validate_named(&notes, &regions).is_ok());
+        assert!(validate_named(&notes, &BTreeMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("ibb-schedules-{}", std::process::id()));
+        let parsed = schedules(r#"{"interval_seconds": 900, "playlist": ["a.svg"]}"#);
+        save(&path, &parsed);
+        assert_eq!(load(&path).unwrap().playlist, vec!["a.svg"]);
+        fs::write(&path, r#"{"interval_seconds": 0}"#).unwrap();
+        assert!(load(&path).is_none());
+        fs::remove_file(&path).unwrap();
+        assert!(load(&path).is_none());
+
+        let named: Vec<NamedSchedule> = serde_json::from_str(
+            r#"[{"name": "menu", "interval_seconds": 900, "playlist": ["a.svg"]}]"#,
+        )
+        .unwrap();
+        save(&path, &named);
+        assert_eq!(load_named(&path, &BTreeMap::new()).unwrap()[0].name, "menu");
+        save(&path, &[named[0].clone(), named[0].clone()]);
+        assert!(load_named(&path, &BTreeMap::new()).is_none());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(
+            schedules(r#"{"interval_seconds": 60, "playlist": ["a.svg"]}"#)
+                .validate()
+                .is_ok()
+        );
+        assert!(schedules(r#"{"interval_seconds": 60}"#).validate().is_err());
+        assert!(schedules(
+            r#"{"interval_seconds": 600, "jitter_seconds": 300, "playlist": ["a.svg"]}"#
+        )
+        .validate()
+        .is_err());
+        assert!(
+            schedules(r#"{"interval_seconds": 0, "playlist": ["a.svg"]}"#)
+                .validate()
+                .is_err()
+        );
+        assert!(schedules(
+            r#"{"interval_seconds": 60, "playlist": ["a.svg"], "weights": {"b.svg": 2}}"#
+        )
+        .validate()
+        .is_err());
+        assert!(schedules(
+            r#"{"interval_seconds": 60, "playlist": ["a.svg"], "rotation": "weighted", "weights": {"a.svg": 0}}"#
+        )
+        .validate()
+        .is_err());
+        assert!(
+            schedules(r#"{"interval_seconds": 60, "playlist": ["../secret.svg"]}"#)
+                .validate()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_next_run() {
+        use time::macros::datetime;
+
+        use crate::holidays::HolidayConfig;
+
+        let limits: TimeLimits =
+            serde_json::from_str(r#"{"start_time": "06:00", "end_time": "20:00"}"#).unwrap();
+        let none = Holidays::default();
+        let hour = Duration::from_secs(3600);
+        let next = datetime!(2026-10-17 18:30 UTC);
+        assert_eq!(next_run(next, hour, None, &none, 0), Some((next, 0)));
+        assert_eq!(
+            next_run(next, hour, Some(&limits), &none, 0),
+            Some((next, 0))
+        );
+        // 20:30 to 05:30 are skipped
+        assert_eq!(
+            next_run(
+                datetime!(2026-10-17 20:30 UTC),
+                hour,
+                Some(&limits),
+                &none,
+                0
+            ),
+            Some((datetime!(2026-10-18 06:30 UTC), 10))
+        );
+        // Skipping the 18:30 and 19:30 cycles on request
+        assert_eq!(
+            next_run(next, hour, Some(&limits), &none, 2),
+            Some((datetime!(2026-10-18 06:30 UTC), 12))
+        );
+        // With a daily interval, the cycle is always outside of the limits
+        let day = Duration::from_secs(24 * 3600);
+        assert_eq!(
+            next_run(
+                datetime!(2026-10-17 22:00 UTC),
+                day,
+                Some(&limits),
+                &none,
+                0
+            ),
+            None
+        );
+
+        // Missed runs are caught up when the time limits begin
+        let limits: TimeLimits = serde_json::from_str(
+            r#"{"start_time": "06:00", "end_time": "20:00", "missed_runs": "catch_up"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            next_run(
+                datetime!(2026-10-17 22:00 UTC),
+                day,
+                Some(&limits),
+                &none,
+                0
+            ),
+            Some((datetime!(2026-10-18 06:00 UTC), 0))
+        );
+        assert_eq!(
+            next_run(next, hour, Some(&limits), &none, 2),
+            Some((datetime!(2026-10-18 06:00 UTC), 2))
+        );
+
+        // Holidays are skipped, also when catching up
+        let holidays = |date: &str| {
+            let config: HolidayConfig =
+                serde_json::from_str(&format!(r#"{{"dates": ["{}"]}}"#, date)).unwrap();
+            Holidays::new_shared(Some(&config))
+        };
+        assert_eq!(
+            next_run(next, day, None, &holidays("2026-10-17"), 0),
+            Some((datetime!(2026-10-18 18:30 UTC), 1))
+        );
+        assert_eq!(
+            next_run(
+                datetime!(2026-10-17 22:00 UTC),
+                day,
+                Some(&limits),
+                &holidays("2026-10-18"),
+                0
+            ),
+            Some((datetime!(2026-10-20 06:00 UTC), 2))
+        );
+    }
+}
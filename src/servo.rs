@@ -0,0 +1,102 @@
+//! Calibration of the pen servo.
+//!
+//! Without calibration, the firmware moves the pen servo to its built-in
+//! positions. Pen holders that don't fit these positions are calibrated with
+//! `/robot/calibrate-pen/`: Every request moves the servo to a new position,
+//! and the final positions are stored in the `pen_servo` config key. The pen
+//! commands then carry these positions.
+use serde_derive::{Deserialize, Serialize};
+
+/// The largest servo position that fits into a command.
+pub(crate) const MAX_POSITION: u16 = 0x0fff;
+
+/// A position of the pen.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum PenPosition {
+    /// The pen is lifted off the board.
+    Lift,
+    /// The pen touches the board.
+    Down,
+}
+
+/// Calibrated servo positions (the firmware default if unset).
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct PenServo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) lift: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) down: Option<u16>,
+}
+
+impl PenServo {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        for value in [self.lift, self.down].into_iter().flatten() {
+            if value == 0 || value > MAX_POSITION {
+                return Err(format!(
+                    "Servo positions must be between 1 and {}",
+                    MAX_POSITION
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the calibrated servo position (0 for the firmware default).
+    pub(crate) fn position(&self, position: PenPosition) -> u16 {
+        match position {
+            PenPosition::Lift => self.lift,
+            PenPosition::Down => self.down,
+        }
+        .unwrap_or(0)
+    }
+
+    /// Set a servo position to `value`, or change it by `step`. Stepping
+    /// requires a calibrated position to start from.
+    pub(crate) fn adjust(
+        &mut self,
+        position: PenPosition,
+        value: Option<u16>,
+        step: i16,
+    ) -> Result<u16, String> {
+        let current = match position {
+            PenPosition::Lift => &mut self.lift,
+            PenPosition::Down => &mut self.down,
+        };
+        let start = value.or(*current).ok_or_else(|| {
+            "The position isn't calibrated yet, so a start value is required".to_string()
+        })?;
+        let new = i32::from(start) + i32::from(step);
+        if new < 1 || new > i32::from(MAX_POSITION) {
+            return Err(format!(
+                "Servo positions must be between 1 and {}",
+                MAX_POSITION
+            ));
+        }
+        *current = Some(new as u16);
+        Ok(new as u16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adjust() {
+        let mut servo = PenServo::default();
+        assert_eq!(servo.position(PenPosition::Down), 0);
+        assert!(servo.adjust(PenPosition::Down, None, 10).is_err());
+        assert_eq!(servo.adjust(PenPosition::Down, Some(1200), 0), Ok(1200));
+        assert_eq!(servo.adjust(PenPosition::Down, None, -50), Ok(1150));
+        assert_eq!(servo.position(PenPosition::Down), 1150);
+        assert_eq!(servo.lift, None);
+
+        // Out of range values don't change the position
+        assert!(servo.adjust(PenPosition::Down, Some(4090), 10).is_err());
+        assert_eq!(servo.down, Some(1150));
+        assert!(servo.validate().is_ok());
+        servo.lift = Some(5000);
+        assert!(servo.validate().is_err());
+    }
+}
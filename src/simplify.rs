@@ -0,0 +1,109 @@
+//! Simplification of polylines with the Ramer–Douglas–Peucker algorithm.
+//!
+//! Dense SVG exports contain thousands of points without any visual benefit:
+//! They bloat the serial traffic and make the pen stop more often. Points
+//! that are closer than the tolerance (in mm) to the simplified polyline are
+//! dropped. The end points of every polyline are always kept.
+use svg2polylines::{CoordinatePair, Polyline};
+
+/// Return the distance of the point from the line through `a` and `b` (or
+/// from `a`, if both are the same).
+fn distance_to_line(point: &CoordinatePair, a: &CoordinatePair, b: &CoordinatePair) -> f64 {
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    let length = dx.hypot(dy);
+    if length == 0.0 {
+        return (point.x - a.x).hypot(point.y - a.y);
+    }
+    (dy * point.x - dx * point.y + b.x * a.y - b.y * a.x).abs() / length
+}
+
+/// Simplify a single polyline.
+fn simplify_polyline(polyline: &[CoordinatePair], tolerance: f64) -> Polyline {
+    if polyline.len() < 3 {
+        return polyline.to_vec();
+    }
+    let mut keep = vec![false; polyline.len()];
+    keep[0] = true;
+    keep[polyline.len() - 1] = true;
+    // Ranges (first, last) whose inner points are still to be checked
+    let mut ranges = vec![(0, polyline.len() - 1)];
+    while let Some((first, last)) = ranges.pop() {
+        let farthest = (first + 1..last)
+            .map(|i| {
+                let distance = distance_to_line(&polyline[i], &polyline[first], &polyline[last]);
+                (i, distance)
+            })
+            .fold(None, |best: Option<(usize, f64)>, candidate| match best {
+                Some(best) if best.1 >= candidate.1 => Some(best),
+                _ => Some(candidate),
+            });
+        if let Some((i, distance)) = farthest {
+            if distance > tolerance {
+                keep[i] = true;
+                ranges.push((first, i));
+                ranges.push((i, last));
+            }
+        }
+    }
+    polyline
+        .iter()
+        .zip(keep)
+        .filter(|(_, keep)| *keep)
+        .map(|(point, _)| *point)
+        .collect()
+}
+
+/// Simplify the polylines with the tolerance in mm.
+pub(crate) fn simplify(polylines: Vec<Polyline>, tolerance: f64) -> Vec<Polyline> {
+    polylines
+        .iter()
+        .map(|polyline| simplify_polyline(polyline, tolerance))
+        .collect()
+}
+
+/// Check that the tolerance can be used.
+pub(crate) fn validate(tolerance: f64) -> Result<(), String> {
+    match tolerance.is_finite() && tolerance >= 0.0 {
+        true => Ok(()),
+        false => Err("The simplification tolerance must not be negative".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::polyline;
+
+    #[test]
+    fn test_simplify() {
+        // A straight line with a slight wobble and a corner
+        let line = polyline(&[
+            (0.0, 0.0),
+            (1.0, 0.05),
+            (2.0, -0.05),
+            (3.0, 0.0),
+            (3.0, 1.0),
+            (3.0, 2.0),
+        ]);
+        assert_eq!(
+            simplify(vec![line.clone()], 0.1),
+            vec![polyline(&[(0.0, 0.0), (3.0, 0.0), (3.0, 2.0)])]
+        );
+        // The wobble is kept with a smaller tolerance
+        assert_eq!(simplify(vec![line.clone()], 0.01)[0].len(), 5);
+        assert_eq!(simplify(vec![line.clone()], 0.0)[0].len(), 5);
+
+        // Short and closed polylines keep their end points
+        let point = polyline(&[(1.0, 1.0)]);
+        assert_eq!(simplify(vec![point.clone()], 1.0), vec![point]);
+        let closed = polyline(&[(0.0, 0.0), (2.0, 0.0), (2.0, 2.0), (0.0, 0.0)]);
+        assert_eq!(simplify(vec![closed.clone()], 0.5), vec![closed]);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(0.2).is_ok());
+        assert!(validate(-1.0).is_err());
+        assert!(validate(f64::NAN).is_err());
+    }
+}
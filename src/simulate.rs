@@ -0,0 +1,190 @@
+//! Simulation of the drawing duration of command blocks.
+//!
+//! The simulator decodes the blocks sent to the robot and models the
+//! physical behavior of the robot: Moves are accelerated and decelerated with
+//! a trapezoidal velocity profile, the servo needs some time to lift or lower
+//! the pen and erase passes run at a reduced speed.
+use std::time::Duration;
+
+use serde_derive::{Deserialize, Serialize};
+
+/// Physical parameters of the robot, used for the simulation.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub(crate) struct SimulationConfig {
+    /// Maximum speed when drawing or travelling, in mm/s.
+    #[serde(default = "default_max_speed")]
+    pub(crate) max_speed: f64,
+    /// Maximum speed when erasing, in mm/s.
+    #[serde(default = "default_erase_speed")]
+    pub(crate) erase_speed: f64,
+    /// Acceleration, in mm/s².
+    #[serde(default = "default_acceleration")]
+    pub(crate) acceleration: f64,
+    /// Time it takes the servo to lift or lower the pen or eraser, in seconds.
+    #[serde(default = "default_servo_delay")]
+    pub(crate) servo_delay: f64,
+}
+
+fn default_max_speed() -> f64 {
+    60.0
+}
+
+fn default_erase_speed() -> f64 {
+    40.0
+}
+
+fn default_acceleration() -> f64 {
+    250.0
+}
+
+fn default_servo_delay() -> f64 {
+    0.25
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        SimulationConfig {
+            max_speed: default_max_speed(),
+            erase_speed: default_erase_speed(),
+            acceleration: default_acceleration(),
+            servo_delay: default_servo_delay(),
+        }
+    }
+}
+
+/// The tool that is currently lowered onto the board.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Tool {
+    None,
+    Pen,
+    Eraser,
+}
+
+/// Stateful simulator. The state (position and tool) is kept between
+/// blocks, so the blocks of a job can be fed one after another.
+#[derive(Debug, Clone)]
+pub(crate) struct Simulator {
+    config: SimulationConfig,
+    /// Current position in mm.
+    position: (f64, f64),
+    tool: Tool,
+    /// Simulated time in seconds.
+    elapsed: f64,
+}
+
+impl Simulator {
+    pub(crate) fn new(config: SimulationConfig) -> Self {
+        Simulator {
+            config,
+            position: (0.0, 0.0),
+            tool: Tool::None,
+            elapsed: 0.0,
+        }
+    }
+
+    /// Return the total simulated duration.
+    pub(crate) fn elapsed(&self) -> Duration {
+        Duration::from_secs_f64(self.elapsed)
+    }
+
+    /// Duration of a straight move, using a trapezoidal velocity profile
+    /// (or a triangular one for short moves).
+    fn move_duration(&self, distance: f64, max_speed: f64) -> f64 {
+        let acceleration = self.config.acceleration;
+        if distance <= 0.0 || max_speed <= 0.0 || acceleration <= 0.0 {
+            return 0.0;
+        }
+        if distance >= max_speed * max_speed / acceleration {
+            distance / max_speed + max_speed / acceleration
+        } else {
+            2.0 * (distance / acceleration).sqrt()
+        }
+    }
+
+    fn set_tool(&mut self, tool: Tool) {
+        if self.tool != tool {
+            self.elapsed += self.config.servo_delay;
+            self.tool = tool;
+        }
+    }
+
+    /// Simulate a single 3 byte command.
+    fn command(&mut self, bytes: [u8; 3]) {
+        match bytes {
+            // The pen commands may carry a servo position in the low bits
+            [0xfa, code, _] if code >> 4 == 0x3 => self.set_tool(Tool::None),
+            [0xfa, code, _] if code >> 4 == 0x4 => self.set_tool(Tool::Pen),
+            [0xfa, 0x50, _] => self.set_tool(Tool::Eraser),
+            [0xfa, 0x60, seconds] => self.elapsed += f64::from(seconds),
+            [0xfa, _, _] => {} // Block start / number, start / stop drawing
+            [b0, b1, b2] => {
+                let x = f64::from((u16::from(b0) << 4) | (u16::from(b1) >> 4)) / 10.0;
+                let y = f64::from((u16::from(b1 & 0x0f) << 8) | u16::from(b2)) / 10.0;
+                let distance = (x - self.position.0).hypot(y - self.position.1);
+                let speed = match self.tool {
+                    Tool::Eraser => self.config.erase_speed,
+                    _ => self.config.max_speed,
+                };
+                self.elapsed += self.move_duration(distance, speed);
+                self.position = (x, y);
+            }
+        }
+    }
+
+    /// Simulate a block of commands.
+    pub(crate) fn feed(&mut self, block: &[u8]) {
+        for chunk in block.chunks_exact(3) {
+            self.command([chunk[0], chunk[1], chunk[2]]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> SimulationConfig {
+        SimulationConfig {
+            max_speed: 50.0,
+            erase_speed: 25.0,
+            acceleration: 100.0,
+            servo_delay: 0.5,
+        }
+    }
+
+    #[test]
+    fn test_move_duration() {
+        let sim = Simulator::new(config());
+        // Long move: 100 mm at 50 mm/s plus 0.5 s for accelerating and braking
+        assert_eq!(sim.move_duration(100.0, 50.0), 2.5);
+        // Short move: Never reaches the maximum speed
+        assert_eq!(sim.move_duration(4.0, 50.0), 0.4);
+        assert_eq!(sim.move_duration(0.0, 50.0), 0.0);
+    }
+
+    #[test]
+    fn test_feed() {
+        let mut sim = Simulator::new(config());
+        sim.feed(&[
+            0xfa, 0x9f, 0xa1, // Block start
+            0xfa, 0x90, 0x01, // Block number 1
+            0x3e, 0x80, 0x00, // Move to 100,0
+            0xfa, 0x40, 0x00, // Pen down
+            0x3e, 0x80, 0x00, // Move to 100,0 (no-op)
+            0xfa, 0x44, 0xb0, // Pen down to 1200 (already down)
+            0xfa, 0x60, 0x02, // Wait 2 seconds
+        ]);
+        assert_eq!(sim.elapsed(), Duration::from_secs_f64(2.5 + 0.5 + 2.0));
+    }
+
+    #[test]
+    fn test_erase_is_slower() {
+        let mut sim = Simulator::new(config());
+        sim.feed(&[
+            0xfa, 0x50, 0x00, // Enable eraser
+            0x3e, 0x80, 0x00, // Move to 100,0
+        ]);
+        // Servo delay plus 100 mm at 25 mm/s plus 0.25 s for accelerating and braking
+        assert_eq!(sim.elapsed(), Duration::from_secs_f64(0.5 + 4.25));
+    }
+}
@@ -0,0 +1,78 @@
+//! Drawing urgent jobs slowly outside of the time limits.
+//!
+//! Outside of the time limits (the quiet hours), single prints are rejected.
+//! With a slow mode window covering the current time, jobs marked as urgent
+//! are accepted anyway, but drawn at the (reduced) speed of the window, which
+//! keeps the motors quieter. The firmware has no setting for the motor
+//! current, so only the speed is reduced. Urgent jobs still have to be
+//! finished within the window.
+use serde_derive::{Deserialize, Serialize};
+use time::Time;
+
+use crate::speed::Speed;
+use crate::timelimits::TimeLimits;
+
+fn default_speed() -> Speed {
+    Speed::Slow
+}
+
+#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+pub(crate) struct SlowWindow {
+    /// When urgent jobs are drawn in slow mode.
+    #[serde(flatten)]
+    pub(crate) window: TimeLimits,
+    /// The speed of urgent jobs within the window.
+    #[serde(default = "default_speed")]
+    pub(crate) speed: Speed,
+}
+
+/// Return the slow mode window that applies to an urgent job at the given
+/// time, i.e. the first window covering it if it is outside of the time
+/// limits.
+pub(crate) fn active<'a>(
+    windows: &'a [SlowWindow],
+    time_limits: Option<&TimeLimits>,
+    time: &Time,
+) -> Option<&'a SlowWindow> {
+    if time_limits.is_none_or(|limits| limits.is_within_limits(time)) {
+        return None;
+    }
+    windows
+        .iter()
+        .find(|window| window.window.is_within_limits(time))
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::time;
+
+    use super::*;
+
+    fn limits(json: &str) -> TimeLimits {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_active() {
+        let windows: Vec<SlowWindow> = serde_json::from_str(
+            r#"[
+                {"start_time": "20:00", "end_time": "22:00"},
+                {"start_time": "06:00", "end_time": "07:00", "speed": "normal"}
+            ]"#,
+        )
+        .unwrap();
+        let limits = limits(r#"{"start_time": "07:00", "end_time": "20:00"}"#);
+
+        // Within the time limits, jobs are drawn normally
+        assert!(active(&windows, Some(&limits), &time!(12:00)).is_none());
+        assert!(active(&windows, None, &time!(21:00)).is_none());
+
+        let window = active(&windows, Some(&limits), &time!(21:00)).unwrap();
+        assert_eq!(window.speed, Speed::Slow);
+        let window = active(&windows, Some(&limits), &time!(6:30)).unwrap();
+        assert_eq!(window.speed, Speed::Normal);
+
+        // Outside of all windows, urgent jobs are rejected as well
+        assert!(active(&windows, Some(&limits), &time!(23:00)).is_none());
+    }
+}
@@ -0,0 +1,170 @@
+//! Drawing speed.
+//!
+//! The firmware has no speed setting: It accelerates at the start of every
+//! move and decelerates at its end. The pace is therefore controlled by the
+//! density of the points. At the `slow` speed, long segments are split into
+//! short moves, so that the pen never gets up to full speed (e.g. for
+//! delicate drawings). At the `fast` speed, points that are very close to
+//! each other are dropped, so that the pen stops less often (e.g. for clock
+//! updates).
+//!
+//! Independent of the speed, a `min_point_spacing` caps the density of the
+//! points: SVG files with thousands of near-duplicate points on a single
+//! curve would otherwise flood the serial link and the buffer of the
+//! firmware.
+use serde_derive::{Deserialize, Serialize};
+use svg2polylines::{CoordinatePair, Polyline};
+
+/// Maximum length of a move at the slow speed, in mm.
+const SLOW_MAX_SEGMENT: f64 = 5.0;
+
+/// Minimum length of a move at the fast speed, in mm.
+const FAST_MIN_SEGMENT: f64 = 1.0;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Speed {
+    Slow,
+    #[default]
+    Normal,
+    Fast,
+}
+
+fn distance(a: &CoordinatePair, b: &CoordinatePair) -> f64 {
+    (b.x - a.x).hypot(b.y - a.y)
+}
+
+/// Split the segments of the polyline into moves of at most `max` mm.
+fn subdivide(polyline: &[CoordinatePair], max: f64) -> Polyline {
+    let mut result: Polyline = polyline.iter().take(1).copied().collect();
+    for segment in polyline.windows(2) {
+        let (a, b) = (segment[0], segment[1]);
+        let steps = (distance(&a, &b) / max).ceil().max(1.0) as usize;
+        for step in 1..=steps {
+            let t = step as f64 / steps as f64;
+            result.push(CoordinatePair {
+                x: a.x + (b.x - a.x) * t,
+                y: a.y + (b.y - a.y) * t,
+            });
+        }
+    }
+    result
+}
+
+/// Drop the points that are closer than `min` mm to the previous point. The
+/// end points are always kept.
+fn thin_out(polyline: &[CoordinatePair], min: f64) -> Polyline {
+    let mut result: Polyline = vec![];
+    for (i, point) in polyline.iter().enumerate() {
+        let is_last = i + 1 == polyline.len();
+        match result.last() {
+            Some(last) if !is_last && distance(last, point) < min => {}
+            _ => result.push(*point),
+        }
+    }
+    result
+}
+
+impl Speed {
+    /// Adjust the density of the points to the speed.
+    pub(crate) fn apply(self, polylines: Vec<Polyline>) -> Vec<Polyline> {
+        match self {
+            Speed::Slow => polylines
+                .iter()
+                .map(|polyline| subdivide(polyline, SLOW_MAX_SEGMENT))
+                .collect(),
+            Speed::Normal => polylines,
+            Speed::Fast => polylines
+                .iter()
+                .map(|polyline| thin_out(polyline, FAST_MIN_SEGMENT))
+                .collect(),
+        }
+    }
+}
+
+/// Drop the points that are closer than `min_spacing` mm to the previous
+/// point. The end points are always kept, instead of the last point before
+/// them if necessary.
+pub(crate) fn resample(polylines: Vec<Polyline>, min_spacing: f64) -> Vec<Polyline> {
+    polylines
+        .iter()
+        .map(|polyline| {
+            let mut result = thin_out(polyline, min_spacing);
+            let len = result.len();
+            if len > 2 && distance(&result[len - 2], &result[len - 1]) < min_spacing {
+                result.remove(len - 2);
+            }
+            result
+        })
+        .collect()
+}
+
+/// Check that the minimum spacing of the points can be used.
+pub(crate) fn validate_spacing(min_spacing: f64) -> Result<(), String> {
+    match min_spacing.is_finite() && min_spacing >= 0.0 {
+        true => Ok(()),
+        false => Err("The minimum point spacing must not be negative".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::polyline;
+
+    #[test]
+    fn test_slow() {
+        let polylines = vec![polyline(&[(0.0, 0.0), (12.0, 0.0), (12.0, 3.0)])];
+        assert_eq!(
+            Speed::Slow.apply(polylines),
+            vec![polyline(&[
+                (0.0, 0.0),
+                (4.0, 0.0),
+                (8.0, 0.0),
+                (12.0, 0.0),
+                (12.0, 3.0)
+            ])]
+        );
+    }
+
+    #[test]
+    fn test_fast() {
+        let polylines = vec![polyline(&[
+            (0.0, 0.0),
+            (0.5, 0.0),
+            (1.2, 0.0),
+            (1.5, 0.0),
+            (1.6, 0.0),
+        ])];
+        assert_eq!(
+            Speed::Fast.apply(polylines.clone()),
+            vec![polyline(&[(0.0, 0.0), (1.2, 0.0), (1.6, 0.0)])]
+        );
+        assert_eq!(Speed::Normal.apply(polylines.clone()), polylines);
+    }
+
+    #[test]
+    fn test_resample() {
+        // A curve with near-duplicate points
+        let polylines = vec![polyline(&[
+            (0.0, 0.0),
+            (0.01, 0.0),
+            (0.02, 0.0),
+            (0.3, 0.0),
+            (0.31, 0.0),
+            (0.6, 0.0),
+            (0.65, 0.0),
+        ])];
+        assert_eq!(
+            resample(polylines.clone(), 0.25),
+            vec![polyline(&[(0.0, 0.0), (0.3, 0.0), (0.65, 0.0)])]
+        );
+        assert_eq!(resample(polylines.clone(), 0.0), polylines);
+        let point = vec![polyline(&[(1.0, 1.0)])];
+        assert_eq!(resample(point.clone(), 1.0), point);
+
+        assert!(validate_spacing(0.1).is_ok());
+        assert!(validate_spacing(-0.1).is_err());
+        assert!(validate_spacing(f64::NAN).is_err());
+    }
+}
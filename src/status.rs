@@ -0,0 +1,469 @@
+//! Current state of the robot, for companion displays and scripts.
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::error;
+use serde_derive::Serialize;
+use time::OffsetDateTime;
+
+use crate::firmware::Firmware;
+
+/// Number of recent errors that are kept.
+const MAX_ERRORS: usize = 5;
+
+pub(crate) type SharedStatus = Arc<Mutex<Status>>;
+
+#[derive(Debug, Serialize, Clone)]
+pub(crate) struct ErrorRecord {
+    #[serde(with = "time::serde::rfc3339")]
+    time: OffsetDateTime,
+    message: String,
+}
+
+/// What the robot is doing, as returned by `/robot/status/`.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, Default)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub(crate) enum RobotState {
+    #[default]
+    Idle,
+    Drawing {
+        label: String,
+    },
+    /// Erasing the board (or a region of it) before drawing.
+    Erasing {
+        label: String,
+    },
+    /// Idle until the next item of the schedule is drawn.
+    WaitingForSchedule,
+    /// The robot did not acknowledge a block in time, which was sent again
+    /// this many times.
+    Stalled {
+        block: u32,
+        retries: u32,
+    },
+    /// The robot is disconnected.
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Debug)]
+pub(crate) struct Status {
+    /// Label of the item that is currently being drawn.
+    drawing: Option<String>,
+    /// Whether the robot is erasing before drawing the current item.
+    erasing: bool,
+    /// Why the robot is not working (e.g. it is disconnected).
+    robot_error: Option<String>,
+    /// The block that the robot did not acknowledge in time, and how often
+    /// it was sent again.
+    stall: Option<(u32, u32)>,
+    /// The current state of the robot and when it was entered.
+    state: RobotState,
+    since: OffsetDateTime,
+    /// Label of the item that will be drawn next.
+    next_item: Option<String>,
+    /// When the next item will be drawn.
+    next_time: Option<OffsetDateTime>,
+    /// The interval of the running schedule.
+    interval: Option<Duration>,
+    /// Names of the items of the current schedule (e.g. the SVG file names
+    /// in headless mode). Unnamed items are labelled by their position.
+    item_names: Vec<String>,
+    errors: VecDeque<ErrorRecord>,
+    /// Remaining length of the paper roll (paper roll variant only).
+    paper_remaining_mm: Option<u32>,
+    /// The firmware of the robot, once it was identified.
+    firmware: Option<Firmware>,
+    /// Since when the robot is offline, i.e. reconnecting failed for too
+    /// long and only previews are available.
+    offline_since: Option<OffsetDateTime>,
+    /// Whether the cycles of the schedule are skipped.
+    schedule_paused: bool,
+    /// The number of upcoming cycles of the schedule that are skipped.
+    skip_cycles: u32,
+}
+
+/// Compact status with a stable schema, as returned by `/kiosk.json`.
+#[derive(Debug, Serialize)]
+pub(crate) struct KioskStatus<'a> {
+    board: &'a str,
+    drawing: Option<&'a str>,
+    next_item: Option<&'a str>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    next_time: Option<OffsetDateTime>,
+    errors: &'a VecDeque<ErrorRecord>,
+}
+
+/// State of the robot, as returned by `/robot/status/`.
+#[derive(Debug, Serialize)]
+pub(crate) struct RobotStatus<'a> {
+    board: &'a str,
+    #[serde(flatten)]
+    state: &'a RobotState,
+    #[serde(with = "time::serde::rfc3339")]
+    since: OffsetDateTime,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    paper_remaining_mm: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    firmware: Option<&'a Firmware>,
+    #[serde(
+        with = "time::serde::rfc3339::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    offline_since: Option<OffsetDateTime>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    schedule_paused: bool,
+    #[serde(skip_serializing_if = "is_zero")]
+    skip_cycles: u32,
+}
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+fn now() -> OffsetDateTime {
+    OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc())
+}
+
+/// Lock the shared status and update it.
+pub(crate) fn update<F: FnOnce(&mut Status)>(status: &SharedStatus, f: F) {
+    match status.lock() {
+        Ok(mut status) => f(&mut status),
+        Err(e) => error!("Could not unlock status mutex: {}", e),
+    }
+}
+
+impl Default for Status {
+    fn default() -> Self {
+        Status {
+            drawing: None,
+            erasing: false,
+            robot_error: None,
+            stall: None,
+            state: RobotState::Idle,
+            since: now(),
+            next_item: None,
+            next_time: None,
+            interval: None,
+            item_names: vec![],
+            errors: VecDeque::new(),
+            paper_remaining_mm: None,
+            firmware: None,
+            offline_since: None,
+            schedule_paused: false,
+            skip_cycles: 0,
+        }
+    }
+}
+
+impl Status {
+    pub(crate) fn new_shared() -> SharedStatus {
+        Arc::new(Mutex::new(Status::default()))
+    }
+
+    pub(crate) fn set_item_names(&mut self, names: Vec<String>) {
+        self.item_names = names;
+    }
+
+    /// Forget the item names if they don't belong to a schedule with the
+    /// specified number of items.
+    pub(crate) fn check_item_names(&mut self, items: usize) {
+        if self.item_names.len() != items {
+            self.item_names.clear();
+        }
+    }
+
+    pub(crate) fn item_label(&self, index: usize) -> String {
+        self.item_names
+            .get(index)
+            .cloned()
+            .unwrap_or_else(|| format!("Item {}", index + 1))
+    }
+
+    /// Update the state of the robot, remembering when it changed.
+    fn update_state(&mut self) {
+        let state = match (&self.robot_error, &self.drawing) {
+            (Some(message), _) => RobotState::Error {
+                message: message.clone(),
+            },
+            (None, _) if self.stall.is_some() => {
+                let (block, retries) = self.stall.unwrap_or_default();
+                RobotState::Stalled { block, retries }
+            }
+            (None, Some(label)) if self.erasing => RobotState::Erasing {
+                label: label.clone(),
+            },
+            (None, Some(label)) => RobotState::Drawing {
+                label: label.clone(),
+            },
+            (None, None) if self.next_time.is_some() => RobotState::WaitingForSchedule,
+            (None, None) => RobotState::Idle,
+        };
+        if state != self.state {
+            self.state = state;
+            self.since = now();
+        }
+    }
+
+    pub(crate) fn set_paper_remaining(&mut self, remaining_mm: Option<u32>) {
+        self.paper_remaining_mm = remaining_mm;
+    }
+
+    pub(crate) fn firmware(&self) -> Option<&Firmware> {
+        self.firmware.as_ref()
+    }
+
+    pub(crate) fn set_firmware(&mut self, firmware: Firmware) {
+        self.firmware = Some(firmware);
+    }
+
+    /// Return the label of the item that is currently being drawn.
+    /// Return whether the robot is offline, so that only previews are
+    /// available.
+    pub(crate) fn offline(&self) -> bool {
+        self.offline_since.is_some()
+    }
+
+    pub(crate) fn set_offline(&mut self, offline: bool) {
+        self.offline_since = match offline {
+            true => self.offline_since.or_else(|| Some(now())),
+            false => None,
+        };
+    }
+
+    pub(crate) fn schedule_paused(&self) -> bool {
+        self.schedule_paused
+    }
+
+    pub(crate) fn set_schedule_paused(&mut self, paused: bool) {
+        self.schedule_paused = paused;
+    }
+
+    pub(crate) fn skip_cycles(&self) -> u32 {
+        self.skip_cycles
+    }
+
+    /// Skip one more upcoming cycle of the schedule.
+    pub(crate) fn skip_cycle(&mut self) {
+        self.skip_cycles += 1;
+    }
+
+    /// Return whether the current cycle of the schedule is skipped, and
+    /// count it.
+    pub(crate) fn take_skip(&mut self) -> bool {
+        let skip = self.skip_cycles > 0;
+        self.skip_cycles = self.skip_cycles.saturating_sub(1);
+        skip
+    }
+
+    pub(crate) fn drawing(&self) -> Option<&str> {
+        self.drawing.as_deref()
+    }
+
+    pub(crate) fn start_drawing(&mut self, label: String) {
+        self.drawing = Some(label);
+        self.erasing = false;
+        self.update_state();
+    }
+
+    /// Start drawing an item that begins with erasing the board.
+    pub(crate) fn start_erasing(&mut self, label: String) {
+        self.drawing = Some(label);
+        self.erasing = true;
+        self.update_state();
+    }
+
+    /// The robot has finished erasing and draws the current item.
+    pub(crate) fn finish_erasing(&mut self) {
+        if self.erasing {
+            self.erasing = false;
+            self.update_state();
+        }
+    }
+
+    pub(crate) fn finish_drawing(&mut self) {
+        self.drawing = None;
+        self.erasing = false;
+        self.update_state();
+    }
+
+    /// Set or clear the reason why the robot is not working.
+    /// Return why the robot is not working, if it isn't.
+    pub(crate) fn robot_error(&self) -> Option<&str> {
+        self.robot_error.as_deref()
+    }
+
+    pub(crate) fn set_robot_error(&mut self, message: Option<String>) {
+        self.robot_error = message;
+        self.update_state();
+    }
+
+    /// Set the block that the robot did not acknowledge in time and how
+    /// often it was sent again, or clear it once the robot responded.
+    pub(crate) fn set_stall(&mut self, stall: Option<(u32, u32)>) {
+        self.stall = stall;
+        self.update_state();
+    }
+
+    pub(crate) fn set_next(&mut self, item: Option<String>, time: Option<OffsetDateTime>) {
+        self.next_item = item;
+        self.next_time = time;
+        self.update_state();
+    }
+
+    /// Return the next item of the schedule, when it is due and the interval
+    /// of the schedule, if one is running.
+    pub(crate) fn next(&self) -> Option<(Option<&str>, OffsetDateTime, Duration)> {
+        Some((self.next_item.as_deref(), self.next_time?, self.interval?))
+    }
+
+    pub(crate) fn set_interval(&mut self, interval: Option<Duration>) {
+        self.interval = interval;
+    }
+
+    /// Draw the next item later than planned.
+    pub(crate) fn postpone(&mut self, time: OffsetDateTime) {
+        self.next_time = Some(time);
+    }
+
+    pub(crate) fn add_error(&mut self, message: String) {
+        if self.errors.len() >= MAX_ERRORS {
+            self.errors.pop_front();
+        }
+        self.errors.push_back(ErrorRecord {
+            time: now(),
+            message,
+        });
+    }
+
+    pub(crate) fn robot<'a>(&'a self, board: &'a str) -> RobotStatus<'a> {
+        RobotStatus {
+            board,
+            state: &self.state,
+            since: self.since,
+            paper_remaining_mm: self.paper_remaining_mm,
+            firmware: self.firmware.as_ref(),
+            offline_since: self.offline_since,
+            schedule_paused: self.schedule_paused,
+            skip_cycles: self.skip_cycles,
+        }
+    }
+
+    pub(crate) fn kiosk<'a>(&'a self, board: &'a str) -> KioskStatus<'a> {
+        KioskStatus {
+            board,
+            drawing: self.drawing.as_deref(),
+            next_item: self.next_item.as_deref(),
+            next_time: self.next_time,
+            errors: &self.errors,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_item_label() {
+        let mut status = Status::default();
+        assert_eq!(status.item_label(1), "Item 2");
+        status.set_item_names(vec!["a.svg".into(), "b.svg".into()]);
+        assert_eq!(status.item_label(1), "b.svg");
+        status.check_item_names(2);
+        assert_eq!(status.item_label(1), "b.svg");
+        status.check_item_names(1);
+        assert_eq!(status.item_label(0), "Item 1");
+    }
+
+    #[test]
+    fn test_offline() {
+        let mut status = Status::default();
+        assert!(!status.offline());
+        status.set_offline(true);
+        let since = status.offline_since;
+        status.set_offline(true);
+        assert!(status.offline());
+        assert_eq!(status.offline_since, since);
+        status.set_offline(false);
+        assert!(!status.offline());
+    }
+
+    #[test]
+    fn test_skip_cycles() {
+        let mut status = Status::default();
+        assert!(!status.take_skip());
+        status.skip_cycle();
+        status.skip_cycle();
+        assert!(status.take_skip());
+        assert!(status.take_skip());
+        assert!(!status.take_skip());
+    }
+
+    #[test]
+    fn test_errors_are_limited() {
+        let mut status = Status::default();
+        for i in 0..(MAX_ERRORS + 2) {
+            status.add_error(format!("Error {}", i));
+        }
+        assert_eq!(status.errors.len(), MAX_ERRORS);
+        assert_eq!(status.errors[0].message, "Error 2");
+    }
+
+    #[test]
+    fn test_robot_state() {
+        let mut status = Status::default();
+        assert_eq!(status.state, RobotState::Idle);
+        status.set_next(Some("a.svg".into()), Some(now()));
+        assert_eq!(status.state, RobotState::WaitingForSchedule);
+
+        status.start_erasing("a.svg".into());
+        let label = "a.svg".to_string();
+        assert_eq!(status.state, RobotState::Erasing { label });
+        status.finish_erasing();
+        let label = "a.svg".to_string();
+        assert_eq!(status.state, RobotState::Drawing { label });
+
+        let message = "Robot disconnected".to_string();
+        status.set_robot_error(Some(message.clone()));
+        assert_eq!(status.state, RobotState::Error { message });
+        status.set_robot_error(None);
+        status.set_stall(Some((12, 1)));
+        assert_eq!(
+            status.state,
+            RobotState::Stalled {
+                block: 12,
+                retries: 1
+            }
+        );
+        let json = serde_json::to_value(status.robot("Lobby")).unwrap();
+        assert_eq!(json["state"], "stalled");
+        assert_eq!(json["block"], 12);
+        status.set_stall(None);
+        status.finish_drawing();
+        assert_eq!(status.state, RobotState::WaitingForSchedule);
+
+        let json = serde_json::to_value(status.robot("Lobby")).unwrap();
+        assert_eq!(json["state"], "waiting_for_schedule");
+        assert_eq!(json["board"], "Lobby");
+    }
+
+    #[test]
+    fn test_kiosk_schema() {
+        let mut status = Status::default();
+        status.start_drawing("a.svg".into());
+        let json = serde_json::to_value(status.kiosk("Lobby")).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "board": "Lobby",
+                "drawing": "a.svg",
+                "next_item": null,
+                "next_time": null,
+                "errors": [],
+            })
+        );
+    }
+}
@@ -0,0 +1,778 @@
+//! Parsing of SVG files into polylines.
+//!
+//! `svg2polylines` ignores `<text>` elements, so before parsing, every text
+//! element is replaced with a `<path>` that draws the text using the built-in
+//! stroke font (see the `text` module).
+//!
+//! SVG files can be split into layers: groups (`<g>`) identified by their
+//! Inkscape layer name (`inkscape:label`) or their `id`. Parsing can be
+//! restricted to some of the layers.
+//!
+//! Animations are drawn frame by frame: Every top-level layer is a frame.
+//! Frames are timed by the `begin` of a SMIL animation (`<set>`,
+//! `<animate>`) within them or by the `animation-delay` of a CSS rule for
+//! their id. If every frame is timed, they are ordered by time, otherwise in
+//! document order.
+//!
+//! The physical size of an SVG follows from the `width` and `height` of its
+//! root element (in `mm`, `cm`, `in`, `pt`, `pc` or `px`) and its `viewBox`,
+//! which maps them to user units. This allows drawing it at its native size.
+use std::io::Cursor;
+use std::time::Duration;
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, Writer};
+use svg2polylines::Polyline;
+
+use crate::text;
+
+/// Default font size of SVG text in user units.
+const DEFAULT_FONT_SIZE: f64 = 16.0;
+
+/// Cap height of a typical font, relative to the font size.
+const CAP_HEIGHT: f64 = 0.7;
+
+/// The size of a pixel in mm (96 pixels per inch, like in CSS).
+const MM_PER_PX: f64 = 25.4 / 96.0;
+
+/// Parse an SVG string into polylines, including the text elements.
+pub(crate) fn parse(svg: &str, tolerance: f64) -> Result<Vec<Polyline>, String> {
+    let svg = replace_text(svg)?;
+    svg2polylines::parse(&svg, tolerance)
+}
+
+/// Parse the specified layers of an SVG string into polylines. If no layers
+/// are specified, the whole SVG is parsed.
+pub(crate) fn parse_layers(
+    svg: &str,
+    tolerance: f64,
+    layers: &[String],
+) -> Result<Vec<Polyline>, String> {
+    if layers.is_empty() {
+        return parse(svg, tolerance);
+    }
+    parse(&select_layers(svg, layers)?, tolerance)
+}
+
+/// Parse an SVG string into polylines, leaving out the specified layers.
+pub(crate) fn parse_without_layers(
+    svg: &str,
+    tolerance: f64,
+    layers: &[String],
+) -> Result<Vec<Polyline>, String> {
+    parse(&remove_layers(svg, layers)?, tolerance)
+}
+
+/// Return whether the element is a group with one of the layer names.
+fn is_layer(element: &BytesStart, layers: &[String]) -> bool {
+    element.local_name() == b"g"
+        && element.attributes().flatten().any(|attribute| {
+            matches!(attribute.key, b"inkscape:label" | b"id")
+                && layers
+                    .iter()
+                    .any(|layer| layer.as_bytes() == &*attribute.value)
+        })
+}
+
+/// Remove all elements from the SVG that are not part of one of the layers.
+/// Other groups are kept (without their content), so that their transforms
+/// still apply to nested layers.
+fn select_layers(svg: &str, layers: &[String]) -> Result<String, String> {
+    let mut reader = Reader::from_str(svg);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    let mut buf = Vec::new();
+
+    let mut depth = 0;
+    // The depth of the selected layer that is currently read
+    let mut selected: Option<usize> = None;
+    // The depth of the element that is currently skipped
+    let mut skipped: Option<usize> = None;
+    let mut found = false;
+
+    loop {
+        let event = reader
+            .read_event(&mut buf)
+            .map_err(|e| format!("Could not parse SVG: {}", e))?;
+        let keep = match event {
+            Event::Eof => break,
+            Event::Start(ref e) => {
+                depth += 1;
+                if skipped.is_some() {
+                    false
+                } else if selected.is_some() {
+                    true
+                } else if is_layer(e, layers) {
+                    selected = Some(depth);
+                    found = true;
+                    true
+                } else if matches!(e.local_name(), b"g" | b"svg") {
+                    true
+                } else {
+                    skipped = Some(depth);
+                    false
+                }
+            }
+            Event::End(_) => {
+                let keep = skipped.is_none();
+                if skipped == Some(depth) {
+                    skipped = None;
+                }
+                if selected == Some(depth) {
+                    selected = None;
+                }
+                depth -= 1;
+                keep
+            }
+            Event::Decl(_) => true,
+            _ => selected.is_some() && skipped.is_none(),
+        };
+        if keep {
+            writer
+                .write_event(event)
+                .map_err(|e| format!("Could not write SVG: {}", e))?;
+        }
+        buf.clear();
+    }
+
+    if !found {
+        return Err(format!("SVG has none of the layers {}", layers.join(", ")));
+    }
+    String::from_utf8(writer.into_inner().into_inner())
+        .map_err(|e| format!("Invalid UTF-8 in SVG: {}", e))
+}
+
+/// Remove the layers from the SVG, keeping everything else.
+fn remove_layers(svg: &str, layers: &[String]) -> Result<String, String> {
+    let mut reader = Reader::from_str(svg);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    let mut buf = Vec::new();
+
+    let mut depth = 0;
+    // The depth of the layer that is currently skipped
+    let mut skipped: Option<usize> = None;
+
+    loop {
+        let event = reader
+            .read_event(&mut buf)
+            .map_err(|e| format!("Could not parse SVG: {}", e))?;
+        let keep = match event {
+            Event::Eof => break,
+            Event::Start(ref e) => {
+                depth += 1;
+                if skipped.is_none() && is_layer(e, layers) {
+                    skipped = Some(depth);
+                }
+                skipped.is_none()
+            }
+            Event::End(_) => {
+                let keep = skipped.is_none();
+                if skipped == Some(depth) {
+                    skipped = None;
+                }
+                depth -= 1;
+                keep
+            }
+            Event::Empty(ref e) => skipped.is_none() && !is_layer(e, layers),
+            _ => skipped.is_none(),
+        };
+        if keep {
+            writer
+                .write_event(event)
+                .map_err(|e| format!("Could not write SVG: {}", e))?;
+        }
+        buf.clear();
+    }
+
+    String::from_utf8(writer.into_inner().into_inner())
+        .map_err(|e| format!("Invalid UTF-8 in SVG: {}", e))
+}
+
+/// A frame of an animated SVG.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Frame {
+    /// The layer name of the frame.
+    pub(crate) name: String,
+    /// How long the frame is shown before the next one, if timed.
+    pub(crate) hold: Option<Duration>,
+}
+
+/// Parse a SMIL or CSS clock value (e.g. `2s`, `500ms` or `1.5`) into
+/// seconds. Only the first of several values is used, and event based
+/// values (e.g. `frame1.end`) are ignored.
+fn parse_clock(value: &str) -> Option<f64> {
+    let value = value.split(';').next()?.trim();
+    let (number, factor) = if let Some(number) = value.strip_suffix("ms") {
+        (number, 0.001)
+    } else if let Some(number) = value.strip_suffix("min") {
+        (number, 60.0)
+    } else if let Some(number) = value.strip_suffix('h') {
+        (number, 3600.0)
+    } else {
+        (value.strip_suffix('s').unwrap_or(value), 1.0)
+    };
+    number
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|seconds| seconds.is_finite() && *seconds >= 0.0)
+        .map(|seconds| seconds * factor)
+}
+
+/// Return the `animation-delay` of the CSS rules for element ids, in
+/// seconds.
+fn css_delays(css: &str) -> Vec<(String, f64)> {
+    css.split('}')
+        .filter_map(|rule| {
+            let (selectors, declarations) = rule.split_once('{')?;
+            let delay = style_property(declarations, "animation-delay").and_then(parse_clock)?;
+            Some((selectors, delay))
+        })
+        .flat_map(|(selectors, delay)| {
+            selectors
+                .split(',')
+                .filter_map(|selector| selector.trim().strip_prefix('#'))
+                .map(move |id| (id.to_string(), delay))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Return the frames of an animated SVG, in the order in which they are
+/// shown. Frames without a layer name can't be selected, and are ignored.
+pub(crate) fn frames(svg: &str) -> Result<Vec<Frame>, String> {
+    let mut reader = Reader::from_str(svg);
+    let mut buf = Vec::new();
+
+    let mut depth = 0;
+    // Name, id and begin (in seconds) of every frame
+    let mut frames: Vec<(String, Option<String>, Option<f64>)> = vec![];
+    let mut in_frame = false;
+    let mut in_style = false;
+    let mut css = String::new();
+
+    loop {
+        let event = reader
+            .read_event(&mut buf)
+            .map_err(|e| format!("Could not parse SVG: {}", e))?;
+        match event {
+            Event::Eof => break,
+            Event::Start(ref e) | Event::Empty(ref e) => {
+                let is_start = matches!(event, Event::Start(_));
+                let attribute = |key: &[u8]| {
+                    e.attributes()
+                        .flatten()
+                        .find(|attribute| attribute.key == key)
+                        .map(|attribute| String::from_utf8_lossy(&attribute.value).into_owned())
+                };
+                if depth == 1 && is_start && e.local_name() == b"g" {
+                    let id = attribute(b"id");
+                    if let Some(name) = attribute(b"inkscape:label").or_else(|| id.clone()) {
+                        frames.push((name, id, None));
+                        in_frame = true;
+                    }
+                } else if in_frame && matches!(e.local_name(), b"set" | b"animate") {
+                    let begin = attribute(b"begin").as_deref().and_then(parse_clock);
+                    if let Some(frame) = frames.last_mut().filter(|frame| frame.2.is_none()) {
+                        frame.2 = begin;
+                    }
+                }
+                if is_start {
+                    depth += 1;
+                    in_style = e.local_name() == b"style";
+                }
+            }
+            Event::End(_) => {
+                depth -= 1;
+                in_style = false;
+                if depth == 1 {
+                    in_frame = false;
+                }
+            }
+            Event::Text(ref e) if in_style => css.push_str(&String::from_utf8_lossy(e)),
+            Event::CData(ref e) if in_style => css.push_str(&String::from_utf8_lossy(e)),
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    for (id, delay) in css_delays(&css) {
+        for frame in frames.iter_mut() {
+            if frame.1.as_deref() == Some(id.as_str()) && frame.2.is_none() {
+                frame.2 = Some(delay);
+            }
+        }
+    }
+    if frames.len() < 2 {
+        return Err("SVG has no frames (top-level groups with an id or label)".to_string());
+    }
+    if frames.iter().all(|frame| frame.2.is_some()) {
+        frames.sort_by(|a, b| a.2.partial_cmp(&b.2).expect("Clock values are finite"));
+    }
+    let begins: Vec<Option<f64>> = frames.iter().map(|frame| frame.2).collect();
+    Ok(frames
+        .into_iter()
+        .enumerate()
+        .map(|(i, (name, _, begin))| {
+            let next = begins.get(i + 1).copied().flatten();
+            let hold = match (begin, next) {
+                (Some(begin), Some(next)) if next > begin => {
+                    Some(Duration::from_secs_f64(next - begin))
+                }
+                _ => None,
+            };
+            Frame { name, hold }
+        })
+        .collect())
+}
+
+/// The presentation attributes of a `<text>` element that are relevant for
+/// rendering it.
+#[derive(Debug, PartialEq)]
+struct TextStyle {
+    x: f64,
+    y: f64,
+    font_size: f64,
+    anchor: String,
+    transform: Option<String>,
+}
+
+/// Parse the first number of a coordinate or length attribute (e.g. `12px`
+/// or `10 20 30`).
+fn parse_number(value: &str) -> Option<f64> {
+    let value = value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .find(|v| !v.is_empty())?;
+    value.trim_end_matches("px").parse().ok()
+}
+
+/// Return the value of a property in a `style` attribute.
+fn style_property<'a>(style: &'a str, property: &str) -> Option<&'a str> {
+    style.split(';').find_map(|declaration| {
+        let (name, value) = declaration.split_once(':')?;
+        if name.trim() == property {
+            Some(value.trim())
+        } else {
+            None
+        }
+    })
+}
+
+impl TextStyle {
+    fn from_element(element: &BytesStart) -> Result<Self, String> {
+        let mut style = TextStyle {
+            x: 0.0,
+            y: 0.0,
+            font_size: DEFAULT_FONT_SIZE,
+            anchor: "start".to_string(),
+            transform: None,
+        };
+        let mut style_attribute = None;
+        for attribute in element.attributes() {
+            let attribute = attribute.map_err(|e| format!("Invalid SVG attribute: {}", e))?;
+            let value = String::from_utf8_lossy(
+                &attribute
+                    .unescaped_value()
+                    .map_err(|e| format!("Invalid SVG attribute value: {}", e))?,
+            )
+            .into_owned();
+            match attribute.key {
+                b"x" => style.x = parse_number(&value).unwrap_or(0.0),
+                b"y" => style.y = parse_number(&value).unwrap_or(0.0),
+                b"font-size" => style.font_size = parse_number(&value).unwrap_or(DEFAULT_FONT_SIZE),
+                b"text-anchor" => style.anchor = value,
+                b"transform" => style.transform = Some(value),
+                b"style" => style_attribute = Some(value),
+                _ => {}
+            }
+        }
+        // The style attribute takes precedence over presentation attributes
+        if let Some(css) = style_attribute {
+            if let Some(size) = style_property(&css, "font-size").and_then(parse_number) {
+                style.font_size = size;
+            }
+            if let Some(anchor) = style_property(&css, "text-anchor") {
+                style.anchor = anchor.to_string();
+            }
+        }
+        Ok(style)
+    }
+
+    /// Return the path data for the text, rendered with the stroke font.
+    fn path_data(&self, content: &str) -> String {
+        let size = self.font_size * CAP_HEIGHT;
+        let width = text::line_width(content, size);
+        let left = match self.anchor.as_str() {
+            "middle" => self.x - width / 2.0,
+            "end" => self.x - width,
+            _ => self.x,
+        };
+        // The y coordinate of SVG text specifies the baseline
+        text::render_line(content, left, self.y - size, size)
+            .iter()
+            .filter(|polyline| !polyline.is_empty())
+            .map(|polyline| {
+                polyline
+                    .iter()
+                    .enumerate()
+                    .map(|(i, point)| {
+                        format!("{}{},{}", if i == 0 { 'M' } else { 'L' }, point.x, point.y)
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Replace all `<text>` elements in the SVG with equivalent `<path>`
+/// elements. Nested `<tspan>` elements are drawn as part of their parent.
+pub(crate) fn replace_text(svg: &str) -> Result<String, String> {
+    let mut reader = Reader::from_str(svg);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    let mut buf = Vec::new();
+
+    // The style and content of the text element that is currently read
+    let mut current: Option<(TextStyle, String)> = None;
+    let mut depth = 0;
+
+    loop {
+        let event = reader
+            .read_event(&mut buf)
+            .map_err(|e| format!("Could not parse SVG: {}", e))?;
+        match (&mut current, event) {
+            (_, Event::Eof) => break,
+            (None, Event::Start(ref e)) if e.local_name() == b"text" => {
+                current = Some((TextStyle::from_element(e)?, String::new()));
+                depth = 1;
+            }
+            (None, Event::Empty(ref e)) if e.local_name() == b"text" => {}
+            (None, event) => writer
+                .write_event(event)
+                .map_err(|e| format!("Could not write SVG: {}", e))?,
+            (Some((_, content)), Event::Text(ref e)) => {
+                let unescaped = e
+                    .unescaped()
+                    .map_err(|e| format!("Invalid SVG text: {}", e))?;
+                content.push_str(&String::from_utf8_lossy(&unescaped));
+            }
+            (Some((_, content)), Event::CData(e)) => {
+                content.push_str(&String::from_utf8_lossy(&e.into_inner()));
+            }
+            (Some(_), Event::Start(_)) => depth += 1,
+            (Some(_), Event::End(_)) => {
+                depth -= 1;
+                if depth == 0 {
+                    let (style, content) = current.take().expect("Text element must be set");
+                    let content = content.split_whitespace().collect::<Vec<_>>().join(" ");
+                    let mut path = BytesStart::borrowed_name(b"path");
+                    path.push_attribute(("d", style.path_data(&content).as_str()));
+                    path.push_attribute(("fill", "none"));
+                    path.push_attribute(("stroke", "black"));
+                    if let Some(ref transform) = style.transform {
+                        path.push_attribute(("transform", transform.as_str()));
+                    }
+                    writer
+                        .write_event(Event::Empty(path))
+                        .map_err(|e| format!("Could not write SVG: {}", e))?;
+                }
+            }
+            (Some(_), _) => {}
+        }
+        buf.clear();
+    }
+
+    String::from_utf8(writer.into_inner().into_inner())
+        .map_err(|e| format!("Invalid UTF-8 in SVG: {}", e))
+}
+
+/// Parse a length with a unit (e.g. `210mm` or `8.5in`) into mm. Lengths
+/// without a unit are in pixels.
+fn parse_length(value: &str) -> Result<f64, String> {
+    let value = value.trim();
+    let number = value.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let factor = match &value[number.len()..] {
+        "" | "px" => MM_PER_PX,
+        "mm" => 1.0,
+        "cm" => 10.0,
+        "in" => 25.4,
+        "pt" => 25.4 / 72.0,
+        "pc" => 25.4 / 6.0,
+        unit => return Err(format!("Unsupported SVG unit: {}", unit)),
+    };
+    match number.trim().parse::<f64>() {
+        Ok(length) if length.is_finite() && length > 0.0 => Ok(length * factor),
+        _ => Err(format!("Invalid SVG length: {}", value)),
+    }
+}
+
+/// How the user units of an SVG map to mm.
+#[derive(Debug, PartialEq)]
+pub(crate) struct NativeSize {
+    /// The origin of the `viewBox`, in user units.
+    pub(crate) origin: (f64, f64),
+    /// The size of a user unit in mm, horizontally and vertically.
+    pub(crate) scale: (f64, f64),
+}
+
+/// Return the native size of the SVG from the `width`, `height` and
+/// `viewBox` of its root element. Without a `viewBox`, user units are
+/// pixels. Sizes in percent are ignored, like missing ones.
+pub(crate) fn native_size(svg: &str) -> Result<NativeSize, String> {
+    let mut reader = Reader::from_str(svg);
+    let mut buf = Vec::new();
+    let root = loop {
+        match reader
+            .read_event(&mut buf)
+            .map_err(|e| format!("Could not parse SVG: {}", e))?
+        {
+            Event::Start(e) | Event::Empty(e) if e.local_name() == b"svg" => break e.into_owned(),
+            Event::Start(_) | Event::Empty(_) | Event::Eof => {
+                return Err("The SVG has no svg root element".to_string())
+            }
+            _ => {}
+        }
+        buf.clear();
+    };
+    let attribute = |key: &[u8]| {
+        root.attributes()
+            .flatten()
+            .find(|attribute| attribute.key == key)
+            .map(|attribute| String::from_utf8_lossy(&attribute.value).trim().to_string())
+            .filter(|value| !value.is_empty() && !value.ends_with('%'))
+    };
+    let width = attribute(b"width").map(|w| parse_length(&w)).transpose()?;
+    let height = attribute(b"height").map(|h| parse_length(&h)).transpose()?;
+    let Some(view_box) = attribute(b"viewBox") else {
+        return Ok(NativeSize {
+            origin: (0.0, 0.0),
+            scale: (MM_PER_PX, MM_PER_PX),
+        });
+    };
+    let numbers = view_box
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|v| !v.is_empty())
+        .map(str::parse)
+        .collect::<Result<Vec<f64>, _>>()
+        .map_err(|_| format!("Invalid SVG viewBox: {}", view_box))?;
+    let [x, y, w, h] = numbers[..] else {
+        return Err(format!("Invalid SVG viewBox: {}", view_box));
+    };
+    if !(w > 0.0 && h > 0.0) {
+        return Err(format!("Invalid SVG viewBox: {}", view_box));
+    }
+    // A missing width or height follows from the aspect ratio of the viewBox
+    let (width, height) = match (width, height) {
+        (Some(width), Some(height)) => (width, height),
+        (Some(width), None) => (width, width * h / w),
+        (None, Some(height)) => (height * w / h, height),
+        (None, None) => (w * MM_PER_PX, h * MM_PER_PX),
+    };
+    let scale = (width / w, height / h);
+    // The viewBox is scaled uniformly, unless its aspect ratio is ignored
+    let scale = match attribute(b"preserveAspectRatio").as_deref() {
+        Some("none") => scale,
+        _ => (scale.0.min(scale.1), scale.0.min(scale.1)),
+    };
+    Ok(NativeSize {
+        origin: (x, y),
+        scale,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number("12"), Some(12.0));
+        assert_eq!(parse_number("12.5px"), Some(12.5));
+        assert_eq!(parse_number("10 20, 30"), Some(10.0));
+        assert_eq!(parse_number("big"), None);
+    }
+
+    #[test]
+    fn test_parse_length() {
+        assert_eq!(parse_length("210mm"), Ok(210.0));
+        assert_eq!(parse_length("2cm"), Ok(20.0));
+        assert_eq!(parse_length("1in"), Ok(25.4));
+        assert_eq!(parse_length("96"), Ok(25.4));
+        assert_eq!(parse_length("72pt"), Ok(25.4));
+        assert!(parse_length("10em").is_err());
+        assert!(parse_length("-5mm").is_err());
+    }
+
+    #[test]
+    fn test_native_size() {
+        let native = |root: &str| native_size(&format!("<?xml version=\"1.0\"?>{}</svg>", root));
+        // Inkscape: The viewBox is in mm
+        assert_eq!(
+            native(r#"<svg width="200mm" height="100mm" viewBox="0 0 200 100">"#),
+            Ok(NativeSize {
+                origin: (0.0, 0.0),
+                scale: (1.0, 1.0)
+            })
+        );
+        assert_eq!(
+            native(r#"<svg width="10cm" viewBox="-10,-20,50,25">"#),
+            Ok(NativeSize {
+                origin: (-10.0, -20.0),
+                scale: (2.0, 2.0)
+            })
+        );
+        // Without a viewBox, user units are pixels
+        assert_eq!(
+            native(r#"<svg width="4in" height="2in">"#).unwrap().scale,
+            (MM_PER_PX, MM_PER_PX)
+        );
+        assert_eq!(
+            native(r#"<svg width="100%" height="100%" viewBox="0 0 96 96">"#)
+                .unwrap()
+                .scale,
+            (MM_PER_PX, MM_PER_PX)
+        );
+        // Uniform scale, unless the aspect ratio is ignored
+        assert_eq!(
+            native(r#"<svg width="100mm" height="100mm" viewBox="0 0 100 50">"#)
+                .unwrap()
+                .scale,
+            (1.0, 1.0)
+        );
+        assert_eq!(
+            native(r#"<svg width="100mm" height="100mm" viewBox="0 0 100 50" preserveAspectRatio="none">"#)
+                .unwrap()
+                .scale,
+            (1.0, 2.0)
+        );
+        assert!(native(r#"<svg width="10em" viewBox="0 0 1 1">"#).is_err());
+        assert!(native(r#"<svg viewBox="0 0 0 1">"#).is_err());
+        assert!(native_size("<html></html>").is_err());
+    }
+
+    #[test]
+    fn test_style_property() {
+        let style = "fill:none; font-size: 20px;text-anchor:middle";
+        assert_eq!(style_property(style, "font-size"), Some("20px"));
+        assert_eq!(style_property(style, "text-anchor"), Some("middle"));
+        assert_eq!(style_property(style, "stroke"), None);
+    }
+
+    #[test]
+    fn test_select_layers() {
+        let svg = concat!(
+            r#"<svg xmlns:inkscape="http://www.inkscape.org/namespaces/inkscape">"#,
+            r#"<path d="M0,0 L1,1"/>"#,
+            r#"<g inkscape:label="grid"><path d="M0,0 L2,2"/></g>"#,
+            r#"<g transform="scale(2)"><g id="content"><path d="M0,0 L3,3"></path></g>"#,
+            r#"<path d="M0,0 L4,4"/></g>"#,
+            r#"</svg>"#
+        );
+        assert_eq!(
+            select_layers(svg, &["content".to_string()]).unwrap(),
+            concat!(
+                r#"<svg xmlns:inkscape="http://www.inkscape.org/namespaces/inkscape">"#,
+                r#"<g inkscape:label="grid"></g>"#,
+                r#"<g transform="scale(2)"><g id="content"><path d="M0,0 L3,3"></path></g>"#,
+                r#"</g></svg>"#
+            )
+        );
+        let polylines = parse_layers(svg, 0.15, &["grid".to_string()]).unwrap();
+        assert_eq!(polylines.len(), 1);
+        assert_eq!(polylines[0][1].x, 2.0);
+        assert_eq!(parse_layers(svg, 0.15, &[]).unwrap().len(), 4);
+        assert!(select_layers(svg, &["missing".to_string()]).is_err());
+
+        let polylines = parse_without_layers(svg, 0.15, &["content".to_string()]).unwrap();
+        assert_eq!(polylines.len(), 3);
+        assert!(polylines.iter().all(|polyline| polyline[1].x != 6.0));
+    }
+
+    #[test]
+    fn test_parse_clock() {
+        assert_eq!(parse_clock("2s"), Some(2.0));
+        assert_eq!(parse_clock("500ms"), Some(0.5));
+        assert_eq!(parse_clock(" 1.5; 4s"), Some(1.5));
+        assert_eq!(parse_clock("0.5min"), Some(30.0));
+        assert_eq!(parse_clock("frame1.end"), None);
+        assert_eq!(parse_clock("-1s"), None);
+    }
+
+    #[test]
+    fn test_frames() {
+        // Frames are timed by SMIL or CSS, and ordered by time
+        let svg = concat!(
+            r#"<svg xmlns:inkscape="http://www.inkscape.org/namespaces/inkscape">"#,
+            r#"<style>#b { animation: show 1s; animation-delay: 4s }</style>"#,
+            r#"<g id="b"><path d="M0,0 L1,1"/></g>"#,
+            r#"<g inkscape:label="a"><set attributeName="display" to="inline" begin="1s"/>"#,
+            r#"<path d="M0,0 L2,2"/></g>"#,
+            r#"<g id="c"><animate attributeName="opacity" begin="6s"/><g id="nested"/></g>"#,
+            r#"</svg>"#
+        );
+        let frames = frames(svg).unwrap();
+        let names: Vec<&str> = frames.iter().map(|frame| frame.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b", "c"]);
+        let holds: Vec<Option<Duration>> = frames.iter().map(|frame| frame.hold).collect();
+        assert_eq!(
+            holds,
+            vec![
+                Some(Duration::from_secs(3)),
+                Some(Duration::from_secs(2)),
+                None
+            ]
+        );
+
+        // Untimed frames are shown in document order
+        let svg = r#"<svg><g id="2"><g/></g><path d="M0,0 L1,1"/><g id="1"><g/></g><g/></svg>"#;
+        let frames = super::frames(svg).unwrap();
+        assert_eq!(frames[0].name, "2");
+        assert_eq!(frames[1].name, "1");
+        assert_eq!(frames.len(), 2);
+        assert!(frames.iter().all(|frame| frame.hold.is_none()));
+
+        assert!(super::frames(r#"<svg><g id="only"/></svg>"#).is_err());
+    }
+
+    #[test]
+    fn test_replace_text() {
+        let svg = r#"<svg><g transform="translate(5)"><text x="10" y="30" font-size="10" transform="scale(2)">I<tspan>-</tspan></text></g></svg>"#;
+        let replaced = replace_text(svg).unwrap();
+        assert!(!replaced.contains("<text"));
+        assert!(!replaced.contains("tspan"));
+        assert!(replaced.starts_with(r#"<svg><g transform="translate(5)"><path d="M"#));
+        assert!(replaced.contains(r#"transform="scale(2)"/></g></svg>"#));
+    }
+
+    #[test]
+    fn test_parse_text() {
+        let svg = r#"<svg><text x="10" y="30" font-size="20">T</text></svg>"#;
+        let polylines = parse(svg, 0.15).unwrap();
+        // T has 2 strokes, with a cap height of 14 starting at the baseline
+        assert_eq!(polylines.len(), 2);
+        let top = polylines[0][0];
+        assert_eq!((top.x, top.y), (10.0, 16.0));
+        let bottom = polylines[1][1];
+        assert!((bottom.y - 30.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_text_anchor() {
+        let svg =
+            r#"<svg><text x="100" y="30" style="font-size:12px;text-anchor:end">AB</text></svg>"#;
+        let polylines = parse(svg, 0.15).unwrap();
+        let max_x = polylines
+            .iter()
+            .flatten()
+            .map(|p| p.x)
+            .fold(f64::MIN, f64::max);
+        assert!(max_x < 100.0);
+        assert!(max_x > 95.0);
+    }
+
+    #[test]
+    fn test_parse_without_text() {
+        let svg = r#"<svg><path d="M0,0 L10,10"/></svg>"#;
+        assert_eq!(parse(svg, 0.15).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,169 @@
+//! Template SVGs with placeholders that are expanded when printing.
+//!
+//! Supported placeholders:
+//!
+//! - `{{date}}`: The current date (`YYYY-MM-DD`)
+//! - `{{time}}`: The current time (`HH:MM`)
+//! - `{{counter}}`: How many times the template has been expanded (from 1)
+//! - `{{name}}` or `{{text:name}}`: The value of the variable `name`
+//!
+//! Placeholders are usually placed in SVG `<text>` elements, which are drawn
+//! with the stroke font.
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+
+use regex::{Captures, Regex};
+use time::OffsetDateTime;
+
+pub(crate) type SharedCounters = Arc<Mutex<Counters>>;
+
+/// Number of expansions per template.
+#[derive(Debug, Default)]
+pub(crate) struct Counters(HashMap<String, u64>);
+
+impl Counters {
+    pub(crate) fn new_shared() -> SharedCounters {
+        Arc::new(Mutex::new(Counters::default()))
+    }
+
+    /// Increment and return the counter of the template.
+    pub(crate) fn next(&mut self, template: &str) -> u64 {
+        let counter = self.0.entry(template.to_string()).or_insert(0);
+        *counter += 1;
+        *counter
+    }
+}
+
+fn placeholder_regex() -> Regex {
+    Regex::new(r"\{\{\s*([A-Za-z0-9_:-]+)\s*\}\}").expect("Could not compile regex")
+}
+
+/// Return whether the SVG contains placeholders.
+pub(crate) fn is_template(svg: &str) -> bool {
+    placeholder_regex().is_match(svg)
+}
+
+/// Escape a value for use in XML text.
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Return the semantic key of an expanded template, which only changes when
+/// the expanded content changes.
+pub(crate) fn key(name: &str, expanded: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    expanded.hash(&mut hasher);
+    format!("template:{}:{:016x}", name, hasher.finish())
+}
+
+/// Expand all placeholders in the SVG.
+pub(crate) fn expand(
+    svg: &str,
+    variables: &HashMap<String, String>,
+    counter: u64,
+    now: OffsetDateTime,
+) -> Result<String, String> {
+    let mut missing = vec![];
+    let expanded = placeholder_regex().replace_all(svg, |captures: &Captures| {
+        let name = &captures[1];
+        let value = match name {
+            "date" => format!(
+                "{:04}-{:02}-{:02}",
+                now.year(),
+                u8::from(now.month()),
+                now.day()
+            ),
+            "time" => format!("{:02}:{:02}", now.hour(), now.minute()),
+            "counter" => counter.to_string(),
+            _ => {
+                let variable = name.strip_prefix("text:").unwrap_or(name);
+                match variables.get(variable) {
+                    Some(value) => value.clone(),
+                    None => {
+                        missing.push(variable.to_string());
+                        String::new()
+                    }
+                }
+            }
+        };
+        escape(&value)
+    });
+    if missing.is_empty() {
+        Ok(expanded.into_owned())
+    } else {
+        Err(format!(
+            "Missing template variables: {}",
+            missing.join(", ")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_is_template() {
+        assert!(is_template("<text>{{date}}</text>"));
+        assert!(is_template("<text>{{ text:visitor }}</text>"));
+        assert!(!is_template("<text>{ date }</text>"));
+    }
+
+    #[test]
+    fn test_expand() {
+        let now = datetime!(2022-05-03 08:05 UTC);
+        let svg =
+            "<text>Welcome {{visitor}}! {{date}} {{time}} #{{counter}} {{text:visitor}}</text>";
+        assert_eq!(
+            expand(svg, &vars(&[("visitor", "Ada & Bob")]), 7, now).unwrap(),
+            "<text>Welcome Ada &amp; Bob! 2022-05-03 08:05 #7 Ada &amp; Bob</text>"
+        );
+    }
+
+    #[test]
+    fn test_expand_missing_variable() {
+        let now = datetime!(2022-05-03 08:05 UTC);
+        assert_eq!(
+            expand("{{a}} {{b}}", &vars(&[]), 1, now),
+            Err("Missing template variables: a, b".to_string())
+        );
+    }
+
+    #[test]
+    fn test_key() {
+        assert_eq!(
+            key("a.svg", "<text>12:05</text>"),
+            key("a.svg", "<text>12:05</text>")
+        );
+        assert_ne!(
+            key("a.svg", "<text>12:05</text>"),
+            key("a.svg", "<text>12:10</text>")
+        );
+        assert_ne!(
+            key("a.svg", "<text>12:05</text>"),
+            key("b.svg", "<text>12:05</text>")
+        );
+    }
+
+    #[test]
+    fn test_counters() {
+        let mut counters = Counters::default();
+        assert_eq!(counters.next("a.svg"), 1);
+        assert_eq!(counters.next("a.svg"), 2);
+        assert_eq!(counters.next("b.svg"), 1);
+    }
+}
@@ -0,0 +1,322 @@
+//! Multi-tenant mode: Several tenants share one board, each with its own SVG
+//! library, API tokens, presets and daily quota. The tenants are configured,
+//! or managed at runtime with the admin token.
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Component, Path};
+use std::sync::{Arc, Mutex};
+
+use log::warn;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use time::Date;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct TenantConfig {
+    pub(crate) name: String,
+    /// API tokens of the tenant, sent as `Authorization: Bearer <token>`.
+    #[serde(default, skip_serializing)]
+    tokens: Vec<String>,
+    /// Subdirectory of the SVG dir with the tenant's library. Defaults to
+    /// the tenant name.
+    library: Option<String>,
+    /// Maximum number of print jobs per day.
+    quota: Option<u32>,
+    /// Named options of print requests (e.g. the size and position of the
+    /// tenant's drawings), which a print request selects with `preset`.
+    #[serde(default)]
+    presets: BTreeMap<String, Map<String, Value>>,
+}
+
+impl TenantConfig {
+    pub(crate) fn library(&self) -> &str {
+        self.library.as_deref().unwrap_or(&self.name)
+    }
+
+    pub(crate) fn presets(&self) -> &BTreeMap<String, Map<String, Value>> {
+        &self.presets
+    }
+
+    /// Fill in the options of the preset that the print request selects,
+    /// unless the request sets them itself.
+    pub(crate) fn apply_preset(&self, request: &mut Map<String, Value>) -> Result<(), String> {
+        let name = match request.remove("preset") {
+            Some(Value::String(name)) => name,
+            Some(_) => return Err("The preset must be given by its name".to_string()),
+            None => return Ok(()),
+        };
+        let preset = self
+            .presets
+            .get(&name)
+            .ok_or_else(|| format!("Unknown preset: {}", name))?;
+        for (key, value) in preset {
+            request.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+        Ok(())
+    }
+}
+
+pub(crate) type SharedTenants = Arc<Mutex<Vec<TenantConfig>>>;
+
+/// A tenant as persisted, including its tokens.
+#[derive(Serialize)]
+struct StoredTenant<'a> {
+    #[serde(flatten)]
+    tenant: &'a TenantConfig,
+    tokens: &'a [String],
+}
+
+/// Check that the tenants have distinct names and tokens (other than the
+/// admin token), and that their libraries are subdirectories of the SVG dir.
+pub(crate) fn validate(tenants: &[TenantConfig], admin_token: Option<&str>) -> Result<(), String> {
+    let mut names = HashSet::new();
+    let mut tokens = HashSet::new();
+    for tenant in tenants {
+        if tenant.name.is_empty() {
+            return Err("Tenants must have a name".to_string());
+        }
+        if !names.insert(&tenant.name) {
+            return Err(format!("Duplicate tenant name: {}", tenant.name));
+        }
+        for token in &tenant.tokens {
+            if token.is_empty() || Some(token.as_str()) == admin_token || !tokens.insert(token) {
+                return Err(format!(
+                    "{}: The tokens must not be empty, shared or the admin token",
+                    tenant.name
+                ));
+            }
+        }
+        let mut components = Path::new(tenant.library()).components();
+        if !matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        ) {
+            return Err(format!(
+                "{}: The library must be a directory within the SVG dir",
+                tenant.name
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Persist the tenants that are managed at runtime, with their tokens.
+pub(crate) fn save(path: &Path, tenants: &[TenantConfig]) {
+    let stored: Vec<StoredTenant> = tenants
+        .iter()
+        .map(|tenant| StoredTenant {
+            tenant,
+            tokens: &tenant.tokens,
+        })
+        .collect();
+    match serde_json::to_string(&stored) {
+        Ok(json) => {
+            if let Err(e) = fs::write(path, json) {
+                warn!("Could not write {}: {}", path.display(), e);
+            }
+        }
+        Err(e) => warn!("Could not serialize the tenants: {}", e),
+    }
+}
+
+/// Load persisted tenants, if they are valid.
+pub(crate) fn load(path: &Path, admin_token: Option<&str>) -> Option<Vec<TenantConfig>> {
+    let json = fs::read_to_string(path).ok()?;
+    match serde_json::from_str::<Vec<TenantConfig>>(&json)
+        .map_err(|e| e.to_string())
+        .and_then(|tenants| validate(&tenants, admin_token).map(|()| tenants))
+    {
+        Ok(tenants) => Some(tenants),
+        Err(e) => {
+            warn!("Ignoring the tenants in {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Return the tenant that owns the specified token.
+pub(crate) fn find_by_token<'a>(
+    tenants: &'a [TenantConfig],
+    token: &str,
+) -> Option<&'a TenantConfig> {
+    tenants
+        .iter()
+        .find(|tenant| tenant.tokens.iter().any(|t| t == token))
+}
+
+pub(crate) type SharedQuotas = Arc<Mutex<Quotas>>;
+
+/// Number of print jobs per tenant on the current day.
+#[derive(Debug, Default)]
+pub(crate) struct Quotas {
+    day: Option<Date>,
+    used: HashMap<String, u32>,
+}
+
+impl Quotas {
+    pub(crate) fn new_shared() -> SharedQuotas {
+        Arc::new(Mutex::new(Quotas::default()))
+    }
+
+    /// Count print jobs of the tenant. Return `false` (and count none of
+    /// them) if the tenant doesn't have enough quota left for today.
+    pub(crate) fn try_use(&mut self, tenant: &TenantConfig, today: Date, jobs: u32) -> bool {
+        if self.day != Some(today) {
+            self.day = Some(today);
+            self.used.clear();
+        }
+        let used = self.used.entry(tenant.name.clone()).or_insert(0);
+        match tenant.quota {
+            Some(quota) if *used + jobs > quota => false,
+            _ => {
+                *used += jobs;
+                true
+            }
+        }
+    }
+
+    /// Give back print jobs of the tenant that were counted today, e.g.
+    /// because they could not be queued.
+    pub(crate) fn refund(&mut self, tenant: &TenantConfig, today: Date, jobs: u32) {
+        if self.day != Some(today) {
+            return;
+        }
+        if let Some(used) = self.used.get_mut(&tenant.name) {
+            *used = used.saturating_sub(jobs);
+        }
+    }
+
+    /// Return how many print jobs the tenant has left today, or `None` if it
+    /// has no quota.
+    pub(crate) fn remaining(&self, tenant: &TenantConfig, today: Date) -> Option<u32> {
+        let used = match self.day {
+            Some(day) if day == today => self.used.get(&tenant.name).copied().unwrap_or(0),
+            _ => 0,
+        };
+        tenant.quota.map(|quota| quota.saturating_sub(used))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::Month;
+
+    fn tenant(json: &str) -> TenantConfig {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_find_by_token() {
+        let tenants = vec![
+            tenant(r#"{"name": "a", "tokens": ["t1", "t2"]}"#),
+            tenant(r#"{"name": "b", "tokens": ["t3"]}"#),
+        ];
+        assert_eq!(find_by_token(&tenants, "t2").unwrap().name, "a");
+        assert_eq!(find_by_token(&tenants, "t3").unwrap().name, "b");
+        assert!(find_by_token(&tenants, "").is_none());
+    }
+
+    #[test]
+    fn test_library() {
+        assert_eq!(tenant(r#"{"name": "a"}"#).library(), "a");
+        assert_eq!(
+            tenant(r#"{"name": "a", "library": "lib"}"#).library(),
+            "lib"
+        );
+    }
+
+    #[test]
+    fn test_apply_preset() {
+        let tenant = tenant(
+            r#"{"name": "a", "presets": {"corner": {"scale_x": 0.5, "scale_y": 0.5, "align": "top-right"}}}"#,
+        );
+        let request = |json: &str| serde_json::from_str::<Map<String, Value>>(json).unwrap();
+        let mut print = request(r#"{"svg": "<svg/>", "preset": "corner", "scale_x": 0.25}"#);
+        tenant.apply_preset(&mut print).unwrap();
+        assert_eq!(
+            print,
+            request(r#"{"svg": "<svg/>", "scale_x": 0.25, "scale_y": 0.5, "align": "top-right"}"#)
+        );
+        // Requests without a preset are kept
+        let mut plain = request(r#"{"svg": "<svg/>"}"#);
+        tenant.apply_preset(&mut plain).unwrap();
+        assert_eq!(plain, request(r#"{"svg": "<svg/>"}"#));
+        assert!(tenant
+            .apply_preset(&mut request(r#"{"preset": "unknown"}"#))
+            .is_err());
+        assert!(tenant
+            .apply_preset(&mut request(r#"{"preset": 1}"#))
+            .is_err());
+    }
+
+    #[test]
+    fn test_quota() {
+        let day1 = Date::from_calendar_date(2022, Month::May, 1).unwrap();
+        let day2 = day1.next_day().unwrap();
+        let limited = tenant(r#"{"name": "a", "quota": 2}"#);
+        let unlimited = tenant(r#"{"name": "b"}"#);
+        let mut quotas = Quotas::default();
+        assert_eq!(quotas.remaining(&limited, day1), Some(2));
+        assert!(quotas.try_use(&limited, day1, 1));
+        assert_eq!(quotas.remaining(&limited, day1), Some(1));
+        assert!(quotas.try_use(&limited, day1, 1));
+        assert!(!quotas.try_use(&limited, day1, 1));
+        assert_eq!(quotas.remaining(&limited, day1), Some(0));
+        // Jobs that were not accepted are given back
+        quotas.refund(&limited, day1, 1);
+        assert_eq!(quotas.remaining(&limited, day1), Some(1));
+        assert!(quotas.try_use(&limited, day1, 1));
+        for _ in 0..10 {
+            assert!(quotas.try_use(&unlimited, day1, 1));
+        }
+        assert_eq!(quotas.remaining(&unlimited, day1), None);
+        // The quota is reset every day
+        assert_eq!(quotas.remaining(&limited, day2), Some(2));
+        quotas.refund(&limited, day2, 1);
+        assert_eq!(quotas.remaining(&limited, day2), Some(2));
+        assert!(quotas.try_use(&limited, day2, 1));
+
+        // Several jobs are only counted if there is quota left for all of them
+        assert!(!quotas.try_use(&limited, day2, 2));
+        assert_eq!(quotas.remaining(&limited, day2), Some(1));
+        quotas.refund(&limited, day2, 1);
+        assert!(quotas.try_use(&limited, day2, 2));
+        assert_eq!(quotas.remaining(&limited, day2), Some(0));
+    }
+
+    #[test]
+    fn test_tokens_not_serialized() {
+        let json = serde_json::to_string(&tenant(r#"{"name": "a", "tokens": ["secret"]}"#));
+        assert!(!json.unwrap().contains("secret"));
+    }
+
+    #[test]
+    fn test_validate() {
+        let a = tenant(r#"{"name": "a", "tokens": ["t1"]}"#);
+        let b = tenant(r#"{"name": "b", "tokens": ["t2"], "library": "shared"}"#);
+        assert!(validate(&[a.clone(), b.clone()], Some("admin")).is_ok());
+        assert!(validate(&[a.clone(), a.clone()], None).is_err());
+        assert!(validate(std::slice::from_ref(&a), Some("t1")).is_err());
+        let shared_token = tenant(r#"{"name": "c", "tokens": ["t1"]}"#);
+        assert!(validate(&[a, shared_token], None).is_err());
+        for library in ["../etc", "/etc", "a/b", ".."] {
+            let json = format!(r#"{{"name": "d", "library": "{}"}}"#, library);
+            assert!(validate(&[tenant(&json)], None).is_err(), "{}", library);
+        }
+        assert!(validate(&[tenant(r#"{"name": ""}"#)], None).is_err());
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("ibb-tenants-{}", std::process::id()));
+        let tenants = vec![tenant(r#"{"name": "a", "tokens": ["t1"], "quota": 3}"#)];
+        save(&path, &tenants);
+        let loaded = load(&path, None).unwrap();
+        assert_eq!(find_by_token(&loaded, "t1").unwrap().quota, Some(3));
+        // Tenants that conflict with the admin token are ignored
+        assert!(load(&path, Some("t1")).is_none());
+        fs::remove_file(&path).unwrap();
+        assert!(load(&path, None).is_none());
+    }
+}
@@ -0,0 +1,22 @@
+//! Helpers for the tests of the polyline transformations.
+
+use svg2polylines::{CoordinatePair, Polyline};
+
+/// Build a polyline from `(x, y)` points.
+pub(crate) fn polyline(points: &[(f64, f64)]) -> Polyline {
+    points
+        .iter()
+        .map(|&(x, y)| CoordinatePair { x, y })
+        .collect()
+}
+
+/// Build a closed square with its top left corner at `(x, y)`.
+pub(crate) fn square(x: f64, y: f64, size: f64) -> Polyline {
+    polyline(&[
+        (x, y),
+        (x + size, y),
+        (x + size, y + size),
+        (x, y + size),
+        (x, y),
+    ])
+}
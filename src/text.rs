@@ -0,0 +1,188 @@
+//! Rendering of text as polylines, using a simple built-in stroke font.
+//!
+//! Glyphs are defined on a grid that is 4 units wide and 6 units high (the
+//! cap height), with the origin in the top left corner and the y axis
+//! pointing downwards (just like SVG). Lowercase letters are rendered as
+//! uppercase letters, unknown characters are rendered as `?`.
+use svg2polylines::{CoordinatePair, Polyline};
+
+/// Height of a glyph in grid units.
+const GLYPH_HEIGHT: f64 = 6.0;
+
+/// Horizontal advance per character in grid units.
+const GLYPH_ADVANCE: f64 = 5.5;
+
+/// Line height, relative to the text size.
+pub(crate) const LINE_HEIGHT: f64 = 1.6;
+
+/// Return the strokes of a glyph.
+///
+/// Strokes are separated by `;`, points within a stroke by a space.
+fn glyph(c: char) -> &'static str {
+    match c.to_ascii_uppercase() {
+        ' ' => "",
+        'A' => "0,6 0,2 2,0 4,2 4,6;0,4 4,4",
+        'B' => "0,6 0,0 3,0 4,1 4,2 3,3 0,3;3,3 4,4 4,5 3,6 0,6",
+        'C' => "4,1 3,0 1,0 0,1 0,5 1,6 3,6 4,5",
+        'D' => "0,0 0,6 2,6 4,4 4,2 2,0 0,0",
+        'E' => "4,0 0,0 0,6 4,6;0,3 3,3",
+        'F' => "4,0 0,0 0,6;0,3 3,3",
+        'G' => "4,1 3,0 1,0 0,1 0,5 1,6 3,6 4,5 4,3 2,3",
+        'H' => "0,0 0,6;4,0 4,6;0,3 4,3",
+        'I' => "1,0 3,0;2,0 2,6;1,6 3,6",
+        'J' => "4,0 4,5 3,6 1,6 0,5",
+        'K' => "0,0 0,6;4,0 0,4;1,3 4,6",
+        'L' => "0,0 0,6 4,6",
+        'M' => "0,6 0,0 2,3 4,0 4,6",
+        'N' => "0,6 0,0 4,6 4,0",
+        'O' => "1,0 3,0 4,1 4,5 3,6 1,6 0,5 0,1 1,0",
+        'P' => "0,6 0,0 3,0 4,1 4,2 3,3 0,3",
+        'Q' => "1,0 3,0 4,1 4,5 3,6 1,6 0,5 0,1 1,0;2,4 4,6",
+        'R' => "0,6 0,0 3,0 4,1 4,2 3,3 0,3;2,3 4,6",
+        'S' => "4,1 3,0 1,0 0,1 0,2 1,3 3,3 4,4 4,5 3,6 1,6 0,5",
+        'T' => "0,0 4,0;2,0 2,6",
+        'U' => "0,0 0,5 1,6 3,6 4,5 4,0",
+        'V' => "0,0 2,6 4,0",
+        'W' => "0,0 1,6 2,3 3,6 4,0",
+        'X' => "0,0 4,6;4,0 0,6",
+        'Y' => "0,0 2,3 4,0;2,3 2,6",
+        'Z' => "0,0 4,0 0,6 4,6",
+        '0' => "1,0 3,0 4,1 4,5 3,6 1,6 0,5 0,1 1,0;0,5 4,1",
+        '1' => "1,1 2,0 2,6;1,6 3,6",
+        '2' => "0,1 1,0 3,0 4,1 4,2 0,6 4,6",
+        '3' => "0,1 1,0 3,0 4,1 4,2 3,3 1,3;3,3 4,4 4,5 3,6 1,6 0,5",
+        '4' => "3,6 3,0 0,4 4,4",
+        '5' => "4,0 0,0 0,3 3,3 4,4 4,5 3,6 0,6",
+        '6' => "4,1 3,0 1,0 0,1 0,5 1,6 3,6 4,5 4,4 3,3 0,3",
+        '7' => "0,0 4,0 1,6",
+        '8' => "1,0 3,0 4,1 4,2 3,3 1,3 0,2 0,1 1,0;1,3 0,4 0,5 1,6 3,6 4,5 4,4 3,3",
+        '9' => "4,3 1,3 0,2 0,1 1,0 3,0 4,1 4,5 3,6 1,6 0,5",
+        '.' => "2,5.5 2,6",
+        ',' => "2,5 2,6 1,7",
+        ':' => "2,1.5 2,2;2,5.5 2,6",
+        ';' => "2,1.5 2,2;2,5 2,6 1,7",
+        '-' => "1,3 3,3",
+        '+' => "0,3 4,3;2,1 2,5",
+        '/' => "0,6 4,0",
+        '!' => "2,0 2,4;2,5.5 2,6",
+        '\'' => "2,0 2,1.5",
+        '"' => "1,0 1,1.5;3,0 3,1.5",
+        '(' => "3,0 1,2 1,4 3,6",
+        ')' => "1,0 3,2 3,4 1,6",
+        '[' => "3,0 1,0 1,6 3,6",
+        ']' => "1,0 3,0 3,6 1,6",
+        '<' => "4,0 0,3 4,6",
+        '>' => "0,0 4,3 0,6",
+        '=' => "0,2 4,2;0,4 4,4",
+        '_' => "0,6 4,6",
+        '*' => "2,1 2,5;0,2 4,4;4,2 0,4",
+        '#' => "1,0 1,6;3,0 3,6;0,2 4,2;0,4 4,4",
+        '%' => "0,6 4,0;0,0 1,0 1,1 0,1 0,0;3,5 4,5 4,6 3,6 3,5",
+        '&' => "4,6 1,2 1,1 2,0 3,1 3,2 0,4 0,5 1,6 2,6 4,4",
+        '@' => "3,4 3,2 1,2 1,4 3,4 4,3 4,1 3,0 1,0 0,1 0,5 1,6 4,6",
+        _ => "0,1 1,0 3,0 4,1 4,2 2,3 2,4;2,5.5 2,6",
+    }
+}
+
+/// Return the width of the specified text when rendered with the specified
+/// size (cap height in mm).
+pub(crate) fn line_width(text: &str, size: f64) -> f64 {
+    let unit = size / GLYPH_HEIGHT;
+    text.chars().count() as f64 * GLYPH_ADVANCE * unit
+}
+
+/// Shorten the text so that it fits into `max_width` (in mm), appending an
+/// ellipsis if the text had to be truncated.
+pub(crate) fn truncate(text: &str, max_width: f64, size: f64) -> String {
+    if line_width(text, size) <= max_width {
+        return text.to_string();
+    }
+    let unit = size / GLYPH_HEIGHT;
+    let max_chars = (max_width / (GLYPH_ADVANCE * unit)).floor() as usize;
+    if max_chars < 3 {
+        return text.chars().take(max_chars).collect();
+    }
+    let mut truncated: String = text.chars().take(max_chars - 3).collect();
+    truncated.push_str("...");
+    truncated
+}
+
+/// Render a single line of text into polylines.
+///
+/// The `x` and `y` coordinates specify the top left corner of the line, the
+/// `size` specifies the cap height in mm.
+pub(crate) fn render_line(text: &str, x: f64, y: f64, size: f64) -> Vec<Polyline> {
+    let unit = size / GLYPH_HEIGHT;
+    let mut polylines = vec![];
+    for (i, c) in text.chars().enumerate() {
+        let x_offset = x + i as f64 * GLYPH_ADVANCE * unit;
+        for stroke in glyph(c).split(';').filter(|s| !s.is_empty()) {
+            let polyline: Polyline = stroke
+                .split(' ')
+                .filter_map(|point| {
+                    let (gx, gy) = point.split_once(',')?;
+                    Some(CoordinatePair {
+                        x: x_offset + gx.parse::<f64>().ok()? * unit,
+                        y: y + gy.parse::<f64>().ok()? * unit,
+                    })
+                })
+                .collect();
+            polylines.push(polyline);
+        }
+    }
+    polylines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_all_glyphs_parse() {
+        for c in (' '..='~').chain(['ä', '€']) {
+            for stroke in glyph(c).split(';').filter(|s| !s.is_empty()) {
+                let points: Vec<&str> = stroke.split(' ').collect();
+                assert!(points.len() >= 2, "Glyph {:?} has a short stroke", c);
+                for point in points {
+                    let (x, y) = point.split_once(',').unwrap();
+                    x.parse::<f64>().unwrap();
+                    y.parse::<f64>().unwrap();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_render_line() {
+        let polylines = render_line("Hi", 10.0, 20.0, 12.0);
+        // H has 3 strokes, I has 3 strokes
+        assert_eq!(polylines.len(), 6);
+        assert_eq!(
+            polylines[0],
+            vec![
+                CoordinatePair { x: 10.0, y: 20.0 },
+                CoordinatePair { x: 10.0, y: 32.0 },
+            ]
+        );
+        // The I starts one advance (5.5 units of 2 mm) further right
+        assert_eq!(polylines[3][0], CoordinatePair { x: 23.0, y: 20.0 });
+    }
+
+    #[test]
+    fn test_render_space() {
+        assert!(render_line("   ", 0.0, 0.0, 6.0).is_empty());
+    }
+
+    #[test]
+    fn test_line_width() {
+        assert_eq!(line_width("", 6.0), 0.0);
+        assert_eq!(line_width("abcd", 6.0), 22.0);
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("Standup", 100.0, 6.0), "Standup");
+        assert_eq!(truncate("Standup meeting", 44.0, 6.0), "Stand...");
+        assert_eq!(truncate("Standup", 11.0, 6.0), "St");
+    }
+}
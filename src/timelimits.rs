@@ -1,54 +1,306 @@
+use std::f64::consts::PI;
 use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use serde_derive::{Deserialize, Serialize};
-use time::Time;
+use time::{Date, OffsetDateTime, Time, UtcOffset};
 
-time::serde::format_description!(hm_time, Time, "[hour]:[minute]");
+/// Time limits that can be changed at runtime, e.g. by importing a schedule.
+pub(crate) type SharedTimeLimits = Arc<Mutex<Option<TimeLimits>>>;
+
+/// Return the current time limits.
+pub(crate) fn current(time_limits: &SharedTimeLimits) -> Option<TimeLimits> {
+    match time_limits.lock() {
+        Ok(time_limits) => *time_limits,
+        Err(e) => {
+            log::error!("Could not unlock time limits mutex: {}", e);
+            None
+        }
+    }
+}
+
+/// The start or end of the time limits: A fixed time like `"06:00"`, or
+/// sunrise or sunset with an optional offset in minutes, like
+/// `"sunrise+30"` or `"sunset-15"`.
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) enum Limit {
+    Time(Time),
+    Sunrise(i16),
+    Sunset(i16),
+}
+
+impl FromStr for Limit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let sun = |rest: &str| match rest {
+            "" => Ok(0),
+            _ => rest
+                .strip_prefix('+')
+                .unwrap_or(rest)
+                .parse::<i16>()
+                .ok()
+                .filter(|minutes| minutes.abs() < 24 * 60)
+                .ok_or_else(|| format!("Invalid offset in minutes: {}", rest)),
+        };
+        if let Some(rest) = s.strip_prefix("sunrise") {
+            return Ok(Limit::Sunrise(sun(rest)?));
+        }
+        if let Some(rest) = s.strip_prefix("sunset") {
+            return Ok(Limit::Sunset(sun(rest)?));
+        }
+        let invalid = || format!("Invalid time (expected HH:MM, sunrise or sunset): {}", s);
+        let (hour, minute) = s.split_once(':').ok_or_else(invalid)?;
+        let hour = hour.parse().map_err(|_| invalid())?;
+        let minute = minute.parse().map_err(|_| invalid())?;
+        Time::from_hms(hour, minute, 0)
+            .map(Limit::Time)
+            .map_err(|_| invalid())
+    }
+}
+
+impl TryFrom<String> for Limit {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Limit> for String {
+    fn from(limit: Limit) -> Self {
+        limit.to_string()
+    }
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (name, minutes) = match self {
+            Limit::Time(time) => return write!(f, "{:02}:{:02}", time.hour(), time.minute()),
+            Limit::Sunrise(minutes) => ("sunrise", minutes),
+            Limit::Sunset(minutes) => ("sunset", minutes),
+        };
+        match minutes {
+            0 => write!(f, "{}", name),
+            _ => write!(f, "{}{:+}", name, minutes),
+        }
+    }
+}
+
+impl Limit {
+    fn is_fixed(&self) -> bool {
+        matches!(self, Limit::Time(_))
+    }
+}
+
+/// What happens to a scheduled run that falls outside of the time limits.
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum MissedRuns {
+    /// The run is skipped, the next one is drawn at its regular time.
+    #[default]
+    Skip,
+    /// The run is drawn as soon as the time limits begin. Several missed
+    /// runs are drawn only once.
+    CatchUp,
+}
+
+impl MissedRuns {
+    fn is_skip(&self) -> bool {
+        *self == MissedRuns::Skip
+    }
+}
+
+/// Return the times of sunrise and sunset in minutes after midnight UTC,
+/// following the approximation of the NOAA. During the polar night, both
+/// are at noon, and during the polar day, they are 12 hours from noon.
+fn sun_times(date: Date, latitude: f64, longitude: f64) -> (f64, f64) {
+    let gamma = 2.0 * PI / 365.0 * f64::from(date.ordinal() - 1);
+    let equation_of_time = 229.18
+        * (0.000075 + 0.001868 * gamma.cos()
+            - 0.032077 * gamma.sin()
+            - 0.014615 * (2.0 * gamma).cos()
+            - 0.040849 * (2.0 * gamma).sin());
+    let declination = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin()
+        - 0.006758 * (2.0 * gamma).cos()
+        + 0.000907 * (2.0 * gamma).sin()
+        - 0.002697 * (3.0 * gamma).cos()
+        + 0.00148 * (3.0 * gamma).sin();
+    let latitude = latitude.to_radians();
+    // The sun is 0.833° below the horizon at sunrise, due to refraction
+    let cos_hour_angle = 90.833f64.to_radians().cos() / (latitude.cos() * declination.cos())
+        - latitude.tan() * declination.tan();
+    let hour_angle = cos_hour_angle.clamp(-1.0, 1.0).acos().to_degrees();
+    let noon = 720.0 - 4.0 * longitude - equation_of_time;
+    (noon - 4.0 * hour_angle, noon + 4.0 * hour_angle)
+}
 
 /// Used for limiting the running time.
 ///
-/// Note: Limiting the time only works for scheduled tasks!
+/// Note: Scheduled tasks are skipped (or deferred, see `MissedRuns`) outside
+/// of the time limits, single prints are rejected (see `preflight`). Limits
+/// relative to sunrise or sunset require the `latitude` and `longitude` of
+/// the board.
 #[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+#[serde(try_from = "RawTimeLimits")]
 pub(crate) struct TimeLimits {
-    #[serde(with = "hm_time")]
-    start_time: Time,
-    #[serde(with = "hm_time")]
-    end_time: Time,
+    start_time: Limit,
+    end_time: Limit,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    longitude: Option<f64>,
+    #[serde(skip_serializing_if = "MissedRuns::is_skip")]
+    missed_runs: MissedRuns,
+}
+
+/// The time limits as configured, before they are validated.
+#[derive(Deserialize)]
+struct RawTimeLimits {
+    start_time: Limit,
+    end_time: Limit,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    #[serde(default)]
+    missed_runs: MissedRuns,
+}
+
+impl TryFrom<RawTimeLimits> for TimeLimits {
+    type Error = String;
+
+    fn try_from(raw: RawTimeLimits) -> Result<Self, Self::Error> {
+        let limits = TimeLimits {
+            start_time: raw.start_time,
+            end_time: raw.end_time,
+            latitude: raw.latitude,
+            longitude: raw.longitude,
+            missed_runs: raw.missed_runs,
+        };
+        if limits.latitude.is_some() != limits.longitude.is_some() {
+            return Err("Both latitude and longitude must be set".to_string());
+        }
+        if limits
+            .latitude
+            .is_some_and(|latitude| latitude.abs() > 90.0)
+            || limits
+                .longitude
+                .is_some_and(|longitude| longitude.abs() > 180.0)
+        {
+            return Err("Invalid latitude or longitude".to_string());
+        }
+        if limits.latitude.is_none()
+            && !(limits.start_time.is_fixed() && limits.end_time.is_fixed())
+        {
+            return Err("Sunrise and sunset require a latitude and longitude".to_string());
+        }
+        Ok(limits)
+    }
 }
 
 impl TimeLimits {
+    /// Return the start and end times on the given day, with sunrise and
+    /// sunset in the given UTC offset.
+    fn times_on(&self, date: Date, offset: UtcOffset) -> (Time, Time) {
+        let sun = match (self.latitude, self.longitude) {
+            (Some(latitude), Some(longitude)) => sun_times(date, latitude, longitude),
+            _ => (0.0, 0.0),
+        };
+        let time = |limit: Limit| {
+            let (utc_minutes, minutes) = match limit {
+                Limit::Time(time) => return time,
+                Limit::Sunrise(minutes) => (sun.0, minutes),
+                Limit::Sunset(minutes) => (sun.1, minutes),
+            };
+            let local =
+                utc_minutes.round() as i64 + i64::from(offset.whole_minutes()) + i64::from(minutes);
+            let local = local.rem_euclid(24 * 60);
+            Time::from_hms((local / 60) as u8, (local % 60) as u8, 0).expect("Invalid time")
+        };
+        (time(self.start_time), time(self.end_time))
+    }
+
+    /// Return the start and end times of today.
+    fn times(&self) -> (Time, Time) {
+        if let (Limit::Time(start), Limit::Time(end)) = (self.start_time, self.end_time) {
+            return (start, end);
+        }
+        let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+        self.times_on(now.date(), now.offset())
+    }
+
+    pub(crate) fn missed_runs(&self) -> MissedRuns {
+        self.missed_runs
+    }
+
+    /// Return when the time limits begin next after the given time.
+    pub(crate) fn next_start(&self, after: OffsetDateTime) -> OffsetDateTime {
+        let mut date = after.date();
+        loop {
+            let (start, _) = self.times_on(date, after.offset());
+            let start = date.with_time(start).assume_offset(after.offset());
+            if start > after {
+                return start;
+            }
+            date = date.next_day().expect("Date out of range");
+        }
+    }
+
     pub(crate) fn is_within_limits(&self, time: &Time) -> bool {
-        if self.start_time < self.end_time {
-            time >= &self.start_time && time <= &self.end_time
-        } else {
-            time >= &self.start_time || time <= &self.end_time
+        within(self.times(), time)
+    }
+
+    /// Return the time left until the end of the time limits, or `None` if
+    /// the time is outside of the limits.
+    pub(crate) fn remaining(&self, time: &Time) -> Option<Duration> {
+        let times = self.times();
+        if !within(times, time) {
+            return None;
         }
+        let end = seconds_since_midnight(&times.1);
+        let now = seconds_since_midnight(time);
+        Some(Duration::from_secs((end + 86400 - now) % 86400))
     }
 }
 
+fn within((start_time, end_time): (Time, Time), time: &Time) -> bool {
+    if start_time < end_time {
+        time >= &start_time && time <= &end_time
+    } else {
+        time >= &start_time || time <= &end_time
+    }
+}
+
+fn seconds_since_midnight(time: &Time) -> u64 {
+    u64::from(time.hour()) * 3600 + u64::from(time.minute()) * 60 + u64::from(time.second())
+}
+
 impl fmt::Display for TimeLimits {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "{:02}:{:02}–{:02}:{:02}",
-            self.start_time.hour(),
-            self.start_time.minute(),
-            self.end_time.hour(),
-            self.end_time.minute()
-        )
+        write!(f, "{}–{}", self.start_time, self.end_time)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use time::macros::time;
+    use time::macros::{date, offset, time};
+
+    fn fixed(start_time: Time, end_time: Time) -> TimeLimits {
+        TimeLimits {
+            start_time: Limit::Time(start_time),
+            end_time: Limit::Time(end_time),
+            latitude: None,
+            longitude: None,
+            missed_runs: MissedRuns::Skip,
+        }
+    }
+
     #[test]
     fn time_limits_simple() {
-        let limits = TimeLimits {
-            start_time: time!(8:00),
-            end_time: time!(12:30),
-        };
+        let limits = fixed(time!(8:00), time!(12:30));
 
         let tm_before = time!(7:59);
         let tm_on = time!(8:00);
@@ -65,10 +317,7 @@ mod tests {
 
     #[test]
     fn time_limits_complex() {
-        let limits = TimeLimits {
-            start_time: time!(22:00),
-            end_time: time!(2:30),
-        };
+        let limits = fixed(time!(22:00), time!(2:30));
 
         let tm_before = time!(21:00);
         let tm_on1 = time!(22:00);
@@ -86,4 +335,58 @@ mod tests {
         assert_eq!(limits.is_within_limits(&tm_within3), true);
         assert_eq!(limits.is_within_limits(&tm_after), false);
     }
+
+    #[test]
+    fn time_limits_remaining() {
+        let limits = fixed(time!(22:00), time!(2:30));
+        assert_eq!(
+            limits.remaining(&time!(23:30)),
+            Some(Duration::from_secs(3 * 3600))
+        );
+        assert_eq!(
+            limits.remaining(&time!(2:00)),
+            Some(Duration::from_secs(30 * 60))
+        );
+        assert_eq!(limits.remaining(&time!(2:30)), Some(Duration::ZERO));
+        assert_eq!(limits.remaining(&time!(12:00)), None);
+    }
+
+    #[test]
+    fn test_parse_limit() {
+        assert_eq!("06:00".parse(), Ok(Limit::Time(time!(6:00))));
+        assert_eq!("sunrise".parse(), Ok(Limit::Sunrise(0)));
+        assert_eq!("sunrise+30".parse(), Ok(Limit::Sunrise(30)));
+        assert_eq!("sunset-15".parse(), Ok(Limit::Sunset(-15)));
+        assert!("sunset+".parse::<Limit>().is_err());
+        assert!("25:00".parse::<Limit>().is_err());
+        assert_eq!(Limit::Sunset(-15).to_string(), "sunset-15");
+        assert_eq!(Limit::Time(time!(6:00)).to_string(), "06:00");
+    }
+
+    #[test]
+    fn test_sun_limits() {
+        let limits: TimeLimits = serde_json::from_str(
+            r#"{"start_time": "sunrise+30", "end_time": "sunset", "latitude": 47.37, "longitude": 8.54}"#,
+        )
+        .unwrap();
+        assert_eq!(limits.to_string(), "sunrise+30–sunset");
+
+        // Zurich: Sunrise at 05:30 and sunset at 21:26 at midsummer,
+        // sunrise at 08:13 and sunset at 16:38 at midwinter
+        let (start, end) = limits.times_on(date!(2026 - 06 - 21), offset!(+2));
+        assert!((time!(5:55)..=time!(6:05)).contains(&start), "{}", start);
+        assert!((time!(21:21)..=time!(21:31)).contains(&end), "{}", end);
+        let (start, end) = limits.times_on(date!(2026 - 12 - 21), offset!(+1));
+        assert!((time!(8:38)..=time!(8:48)).contains(&start), "{}", start);
+        assert!((time!(16:33)..=time!(16:43)).contains(&end), "{}", end);
+
+        assert!(serde_json::from_str::<TimeLimits>(
+            r#"{"start_time": "sunrise", "end_time": "22:00"}"#
+        )
+        .is_err());
+        assert!(serde_json::from_str::<TimeLimits>(
+            r#"{"start_time": "06:00", "end_time": "22:00"}"#
+        )
+        .is_ok());
+    }
 }
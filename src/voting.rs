@@ -0,0 +1,283 @@
+//! Audience voting: Single prints for the default board enter a pool instead
+//! of being drawn right away. Every interval, the entry with the most votes
+//! is drawn and removed from the pool (ties are broken by the submission
+//! time). Every client (by IP address) can vote once per entry.
+use std::collections::HashSet;
+use std::sync::mpsc::TrySendError;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, error, info, warn};
+use serde_derive::{Deserialize, Serialize};
+use svg2polylines::Polyline;
+use time::OffsetDateTime;
+
+use crate::jobqueue::Job;
+use crate::robot::PrintTask;
+use crate::RobotQueue;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub(crate) struct VotingConfig {
+    /// How often the top-voted entry is drawn.
+    #[serde(default = "default_interval_seconds")]
+    pub(crate) interval_seconds: u64,
+    /// Entries with fewer votes are not drawn.
+    #[serde(default = "default_min_votes")]
+    min_votes: u32,
+    /// Maximum number of entries in the pool.
+    #[serde(default = "default_max_entries")]
+    max_entries: usize,
+}
+
+impl VotingConfig {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.interval_seconds == 0 {
+            return Err("interval_seconds must be positive".to_string());
+        }
+        if self.max_entries == 0 {
+            return Err("The pool must hold at least one entry".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn default_interval_seconds() -> u64 {
+    600
+}
+
+fn default_min_votes() -> u32 {
+    1
+}
+
+fn default_max_entries() -> usize {
+    50
+}
+
+#[derive(Debug)]
+struct Entry {
+    id: u64,
+    /// The owner (tenant) of the job, if any.
+    owner: Option<String>,
+    job: Job,
+    submitted: OffsetDateTime,
+    /// The clients that voted for the entry.
+    voters: HashSet<String>,
+}
+
+/// An entry of the pool, as shown to the audience.
+#[derive(Debug, Serialize)]
+pub(crate) struct EntryView<'a> {
+    id: u64,
+    label: &'a str,
+    votes: usize,
+    #[serde(with = "time::serde::rfc3339")]
+    submitted: OffsetDateTime,
+    polylines: &'a [Polyline],
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum VoteError {
+    NotFound,
+    AlreadyVoted,
+}
+
+pub(crate) type SharedVotingPool = Arc<Mutex<VotingPool>>;
+
+#[derive(Debug)]
+pub(crate) struct VotingPool {
+    config: VotingConfig,
+    entries: Vec<Entry>,
+    next_id: u64,
+}
+
+impl VotingPool {
+    pub(crate) fn new_shared(config: VotingConfig) -> SharedVotingPool {
+        Arc::new(Mutex::new(VotingPool {
+            config,
+            entries: vec![],
+            next_id: 1,
+        }))
+    }
+
+    /// Add a job to the pool, and return the id of its entry.
+    pub(crate) fn submit(
+        &mut self,
+        owner: Option<String>,
+        job: Job,
+        now: OffsetDateTime,
+    ) -> Result<u64, String> {
+        if self.entries.len() >= self.config.max_entries {
+            return Err(format!(
+                "The voting pool is full ({} entries)",
+                self.config.max_entries
+            ));
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entries.push(Entry {
+            id,
+            owner,
+            job,
+            submitted: now,
+            voters: HashSet::new(),
+        });
+        Ok(id)
+    }
+
+    /// Vote for an entry, and return its number of votes.
+    pub(crate) fn vote(&mut self, id: u64, voter: &str) -> Result<usize, VoteError> {
+        let entry = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.id == id)
+            .ok_or(VoteError::NotFound)?;
+        if !entry.voters.insert(voter.to_string()) {
+            return Err(VoteError::AlreadyVoted);
+        }
+        Ok(entry.voters.len())
+    }
+
+    /// Return the entries, the one drawn next first.
+    pub(crate) fn ranking(&self) -> Vec<EntryView<'_>> {
+        let mut entries: Vec<&Entry> = self.entries.iter().collect();
+        entries.sort_by_key(|entry| (std::cmp::Reverse(entry.voters.len()), entry.id));
+        entries
+            .into_iter()
+            .map(|entry| EntryView {
+                id: entry.id,
+                label: &entry.job.label,
+                votes: entry.voters.len(),
+                submitted: entry.submitted,
+                polylines: &entry.job.polylines,
+            })
+            .collect()
+    }
+
+    /// Remove the top-voted entry from the pool, if it has enough votes.
+    fn take_winner(&mut self) -> Option<Entry> {
+        let winner = self.ranking().first().map(|entry| entry.id)?;
+        let index = self.entries.iter().position(|entry| entry.id == winner)?;
+        if self.entries[index].voters.len() < self.config.min_votes as usize {
+            return None;
+        }
+        Some(self.entries.remove(index))
+    }
+
+    /// Put an entry that could not be drawn back into the pool.
+    fn restore(&mut self, entry: Entry) {
+        self.entries.push(entry);
+    }
+}
+
+/// Spawn a thread that draws the top-voted entry every interval.
+pub(crate) fn spawn(pool: SharedVotingPool, interval: Duration, robot_queue: RobotQueue) {
+    info!(
+        "Voting: Drawing the top-voted entry every {} seconds",
+        interval.as_secs()
+    );
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        let winner = match pool.lock() {
+            Ok(mut pool) => pool.take_winner(),
+            Err(e) => {
+                error!("Voting: Could not access the pool: {}", e);
+                continue;
+            }
+        };
+        let Some(entry) = winner else {
+            debug!("Voting: No entry with enough votes");
+            continue;
+        };
+        info!(
+            "Voting: Drawing {} ({} votes)",
+            entry.job.label,
+            entry.voters.len()
+        );
+        let sent = match robot_queue.lock() {
+            Ok(tx) => tx.try_send(PrintTask::Job(entry.owner.clone(), entry.job.clone())),
+            Err(e) => {
+                error!("Voting: Could not communicate with robot thread: {}", e);
+                continue;
+            }
+        };
+        match sent {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                warn!("Voting: The robot queue is full, trying again later");
+                if let Ok(mut pool) = pool.lock() {
+                    pool.restore(entry);
+                }
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                error!("Voting: The robot thread is gone");
+                return;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::datetime;
+
+    use super::*;
+
+    fn pool(min_votes: u32) -> VotingPool {
+        VotingPool {
+            config: VotingConfig {
+                interval_seconds: 60,
+                min_votes,
+                max_entries: 3,
+            },
+            entries: vec![],
+            next_id: 1,
+        }
+    }
+
+    fn submit(pool: &mut VotingPool, label: &str) -> Result<u64, String> {
+        let now = datetime!(2026-10-16 12:00 UTC);
+        pool.submit(None, Job::new(label.to_string(), vec![]), now)
+    }
+
+    #[test]
+    fn test_vote() {
+        let mut pool = pool(1);
+        let first = submit(&mut pool, "first").unwrap();
+        let second = submit(&mut pool, "second").unwrap();
+        assert_eq!(pool.vote(second, "10.0.0.1"), Ok(1));
+        assert_eq!(pool.vote(second, "10.0.0.2"), Ok(2));
+        assert_eq!(pool.vote(second, "10.0.0.1"), Err(VoteError::AlreadyVoted));
+        assert_eq!(pool.vote(42, "10.0.0.1"), Err(VoteError::NotFound));
+        assert_eq!(pool.vote(first, "10.0.0.1"), Ok(1));
+
+        let ranking: Vec<(u64, usize)> = pool.ranking().iter().map(|e| (e.id, e.votes)).collect();
+        assert_eq!(ranking, vec![(second, 2), (first, 1)]);
+    }
+
+    #[test]
+    fn test_take_winner() {
+        let mut pool = pool(2);
+        let first = submit(&mut pool, "first").unwrap();
+        let second = submit(&mut pool, "second").unwrap();
+        submit(&mut pool, "third").unwrap();
+        assert!(submit(&mut pool, "fourth").is_err());
+
+        // Not enough votes yet
+        pool.vote(second, "a").unwrap();
+        assert!(pool.take_winner().is_none());
+
+        // Ties are broken by the submission time
+        pool.vote(second, "b").unwrap();
+        pool.vote(first, "a").unwrap();
+        pool.vote(first, "b").unwrap();
+        let winner = pool.take_winner().unwrap();
+        assert_eq!(winner.job.label, "first");
+        assert_eq!(pool.take_winner().unwrap().job.label, "second");
+        assert!(pool.take_winner().is_none());
+
+        // Entries that could not be drawn are put back
+        pool.restore(winner);
+        assert_eq!(pool.ranking()[0].id, first);
+    }
+}
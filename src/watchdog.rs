@@ -0,0 +1,327 @@
+//! A watchdog for the resources of the host, e.g. a Raspberry Pi whose SD
+//! card fills up with serial captures.
+//!
+//! With a `watchdog` section in the config, the free disk space (of `path`,
+//! the SVG dir by default), the available memory and the number of serial
+//! errors (stalls and disconnects) within the last hour are checked every
+//! `interval_seconds`. Exceeded thresholds are logged, listed in
+//! `/robot/status/` and reported as `resource_warning` events (once, until
+//! they are resolved). The measurements are returned by `/watchdog/`. While
+//! the disk is nearly full, serial captures and library uploads are paused.
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+use serde_derive::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::notify::Event;
+
+/// The window in which serial errors are counted.
+const SERIAL_ERROR_WINDOW: Duration = Duration::from_secs(3600);
+
+pub(crate) type SharedWatchdog = Arc<Watchdog>;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct WatchdogConfig {
+    /// The directory whose file system is checked (the SVG dir if unset).
+    #[serde(default)]
+    pub(crate) path: Option<String>,
+    #[serde(default = "default_interval_seconds")]
+    pub(crate) interval_seconds: u64,
+    #[serde(default = "default_min_free_disk_mb")]
+    pub(crate) min_free_disk_mb: u64,
+    #[serde(default = "default_min_available_memory_mb")]
+    pub(crate) min_available_memory_mb: u64,
+    #[serde(default = "default_max_serial_errors_per_hour")]
+    pub(crate) max_serial_errors_per_hour: u32,
+}
+
+fn default_interval_seconds() -> u64 {
+    60
+}
+
+fn default_min_free_disk_mb() -> u64 {
+    100
+}
+
+fn default_min_available_memory_mb() -> u64 {
+    32
+}
+
+fn default_max_serial_errors_per_hour() -> u32 {
+    10
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Resource {
+    Disk,
+    Memory,
+    Serial,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub(crate) struct Warning {
+    pub(crate) resource: Resource,
+    pub(crate) message: String,
+}
+
+/// The latest measurements, as returned by `/watchdog/`.
+#[derive(Debug, Serialize, Clone, Default)]
+pub(crate) struct Report {
+    #[serde(with = "time::serde::rfc3339::option")]
+    checked: Option<OffsetDateTime>,
+    free_disk_mb: Option<u64>,
+    available_memory_mb: Option<u64>,
+    serial_errors_last_hour: u32,
+    writes_paused: bool,
+    warnings: Vec<Warning>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Watchdog {
+    /// When the recent serial errors occurred.
+    serial_errors: Mutex<VecDeque<Instant>>,
+    /// Whether optional writes are paused because the disk is nearly full.
+    writes_paused: AtomicBool,
+    report: Mutex<Report>,
+}
+
+impl Watchdog {
+    pub(crate) fn new_shared() -> SharedWatchdog {
+        Arc::new(Watchdog::default())
+    }
+
+    /// Count a serial error (e.g. a stall or a disconnect).
+    pub(crate) fn serial_error(&self) {
+        match self.serial_errors.lock() {
+            Ok(mut errors) => errors.push_back(Instant::now()),
+            Err(e) => error!("Could not unlock serial errors mutex: {}", e),
+        }
+    }
+
+    /// Return the number of serial errors since `since`, forgetting older
+    /// ones.
+    fn serial_errors_since(&self, since: Instant) -> u32 {
+        match self.serial_errors.lock() {
+            Ok(mut errors) => {
+                while errors.front().is_some_and(|&time| time < since) {
+                    errors.pop_front();
+                }
+                errors.len() as u32
+            }
+            Err(e) => {
+                error!("Could not unlock serial errors mutex: {}", e);
+                0
+            }
+        }
+    }
+
+    /// Return whether optional writes (e.g. serial captures) are paused.
+    pub(crate) fn writes_paused(&self) -> bool {
+        self.writes_paused.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn report(&self) -> Report {
+        match self.report.lock() {
+            Ok(report) => report.clone(),
+            Err(e) => {
+                error!("Could not unlock watchdog report mutex: {}", e);
+                Report::default()
+            }
+        }
+    }
+
+    /// Return the current warnings.
+    pub(crate) fn warnings(&self) -> Vec<Warning> {
+        self.report().warnings
+    }
+}
+
+/// Return the warnings for the measurements.
+fn check(
+    config: &WatchdogConfig,
+    free_disk_mb: Option<u64>,
+    available_memory_mb: Option<u64>,
+    serial_errors: u32,
+) -> Vec<Warning> {
+    let mut warnings = vec![];
+    if let Some(free) = free_disk_mb.filter(|&free| free < config.min_free_disk_mb) {
+        warnings.push(Warning {
+            resource: Resource::Disk,
+            message: format!(
+                "Only {} MB of disk space left, serial captures and uploads are paused",
+                free
+            ),
+        });
+    }
+    if let Some(available) =
+        available_memory_mb.filter(|&available| available < config.min_available_memory_mb)
+    {
+        warnings.push(Warning {
+            resource: Resource::Memory,
+            message: format!("Only {} MB of memory available", available),
+        });
+    }
+    if serial_errors > config.max_serial_errors_per_hour {
+        warnings.push(Warning {
+            resource: Resource::Serial,
+            message: format!("{} serial errors within the last hour", serial_errors),
+        });
+    }
+    warnings
+}
+
+/// Return the space available to unprivileged users on the file system of
+/// the path, in MB.
+fn free_disk_mb(path: &Path) -> io::Result<u64> {
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // Safety: The path is a valid C string and `stat` is a valid buffer
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64 / (1024 * 1024))
+}
+
+/// Parse the available memory in MB from `/proc/meminfo`.
+fn parse_meminfo(meminfo: &str) -> Option<u64> {
+    let line = meminfo
+        .lines()
+        .find(|line| line.starts_with("MemAvailable:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb / 1024)
+}
+
+fn available_memory_mb() -> io::Result<u64> {
+    parse_meminfo(&fs::read_to_string("/proc/meminfo")?).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "No MemAvailable in /proc/meminfo",
+        )
+    })
+}
+
+/// Spawn the watchdog thread, which checks the resources every interval.
+pub(crate) fn spawn(
+    config: WatchdogConfig,
+    path: String,
+    watchdog: SharedWatchdog,
+    events: Sender<Event>,
+) {
+    info!(
+        "Checking resources every {} seconds",
+        config.interval_seconds
+    );
+    thread::spawn(move || loop {
+        let free_disk_mb = free_disk_mb(Path::new(&path))
+            .map_err(|e| warn!("Could not check the disk space of {}: {}", path, e))
+            .ok();
+        let available_memory_mb = available_memory_mb()
+            .map_err(|e| warn!("Could not check the available memory: {}", e))
+            .ok();
+        let serial_errors = Instant::now()
+            .checked_sub(SERIAL_ERROR_WINDOW)
+            .map_or(0, |since| watchdog.serial_errors_since(since));
+        let warnings = check(&config, free_disk_mb, available_memory_mb, serial_errors);
+        let writes_paused = warnings.iter().any(|w| w.resource == Resource::Disk);
+        if writes_paused
+            != watchdog
+                .writes_paused
+                .swap(writes_paused, Ordering::Relaxed)
+        {
+            match writes_paused {
+                true => warn!("Disk nearly full, pausing serial captures and uploads"),
+                false => info!("Disk space recovered, resuming serial captures and uploads"),
+            }
+        }
+        let previous = watchdog.warnings();
+        for warning in warnings.iter() {
+            if !previous.iter().any(|p| p.resource == warning.resource) {
+                warn!("Watchdog: {}", warning.message);
+                let _ = events.send(Event::ResourceWarning {
+                    resource: warning.resource,
+                    message: warning.message.clone(),
+                });
+            }
+        }
+        for resolved in previous
+            .iter()
+            .filter(|p| !warnings.iter().any(|w| w.resource == p.resource))
+        {
+            info!("Watchdog: Resolved: {}", resolved.message);
+        }
+        match watchdog.report.lock() {
+            Ok(mut report) => {
+                *report = Report {
+                    checked: Some(OffsetDateTime::now_utc()),
+                    free_disk_mb,
+                    available_memory_mb,
+                    serial_errors_last_hour: serial_errors,
+                    writes_paused,
+                    warnings,
+                }
+            }
+            Err(e) => error!("Could not unlock watchdog report mutex: {}", e),
+        }
+        thread::sleep(Duration::from_secs(config.interval_seconds));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> WatchdogConfig {
+        serde_json::from_str("{}").unwrap()
+    }
+
+    #[test]
+    fn test_check() {
+        let config = config();
+        assert!(check(&config, Some(500), Some(200), 3).is_empty());
+        assert!(check(&config, None, None, 0).is_empty());
+
+        let warnings = check(&config, Some(50), Some(200), 11);
+        let resources: Vec<Resource> = warnings.iter().map(|w| w.resource).collect();
+        assert_eq!(resources, vec![Resource::Disk, Resource::Serial]);
+        assert_eq!(
+            check(&config, Some(500), Some(10), 0)[0].message,
+            "Only 10 MB of memory available"
+        );
+    }
+
+    #[test]
+    fn test_parse_meminfo() {
+        let meminfo = "MemTotal:        3884376 kB\n\
+                       MemFree:          183688 kB\n\
+                       MemAvailable:    2048000 kB\n";
+        assert_eq!(parse_meminfo(meminfo), Some(2000));
+        assert_eq!(parse_meminfo("MemTotal: 1 kB\n"), None);
+    }
+
+    #[test]
+    fn test_serial_errors() {
+        let watchdog = Watchdog::default();
+        let before = Instant::now();
+        watchdog.serial_error();
+        watchdog.serial_error();
+        assert_eq!(watchdog.serial_errors_since(before), 2);
+        assert_eq!(
+            watchdog.serial_errors_since(Instant::now() + Duration::from_secs(1)),
+            0
+        );
+        assert_eq!(watchdog.serial_errors_since(before), 0);
+    }
+}
@@ -21,6 +21,7 @@ async function loadSvg(ev, svg, layer) {
             const polylines = await r.json();
             layer.destroyChildren();
             drawPreview(layer, polylines);
+            showAnalysis(svg, layer);
         } else {
             console.error('Error: HTTP', r.status);
             if (r.status == 400) {
@@ -32,6 +33,43 @@ async function loadSvg(ev, svg, layer) {
     }
 }
 
+/**
+ * Show statistics of the drawing, as it is placed in the preview.
+ */
+async function showAnalysis(svg, layer) {
+    // The preview mode can't analyze drawings
+    const output = document.getElementById('analysis');
+    if (!output) {
+        return;
+    }
+    output.textContent = '';
+    const [obj] = layer.getChildren((node) => node.hasName('polylines'));
+    if (!obj) {
+        return;
+    }
+    const r = await fetch('/analyze/', {
+        method: 'POST',
+        headers: { 'Content-Type': 'application/json' },
+        body: JSON.stringify({
+            svg: svg.text,
+            offset_x: obj.x(),
+            offset_y: obj.y(),
+            scale_x: obj.scaleX(),
+            scale_y: obj.scaleY(),
+        }),
+    });
+    if (!r.ok) {
+        console.error('Error: HTTP', r.status);
+        return;
+    }
+    const analysis = await r.json();
+    const meters = (mm) => (mm / 1000).toFixed(1);
+    const minutes = Math.ceil(analysis.estimated_seconds / 60);
+    output.textContent =
+        `${analysis.paths} paths, ${meters(analysis.draw_length)} m drawn, ` +
+        `${meters(analysis.travel_length)} m pen travel, about ${minutes} min`;
+}
+
 // Re-scale group to fit and center it in bounds
 function fitGroup(group, bounds, margin) {
     // note: Don't use offset(), as it will change how scaling is
@@ -99,7 +137,19 @@ function drawPreview(layer, polylines) {
  * Send the object to the printer.
  */
 function printObject(svg, layer) {
-    const printMode = document.querySelector('input[name=mode]:checked').value;
+    let printMode = document.querySelector('input[name=mode]:checked').value;
+    if (printMode == 'custom') {
+        const minutes = parseInt(document.getElementById('mode-schedule-minutes').value, 10);
+        if (!(minutes > 0)) {
+            alert('Please enter a schedule interval of at least one minute.');
+            return;
+        }
+        printMode = { schedule: { minutes } };
+    }
+
+    const fit = document.getElementById('fit').checked;
+    const align = document.getElementById('align').value || null;
+    const nativeSize = document.getElementById('native-size').checked;
 
     const children = layer.getChildren((node) => node.hasName('polylines'));
     if (children.length == 0) {
@@ -113,16 +163,24 @@ function printObject(svg, layer) {
         const dy = obj.y();
         console.debug('  Moved by', dx, dy);
         console.debug('  Scaled by', obj.scaleX, obj.scaleY);
+        // At its native size, the SVG is sized by its units instead
+        const transform = nativeSize
+            ? { native_size: true }
+            : {
+                  offset_x: dx,
+                  offset_y: dy,
+                  scale_x: obj.scaleX(),
+                  scale_y: obj.scaleY(),
+              };
 
         const r = await fetch('/print/', {
             method: 'POST',
             headers: { 'Content-Type': 'application/json' },
             body: JSON.stringify({
                 svg: svg.text,
-                offset_x: dx,
-                offset_y: dy,
-                scale_x: obj.scaleX(),
-                scale_y: obj.scaleY(),
+                ...transform,
+                fit,
+                align,
                 mode: printMode,
             }),
         });